@@ -2,17 +2,29 @@ extern crate docopt;
 
 use std::collections::HashSet;
 
+// Arguments given on the command line.
+pub struct Args {
+    // Functions to analyze, all of them if empty.
+    pub functions: Vec<String>,
+    // Rule file applied along with the built-in idioms.
+    pub rules: Option<String>,
+}
+
 // Create Docopt parser and fetches the CLI arguments as appropriate
-// Returns an empty vector if no args were found
-pub fn init_for_args(usage: &str) -> Vec<String> {
+// Returns no functions and no rule file if no args were found
+pub fn init_for_args(usage: &str) -> Args {
 
     let args = docopt::Docopt::new(usage).and_then(|d| d.parse());
 
-    let mut arg_vect: Vec<String> = Vec::new();
+    let mut parsed = Args { functions: Vec::new(), rules: None };
     if let Ok(ref arg_map) = args {
-        arg_vect = arg_map.get_vec("<names>").iter().map(|&slice| String::from(slice)).collect()
+        parsed.functions = arg_map.get_vec("<names>").iter().map(|&slice| String::from(slice)).collect();
+        let rules = arg_map.get_str("--rules");
+        if !rules.is_empty() {
+            parsed.rules = Some(rules.to_owned());
+        }
     }
-    arg_vect
+    parsed
 }
 
 // Prints summary of the matching if any command line arguments were
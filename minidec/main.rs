@@ -12,32 +12,54 @@ use std::fs::{self, File};
 use std::io::Write;
 use std::path::PathBuf;
 use std::process;
+use std::sync::Arc;
 
-use radeco_lib::analysis::cse::cse::CSE;
+use radeco_lib::analysis::arrays;
+use radeco_lib::analysis::cse::gvn::GVN;
+use radeco_lib::analysis::idioms::{bits, magic_div, memory};
+use radeco_lib::analysis::ranges;
+use radeco_lib::analysis::records::{self, Record};
+use radeco_lib::analysis::reference_marking::ReferenceMarker;
+use radeco_lib::analysis::reference_marking::reference_marking_inter::InterProceduralAnalyzer;
 use radeco_lib::analysis::sccp;
-//use radeco_lib::analysis::valueset::analyzer_wysinwyx::FnAnalyzer;
-//use radeco_lib::analysis::valueset::mem_structs::{A_Loc,AbstractAddress};
+use radeco_lib::analysis::tie;
+use radeco_lib::analysis::vsa::analyzer as vsa;
 use radeco_lib::analysis::interproc::fixcall::CallFixer;
-use radeco_lib::frontend::radeco_containers::ProjectLoader;
+use radeco_lib::analysis::interproc::frame::FrameCleaner;
+use radeco_lib::analysis::matcher::rules::RuleSet;
+use radeco_lib::frontend::llanalyzer;
+use radeco_lib::frontend::radeco_containers::{ProjectLoader, RadecoFunction};
+use radeco_lib::frontend::rodata::ReadOnlyData;
 use radeco_lib::middle::{dce, dot};
 use radeco_lib::middle::ir_writer;
 use radeco_lib::middle::ir_reader::parse_il;
+use radeco_lib::middle::ssa::ssa_traits::SSA;
 use radeco_lib::middle::ssa::verifier;
 use radeco_lib::backend::lang_c::{c_simple_ast, c_simple_ast_builder};
 
-//use radeco_lib::analysis::mark_refs;
+// Cap on the rounds of inter-function reference propagation.
+const REFERENCE_MARKING_ITERATIONS: u64 = 16;
 
 const USAGE: &'static str = "
-Usage: minidec [-f <names>...] <target>
+Usage: minidec [-r <file>] [-f <names>...] <target>
 
 Options:
-    -f, --functions  Analayze only some functions
+    -f, --functions     Analayze only some functions
+    -r, --rules <file>  Apply the rewrite rules in <file> along with the idioms
 ";
 
 fn main() {
     #[cfg(feature="trace_log")] env_logger::init();
 
-    let requested_functions = cli::init_for_args(USAGE);
+    let args = cli::init_for_args(USAGE);
+    let requested_functions = args.functions;
+    // Rules are checked before loading the binary, which takes much longer.
+    let rules = args.rules.map(|path| {
+        RuleSet::load(&path).unwrap_or_else(|e| {
+            println!("[!] Invalid rule file {}: {}", path, e);
+            process::exit(1);
+        })
+    });
 
     let proj_name = env::args().nth(env::args().len() - 1).unwrap();
     let mut rproj = {
@@ -53,23 +75,38 @@ fn main() {
         // Reduce the complexity of rmod.functions to just a vec of (u64,&String)
         // for easier extraction and matching
    
+        let bp_name = regfile.get_name_by_alias(&"BP".to_string());
+        let bp_name = bp_name.map(|s| s.to_owned());
+        let sp_name = regfile.get_name_by_alias(&"SP".to_string());
+        let sp_name = sp_name.map(|s| s.to_owned());
+
         // Analyze preserved for all functions.
         {
             println!("[*] Fixing Callee Information");
-            let bp_name = regfile.get_name_by_alias(&"BP".to_string());
-            let bp_name = bp_name.map(|s| s.to_owned());
-            let sp_name = regfile.get_name_by_alias(&"SP".to_string());
-            let sp_name = sp_name.map(|s| s.to_owned());
-            let mut callfixer = CallFixer::new(rmod, bp_name, sp_name);
+            let mut callfixer = CallFixer::new(rmod, bp_name.clone(), sp_name.clone());
             callfixer.rounded_analysis();
         }
 
+        // Remove stack protector checks, register spills and frame setup.
+        {
+            println!("[*] Removing Prologue and Epilogue Noise");
+            let mut cleaner = FrameCleaner::new(rmod, bp_name, sp_name);
+            cleaner.run();
+        }
+
         // Fix call sites
         radeco_lib::analysis::functions::fix_ssa_opcalls::go(rmod);
 
         // Infer calling conventions
         radeco_lib::analysis::functions::infer_regusage::run(rmod, &*regfile);
 
+        // Replace inlined string functions by calls to pseudo-imports, once call sites
+        // are fixed so that the calls inserted are not taken for unresolved ones.
+        {
+            println!("[*] Recovering Inlined String Functions");
+            memory::run_module(rmod);
+        }
+
         // Filter the data if the user provided some args to be matched upon
         let matched_func_addrs = if requested_functions.len() != 0 {
  
@@ -92,6 +129,15 @@ fn main() {
             Vec::new()
         };
 
+        // Loads from read-only data are folded during constant propagation.
+        let rodata = Arc::new(ReadOnlyData::load(rmod));
+
+        // Calls to known library functions constrain the types inferred.
+        let prototypes = tie::prototypes(rmod, &regfile);
+
+        // Calls to allocators return memory in a heap region of their own.
+        let allocators = vsa::allocators(rmod);
+
         // Main file to contain IRs of all rfns
         let mut ffm = {
             let mut fname = PathBuf::from(&dir);
@@ -99,7 +145,7 @@ fn main() {
             File::create(&fname).expect("Unable to create file")
         };
 
-        for addr in matched_func_addrs {
+        for &addr in &matched_func_addrs {
 
             let ref mut rfn = rmod.functions.get_mut(&addr).unwrap();
 
@@ -109,9 +155,13 @@ fn main() {
                 dce::collect(rfn.ssa_mut());
             }
             let mut ssa = {
+                println!("  [*] Analyzing Value Ranges");
+                let ranges = ranges::analyze(rfn.ssa());
                 // Constant Propagation (sccp)
                 println!("  [*] Propagating Constants");
-                let mut analyzer = sccp::Analyzer::new(rfn.ssa_mut());
+                let mut analyzer = sccp::Analyzer::new(rfn.ssa_mut())
+                    .rodata(rodata.clone())
+                    .ranges(&ranges);
                 analyzer.analyze();
                 analyzer.emit_ssa()
             };
@@ -121,10 +171,29 @@ fn main() {
             }
             *rfn.ssa_mut() = ssa;
             {
-                // Common SubExpression Elimination (cse)
+                println!("  [*] Recovering Idioms");
+                magic_div::run(rfn.ssa_mut());
+                bits::run(rfn.ssa_mut());
+                if let Some(ref rules) = rules {
+                    let stats = rules.apply(rfn.ssa_mut());
+                    println!("  [*] Applied {} rewrite rules", stats.total());
+                }
+                dce::collect(rfn.ssa_mut());
+            }
+            {
+                // Global Value Numbering (gvn)
                 println!("  [*] Eliminating Common SubExpressions");
-                let mut cse = CSE::new(rfn.ssa_mut());
-                cse.run();
+                let merged = GVN::new(rfn.ssa_mut()).run();
+                println!("  [*] Merged {} values", merged);
+            }
+            {
+                // Type Inference (tie)
+                println!("  [*] Inferring Types");
+                tie::run(rfn, &prototypes);
+            }
+            {
+                println!("  [*] Recovering Array Accesses");
+                arrays::run(rfn);
             }
             {
                 // Verify SSA 
@@ -150,28 +219,27 @@ fn main() {
                 //     mssa
                 // };
             }
-            //if false {
-            //    if (!rfn.name.eq("sym.main")) & (!rfn.name.eq("main")) {
-            //        continue;
-            //    }
-            //    println!("  [*] Analyzing Value Sets");
-            //    let fn_analyzer = FnAnalyzer::from((*rfn).clone());
-            //    let a_store_fn = fn_analyzer.analyze_rfn();
-            //    for (a_loc, strided_interval) in a_store_fn.store {
-            //        if let A_Loc{addr: AbstractAddress::Node{..}, ..} = a_loc {
-            //            continue;
-            //        };
-            //        println!("{}", a_loc);
-            //        println!("Strided Interval: {}", strided_interval);
-            //    };
-            //}
-            
-            //{
-                //// Expreimental reference marking pass
-                //println!("  [*] Unstable> Marking References");
-                //let mut rmark = mark_refs::ReferenceMarker { };
-                //rmark.resolve_refs(&mut rfn.ssa);
-            //}
+            {
+                println!("  [*] Analyzing Value Sets");
+                let value_sets = vsa::run(rfn, &allocators);
+                println!("  [*] Found {} a-locs", value_sets.alocs().len());
+            }
+        }
+
+        // References are propagated across call sites, so all functions are marked at once. The
+        // call sites were rewritten since loading and are mapped to their callees again.
+        println!("[*] Marking References");
+        llanalyzer::init_call_ctx(rmod);
+        InterProceduralAnalyzer::<ReferenceMarker>::analyze(rmod, &regfile,
+                                                            Some(REFERENCE_MARKING_ITERATIONS));
+
+        // Record layouts are recovered from the accesses of all functions at once.
+        println!("[*] Recovering Records");
+        let records = records::run(rmod);
+
+        for addr in matched_func_addrs {
+
+            let ref mut rfn = rmod.functions.get_mut(&addr).unwrap();
 
             let mut fname = PathBuf::from(&dir);
             fname.push(rfn.name.as_ref());
@@ -227,6 +295,9 @@ fn main() {
                 let mut df = File::create(format!("{}.c", fname.to_string_lossy())).expect("Unable to create .c file");
                 let ast = c_simple_ast_builder::recover_simple_ast(&rfn);
                 let code = ast.to_c_ast().print();
+                for record in records.iter().filter(|r| uses_record(rfn, r)) {
+                    writeln!(df, "{}\n", record.to_c()).expect("Error writing to file");
+                }
                 writeln!(df, "{}", code).expect("Error writing to file");
             }
         }
@@ -238,6 +309,14 @@ fn main() {
     }
 }
 
+// Whether the pseudo-C code of `rfn` accesses fields of `record`
+fn uses_record(rfn: &RadecoFunction, record: &Record) -> bool {
+    rfn.ssa()
+       .values()
+       .into_iter()
+       .any(|v| rfn.field_access(v).map_or(false, |a| a.record == record.name))
+}
+
 // Filters the functions that were in Radeco output AND requested by user
 fn filter_with<'a>(all_funcs: &Vec<(u64, &'a str)>,
                    requested: &Vec<&'a str>)
@@ -0,0 +1,198 @@
+//! Recognises memory accesses which index an array.
+//!
+//! An address `base + index * scale`, with the scale possibly written as a shift, is
+//! an access to element `index` of the array at `base` if the scale is the width of
+//! the access. The index is usually a loop induction variable, which is a phi
+//! incremented by one on every iteration, extended to the width of the address on
+//! architectures where it is narrower. The C backend prints these accesses as
+//! `base[index]` and declares the base as a pointer to the element type.
+
+use std::collections::HashMap;
+
+use frontend::radeco_containers::RadecoFunction;
+use middle::ir::MOpcode;
+use middle::ssa::ssa_traits::*;
+use middle::ssa::ssastorage::SSAStorage;
+
+pub type SSAValue = <SSAStorage as SSA>::ValueRef;
+
+/// Access to an element of an array, recorded for the value of its address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArrayAccess {
+    /// Address of the first element.
+    pub base: SSAValue,
+    /// Index of the element accessed.
+    pub index: SSAValue,
+    /// Width of an element in bits.
+    pub element_width: u16,
+}
+
+/// Recognises the array accesses of `rfn` and stores them in it.
+pub fn run(rfn: &mut RadecoFunction) {
+    let accesses = recover(rfn.ssa());
+    radeco_trace!("arrays|{} array accesses in {}", accesses.len(), rfn.name);
+    rfn.set_array_accesses(accesses);
+}
+
+/// Array accesses of `ssa` by the value of their address.
+pub fn recover(ssa: &SSAStorage) -> HashMap<SSAValue, ArrayAccess> {
+    let mut accesses = HashMap::new();
+    for value in ssa.values() {
+        let width = match ssa.opcode(value) {
+            Some(MOpcode::OpLoad(width, _)) | Some(MOpcode::OpStore(width, _)) => width,
+            _ => continue,
+        };
+        let address = match ssa.operands_of(value).get(1) {
+            Some(&address) => address,
+            None => continue,
+        };
+        if let Some((base, index)) = scaled_index(ssa, address, width as u64 / 8) {
+            let access = ArrayAccess {
+                base: base,
+                index: index,
+                element_width: width,
+            };
+            // Accesses of different widths through one address are no array.
+            if accesses.get(&address).map_or(false, |a: &ArrayAccess| *a != access) {
+                radeco_trace!("arrays|Conflicting accesses through {:?}", address);
+                accesses.remove(&address);
+                continue;
+            }
+            accesses.insert(address, access);
+        }
+    }
+    accesses
+}
+
+/// Whether `value` increases by one on every iteration of a loop.
+pub fn is_induction_variable(ssa: &SSAStorage, value: SSAValue) -> bool {
+    if !ssa.is_phi(value) {
+        return false;
+    }
+    ssa.operands_of(value).into_iter().any(|op| {
+        ssa.opcode(op) == Some(MOpcode::OpAdd) &&
+        ssa.operands_of(op).contains(&value) &&
+        ssa.operands_of(op).into_iter().any(|o| ssa.constant(o) == Some(1))
+    })
+}
+
+/// Splits `address` into a base and an index scaled by `scale`.
+fn scaled_index(ssa: &SSAStorage,
+                address: SSAValue,
+                scale: u64)
+                -> Option<(SSAValue, SSAValue)> {
+    if scale == 0 || ssa.opcode(address) != Some(MOpcode::OpAdd) {
+        return None;
+    }
+    let operands = ssa.operands_of(address);
+    let (a, b) = (*operands.get(0)?, *operands.get(1)?);
+    let (base, index) = match (unscaled(ssa, a, scale), unscaled(ssa, b, scale)) {
+        (_, Some(index)) => (a, index),
+        (Some(index), None) => (b, index),
+        (None, None) => {
+            // Byte arrays are indexed without scaling, only their induction variables
+            // tell an index from an offset.
+            if scale != 1 {
+                return None;
+            }
+            match (is_induction_variable(ssa, a), is_induction_variable(ssa, b)) {
+                (_, true) => (a, b),
+                (true, false) => (b, a),
+                (false, false) => return None,
+            }
+        }
+    };
+    if ssa.is_constant(index) {
+        None
+    } else {
+        Some((base, index))
+    }
+}
+
+/// The index `value` is `scale` times, if it is computed as such.
+fn unscaled(ssa: &SSAStorage, value: SSAValue, scale: u64) -> Option<SSAValue> {
+    let operands = ssa.operands_of(value);
+    match ssa.opcode(value) {
+        Some(MOpcode::OpMul) => {
+            if ssa.constant(*operands.get(1)?) == Some(scale) {
+                Some(operands[0])
+            } else if ssa.constant(operands[0]) == Some(scale) {
+                Some(operands[1])
+            } else {
+                None
+            }
+        }
+        Some(MOpcode::OpLsl) => {
+            match ssa.constant(*operands.get(1)?) {
+                Some(shift) if shift < 64 && 1 << shift == scale => Some(operands[0]),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir_reader::test_util::{at, parse};
+
+    // Copies `%1[i]` to `%2[i]` and sums the bytes of `%3` for `i` up to `%4`.
+    const LOOP: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+        %2: $Unknown64 = $rsi;
+        %3: $Unknown64 = $rdx;
+        %4: $Unknown64 = $rcx;
+        %5: $Unknown0 = $mem;
+    bb_0x001000.0000(sz 0x4):
+        JMP 0x001004.0000
+    bb_0x001004.0000(sz 0x20):
+        %6: $Unknown64 = Phi(#x0, %15);
+        %7: $Unknown0 = Phi(%5, %11);
+        %8: $Unknown64 = Phi(#x0, %14);
+        [@0x001004.0000] %9: $Unknown64 = %6 * #x4;
+        [@0x001004.0001] %10: $Unknown64 = %1 + %9;
+        [@0x001004.0002] %16: $Unknown32 = Load32(%7, %10);
+        [@0x001008.0000] %17: $Unknown64 = %6 << #x2;
+        [@0x001008.0001] %18: $Unknown64 = %17 + %2;
+        [@0x001008.0002] %11: $Unknown0 = Store32(%7, %18, %16);
+        [@0x00100C.0000] %12: $Unknown64 = %3 + %6;
+        [@0x00100C.0001] %13: $Unknown8 = Load8(%11, %12);
+        [@0x001010.0000] %19: $Unknown64 = ZeroExt64(%13);
+        [@0x001010.0001] %14: $Unknown64 = %8 + %19;
+        [@0x001014.0000] %15: $Unknown64 = %6 + #x1;
+        [@0x001018.0000] %20: $Unknown1 = %15 < %4;
+        [@0x001020.0000] %21: $Unknown64 = %1 + %4;
+        [@0x001020.0001] %22: $Unknown64 = Load64(%11, %21);
+        JMP IF %20 0x001004.0000 ELSE 0x001024.0000
+    bb_0x001024.0000(sz 0x4):
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $mem = %11;
+        $rax = %14;
+        $rbx = %22;
+}
+";
+
+    #[test]
+    fn recover_test() {
+        let ssa = parse(LOOP);
+        let accesses = recover(&ssa);
+        let i = ssa.operands_of(at(&ssa, 0x1004, 0))[0];
+        assert!(is_induction_variable(&ssa, i));
+        let load = accesses[&at(&ssa, 0x1004, 1)];
+        assert_eq!(load.base, ssa.operands_of(at(&ssa, 0x1004, 1))[0]);
+        assert_eq!((load.index, load.element_width), (i, 32));
+        let store = accesses[&at(&ssa, 0x1008, 1)];
+        assert_eq!(store.base, ssa.operands_of(at(&ssa, 0x1008, 1))[1]);
+        assert_eq!((store.index, store.element_width), (i, 32));
+        let bytes = accesses[&at(&ssa, 0x100C, 0)];
+        assert_eq!((bytes.index, bytes.element_width), (i, 8));
+        // An unscaled sum of values is no array access of a wider element.
+        assert!(!accesses.contains_key(&at(&ssa, 0x1020, 0)));
+        assert_eq!(accesses.len(), 3);
+    }
+}
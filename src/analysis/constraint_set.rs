@@ -1,50 +1,116 @@
 //! Implements Constraint and ConstraintSet
 //!
-//! NOTE: Current implementation is limited to work with reference marking.
-//! However, this principle is quite general and can be extended as needed later on.
-//! This needs a bit more engineering (generics) to make it work for the general case.
-
-// This is what the current implementation solves. Extend to any generic lattice.
-// T_a = C
-//     = R
-//     = op1 U op2
-//
-//   C = op1 U op2 => op1 = C /\ op2 = C
-//   R = op1 U op2 => (op1 = R /\ op2 = C) \/ (op1 = C /\ op2 = R)
-//
-
-
-use middle::ssa::ssa_traits::ValueType;
-use std::collections::{HashMap, VecDeque};
+//! Variables of a `ConstraintSet` are bound to values of a finite `Lattice`. Every variable
+//! starts at the bottom of the lattice and only moves up, towards the top, as constraints are
+//! solved. A variable that reaches the top was constrained to conflicting values.
+//!
+//! Supported constraints are:
+//!
+//! ```text
+//! T_a = C              -- Equality to a value
+//!     = op1 U op2      -- Equality to the union of two variables
+//! T_a = T_b = ...      -- Equivalence of variables
+//! T_a <= T_b           -- Subset, the value of T_a flows into T_b
+//! ```
+//!
+//! The meaning of the union is defined by the lattice. By default it is the join, but a
+//! lattice can refine operands from the result, as reference marking does:
+//!
+//! ```text
+//!   C = op1 U op2 => op1 = C /\ op2 = C
+//!   R = op1 U op2 => (op1 = R /\ op2 = C) \/ (op1 = C /\ op2 = R)
+//! ```
+//!
+//! Solving is incremental: constraints can be added to a solved set, and the next call to
+//! `solve` only revisits the constraints that are new or mention a variable that changed.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
 use std::collections::hash_map;
 use std::fmt::{Debug, Display, Formatter, Error};
 use std::hash::Hash;
 
+/// A finite lattice of values that the variables of a `ConstraintSet` are bound to.
+pub trait Lattice: Copy + Debug + Eq {
+    /// Least element, the value of variables that nothing is known about.
+    fn bottom() -> Self;
+    /// Greatest element, the value of variables constrained to conflicting values.
+    fn top() -> Self;
+    /// Least upper bound of `self` and `other`.
+    fn join(&self, other: &Self) -> Self;
+    /// Refines the values of `lhs`, `op1` and `op2` of the constraint `lhs = op1 U op2` from
+    /// what is known about each of them. The union is the join unless overridden.
+    fn union(lhs: Self, op1: Self, op2: Self) -> (Self, Self, Self) {
+        (lhs.join(&op1.join(&op2)), op1, op2)
+    }
+}
+
 #[derive(Debug, Clone)]
-pub enum Constraint<I: Debug + Clone + Copy> {
+pub enum Constraint<I: Debug + Clone + Copy, L: Lattice> {
     Union(I, I),
-    // Solve eq. Equation of form, I = Constraint<I>
-    Equality(I, Box<Constraint<I>>),
+    // Solve eq. Equation of form, I = Constraint<I>, where the rhs is a `Union` or a `Value`.
+    Equality(I, Box<Constraint<I, L>>),
     // Equivalence. All elements in Vec<I> have the same value.
-    // Solve equivalence
     AssertEquivalence(Vec<I>),
-    // Solve assert
-    Assertion(Box<Constraint<I>>),
-    Or(Box<Constraint<I>>, Box<Constraint<I>>),
-    And(Box<Constraint<I>>, Box<Constraint<I>>),
+    // The value of the first is included in the value of the second.
+    Subset(I, I),
     // No solve
-    Value(ValueType),
+    Value(L),
+}
+
+impl<I: Debug + Clone + Copy, L: Lattice> Constraint<I, L> {
+    // Variables mentioned by the constraint
+    fn vars(&self) -> Vec<I> {
+        match *self {
+            Constraint::Union(op1, op2) |
+            Constraint::Subset(op1, op2) => vec![op1, op2],
+            Constraint::Equality(lhs, box ref rhs) => {
+                let mut vars = rhs.vars();
+                vars.insert(0, lhs);
+                vars
+            }
+            Constraint::AssertEquivalence(ref list) => list.clone(),
+            Constraint::Value(_) => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConstraintSet<I: Eq + Debug + Hash + Copy, L: Lattice> {
+    pub bindings: HashMap<I, L>,
+    // All constraints, in the order they were added
+    constraints: Vec<Constraint<I, L>>,
+    // Constraints that mention a variable which is still unresolved after solving
+    set: VecDeque<usize>,
+    // Constraints to be (re-)visited by the next call to `solve`
+    worklist: VecDeque<usize>,
+    // Constraints that mention each variable
+    uses: HashMap<I, Vec<usize>>,
+    // Constraints that contributed to the value of each variable
+    reasons: HashMap<I, BTreeSet<usize>>,
 }
 
-#[derive(Default, Debug)]
-pub struct ConstraintSet<I: Eq + Debug + Hash + Copy> {
-    pub bindings: HashMap<I, ValueType>,
-    set: VecDeque<Constraint<I>>,
+impl<I: Eq + Debug + Hash + Copy, L: Lattice> Default for ConstraintSet<I, L> {
+    fn default() -> ConstraintSet<I, L> {
+        ConstraintSet {
+            bindings: HashMap::new(),
+            constraints: Vec::new(),
+            set: VecDeque::new(),
+            worklist: VecDeque::new(),
+            uses: HashMap::new(),
+            reasons: HashMap::new(),
+        }
+    }
 }
 
-impl<T: Clone + Debug + Hash + Eq + Copy> ConstraintSet<T> {
-    pub fn add_constraint(&mut self, c: Constraint<T>) {
-        self.set.push_back(c);
+impl<T: Clone + Debug + Hash + Eq + Copy, L: Lattice> ConstraintSet<T, L> {
+    pub fn add_constraint(&mut self, c: Constraint<T, L>) {
+        let idx = self.constraints.len();
+        for var in c.vars() {
+            self.uses.entry(var).or_insert_with(Vec::new).push(idx);
+        }
+        self.constraints.push(c);
+        self.set.push_back(idx);
+        self.worklist.push_back(idx);
     }
 
     pub fn add_union(&mut self, lhs: T, ops: &[T]) {
@@ -65,7 +131,7 @@ impl<T: Clone + Debug + Hash + Eq + Copy> ConstraintSet<T> {
         }
     }
 
-    pub fn add_eq(&mut self, lhs: T, vt: ValueType) {
+    pub fn add_eq(&mut self, lhs: T, vt: L) {
         self.add_constraint(Constraint::Equality(lhs, Box::new(Constraint::Value(vt))));
     }
 
@@ -73,162 +139,108 @@ impl<T: Clone + Debug + Hash + Eq + Copy> ConstraintSet<T> {
         self.add_constraint(Constraint::AssertEquivalence(Vec::from(eq_set)));
     }
 
+    pub fn add_subset(&mut self, sub: T, sup: T) {
+        self.add_constraint(Constraint::Subset(sub, sup));
+    }
+
     // Retrive binding value for `bind` or insert default if none exist
-    pub fn bvalue(&mut self, bind: T) -> ValueType {
-        *self.bindings.entry(bind).or_insert(ValueType::Unresolved)
+    pub fn bvalue(&mut self, bind: T) -> L {
+        *self.bindings.entry(bind).or_insert_with(L::bottom)
+    }
+
+    fn value(&self, bind: T) -> L {
+        self.bindings.get(&bind).cloned().unwrap_or_else(L::bottom)
     }
 
+    /// Propagates the constraints that were added, or mention a variable that changed, since
+    /// the last call until no binding changes. Returns true if any binding changed.
     pub fn solve(&mut self) -> bool {
-        let mut first_false = 0;
         let mut made_progress = false;
-        while !self.set.is_empty() {
-            // See if we went a complete circle. If we did, this means that the equations in the
-            // set are no longer solvable.
-            if first_false > self.set.len() {
-                break;
-            }
-
-            let constraint = self.set.pop_front().expect("Cannot be `None`");
-            // Match to see if this is an Eq constraint or an assert
-            let res = match constraint {
-                Constraint::Equality(_, box _) => self.solve_eq(&constraint),
-                Constraint::Assertion(_) => self.solve_assert(&constraint),
-                Constraint::AssertEquivalence(_) => self.solve_equivalence(&constraint),
-                _ => false,
-            };
-
-            made_progress |= res;
-
-            if !res {
-                // It wasn't solved. Push it back in.
-                self.set.push_back(constraint);
-                first_false += 1;
-            } else {
-                first_false = 0;
-            }
+        // Terminates as every binding can only move up a finite lattice, and constraints are
+        // only revisited when a binding they mention moved.
+        while let Some(idx) = self.worklist.pop_front() {
+            made_progress |= self.propagate(idx);
         }
+
+        let unsolved = (0..self.constraints.len())
+            .filter(|&idx| {
+                self.constraints[idx].vars().iter().any(|&var| self.value(var) == L::bottom())
+            })
+            .collect();
+        self.set = unsolved;
         made_progress
     }
 
-    fn solve_equivalence(&mut self, constraint: &Constraint<T>) -> bool {
-        if let &Constraint::AssertEquivalence(ref list) = constraint {
-            if let Some(found) = list.iter().find(|&var| {
-                // Check if any var has a known value
-                self.bvalue(*var) != ValueType::Unresolved
-            }) {
-                let vt = self.bvalue(*found);
-                for var in list {
-                    self.bindings.insert(*var, vt);
-                }
-                true
-            } else {
-                false
+    fn propagate(&mut self, idx: usize) -> bool {
+        match self.constraints[idx].clone() {
+            Constraint::Equality(lhs, box Constraint::Value(vt)) => self.update(lhs, vt, idx, &[]),
+            Constraint::Equality(lhs, box Constraint::Union(op1, op2)) => {
+                let (lvt, vt1, vt2) = L::union(self.value(lhs), self.value(op1), self.value(op2));
+                let from = [lhs, op1, op2];
+                let changed = self.update(lhs, lvt, idx, &from);
+                let changed = self.update(op1, vt1, idx, &from) || changed;
+                self.update(op2, vt2, idx, &from) || changed
             }
-        } else {
-            false
-        }
-    }
-
-    fn solve_eq(&mut self, constraint: &Constraint<T>) -> bool {
-        match *constraint {
-            Constraint::Equality(ni, box ref cs) => {
-                match *cs {
-                    Constraint::Value(vt) => {
-                        self.bindings.insert(ni, vt);
-                        true
-                    }
-                    Constraint::Union(op1, op2) => {
-                        match self.bvalue(ni) {
-                            ValueType::Scalar => {
-                                // Both, op1 and op2, are scalars
-                                self.bindings.insert(op1, ValueType::Scalar);
-                                self.bindings.insert(op2, ValueType::Scalar);
-                                true
-                            }
-                            ValueType::Reference => {
-                                // Assert some facts
-                                let cons = Constraint::Or(
-                                    Box::new(Constraint::And(Box::new(Constraint::Equality(op1, Box::new(Constraint::Value(ValueType::Reference)))),
-                                                             Box::new(Constraint::Equality(op2, Box::new(Constraint::Value(ValueType::Scalar)))))),
-                                    Box::new(Constraint::And(Box::new(Constraint::Equality(op2, Box::new(Constraint::Value(ValueType::Reference)))),
-                                                             Box::new(Constraint::Equality(op1, Box::new(Constraint::Value(ValueType::Scalar))))))
-                                    );
-                                self.set.push_back(Constraint::Assertion(Box::new(cons)));
-                                true
-                            }
-                            ValueType::Unresolved => {
-                                // Look at the Union
-                                let op1_vt = self.bvalue(op1);
-                                let op2_vt = self.bvalue(op2);
-                                let (vt, retval) = match (op1_vt, op2_vt) {
-                                    (ValueType::Invalid, _) |
-                                    (_, ValueType::Invalid) |
-                                    (ValueType::Reference, ValueType::Reference) => {
-                                        (ValueType::Invalid, true)
-                                    }
-
-                                    (ValueType::Reference, _) => {
-                                        self.bindings.insert(op2, ValueType::Scalar);
-                                        (ValueType::Reference, true)
-                                    }
-
-                                    (_, ValueType::Reference) => {
-                                        self.bindings.insert(op1, ValueType::Scalar);
-                                        (ValueType::Reference, true)
-                                    }
-
-                                    (ValueType::Scalar, ValueType::Scalar) => {
-                                        (ValueType::Scalar, true)
-                                    }
-                                    (_, _) => (ValueType::Unresolved, false),
-                                };
-                                self.bindings.insert(ni, vt);
-                                retval
-                            }
-                            _ => false,
-                        }
-                    }
-                    _ => false,
-                }
+            Constraint::AssertEquivalence(list) => {
+                let vt = list.iter().fold(L::bottom(), |acc, &var| acc.join(&self.value(var)));
+                list.iter().fold(false, |changed, &var| self.update(var, vt, idx, &list) || changed)
+            }
+            Constraint::Subset(sub, sup) => {
+                let vt = self.value(sub);
+                self.update(sup, vt, idx, &[sub])
             }
             _ => false,
         }
     }
 
-    fn solve_assert(&mut self, constraint: &Constraint<T>) -> bool {
-        // Will be of the form, Assertion(<inner>)
-        // inner will be of the form Or(<c1>, <c2>)
-        if let &Constraint::Assertion(box Constraint::Or(box ref c1, box ref c2)) = constraint {
-            // c1, c2 will be of the form And(Eq(ci1), Eq(ci2))
-            [c1, c2].iter().any(|&ci| {
-                if let Constraint::And(box Constraint::Equality(op1,
-                                                                box Constraint::Value(vt1)),
-                                       box Constraint::Equality(op2,
-                                                                box Constraint::Value(vt2))) = *ci {
-                    if self.bvalue(op1) == vt1 {
-                        self.bindings.insert(op2, vt2);
-                        true
-                    } else if self.bvalue(op2) == vt2 {
-                        self.bindings.insert(op1, vt1);
-                        true
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                }
-            })
-        } else {
-            false
+    // Joins `vt` into the binding of `var`, as implied by the constraint `idx` and the values
+    // of `from`. Returns true if the binding changed.
+    fn update(&mut self, var: T, vt: L, idx: usize, from: &[T]) -> bool {
+        let old = self.value(var);
+        let new = old.join(&vt);
+        if new == old {
+            return false;
+        }
+        let mut why = from.iter()
+            .filter_map(|v| self.reasons.get(v))
+            .flat_map(|r| r.iter().cloned())
+            .collect::<BTreeSet<_>>();
+        why.insert(idx);
+        self.reasons.entry(var).or_insert_with(BTreeSet::new).extend(why);
+        self.bindings.insert(var, new);
+        if let Some(uses) = self.uses.get(&var) {
+            self.worklist.extend(uses.iter().cloned());
         }
+        true
     }
 
-    pub fn iter_bindings<'a>(&'a self) -> hash_map::Iter<'a, T, ValueType> {
+    pub fn iter_bindings<'a>(&'a self) -> hash_map::Iter<'a, T, L> {
         self.bindings.iter()
     }
+
+    /// Variables that are bound to conflicting values.
+    pub fn conflicts(&self) -> Vec<T> {
+        self.bindings
+            .iter()
+            .filter(|&(_, vt)| *vt == L::top())
+            .map(|(var, _)| *var)
+            .collect()
+    }
+
+    /// Constraints that together bind `var` to conflicting values, in the order they were
+    /// added. These are all constraints the conflict was derived from, and not necessarily a
+    /// minimal set of them. Returns `None` if `var` is not in conflict.
+    pub fn unsat_core(&self, var: T) -> Option<Vec<&Constraint<T, L>>> {
+        if self.value(var) != L::top() {
+            return None;
+        }
+        Some(self.reasons
+            .get(&var)
+            .map_or(Vec::new(), |r| r.iter().map(|&idx| &self.constraints[idx]).collect()))
+    }
 }
 
-impl<T: Debug + Clone + Copy> Display for Constraint<T> {
+impl<T: Debug + Clone + Copy, L: Lattice> Display for Constraint<T, L> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         let s = match *self {
             Constraint::Union(op1, op2) => format!("{:?} U {:?}", op1, op2),
@@ -239,16 +251,14 @@ impl<T: Debug + Clone + Copy> Display for Constraint<T> {
                     acc
                 })
             }
-            Constraint::Assertion(box ref op) => format!("Assert {}", op),
-            Constraint::Or(box ref op1, box ref op2) => format!("{} \\/ {}", op1, op2),
-            Constraint::And(box ref op1, box ref op2) => format!("{} /\\ {}", op1, op2),
+            Constraint::Subset(op1, op2) => format!("{:?} <= {:?}", op1, op2),
             Constraint::Value(ref vt) => format!("{:?}", vt),
         };
         write!(f, "{}", s)
     }
 }
 
-impl<T: Clone + Debug + Hash + Eq + Copy> Display for ConstraintSet<T> {
+impl<T: Clone + Debug + Hash + Eq + Copy, L: Lattice> Display for ConstraintSet<T, L> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         // First Bindings information.
         let mut display_str = String::new();
@@ -258,8 +268,8 @@ impl<T: Clone + Debug + Hash + Eq + Copy> Display for ConstraintSet<T> {
 
         display_str.push_str("-----------------------\nConstraints:\n");
 
-        for constraint in &self.set {
-            display_str.push_str(&constraint.to_string());
+        for &idx in &self.set {
+            display_str.push_str(&self.constraints[idx].to_string());
             display_str.push_str("\n");
         }
 
@@ -274,7 +284,7 @@ mod test {
 
     #[test]
     fn scalar_union_reference_is_reference() {
-        let mut cs = ConstraintSet::<u64>::default();
+        let mut cs = ConstraintSet::<u64, ValueType>::default();
 
         cs.add_constraint(Constraint::Equality(1, Box::new(Constraint::Value(ValueType::Scalar))));
         cs.add_constraint(Constraint::Equality(2, Box::new(Constraint::Value(ValueType::Reference))));
@@ -286,7 +296,7 @@ mod test {
 
     #[test]
     fn reference_union_reference_is_invalid() {
-        let mut cs = ConstraintSet::<u64>::default();
+        let mut cs = ConstraintSet::<u64, ValueType>::default();
 
         cs.add_constraint(Constraint::Equality(1, Box::new(Constraint::Value(ValueType::Reference))));
         cs.add_constraint(Constraint::Equality(2, Box::new(Constraint::Value(ValueType::Reference))));
@@ -298,7 +308,7 @@ mod test {
 
     #[test]
     fn know_reference_and_scalar_infer_reference() {
-        let mut cs = ConstraintSet::<u64>::default();
+        let mut cs = ConstraintSet::<u64, ValueType>::default();
 
         cs.add_constraint(Constraint::Equality(0, Box::new(Constraint::Union(1, 2))));
         cs.add_constraint(Constraint::Equality(0, Box::new(Constraint::Value(ValueType::Reference))));
@@ -311,7 +321,7 @@ mod test {
 
     #[test]
     fn know_reference_and_reference_infer_scalar() {
-        let mut cs = ConstraintSet::<u64>::default();
+        let mut cs = ConstraintSet::<u64, ValueType>::default();
 
         cs.add_constraint(Constraint::Equality(0, Box::new(Constraint::Value(ValueType::Reference))));
         cs.add_constraint(Constraint::Equality(2, Box::new(Constraint::Value(ValueType::Reference))));
@@ -323,7 +333,7 @@ mod test {
 
     #[test]
     fn know_scalar_infer_scalar_scalar() {
-        let mut cs = ConstraintSet::<u64>::default();
+        let mut cs = ConstraintSet::<u64, ValueType>::default();
 
         cs.add_constraint(Constraint::Equality(0, Box::new(Constraint::Value(ValueType::Scalar))));
         cs.add_constraint(Constraint::Equality(0, Box::new(Constraint::Union(1, 2))));
@@ -335,7 +345,7 @@ mod test {
 
     #[test]
     fn disjoint_tree_infer_references() {
-        let mut cs = ConstraintSet::<u64>::default();
+        let mut cs = ConstraintSet::<u64, ValueType>::default();
 
         cs.add_constraint(Constraint::Equality(4, Box::new(Constraint::Value(ValueType::Reference))));
         cs.add_constraint(Constraint::Equality(0, Box::new(Constraint::Value(ValueType::Reference))));
@@ -354,7 +364,7 @@ mod test {
 
     #[test]
     fn unsolvable_constraints_remain_in_set() {
-        let mut cs = ConstraintSet::<u64>::default();
+        let mut cs = ConstraintSet::<u64, ValueType>::default();
 
         let constraint = Constraint::Equality(0, Box::new(Constraint::Union(1, 2)));
         cs.add_constraint(constraint);
@@ -363,4 +373,71 @@ mod test {
 
         assert!(!cs.set.is_empty());
     }
+
+    // Flat lattice of signs
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Sign {
+        Unknown,
+        Negative,
+        Positive,
+        Conflict,
+    }
+
+    impl Lattice for Sign {
+        fn bottom() -> Sign {
+            Sign::Unknown
+        }
+
+        fn top() -> Sign {
+            Sign::Conflict
+        }
+
+        fn join(&self, other: &Sign) -> Sign {
+            match (*self, *other) {
+                (Sign::Unknown, s) | (s, Sign::Unknown) => s,
+                (s, t) if s == t => s,
+                _ => Sign::Conflict,
+            }
+        }
+    }
+
+    #[test]
+    fn subset_flows_values() {
+        let mut cs = ConstraintSet::<u64, Sign>::default();
+
+        cs.add_subset(1, 2);
+        cs.add_subset(2, 3);
+        cs.add_eq(1, Sign::Positive);
+        cs.add_union(4, &[3, 5]);
+        assert!(cs.solve());
+
+        assert_eq!(cs.bindings[&3], Sign::Positive);
+        assert_eq!(cs.bindings[&4], Sign::Positive);
+        // Subsets only flow one way.
+        assert_eq!(cs.bvalue(5), Sign::Unknown);
+        assert!(cs.conflicts().is_empty());
+    }
+
+    #[test]
+    fn incremental_solve_reports_unsat_core() {
+        let mut cs = ConstraintSet::<u64, Sign>::default();
+
+        cs.add_eq(0, Sign::Positive);
+        cs.add_subset(0, 1);
+        cs.add_eq(2, Sign::Negative);
+        cs.solve();
+        assert_eq!(cs.bindings[&1], Sign::Positive);
+        assert!(!cs.solve());
+
+        cs.add_equivalence_assertion(&[1, 3]);
+        cs.add_subset(2, 3);
+        assert!(cs.solve());
+        assert_eq!(cs.bindings[&3], Sign::Conflict);
+        assert_eq!(cs.bindings[&0], Sign::Positive);
+
+        let core = cs.unsat_core(3).unwrap().iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(core,
+                   vec!["0 = Positive", "0 <= 1", "2 = Negative", "Assert 1 = 3 = ", "2 <= 3"]);
+        assert!(cs.unsat_core(0).is_none());
+    }
 }
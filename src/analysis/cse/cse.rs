@@ -5,6 +5,8 @@
 //! opcodes. On the other hand, considering too much will cause
 //! a huge memory consume. Thus, a balanced solution should be 
 //! improved.
+//!
+//! `gvn` also merges values across blocks and handles commutative opcodes.
 
 use std::collections::HashMap;
 use std::marker::PhantomData;
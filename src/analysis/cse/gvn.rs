@@ -0,0 +1,370 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Global Value Numbering (GVN)
+//!
+//! Unlike `cse`, values are merged across blocks. The blocks are visited in a
+//! walk over the dominator tree and only the expressions of the dominating
+//! blocks are in scope, so a value is only ever replaced by one dominating it.
+//!
+//! Operands of commutative opcodes are put in canonical order by `ssasort`.
+//! Constants are numbered by their value and width rather than by node, so
+//! that equal constants of the same width are the same value while those of
+//! different widths are not.
+//!
+//! Phi nodes do not record which predecessor each operand flows in from. Two
+//! phis of a block are only unified if, for every predecessor, exactly one of
+//! their operands dominates it and these are the same values. A phi whose
+//! operands are all the same value is replaced by that value.
+
+use std::collections::{HashMap, HashSet};
+
+use petgraph::graph::Graph;
+use petgraph::visit::DfsPostOrder;
+
+use analysis::dom::DomTree;
+use middle::ir::MOpcode;
+use middle::ssa::cfg_traits::CFG;
+use middle::ssa::ssa_traits::*;
+use middle::ssa::ssastorage::SSAStorage;
+use super::ssasort::Sorter;
+
+type SSAValue = <SSAStorage as SSA>::ValueRef;
+type SSABlock = <SSAStorage as CFG>::ActionRef;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Expr {
+    Const(u64, Option<u16>),
+    Op(MOpcode, Option<u16>, Vec<SSAValue>),
+    /// The value a block's phi receives from each of its predecessors
+    Phi(SSABlock, Vec<SSAValue>),
+}
+
+enum Visit {
+    Enter(SSABlock),
+    /// Leaves a block, taking the expressions it added out of scope
+    Leave(Vec<Expr>),
+}
+
+pub struct GVN<'a> {
+    ssa: &'a mut SSAStorage,
+    /// Constant each constant is numbered as, other values are their own number
+    numbers: HashMap<SSAValue, SSAValue>,
+    /// Values computing the expressions in scope
+    leaders: HashMap<Expr, SSAValue>,
+    /// Dominators of each block reachable from the entry, including itself
+    doms: HashMap<SSABlock, HashSet<SSABlock>>,
+    merged: usize,
+}
+
+impl<'a> GVN<'a> {
+    pub fn new(ssa: &'a mut SSAStorage) -> GVN<'a> {
+        GVN {
+            ssa: ssa,
+            numbers: HashMap::new(),
+            leaders: HashMap::new(),
+            doms: HashMap::new(),
+            merged: 0,
+        }
+    }
+
+    /// Replaces every value by an equal one dominating it, if any. Returns the
+    /// number of values replaced.
+    pub fn run(&mut self) -> usize {
+        {
+            let mut sorter = Sorter::new(self.ssa);
+            sorter.run();
+        }
+        let entry = match self.ssa.entry_node() {
+            Some(entry) => entry,
+            None => return 0,
+        };
+        let children = self.dominator_tree(entry);
+        self.number_constants();
+
+        let mut stack = vec![Visit::Enter(entry)];
+        while let Some(visit) = stack.pop() {
+            match visit {
+                Visit::Enter(block) => {
+                    let added = self.visit_block(block);
+                    stack.push(Visit::Leave(added));
+                    if let Some(children) = children.get(&block) {
+                        stack.extend(children.iter().rev().map(|&child| Visit::Enter(child)));
+                    }
+                }
+                Visit::Leave(added) => {
+                    for expr in added {
+                        self.leaders.remove(&expr);
+                    }
+                }
+            }
+        }
+        radeco_trace!("gvn|merged {} values", self.merged);
+        self.merged
+    }
+
+    /// Computes the dominators of the blocks reachable from `entry` and returns
+    /// the children of every block in the dominator tree.
+    fn dominator_tree(&mut self, entry: SSABlock) -> HashMap<SSABlock, Vec<SSABlock>> {
+        // `DomTree` expects every node to be reachable from the start node.
+        let mut g = Graph::<SSABlock, ()>::new();
+        let mut indices = HashMap::new();
+        indices.insert(entry, g.add_node(entry));
+        let mut worklist = vec![entry];
+        while let Some(block) = worklist.pop() {
+            for succ in self.ssa.succs_of(block) {
+                if !indices.contains_key(&succ) {
+                    indices.insert(succ, g.add_node(succ));
+                    worklist.push(succ);
+                }
+                g.add_edge(indices[&block], indices[&succ], ());
+            }
+        }
+
+        let tree = DomTree::build_dom_tree(&g, indices[&entry]);
+        let mut children = HashMap::new();
+        // Children are visited in reverse postorder of the CFG, so that the
+        // predecessors of a join are numbered before its phis.
+        let mut postorder = Vec::new();
+        let mut dfs = DfsPostOrder::new(&g, indices[&entry]);
+        while let Some(i) = dfs.next(&g) {
+            postorder.push(i);
+        }
+        for &i in postorder.iter().rev() {
+            let block = g[i];
+            let doms = tree.doms(i).into_iter().map(|d| g[d]).collect();
+            self.doms.insert(block, doms);
+            if block != entry {
+                children.entry(g[tree.idom(i)]).or_insert_with(Vec::new).push(block);
+            }
+        }
+        children
+    }
+
+    fn number_constants(&mut self) {
+        let mut leaders = HashMap::new();
+        for value in self.ssa.values() {
+            if let Some(c) = self.ssa.constant(value) {
+                let expr = Expr::Const(c, self.width_of(value));
+                let leader = *leaders.entry(expr).or_insert(value);
+                self.numbers.insert(value, leader);
+            }
+        }
+    }
+
+    fn number(&self, value: SSAValue) -> SSAValue {
+        *self.numbers.get(&value).unwrap_or(&value)
+    }
+
+    fn width_of(&self, value: SSAValue) -> Option<u16> {
+        self.ssa.node_data(value).ok()?.vt.width().get_width()
+    }
+
+    /// Numbers the phis and expressions of `block` and returns the expressions
+    /// it brought into scope.
+    fn visit_block(&mut self, block: SSABlock) -> Vec<Expr> {
+        let mut added = Vec::new();
+        for phi in self.ssa.phis_in(block) {
+            added.extend(self.visit_phi(block, phi));
+        }
+        for expr in self.ssa.exprs_in(block) {
+            added.extend(self.visit_expr(expr));
+        }
+        added
+    }
+
+    fn visit_expr(&mut self, node: SSAValue) -> Option<Expr> {
+        let opcode = self.ssa.opcode(node)?;
+        if !is_numbered(&opcode) {
+            return None;
+        }
+        let mut operands = self.ssa
+                               .operands_of(node)
+                               .into_iter()
+                               .map(|o| self.number(o))
+                               .collect::<Vec<_>>();
+        // `Sorter` orders operands by their structure, which may differ between
+        // equal values, e.g. unified phis.
+        if opcode.is_commutative() {
+            operands.sort();
+        }
+        let expr = Expr::Op(opcode, self.width_of(node), operands);
+        self.merge(node, expr)
+    }
+
+    fn visit_phi(&mut self, block: SSABlock, phi: SSAValue) -> Option<Expr> {
+        let mut operands = self.ssa
+                               .operands_of(phi)
+                               .into_iter()
+                               .map(|o| self.number(o))
+                               .filter(|&o| o != phi)
+                               .collect::<Vec<_>>();
+        operands.sort();
+        operands.dedup();
+        if operands.len() == 1 {
+            radeco_trace!("gvn|{:?} is the trivial phi of {:?}", phi, operands[0]);
+            self.ssa.replace_value(phi, operands[0]);
+            self.merged += 1;
+            return None;
+        }
+        let incoming = self.incoming_values(block, &operands)?;
+        self.merge(phi, Expr::Phi(block, incoming))
+    }
+
+    /// The value `block` receives from each of its predecessors, if exactly one
+    /// of `operands` dominates it.
+    fn incoming_values(&self, block: SSABlock, operands: &[SSAValue]) -> Option<Vec<SSAValue>> {
+        self.ssa
+            .preds_of(block)
+            .into_iter()
+            .map(|pred| {
+                let doms = self.doms.get(&pred)?;
+                let mut dominating = operands.iter().filter(|&&o| {
+                    self.ssa.block_for(o).map_or(true, |b| doms.contains(&b))
+                });
+                match (dominating.next(), dominating.next()) {
+                    (Some(&o), None) => Some(o),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Replaces `node` by the value computing `expr` if there is one in scope,
+    /// otherwise brings `expr` into scope.
+    fn merge(&mut self, node: SSAValue, expr: Expr) -> Option<Expr> {
+        if let Some(&leader) = self.leaders.get(&expr) {
+            // A value selecting the branch of more than one block is not
+            // supported by `SSAStorage`.
+            if self.ssa.selector_for(node).is_some() && self.ssa.selector_for(leader).is_some() {
+                return None;
+            }
+            radeco_trace!("gvn|{:?} = {:?}", node, leader);
+            self.ssa.replace_value(node, leader);
+            self.merged += 1;
+            return None;
+        }
+        self.leaders.insert(expr.clone(), node);
+        Some(expr)
+    }
+}
+
+/// Opcodes whose result depends on nothing but their operands.
+fn is_numbered(opcode: &MOpcode) -> bool {
+    match *opcode {
+        MOpcode::OpConst(_) | MOpcode::OpCustom(_) | MOpcode::OpInvalid | MOpcode::OpNop |
+        MOpcode::OpIf => false,
+        _ => !opcode.has_sideeffects(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir::WidthSpec;
+    use middle::ir_reader::test_util::parse;
+
+    // `%5` equals `%3` and makes the phi `%18` trivial, `%9` equals `%8` and
+    // makes `%11` equal `%10`. `%7` does not equal `%6`, since neither dominates
+    // the other, and `%15` does not equal `%14` as the phis may swap `%1` and `%2`.
+    const DIAMOND: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+        %2: $Unknown64 = $rsi;
+    bb_0x001000.0000(sz 0x4):
+        [@0x001000.0000] %3: $Unknown64 = %1 + %2;
+        [@0x001000.0001] %4: $Unknown1 = %1 == %2;
+        JMP IF %4 0x001004.0000 ELSE 0x001008.0000
+    bb_0x001004.0000(sz 0x4):
+        [@0x001004.0000] %5: $Unknown64 = %2 + %1;
+        [@0x001004.0001] %6: $Unknown64 = %5 * %1;
+        JMP 0x00100C.0000
+    bb_0x001008.0000(sz 0x4):
+        [@0x001008.0000] %7: $Unknown64 = %3 * %1;
+        [@0x001008.0001] %8: $Unknown64 = %1 ^ %2;
+        [@0x001008.0002] %9: $Unknown64 = %1 ^ %2;
+        JMP 0x00100C.0000
+    bb_0x00100C.0000(sz 0x4):
+        %10: $Unknown64 = Phi(%6, %8);
+        %11: $Unknown64 = Phi(%6, %9);
+        %14: $Unknown64 = Phi(%1, %2);
+        %15: $Unknown64 = Phi(%2, %1);
+        %18: $Unknown64 = Phi(%5, %3);
+        [@0x00100C.0000] %16: $Unknown64 = %10 + %14;
+        [@0x00100C.0001] %17: $Unknown64 = %11 + %15;
+        [@0x00100C.0002] %19: $Unknown64 = %16 - %17;
+        [@0x00100C.0003] %20: $Unknown64 = %19 - %18;
+        JMP TO %20
+    exit-node:
+    final-register-state:
+        $rax = %20;
+        $rdi = %1;
+        $rsi = %2;
+}
+";
+
+    const CONSTANTS: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+    bb_0x001000.0000(sz 0x4):
+        [@0x001000.0000] %2: $Unknown64 = %1 + #x5;
+        [@0x001000.0001] %3: $Unknown64 = %1 + #x6;
+        [@0x001000.0002] %4: $Unknown64 = %1 + #x7;
+        [@0x001000.0003] %5: $Unknown64 = %3 - %4;
+        [@0x001000.0004] %6: $Unknown64 = %5 - %2;
+        JMP TO %6
+    exit-node:
+    final-register-state:
+        $rax = %6;
+        $rdi = %1;
+}
+";
+
+    fn count(ssa: &SSAStorage, opcode: MOpcode) -> usize {
+        ssa.inorder_walk().filter(|&n| ssa.opcode(n) == Some(opcode.clone())).count()
+    }
+
+    fn phis(ssa: &SSAStorage) -> usize {
+        ssa.values().into_iter().filter(|&n| ssa.is_phi(n)).count()
+    }
+
+    #[test]
+    fn diamond_test() {
+        let mut ssa = parse(DIAMOND);
+        assert_eq!(GVN::new(&mut ssa).run(), 4);
+        assert_eq!(count(&ssa, MOpcode::OpAdd), 3);
+        assert_eq!(count(&ssa, MOpcode::OpMul), 2);
+        assert_eq!(count(&ssa, MOpcode::OpXor), 1);
+        assert_eq!(phis(&ssa), 3);
+    }
+
+    #[test]
+    fn constant_width_test() {
+        let mut ssa = parse(CONSTANTS);
+        let adds = ssa.inorder_walk()
+                      .filter(|&n| ssa.opcode(n) == Some(MOpcode::OpAdd))
+                      .collect::<Vec<_>>();
+        assert_eq!(adds.len(), 3);
+        // Use a `#x5` of the same width in the second add and one of a
+        // different width in the third.
+        for (&add, &width) in adds[1..].iter().zip(&[64, 32]) {
+            let vt = ValueInfo::new_scalar(WidthSpec::Known(width));
+            let c = ssa.insert_op(MOpcode::OpConst(5), vt, None).unwrap();
+            let (i, old) = ssa.sparse_operands_of(add)
+                              .into_iter()
+                              .find(|&(_, o)| ssa.is_constant(o))
+                              .unwrap();
+            ssa.op_unuse(add, old);
+            ssa.op_use(add, i, c);
+        }
+
+        assert_eq!(GVN::new(&mut ssa).run(), 1);
+        assert_eq!(count(&ssa, MOpcode::OpAdd), 2);
+    }
+}
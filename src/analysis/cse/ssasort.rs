@@ -61,7 +61,14 @@ impl<'a, I, T> Sorter<'a, I, T>
                      MOpcode::OpConst(num) => num,
                      MOpcode::OpNarrow(num) |
                      MOpcode::OpZeroExt(num) |
-                     MOpcode::OpSignExt(num) => num as u64,
+                     MOpcode::OpSignExt(num) |
+                     MOpcode::OpSGt(num) |
+                     MOpcode::OpSLt(num) |
+                     MOpcode::OpSDiv(num) |
+                     MOpcode::OpSMod(num) |
+                     MOpcode::OpMulHi(num) |
+                     MOpcode::OpSMulHi(num) |
+                     MOpcode::OpBSwap(num) => num as u64,
                      _ => 0,
                  })
             }
@@ -124,7 +131,7 @@ impl<'a, I, T> Sorter<'a, I, T>
                 MOpcode::OpNop => {
                     return self.return_value(Ordering::Equal, op1, op2);
                 }
-                MOpcode::OpCall | MOpcode::OpLoad | MOpcode::OpStore | MOpcode::OpITE => {
+                MOpcode::OpCall | MOpcode::OpLoad(..) | MOpcode::OpStore(..) | MOpcode::OpITE => {
                     let addr1 = self.ssa.address(op1).expect("No address information found");
                     let addr2 = self.ssa.address(op2).expect("No address information found");
                     return self.return_value(addr1.cmp(&addr2), op1, op2);
@@ -134,7 +141,14 @@ impl<'a, I, T> Sorter<'a, I, T>
                 }
                 MOpcode::OpSignExt(_) |
                 MOpcode::OpZeroExt(_) |
-                MOpcode::OpNarrow(_) => {
+                MOpcode::OpNarrow(_) |
+                MOpcode::OpSGt(_) |
+                MOpcode::OpSLt(_) |
+                MOpcode::OpSDiv(_) |
+                MOpcode::OpSMod(_) |
+                MOpcode::OpMulHi(_) |
+                MOpcode::OpSMulHi(_) |
+                MOpcode::OpBSwap(_) => {
                     if priority1.1.cmp(&priority2.1) != Ordering::Equal {
                         return self.return_value(priority1.1.cmp(&priority2.1), op1, op2);
                     } else {
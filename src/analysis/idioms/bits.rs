@@ -0,0 +1,594 @@
+//! Recovers rotates, byte swaps and population counts from the shifts, masks and
+//! additions they are written as in languages without such operators.
+//!
+//! Rotates by a variable amount are matched as `(x << n) | (x >> (w - n))`, with
+//! `-n & (w - 1)` in place of `w - n` and either amount possibly masked by `w - 1`.
+//! Masked amounts shift by zero on both sides for a multiple of `w`, which only `|`
+//! turns into `x`, so `+` or `^` in place of `|` are only matched without masks.
+//!
+//! Rotates by a constant, byte swaps of 16, 32 and 64 bit values and population
+//! counts such as the usual SWAR sequence are recognised from the sequence computing a
+//! value from a single input. Every value in it is written as a sum of the bits of the
+//! input, each with a constant coefficient: a rotate or byte swap moves every bit to
+//! a single other position, and a population count adds them all up. Additions,
+//! subtractions, multiplications and left shifts keep such a sum, modulo the width.
+//! Masks, right shifts and bitwise combinations only do if the sum is held without
+//! carries, see `fields`.
+
+use std::collections::HashMap;
+
+use middle::ir::MOpcode;
+use middle::ssa::ssa_traits::*;
+use middle::ssa::ssastorage::SSAStorage;
+
+use super::{is_arithmetic, mask, pseudo_random, width_of, SSAValue, Sequence};
+
+/// Smallest number of operations worth replacing by a single one.
+const MIN_SEQUENCE: usize = 3;
+
+/// Recognises every rotate, byte swap and population count in `ssa`. Operations which
+/// are no longer used afterwards are left for `dce::collect`.
+pub fn run(ssa: &mut SSAStorage) {
+    for node in ssa.inorder_walk() {
+        if let Some((opcode, value, amount)) = variable_rotate(ssa, node) {
+            radeco_trace!("bits|{:?} = {}({:?}, {:?})", node, opcode, value, amount);
+            super::replace(ssa, node, opcode, &[value, amount]);
+        } else if let Some((opcode, input, amount)) = recognise(ssa, node) {
+            radeco_trace!("bits|{:?} = {}({:?}, {:?})", node, opcode, input, amount);
+            let operands = match amount.map(|c| ssa.insert_const(c)) {
+                None => vec![input],
+                Some(Some(c)) => vec![input, c],
+                Some(None) => continue,
+            };
+            super::replace(ssa, node, opcode, &operands);
+        }
+    }
+}
+
+/// Returns the opcode, value and amount of the rotate `root` computes with two shifts
+/// by amounts adding up to its width.
+fn variable_rotate(ssa: &SSAStorage, root: SSAValue) -> Option<(MOpcode, SSAValue, SSAValue)> {
+    let masks = match ssa.opcode(root)? {
+        MOpcode::OpOr => true,
+        MOpcode::OpAdd | MOpcode::OpXor => false,
+        _ => return None,
+    };
+    let width = width_of(ssa, root)?;
+    let operands = ssa.operands_of(root);
+    if operands.len() != 2 {
+        return None;
+    }
+    let (value, l, r) = match (shift(ssa, operands[0])?, shift(ssa, operands[1])?) {
+        ((MOpcode::OpLsl, x, l), (MOpcode::OpLsr, y, r)) |
+        ((MOpcode::OpLsr, y, r), (MOpcode::OpLsl, x, l)) if x == y => (x, l, r),
+        _ => return None,
+    };
+    if width_of(ssa, value) != Some(width) {
+        return None;
+    }
+    let ((l, l_masked), (r, r_masked)) = (amount(ssa, l, width), amount(ssa, r, width));
+    if !masks && (l_masked || r_masked) {
+        return None;
+    }
+    if complement(ssa, r, r_masked, width) == Some(l) {
+        Some((MOpcode::OpRol, value, l))
+    } else if complement(ssa, l, l_masked, width) == Some(r) {
+        Some((MOpcode::OpRor, value, r))
+    } else {
+        None
+    }
+}
+
+fn shift(ssa: &SSAStorage, node: SSAValue) -> Option<(MOpcode, SSAValue, SSAValue)> {
+    let operands = ssa.operands_of(node);
+    match ssa.opcode(node)? {
+        op @ MOpcode::OpLsl | op @ MOpcode::OpLsr if operands.len() == 2 => {
+            Some((op, operands[0], operands[1]))
+        }
+        _ => None,
+    }
+}
+
+/// The shift amount `node` without masking by `width - 1`, which does not change a
+/// shift by less than `width`, and whether it was masked.
+fn amount(ssa: &SSAStorage, node: SSAValue, width: u16) -> (SSAValue, bool) {
+    let operands = ssa.operands_of(node);
+    if ssa.opcode(node) != Some(MOpcode::OpAnd) || operands.len() != 2 {
+        return (node, false);
+    }
+    let w = width as u64 - 1;
+    match (ssa.constant(operands[0]), ssa.constant(operands[1])) {
+        (_, Some(c)) if c == w => (operands[0], true),
+        (Some(c), _) if c == w => (operands[1], true),
+        _ => (node, false),
+    }
+}
+
+/// `n` if `node` computes `width - n`, or, if it is `masked`, `-n` modulo `width`.
+fn complement(ssa: &SSAStorage, node: SSAValue, masked: bool, width: u16) -> Option<SSAValue> {
+    if ssa.opcode(node)? != MOpcode::OpSub {
+        return None;
+    }
+    let operands = ssa.operands_of(node);
+    match ssa.constant(*operands.get(0)?) {
+        Some(c) if c == width as u64 || (masked && c % width as u64 == 0) => {
+            Some(amount(ssa, *operands.get(1)?, width).0)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the opcode, input and constant amount, if any, of the operation computed
+/// by `root`.
+fn recognise(ssa: &SSAStorage, root: SSAValue) -> Option<(MOpcode, SSAValue, Option<u64>)> {
+    match ssa.opcode(root) {
+        Some(MOpcode::OpRol) | Some(MOpcode::OpRor) |
+        Some(MOpcode::OpBSwap(_)) | Some(MOpcode::OpPopCount) => return None,
+        Some(ref op) if is_arithmetic(op) => {}
+        _ => return None,
+    }
+    let width = width_of(ssa, root)?;
+    if width < 8 || width > 64 {
+        return None;
+    }
+    let seq = Sequence::collect(ssa, root, None)?;
+    for (input, seq) in seq.single_inputs(ssa, root) {
+        if seq.ops.len() < MIN_SEQUENCE {
+            continue;
+        }
+        let found = Bits::new(ssa, input, width).of(root).and_then(|c| classify(&c, width));
+        if let Some((opcode, amount)) = found {
+            debug_assert!(agrees(ssa, &seq, input, width, &opcode, amount),
+                          "{:?} does not compute {}({:?})",
+                          root,
+                          opcode,
+                          input);
+            return Some((opcode, input, amount));
+        }
+    }
+    None
+}
+
+/// Finds the operation computing the value with the coefficients `c` from a `width` bit
+/// input.
+fn classify(c: &[u128], width: u16) -> Option<(MOpcode, Option<u64>)> {
+    let w = width as usize;
+    // Every bit `i` of the input ends up at `target(i)` alone.
+    let moves = |target: &Fn(usize) -> usize| {
+        c.iter().enumerate().all(|(i, &x)| x == 1 << target(i))
+    };
+
+    // A byte swap of a 16 bit value is a rotate by 8 as well, and reported as the
+    // former.
+    if width % 8 == 0 && width >= 16 && moves(&|i| (w / 8 - 1 - i / 8) * 8 + i % 8) {
+        return Some((MOpcode::OpBSwap(width), None));
+    }
+    let k = c.get(0)?.trailing_zeros() as usize;
+    if k > 0 && k < w && moves(&|i| (i + k) % w) {
+        return Some((MOpcode::OpRol, Some(k as u64)));
+    }
+    if c.iter().all(|&x| x == 1) {
+        return Some((MOpcode::OpPopCount, None));
+    }
+    None
+}
+
+/// Follows the operations computing values from the `width` bit `input`, each written as
+/// the coefficients of the bits of `input` it is the sum of, modulo its width.
+struct Bits<'a> {
+    ssa: &'a SSAStorage,
+    input: SSAValue,
+    width: u16,
+    values: HashMap<SSAValue, Vec<u128>>,
+}
+
+impl<'a> Bits<'a> {
+    fn new(ssa: &'a SSAStorage, input: SSAValue, width: u16) -> Bits<'a> {
+        Bits {
+            ssa: ssa,
+            input: input,
+            width: width,
+            values: HashMap::new(),
+        }
+    }
+
+    fn of(&mut self, node: SSAValue) -> Option<Vec<u128>> {
+        if let Some(c) = self.values.get(&node) {
+            return Some(c.clone());
+        }
+        let c = self.compute(node)?;
+        self.values.insert(node, c.clone());
+        Some(c)
+    }
+
+    fn operand(&mut self, node: SSAValue, i: usize) -> Option<Vec<u128>> {
+        let operand = *self.ssa.operands_of(node).get(i)?;
+        self.of(operand)
+    }
+
+    fn compute(&mut self, node: SSAValue) -> Option<Vec<u128>> {
+        let ssa = self.ssa;
+        if node == self.input {
+            return Some((0..self.width).map(|i| 1 << i).collect());
+        }
+        let width = width_of(ssa, node)?;
+        if width > 64 {
+            return None;
+        }
+        let operands = ssa.operands_of(node);
+        let constant = |i: usize| {
+            operands.get(i).and_then(|&n| ssa.constant(n)).map(|c| c as u128)
+        };
+        let zip = |a: Vec<u128>, b: Vec<u128>, f: &Fn(u128, u128) -> u128| {
+            a.into_iter().zip(b).map(|(x, y)| f(x, y)).collect::<Vec<_>>()
+        };
+        let c = match ssa.opcode(node)? {
+            // Sums stay sums, modulo the width.
+            MOpcode::OpAdd => {
+                zip(self.operand(node, 0)?, self.operand(node, 1)?, &|x, y| x.wrapping_add(y))
+            }
+            MOpcode::OpSub => {
+                zip(self.operand(node, 0)?, self.operand(node, 1)?, &|x, y| x.wrapping_sub(y))
+            }
+            MOpcode::OpMul => {
+                let (i, factor) = match (constant(0), constant(1)) {
+                    (None, Some(c)) => (0, c),
+                    (Some(c), None) => (1, c),
+                    _ => return None,
+                };
+                self.operand(node, i)?.into_iter().map(|x| x.wrapping_mul(factor)).collect()
+            }
+            MOpcode::OpLsl => {
+                let k = constant(1).filter(|&k| k < width as u128)?;
+                self.operand(node, 0)?.into_iter().map(|x| x << k).collect()
+            }
+            MOpcode::OpNarrow(_) => self.operand(node, 0)?,
+            // The others need the counts as they are.
+            MOpcode::OpZeroExt(_) => {
+                let n = width_of(ssa, *operands.get(0)?)?;
+                let a = self.operand(node, 0)?;
+                fields(&a, n)?;
+                a
+            }
+            MOpcode::OpLsr => {
+                let n = width_of(ssa, *operands.get(0)?)?;
+                let k = constant(1).filter(|&k| k < n as u128)? as u16;
+                let a = self.operand(node, 0)?;
+                if fields(&a, n)?.into_iter().any(|(o, len)| o < k && o + len > k) {
+                    return None;
+                }
+                a.into_iter().map(|x| x >> k).collect()
+            }
+            MOpcode::OpAnd => {
+                let (i, m) = match (constant(0), constant(1)) {
+                    (None, Some(c)) => (0, c),
+                    (Some(c), None) => (1, c),
+                    _ => return None,
+                };
+                let a = self.operand(node, i)?;
+                if fields(&a, width)?.into_iter().any(|(o, len)| {
+                    let kept = (m >> o) & mask(len);
+                    kept != 0 && kept != mask(len)
+                }) {
+                    return None;
+                }
+                a.into_iter().map(|x| x & m).collect()
+            }
+            MOpcode::OpOr | MOpcode::OpXor => {
+                let a = self.operand(node, 0)?;
+                let b = self.operand(node, 1)?;
+                let used = |c: &[u128]| {
+                    fields(c, width).map(|f| f.into_iter().fold(0, |u, (o, len)| u | mask(len) << o))
+                };
+                // Without common bits, both are the same as an addition.
+                if used(&a)? & used(&b)? != 0 {
+                    return None;
+                }
+                zip(a, b, &|x, y| x | y)
+            }
+            _ => return None,
+        };
+        Some(c.into_iter().map(|x| x & mask(width)).collect())
+    }
+}
+
+/// Offsets and lengths of the counts a value with the coefficients `c` consists of, if
+/// they are held in `width` bits without carries.
+///
+/// The `o`-th bit of the coefficients of the input bits which are set is counted at
+/// offset `o`, taking up as many bits as that count may need. If these do not overlap,
+/// masks and right shifts which keep or drop every count as a whole act on the
+/// coefficients alone.
+fn fields(c: &[u128], width: u16) -> Option<Vec<(u16, u16)>> {
+    let mut fields = Vec::new();
+    let mut used = 0u128;
+    for o in 0..width {
+        let count = c.iter().filter(|&&x| (x >> o) & 1 == 1).count() as u32;
+        if count == 0 {
+            continue;
+        }
+        let len = (32 - count.leading_zeros()) as u16;
+        if o + len > width || used & (mask(len) << o) != 0 {
+            return None;
+        }
+        used |= mask(len) << o;
+        fields.push((o, len));
+    }
+    Some(fields)
+}
+
+fn byte_swap(x: u128, width: u16) -> u128 {
+    (x & mask(width)).swap_bytes() >> (128 - width as u32)
+}
+
+fn rotate_left(x: u128, c: u16, width: u16) -> u128 {
+    let x = x & mask(width);
+    ((x << c) | (x >> (width - c))) & mask(width)
+}
+
+/// Checks a recognised sequence against the operation it is replaced by, on every single
+/// bit set and on pseudo-random values.
+fn agrees(ssa: &SSAStorage,
+          seq: &Sequence,
+          input: SSAValue,
+          width: u16,
+          opcode: &MOpcode,
+          amount: Option<u64>)
+          -> bool {
+    let max = mask(width);
+    let expect = |x: u128| match *opcode {
+        MOpcode::OpBSwap(_) => byte_swap(x, width),
+        MOpcode::OpRol => rotate_left(x, amount.unwrap_or(0) as u16, width),
+        _ => x.count_ones() as u128,
+    };
+    (0..width).map(|i| 1 << i).chain(pseudo_random(256)).all(|x| {
+        let x = x & max;
+        seq.evaluate(ssa, input, x) == Some(expect(x))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::dce;
+    use middle::ir_reader::test_util::{last_op, parse};
+
+    // `body` computes `%4` from the 64 bit arguments `%1` and `%2` and the low half
+    // `%3` of `%1`.
+    fn function(body: &str) -> SSAStorage {
+        let il = format!("\
+define-fun main(unknown) -> unknown {{
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+        %2: $Unknown64 = $rsi;
+    bb_0x001000.0000(sz 0x40):
+        [@0x001000.0000] %3: $Unknown32 = Narrow32(%1);
+{}
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $rax = %4;
+}}
+", body);
+        parse(&il)
+    }
+
+    /// Runs the pass on `body` and returns the result with its opcode and operands.
+    fn recover(body: &str) -> (SSAStorage, MOpcode, Vec<SSAValue>) {
+        let mut ssa = function(body);
+        run(&mut ssa);
+        dce::collect(&mut ssa);
+        let root = last_op(&ssa);
+        let opcode = ssa.opcode(root).unwrap();
+        let operands = ssa.operands_of(root);
+        (ssa, opcode, operands)
+    }
+
+    /// Checks that the sequence computing the result from `%3`, or `%1` for 64 bit
+    /// results, evaluates to `expect`, then that it is replaced by `opcode`.
+    fn check(body: &str, opcode: MOpcode, amount: Option<u64>, expect: &Fn(u128) -> u128) {
+        let ssa = function(body);
+        let root = last_op(&ssa);
+        let width = width_of(&ssa, root).unwrap();
+        let seq = Sequence::collect(&ssa, root, None).unwrap();
+        let input = *seq.inputs.iter().find(|&&n| width_of(&ssa, n) == Some(width))
+                        .or_else(|| seq.ops.iter().find(|&&n| {
+                            ssa.opcode(n) == Some(MOpcode::OpNarrow(32))
+                        }))
+                        .unwrap();
+        let seq = Sequence::collect(&ssa, root, Some(input)).unwrap();
+        let max = mask(width);
+        for x in (0..1024).chain(pseudo_random(1024)) {
+            let x = x & max;
+            assert_eq!(seq.evaluate(&ssa, input, x), Some(expect(x)), "x = {:#x}", x);
+        }
+
+        let (ssa, op, operands) = recover(body);
+        assert_eq!(op, opcode);
+        assert_eq!(operands[0], input);
+        assert_eq!(operands.get(1).and_then(|&c| ssa.constant(c)), amount);
+        assert!(!ssa.inorder_walk().any(|n| ssa.opcode(n) == Some(MOpcode::OpLsr)));
+    }
+
+    #[test]
+    fn rotate_const32() {
+        check("\
+        [@0x001000.0001] %5: $Unknown32 = %3 << #x5;
+        [@0x001000.0002] %6: $Unknown32 = %3 >> #x1b;
+        [@0x001000.0003] %4: $Unknown32 = %5 | %6;",
+              MOpcode::OpRol, Some(5),
+              &|x: u128| (x as u32).rotate_left(5) as u128);
+    }
+
+    #[test]
+    fn rotate_right_const64() {
+        check("\
+        [@0x001000.0001] %5: $Unknown64 = %1 >> #xd;
+        [@0x001000.0002] %6: $Unknown64 = %1 << #x33;
+        [@0x001000.0003] %4: $Unknown64 = %5 + %6;",
+              MOpcode::OpRol, Some(51),
+              &|x: u128| (x as u64).rotate_right(13) as u128);
+    }
+
+    #[test]
+    fn rotate_left_variable() {
+        let (ssa, op, operands) = recover("\
+        [@0x001000.0001] %5: $Unknown32 = Narrow32(%2);
+        [@0x001000.0002] %6: $Unknown32 = %3 << %5;
+        [@0x001000.0003] %7: $Unknown32 = #x20 - %5;
+        [@0x001000.0004] %8: $Unknown32 = %3 >> %7;
+        [@0x001000.0005] %4: $Unknown32 = %6 | %8;");
+        assert_eq!(op, MOpcode::OpRol);
+        assert_eq!(ssa.opcode(operands[0]), Some(MOpcode::OpNarrow(32)));
+        assert_eq!(ssa.opcode(operands[1]), Some(MOpcode::OpNarrow(32)));
+        assert!(operands[0] != operands[1]);
+    }
+
+    #[test]
+    fn rotate_right_variable_masked() {
+        let (ssa, op, operands) = recover("\
+        [@0x001000.0001] %5: $Unknown64 = %2 & #x3f;
+        [@0x001000.0002] %6: $Unknown64 = %1 >> %5;
+        [@0x001000.0003] %7: $Unknown64 = #x0 - %2;
+        [@0x001000.0004] %8: $Unknown64 = %7 & #x3f;
+        [@0x001000.0005] %9: $Unknown64 = %1 << %8;
+        [@0x001000.0006] %4: $Unknown64 = %6 | %9;");
+        assert_eq!(op, MOpcode::OpRor);
+        assert!(!ssa.is_expr(operands[0]) && !ssa.is_expr(operands[1]));
+        assert!(operands[0] != operands[1]);
+        assert!(!ssa.inorder_walk().any(|n| ssa.opcode(n) == Some(MOpcode::OpLsl)));
+    }
+
+    #[test]
+    fn rotate_variable_unmasked_negation_is_kept() {
+        // `x >> -n` shifts by more than the width without the mask.
+        let (_, op, _) = recover("\
+        [@0x001000.0001] %5: $Unknown64 = %1 << %2;
+        [@0x001000.0002] %6: $Unknown64 = #x0 - %2;
+        [@0x001000.0003] %7: $Unknown64 = %1 >> %6;
+        [@0x001000.0004] %4: $Unknown64 = %5 | %7;");
+        assert_eq!(op, MOpcode::OpOr);
+    }
+
+    #[test]
+    fn rotate_variable_masked_sum_is_kept() {
+        // Both shifts are by zero for `n = 0`, which adds `x` to itself.
+        let (_, op, _) = recover("\
+        [@0x001000.0001] %5: $Unknown64 = %2 & #x3f;
+        [@0x001000.0002] %6: $Unknown64 = %1 << %5;
+        [@0x001000.0003] %7: $Unknown64 = #x0 - %2;
+        [@0x001000.0004] %8: $Unknown64 = %7 & #x3f;
+        [@0x001000.0005] %9: $Unknown64 = %1 >> %8;
+        [@0x001000.0006] %4: $Unknown64 = %6 + %9;");
+        assert_eq!(op, MOpcode::OpAdd);
+    }
+
+    #[test]
+    fn bswap32() {
+        check("\
+        [@0x001000.0001] %5: $Unknown32 = %3 << #x18;
+        [@0x001000.0002] %6: $Unknown32 = %3 << #x8;
+        [@0x001000.0003] %7: $Unknown32 = %6 & #xff0000;
+        [@0x001000.0004] %8: $Unknown32 = %3 >> #x8;
+        [@0x001000.0005] %9: $Unknown32 = %8 & #xff00;
+        [@0x001000.0006] %10: $Unknown32 = %3 >> #x18;
+        [@0x001000.0007] %11: $Unknown32 = %5 | %7;
+        [@0x001000.0008] %12: $Unknown32 = %11 | %9;
+        [@0x001000.0009] %4: $Unknown32 = %12 | %10;",
+              MOpcode::OpBSwap(32), None,
+              &|x: u128| (x as u32).swap_bytes() as u128);
+    }
+
+    #[test]
+    fn bswap64() {
+        // Swaps neighbouring bytes, then 16 bit halves, then 32 bit halves.
+        check("\
+        [@0x001000.0001] %5: $Unknown64 = %1 & #xff00ff00ff00ff;
+        [@0x001000.0002] %6: $Unknown64 = %5 << #x8;
+        [@0x001000.0003] %7: $Unknown64 = %1 >> #x8;
+        [@0x001000.0004] %8: $Unknown64 = %7 & #xff00ff00ff00ff;
+        [@0x001000.0005] %9: $Unknown64 = %6 | %8;
+        [@0x001000.0006] %10: $Unknown64 = %9 & #xffff0000ffff;
+        [@0x001000.0007] %11: $Unknown64 = %10 << #x10;
+        [@0x001000.0008] %12: $Unknown64 = %9 >> #x10;
+        [@0x001000.0009] %13: $Unknown64 = %12 & #xffff0000ffff;
+        [@0x001000.000a] %14: $Unknown64 = %11 | %13;
+        [@0x001000.000b] %15: $Unknown64 = %14 << #x20;
+        [@0x001000.000c] %16: $Unknown64 = %14 >> #x20;
+        [@0x001000.000d] %4: $Unknown64 = %15 | %16;",
+              MOpcode::OpBSwap(64), None,
+              &|x: u128| (x as u64).swap_bytes() as u128);
+    }
+
+    #[test]
+    fn popcount32() {
+        check("\
+        [@0x001000.0001] %5: $Unknown32 = %3 >> #x1;
+        [@0x001000.0002] %6: $Unknown32 = %5 & #x55555555;
+        [@0x001000.0003] %7: $Unknown32 = %3 - %6;
+        [@0x001000.0004] %8: $Unknown32 = %7 & #x33333333;
+        [@0x001000.0005] %9: $Unknown32 = %7 >> #x2;
+        [@0x001000.0006] %10: $Unknown32 = %9 & #x33333333;
+        [@0x001000.0007] %11: $Unknown32 = %8 + %10;
+        [@0x001000.0008] %12: $Unknown32 = %11 >> #x4;
+        [@0x001000.0009] %13: $Unknown32 = %11 + %12;
+        [@0x001000.000a] %14: $Unknown32 = %13 & #xf0f0f0f;
+        [@0x001000.000b] %15: $Unknown32 = %14 * #x1010101;
+        [@0x001000.000c] %4: $Unknown32 = %15 >> #x18;",
+              MOpcode::OpPopCount, None,
+              &|x: u128| x.count_ones() as u128);
+    }
+
+    #[test]
+    fn popcount64() {
+        check("\
+        [@0x001000.0001] %5: $Unknown64 = %1 >> #x1;
+        [@0x001000.0002] %6: $Unknown64 = %5 & #x5555555555555555;
+        [@0x001000.0003] %7: $Unknown64 = %1 - %6;
+        [@0x001000.0004] %8: $Unknown64 = %7 & #x3333333333333333;
+        [@0x001000.0005] %9: $Unknown64 = %7 >> #x2;
+        [@0x001000.0006] %10: $Unknown64 = %9 & #x3333333333333333;
+        [@0x001000.0007] %11: $Unknown64 = %8 + %10;
+        [@0x001000.0008] %12: $Unknown64 = %11 >> #x4;
+        [@0x001000.0009] %13: $Unknown64 = %11 + %12;
+        [@0x001000.000a] %14: $Unknown64 = %13 & #xf0f0f0f0f0f0f0f;
+        [@0x001000.000b] %15: $Unknown64 = %14 * #x101010101010101;
+        [@0x001000.000c] %4: $Unknown64 = %15 >> #x38;",
+              MOpcode::OpPopCount, None,
+              &|x: u128| x.count_ones() as u128);
+    }
+
+    #[test]
+    fn other_bit_operations_are_kept() {
+        // Gray code of the low 31 bits.
+        let (_, op, _) = recover("\
+        [@0x001000.0001] %5: $Unknown32 = %3 >> #x1;
+        [@0x001000.0002] %6: $Unknown32 = %3 ^ %5;
+        [@0x001000.0003] %4: $Unknown32 = %6 & #x7fffffff;");
+        assert_eq!(op, MOpcode::OpAnd);
+
+        // Shifts by amounts which do not add up to the width.
+        let (_, op, _) = recover("\
+        [@0x001000.0001] %5: $Unknown64 = %1 << %2;
+        [@0x001000.0002] %6: $Unknown64 = #x20 - %2;
+        [@0x001000.0003] %7: $Unknown64 = %1 >> %6;
+        [@0x001000.0004] %4: $Unknown64 = %5 | %7;");
+        assert_eq!(op, MOpcode::OpOr);
+
+        // A population count which loses the highest bit of the count of the low byte.
+        let (_, op, _) = recover("\
+        [@0x001000.0001] %5: $Unknown32 = %3 >> #x1;
+        [@0x001000.0002] %6: $Unknown32 = %5 & #x55555555;
+        [@0x001000.0003] %7: $Unknown32 = %3 - %6;
+        [@0x001000.0004] %8: $Unknown32 = %7 & #x33333333;
+        [@0x001000.0005] %9: $Unknown32 = %7 >> #x2;
+        [@0x001000.0006] %10: $Unknown32 = %9 & #x33333333;
+        [@0x001000.0007] %11: $Unknown32 = %8 + %10;
+        [@0x001000.0008] %12: $Unknown32 = %11 >> #x4;
+        [@0x001000.0009] %13: $Unknown32 = %11 + %12;
+        [@0x001000.000a] %14: $Unknown32 = %13 & #xf0f0f07;
+        [@0x001000.000b] %15: $Unknown32 = %14 * #x1010101;
+        [@0x001000.000c] %4: $Unknown32 = %15 >> #x18;");
+        assert_eq!(op, MOpcode::OpLsr);
+    }
+}
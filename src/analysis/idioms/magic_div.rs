@@ -0,0 +1,824 @@
+//! Recovers divisions and remainders by constants from the multiply-and-shift
+//! sequences compilers emit in their place.
+//!
+//! Compilers turn `x / 10` on an unsigned 32 bit `x` into
+//! `Narrow32((ZeroExt64(x) * #xcccccccd) >> #x23)`, or into
+//! `MulHi64(x, #xcccccccccccccccd) >> #x3` on a 64 bit one, and there are several variants
+//! with additional fix-ups for other divisors and signed operands. Instead of matching
+//! every one of them, the operations computing a value from a single input are followed
+//! symbolically. Every value is written as `floor((a*x + b*f + c*s) / 2^k)`, where `f`
+//! is the high part `floor(x*m / 2^l)` of the product with the magic number `m` and `s`
+//! is the sign bit of `x`, together with the number of low bits the operations actually
+//! compute. A sequence computing `floor(x*M / 2^S)`, plus `s` for signed dividends,
+//! divides by `d` if `M = ceil(2^S / d)` and the rounding error `M*d - 2^S` is within
+//! the bound given by Granlund and Montgomery. It is replaced by an `OpDiv` or
+//! `OpSDiv` by `d`, and `x - (x / d) * d` by an `OpMod` or `OpSMod`.
+//!
+//! Arithmetic shifts of `w` bit values are expected as `Narrow{w}(SignExt{2w}(v) >> k)`.
+//! The high half of a product is taken either from a product of extended operands or
+//! from `OpMulHi`/`OpSMulHi`, which is the only way for 64 bit dividends.
+//!
+//! Signed divisions and remainders by positive divisors of dividends whose sign bit
+//! is known to be clear are replaced by unsigned ones.
+
+use std::cmp;
+use std::collections::HashMap;
+
+use num::bigint::BigInt;
+use num::{Integer, ToPrimitive};
+
+use analysis::known_bits::{self, KnownBitsAnalysis};
+use middle::ir::MOpcode;
+use middle::ssa::ssa_traits::*;
+use middle::ssa::ssastorage::SSAStorage;
+
+use super::{is_arithmetic, mask, pseudo_random, sign_extend, width_of, SSAValue, Sequence};
+
+/// Widest dividend recognised, wider ones have no multiply-high operation.
+const MAX_WIDTH: u16 = 64;
+
+/// Recognises every division or remainder sequence in `ssa`. Operations which are no
+/// longer used afterwards are left for `dce::collect`.
+pub fn run(ssa: &mut SSAStorage) {
+    let mut known_bits = known_bits::analyze(ssa);
+    for node in ssa.inorder_walk() {
+        if let Some((opcode, input, divisor)) = recognise(ssa, node) {
+            let opcode = unsigned(&known_bits, opcode, input, divisor);
+            radeco_trace!("magic_div|{:?} = {}({:?}, {:#x})", node, opcode, input, divisor);
+            if let Some(c) = ssa.insert_const(divisor) {
+                if super::replace(ssa, node, opcode, &[input, c]).is_some() {
+                    known_bits.forget(node);
+                }
+            }
+        }
+    }
+}
+
+/// The unsigned counterpart of a signed `opcode` if it computes the same on `input`.
+fn unsigned(known_bits: &KnownBitsAnalysis,
+            opcode: MOpcode,
+            input: SSAValue,
+            divisor: u64)
+            -> MOpcode {
+    let width = match opcode {
+        MOpcode::OpSDiv(w) | MOpcode::OpSMod(w) => w,
+        _ => return opcode,
+    };
+    if known_bits.sign_bit(input) != Some(false) || divisor as u128 > mask(width) >> 1 {
+        return opcode;
+    }
+    match opcode {
+        MOpcode::OpSDiv(_) => MOpcode::OpDiv,
+        _ => MOpcode::OpMod,
+    }
+}
+
+/// Multiplications by constants, one of which has to be part of a sequence worth
+/// replacing.
+fn has_constant_factor(ssa: &SSAStorage, node: SSAValue) -> bool {
+    match ssa.opcode(node) {
+        Some(MOpcode::OpMul) | Some(MOpcode::OpMulHi(_)) | Some(MOpcode::OpSMulHi(_)) => {
+            ssa.operands_of(node).iter().any(|&op| ssa.is_constant(op))
+        }
+        _ => false,
+    }
+}
+
+/// Returns the opcode, dividend and divisor of the division or remainder computed by
+/// `root`.
+fn recognise(ssa: &SSAStorage, root: SSAValue) -> Option<(MOpcode, SSAValue, u64)> {
+    if let Some(rem) = remainder(ssa, root) {
+        return Some(rem);
+    }
+    match ssa.opcode(root) {
+        Some(MOpcode::OpDiv) | Some(MOpcode::OpMod) |
+        Some(MOpcode::OpSDiv(_)) | Some(MOpcode::OpSMod(_)) => return None,
+        Some(ref op) if is_arithmetic(op) => {}
+        _ => return None,
+    }
+    let width = width_of(ssa, root)?;
+    if width < 2 || width > MAX_WIDTH {
+        return None;
+    }
+    let seq = Sequence::collect(ssa, root, None)?;
+    if !seq.ops.iter().any(|&n| has_constant_factor(ssa, n)) {
+        return None;
+    }
+
+    for (input, seq) in seq.single_inputs(ssa, root) {
+        for &signed in &[false, true] {
+            let mut terms = Terms::new(ssa, input, width, signed);
+            if let Some((opcode, divisor)) = terms.of(root).and_then(|t| terms.division(&t)) {
+                debug_assert!(agrees(ssa, &seq, input, width, &opcode, divisor),
+                              "{:?} does not compute {}({:?}, {})",
+                              root,
+                              opcode,
+                              input,
+                              divisor);
+                return Some((opcode, input, divisor));
+            }
+        }
+    }
+    None
+}
+
+/// Recognises `x - (x / d) * d` once the division has been recognised.
+fn remainder(ssa: &SSAStorage, root: SSAValue) -> Option<(MOpcode, SSAValue, u64)> {
+    if ssa.opcode(root)? != MOpcode::OpSub {
+        return None;
+    }
+    let operands = ssa.operands_of(root);
+    let (x, product) = (*operands.get(0)?, *operands.get(1)?);
+    if ssa.opcode(product)? != MOpcode::OpMul {
+        return None;
+    }
+    let factors = ssa.operands_of(product);
+    let (quotient, d) = match (ssa.constant(*factors.get(0)?), ssa.constant(*factors.get(1)?)) {
+        (None, Some(d)) => (factors[0], d),
+        (Some(d), None) => (factors[1], d),
+        _ => return None,
+    };
+    let operands = ssa.operands_of(quotient);
+    if operands.get(0) != Some(&x) || operands.get(1).and_then(|&n| ssa.constant(n)) != Some(d) {
+        return None;
+    }
+    match ssa.opcode(quotient)? {
+        MOpcode::OpDiv => Some((MOpcode::OpMod, x, d)),
+        MOpcode::OpSDiv(w) => Some((MOpcode::OpSMod(w), x, d)),
+        _ => None,
+    }
+}
+
+/// `self.x * x + self.f * f + self.s * s` for the dividend `x`, the high part `f` of its
+/// product with the magic number and its sign bit `s`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Linear {
+    x: i128,
+    f: i128,
+    s: i128,
+}
+
+impl Linear {
+    fn scale(&self, factor: i128) -> Option<Linear> {
+        Some(Linear {
+            x: self.x.checked_mul(factor)?,
+            f: self.f.checked_mul(factor)?,
+            s: self.s.checked_mul(factor)?,
+        })
+    }
+
+    fn add(&self, other: &Linear) -> Option<Linear> {
+        Some(Linear {
+            x: self.x.checked_add(other.x)?,
+            f: self.f.checked_add(other.f)?,
+            s: self.s.checked_add(other.s)?,
+        })
+    }
+}
+
+/// A value which is `floor(lin / 2^shift)` in its low `valid` bits.
+#[derive(Clone, Copy, Debug)]
+struct Term {
+    lin: Linear,
+    shift: u32,
+    valid: u16,
+}
+
+/// Follows the operations computing values from the dividend `input`, read as a signed
+/// or unsigned `width` bit number.
+struct Terms<'a> {
+    ssa: &'a SSAStorage,
+    input: SSAValue,
+    width: u16,
+    signed: bool,
+    /// Magic number and shift of `f`, once the sequence uses it.
+    high: Option<(i128, u32)>,
+    terms: HashMap<SSAValue, Term>,
+}
+
+impl<'a> Terms<'a> {
+    fn new(ssa: &'a SSAStorage, input: SSAValue, width: u16, signed: bool) -> Terms<'a> {
+        Terms {
+            ssa: ssa,
+            input: input,
+            width: width,
+            signed: signed,
+            high: None,
+            terms: HashMap::new(),
+        }
+    }
+
+    fn of(&mut self, node: SSAValue) -> Option<Term> {
+        if let Some(&t) = self.terms.get(&node) {
+            return Some(t);
+        }
+        let t = self.compute(node)?;
+        self.terms.insert(node, t);
+        Some(t)
+    }
+
+    fn compute(&mut self, node: SSAValue) -> Option<Term> {
+        let ssa = self.ssa;
+        if node == self.input {
+            let lin = Linear { x: 1, ..Linear::default() };
+            return Some(Term { lin: lin, shift: 0, valid: self.width });
+        }
+        let width = width_of(ssa, node)?;
+        if width > 64 {
+            return None;
+        }
+        let operands = ssa.operands_of(node);
+        let constant = |i: usize| operands.get(i).and_then(|&n| ssa.constant(n));
+        let opcode = ssa.opcode(node)?;
+        match opcode {
+            MOpcode::OpZeroExt(_) | MOpcode::OpSignExt(_) => {
+                let operand = *operands.get(0)?;
+                let n = width_of(ssa, operand)?;
+                let t = self.of(operand)?;
+                let (lo, hi) = self.range(&t)?;
+                // Extensions keep the value only if it fits into the operand.
+                let (min, max) = match opcode {
+                    MOpcode::OpZeroExt(_) => (0, mask(n) as i128),
+                    _ => (-(mask(n - 1) as i128) - 1, mask(n - 1) as i128),
+                };
+                if t.valid < n || lo < min || hi > max {
+                    return None;
+                }
+                Some(Term { valid: width, ..t })
+            }
+            MOpcode::OpNarrow(_) => {
+                let t = self.of(*operands.get(0)?)?;
+                Some(Term { valid: cmp::min(t.valid, width), ..t })
+            }
+            MOpcode::OpMul => {
+                let (operand, c) = match (constant(0), constant(1)) {
+                    (None, Some(c)) => (operands[0], c),
+                    (Some(c), None) => (operands[1], c),
+                    _ => return None,
+                };
+                let t = self.of(operand)?;
+                let t = self.integral(t)?;
+                // Any factor congruent to `c` gives the same low bits, and negative
+                // magic numbers are written with their sign bit set.
+                let c = sign_extend(c as u128 & mask(width), width);
+                Some(Term {
+                    lin: t.lin.scale(c)?,
+                    shift: 0,
+                    valid: cmp::min(t.valid, width),
+                })
+            }
+            MOpcode::OpMulHi(w) | MOpcode::OpSMulHi(w) if w == width && w > 0 => {
+                let (operand, c) = match (constant(0), constant(1)) {
+                    (None, Some(c)) => (operands[0], c),
+                    (Some(c), None) => (operands[1], c),
+                    _ => return None,
+                };
+                let t = self.of(operand)?;
+                let t = self.integral(t)?;
+                let (lo, hi) = self.range(&t)?;
+                // The high half is `floor(v*c / 2^w)` only if both factors are held
+                // exactly as numbers of the signedness of the operation.
+                let (min, max, c) = match opcode {
+                    MOpcode::OpMulHi(_) => (0, mask(w) as i128, (c as u128 & mask(w)) as i128),
+                    _ => {
+                        (-(mask(w - 1) as i128) - 1,
+                         mask(w - 1) as i128,
+                         sign_extend(c as u128 & mask(w), w))
+                    }
+                };
+                if t.valid < w || lo < min || hi > max {
+                    return None;
+                }
+                Some(Term {
+                    lin: t.lin.scale(c)?,
+                    shift: w as u32,
+                    valid: w,
+                })
+            }
+            MOpcode::OpAdd | MOpcode::OpSub => {
+                let a = self.of(*operands.get(0)?)?;
+                let b = self.of(*operands.get(1)?)?;
+                let (a, b, sign) = match opcode {
+                    MOpcode::OpAdd if a.shift == 0 => (b, a, 1),
+                    MOpcode::OpAdd => (a, b, 1),
+                    _ => (a, b, -1),
+                };
+                // `floor(u / 2^k) + v` is `floor((u + v*2^k) / 2^k)` for integral `v`.
+                let b = self.integral(b)?;
+                let lin = a.lin.add(&b.lin.scale(sign * pow2(a.shift)?)?)?;
+                Some(Term {
+                    lin: lin,
+                    shift: a.shift,
+                    valid: cmp::min(cmp::min(a.valid, b.valid), width),
+                })
+            }
+            MOpcode::OpLsr => {
+                let operand = *operands.get(0)?;
+                let j = constant(1)?;
+                if j >= width as u64 {
+                    return None;
+                }
+                if operand == self.input && j == self.width as u64 - 1 {
+                    let lin = Linear { s: 1, ..Linear::default() };
+                    return Some(Term { lin: lin, shift: 0, valid: width });
+                }
+                let t = self.of(operand)?;
+                let (lo, hi) = self.range(&t)?;
+                // Shifting a value held exactly divides it. Otherwise, its bits above
+                // `valid` are unknown and shifted into the low ones.
+                let valid = if t.valid == width && lo >= 0 && hi <= mask(width) as i128 {
+                    width
+                } else if j < t.valid as u64 {
+                    t.valid - j as u16
+                } else {
+                    return None;
+                };
+                Some(Term {
+                    lin: t.lin,
+                    shift: t.shift.checked_add(j as u32)?,
+                    valid: valid,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// `t` without a shift, which is turned into the sign bit or into `f`.
+    fn integral(&mut self, t: Term) -> Option<Term> {
+        if t.shift == 0 {
+            return Some(t);
+        }
+        if t.lin.f != 0 || t.lin.s != 0 {
+            return None;
+        }
+        let w = self.width as u32;
+        let lin = if t.lin.x == 1 && self.signed && t.shift >= w - 1 {
+            // Shifting out everything but copies of the sign bit leaves `-1` for
+            // negative dividends and `0` otherwise.
+            Linear { s: -1, ..Linear::default() }
+        } else if t.lin.x == 1 && !self.signed && t.shift == w - 1 {
+            Linear { s: 1, ..Linear::default() }
+        } else {
+            let high = (t.lin.x, t.shift);
+            if *self.high.get_or_insert(high) != high {
+                return None;
+            }
+            Linear { f: 1, ..Linear::default() }
+        };
+        Some(Term { lin: lin, shift: 0, valid: t.valid })
+    }
+
+    /// Bounds of the value of `t` over every dividend.
+    fn range(&self, t: &Term) -> Option<(i128, i128)> {
+        let half = 1i128 << (self.width - 1);
+        // `s` is constant on either half of the dividends, and the rest of `t.lin` is
+        // linear in `x` but for rounding `f` down.
+        let halves = if self.signed {
+            [(-half, -1, 1), (0, half - 1, 0)]
+        } else {
+            [(0, half - 1, 0), (half, 2 * half - 1, 1)]
+        };
+        let (m, l) = self.high.unwrap_or((0, 0));
+        // The products of 64 bit dividends with the magic number scaled by `2^l` do not
+        // fit into 128 bits.
+        let one = BigInt::from(1) << l as usize;
+        let (a, b, c, m) = (big(t.lin.x), big(t.lin.f), big(t.lin.s), big(m));
+        let (mut los, mut his) = (Vec::new(), Vec::new());
+        for &(first, last, s) in &halves {
+            for &x in &[first, last] {
+                let x = big(x);
+                // `2^l` times `t.lin` with `f` not rounded.
+                let g = &a * &x * &one + &b * &x * &m + &c * BigInt::from(s) * &one;
+                // Rounding `f` down changes `t.lin` by less than `b`.
+                let g_b = &g - &b * &one;
+                let (l0, h0) = if t.lin.f > 0 {
+                    (g_b.div_floor(&one) + 1, g.div_floor(&one))
+                } else if t.lin.f < 0 {
+                    (-(-g).div_floor(&one), -(-g_b).div_floor(&one) - 1)
+                } else {
+                    (g.div_floor(&one), g.div_floor(&one))
+                };
+                los.push(l0);
+                his.push(h0);
+            }
+        }
+        let k = BigInt::from(1) << t.shift as usize;
+        let lo = los.iter().min()?.div_floor(&k);
+        let hi = his.iter().max()?.div_floor(&k);
+        Some((small(&lo)?, small(&hi)?))
+    }
+
+    /// The division of the whole dividend `t` computes.
+    fn division(&self, t: &Term) -> Option<(MOpcode, u64)> {
+        if t.valid < self.width {
+            return None;
+        }
+        let lin = t.lin;
+        // `a*x + f` is `floor(x * (m + a*2^l) / 2^l)`.
+        let (magic, shift) = match (lin.f, self.high) {
+            (0, _) => (lin.x, t.shift),
+            (1, Some((m, l))) => {
+                (m.checked_add(lin.x.checked_mul(pow2(l)?)?)?, l.checked_add(t.shift)?)
+            }
+            _ => return None,
+        };
+        // `floor((u + 2^k*s) / 2^k)` is `floor(u / 2^k) + s`, which rounds the quotients
+        // of negative dividends toward zero.
+        let rounded = if lin.s == 0 {
+            false
+        } else if lin.s == pow2(t.shift)? {
+            true
+        } else {
+            return None;
+        };
+        match (self.signed, rounded) {
+            (false, false) => divisor(magic, shift, self.width, false).map(|d| (MOpcode::OpDiv, d)),
+            (true, true) => {
+                divisor(magic, shift, self.width, true).map(|d| (MOpcode::OpSDiv(self.width), d))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn pow2(k: u32) -> Option<i128> {
+    if k < 126 { Some(1 << k) } else { None }
+}
+
+fn big(v: i128) -> BigInt {
+    (BigInt::from((v >> 64) as i64) << 64) + BigInt::from(v as u64)
+}
+
+fn small(v: &BigInt) -> Option<i128> {
+    let (hi, lo) = v.div_mod_floor(&(BigInt::from(1) << 64));
+    Some(((hi.to_i64()? as i128) << 64) + lo.to_u64()? as i128)
+}
+
+fn floor_div(a: i128, b: i128) -> i128 {
+    let q = a / b;
+    if a % b != 0 && (a < 0) != (b < 0) { q - 1 } else { q }
+}
+
+fn ceil_div(a: i128, b: i128) -> i128 {
+    -floor_div(-a, b)
+}
+
+/// The `d` for which `floor(x * magic / 2^shift)` is `x / d` for every unsigned `width`
+/// bit `x`, or, if `signed`, for which adding one for negative `x` rounds `x / d`
+/// toward zero.
+///
+/// `magic` has to be `ceil(2^shift / d)`, and `magic * d` may exceed `2^shift` by at most
+/// `2^(shift - width)` for unsigned dividends (Granlund and Montgomery, "Division by
+/// Invariant Integers using Multiplication"). Signed ones are a bit narrower in
+/// magnitude, but exact multiples of `d` need a non-zero error for the correction.
+fn divisor(magic: i128, shift: u32, width: u16, signed: bool) -> Option<u64> {
+    let w = width as u32;
+    if magic <= 1 || shift < w || pow2(shift).is_none() {
+        return None;
+    }
+    let one = 1i128 << shift;
+    let d = ceil_div(one, magic);
+    let max = (if signed { mask(width - 1) } else { mask(width) }) as i128;
+    if d < 2 || d > max || ceil_div(one, d) != magic {
+        return None;
+    }
+    let error = magic.checked_mul(d)? - one;
+    let bound = if signed { 1 << (shift - w + 1) } else { 1 << (shift - w) };
+    if error > bound || (signed && error == 0) {
+        return None;
+    }
+    Some(d as u64)
+}
+
+/// Checks a recognised sequence against the division by `d` it is replaced by, on the
+/// limits of the value range and on pseudo-random dividends.
+fn agrees(ssa: &SSAStorage,
+          seq: &Sequence,
+          input: SSAValue,
+          width: u16,
+          opcode: &MOpcode,
+          d: u64)
+          -> bool {
+    let max = mask(width);
+    let expect = |x: u128| match *opcode {
+        MOpcode::OpDiv => x / d as u128,
+        _ => (sign_extend(x, width) / d as i128) as u128 & max,
+    };
+    [0, 1, max >> 1, (max >> 1) + 1, max].iter().cloned().chain(pseudo_random(256)).all(|x| {
+        let x = x & max;
+        seq.evaluate(ssa, input, x) == Some(expect(x))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::dce;
+    use middle::ir_reader::test_util::{last_op, parse};
+
+    // `body` computes `%3` from the 64 bit argument `%1` and its low half `%2`.
+    fn function(body: &str) -> SSAStorage {
+        let il = format!("\
+define-fun main(unknown) -> unknown {{
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+    bb_0x001000.0000(sz 0x40):
+        [@0x001000.0000] %2: $Unknown32 = Narrow32(%1);
+{}
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $rax = %3;
+}}
+", body);
+        parse(&il)
+    }
+
+    /// Checks that the sequence computing the result evaluates to `expect` on every
+    /// sample, then that it is replaced by `opcode` by `divisor`.
+    fn check(body: &str,
+             opcode: MOpcode,
+             divisor: u64,
+             samples: &[u128],
+             expect: &Fn(u128) -> u128) {
+        let mut ssa = function(body);
+        let root = last_op(&ssa);
+        let seq = Sequence::collect(&ssa, root, None).unwrap();
+        let width = width_of(&ssa, root).unwrap();
+        let input = *seq.inputs.iter().find(|&&n| width_of(&ssa, n) == Some(width))
+                        .or_else(|| seq.ops.iter().find(|&&n| {
+                            ssa.opcode(n) == Some(MOpcode::OpNarrow(32))
+                        }))
+                        .unwrap();
+        let seq = Sequence::collect(&ssa, root, Some(input)).unwrap();
+        for &x in samples {
+            assert_eq!(seq.evaluate(&ssa, input, x), Some(expect(x)), "x = {:#x}", x);
+        }
+
+        run(&mut ssa);
+        dce::collect(&mut ssa);
+        let root = last_op(&ssa);
+        assert_eq!(ssa.opcode(root), Some(opcode));
+        let operands = ssa.operands_of(root);
+        assert_eq!(operands[0], input);
+        assert_eq!(ssa.constant(operands[1]), Some(divisor));
+        assert!(!ssa.inorder_walk().any(|n| match ssa.opcode(n) {
+            Some(MOpcode::OpMul) | Some(MOpcode::OpMulHi(_)) | Some(MOpcode::OpSMulHi(_)) => true,
+            _ => false,
+        }));
+    }
+
+    fn samples(width: u16) -> Vec<u128> {
+        let max = mask(width);
+        let mut v = (0..2048).chain(max - 2048..max + 1).collect::<Vec<_>>();
+        let mut x = 0x9e3779b97f4a7c15u64;
+        for _ in 0..4096 {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            v.push(x as u128 & max);
+        }
+        v
+    }
+
+    fn s32(x: u128) -> i32 {
+        x as u32 as i32
+    }
+
+    #[test]
+    fn udiv32_shift() {
+        check("\
+        [@0x001000.0001] %4: $Unknown64 = ZeroExt64(%2);
+        [@0x001000.0002] %5: $Unknown64 = %4 * #xcccccccd;
+        [@0x001000.0003] %6: $Unknown64 = %5 >> #x23;
+        [@0x001000.0004] %3: $Unknown32 = Narrow32(%6);",
+              MOpcode::OpDiv, 10, &samples(32), &|x: u128| x / 10);
+    }
+
+    #[test]
+    fn udiv32_add() {
+        check("\
+        [@0x001000.0001] %4: $Unknown64 = ZeroExt64(%2);
+        [@0x001000.0002] %5: $Unknown64 = %4 * #x24924925;
+        [@0x001000.0003] %6: $Unknown64 = %5 >> #x20;
+        [@0x001000.0004] %7: $Unknown32 = Narrow32(%6);
+        [@0x001000.0005] %8: $Unknown32 = %2 - %7;
+        [@0x001000.0006] %9: $Unknown32 = %8 >> #x1;
+        [@0x001000.0007] %10: $Unknown32 = %9 + %7;
+        [@0x001000.0008] %3: $Unknown32 = %10 >> #x2;",
+              MOpcode::OpDiv, 7, &samples(32), &|x: u128| x / 7);
+    }
+
+    #[test]
+    fn umod32() {
+        check("\
+        [@0x001000.0001] %4: $Unknown64 = ZeroExt64(%2);
+        [@0x001000.0002] %5: $Unknown64 = %4 * #xcccccccd;
+        [@0x001000.0003] %6: $Unknown64 = %5 >> #x23;
+        [@0x001000.0004] %7: $Unknown32 = Narrow32(%6);
+        [@0x001000.0005] %8: $Unknown32 = %7 * #xa;
+        [@0x001000.0006] %3: $Unknown32 = %2 - %8;",
+              MOpcode::OpMod, 10, &samples(32), &|x: u128| x % 10);
+    }
+
+    #[test]
+    fn sdiv32() {
+        // hi(x * m) - (x >> 31), with the arithmetic shift done on a wider value.
+        check("\
+        [@0x001000.0001] %4: $Unknown64 = SignExt64(%2);
+        [@0x001000.0002] %5: $Unknown64 = %4 * #x55555556;
+        [@0x001000.0003] %6: $Unknown64 = %5 >> #x20;
+        [@0x001000.0004] %7: $Unknown32 = Narrow32(%6);
+        [@0x001000.0005] %8: $Unknown64 = %4 >> #x1f;
+        [@0x001000.0006] %9: $Unknown32 = Narrow32(%8);
+        [@0x001000.0007] %3: $Unknown32 = %7 - %9;",
+              MOpcode::OpSDiv(32), 3, &samples(32),
+              &|x: u128| (s32(x) / 3) as u32 as u128);
+    }
+
+    #[test]
+    fn sdiv32_add() {
+        // ((hi(x * m) + x) >> 2) + (x >>> 31) for a negative magic number.
+        check("\
+        [@0x001000.0001] %4: $Unknown64 = SignExt64(%2);
+        [@0x001000.0002] %5: $Unknown64 = %4 * #xffffffff92492493;
+        [@0x001000.0003] %6: $Unknown64 = %5 >> #x20;
+        [@0x001000.0004] %7: $Unknown32 = Narrow32(%6);
+        [@0x001000.0005] %8: $Unknown32 = %7 + %2;
+        [@0x001000.0006] %9: $Unknown64 = SignExt64(%8);
+        [@0x001000.0007] %10: $Unknown64 = %9 >> #x2;
+        [@0x001000.0008] %11: $Unknown32 = Narrow32(%10);
+        [@0x001000.0009] %12: $Unknown32 = %2 >> #x1f;
+        [@0x001000.000a] %3: $Unknown32 = %11 + %12;",
+              MOpcode::OpSDiv(32), 7, &samples(32),
+              &|x: u128| (s32(x) / 7) as u32 as u128);
+    }
+
+    #[test]
+    fn smod32() {
+        check("\
+        [@0x001000.0001] %4: $Unknown64 = SignExt64(%2);
+        [@0x001000.0002] %5: $Unknown64 = %4 * #xffffffff92492493;
+        [@0x001000.0003] %6: $Unknown64 = %5 >> #x20;
+        [@0x001000.0004] %7: $Unknown32 = Narrow32(%6);
+        [@0x001000.0005] %8: $Unknown32 = %7 + %2;
+        [@0x001000.0006] %9: $Unknown64 = SignExt64(%8);
+        [@0x001000.0007] %10: $Unknown64 = %9 >> #x2;
+        [@0x001000.0008] %11: $Unknown32 = Narrow32(%10);
+        [@0x001000.0009] %12: $Unknown32 = %2 >> #x1f;
+        [@0x001000.000a] %13: $Unknown32 = %11 + %12;
+        [@0x001000.000b] %14: $Unknown32 = %13 * #x7;
+        [@0x001000.000c] %3: $Unknown32 = %2 - %14;",
+              MOpcode::OpSMod(32), 7, &samples(32),
+              &|x: u128| (s32(x) % 7) as u32 as u128);
+    }
+
+    #[test]
+    fn udiv64_shift() {
+        check("\
+        [@0x001000.0001] %4: $Unknown64 = MulHi64(%1, #xcccccccccccccccd);
+        [@0x001000.0002] %3: $Unknown64 = %4 >> #x3;",
+              MOpcode::OpDiv, 10, &samples(64), &|x: u128| x / 10);
+    }
+
+    #[test]
+    fn udiv64_add() {
+        check("\
+        [@0x001000.0001] %4: $Unknown64 = MulHi64(%1, #x2492492492492493);
+        [@0x001000.0002] %5: $Unknown64 = %1 - %4;
+        [@0x001000.0003] %6: $Unknown64 = %5 >> #x1;
+        [@0x001000.0004] %7: $Unknown64 = %6 + %4;
+        [@0x001000.0005] %3: $Unknown64 = %7 >> #x2;",
+              MOpcode::OpDiv, 7, &samples(64), &|x: u128| x / 7);
+    }
+
+    #[test]
+    fn umod64() {
+        check("\
+        [@0x001000.0001] %4: $Unknown64 = MulHi64(%1, #xcccccccccccccccd);
+        [@0x001000.0002] %5: $Unknown64 = %4 >> #x3;
+        [@0x001000.0003] %6: $Unknown64 = %5 * #xa;
+        [@0x001000.0004] %3: $Unknown64 = %1 - %6;",
+              MOpcode::OpMod, 10, &samples(64), &|x: u128| x % 10);
+    }
+
+    #[test]
+    fn sdiv64() {
+        // hi(x * m) + (x >>> 63), as `imul` leaves it in `rdx`.
+        check("\
+        [@0x001000.0001] %4: $Unknown64 = SMulHi64(%1, #x5555555555555556);
+        [@0x001000.0002] %5: $Unknown64 = %1 >> #x3f;
+        [@0x001000.0003] %3: $Unknown64 = %4 + %5;",
+              MOpcode::OpSDiv(64), 3, &samples(64),
+              &|x: u128| (x as u64 as i64 / 3) as u64 as u128);
+    }
+
+    #[test]
+    fn unsigned_high_half_of_signed_dividend_is_kept() {
+        // `MulHi` reads the dividend as unsigned, which does not round negative ones.
+        let mut ssa = function("\
+        [@0x001000.0001] %4: $Unknown64 = MulHi64(%1, #x5555555555555556);
+        [@0x001000.0002] %5: $Unknown64 = %1 >> #x3f;
+        [@0x001000.0003] %3: $Unknown64 = %4 + %5;");
+        run(&mut ssa);
+        let root = last_op(&ssa);
+        assert_eq!(ssa.opcode(root), Some(MOpcode::OpAdd));
+    }
+
+    #[test]
+    fn sdiv32_non_negative() {
+        // The same sequence as in `sdiv32` on a dividend whose sign bit is clear.
+        let mut ssa = function("\
+        [@0x001000.0001] %10: $Unknown32 = %2 >> #x1;
+        [@0x001000.0002] %4: $Unknown64 = SignExt64(%10);
+        [@0x001000.0003] %5: $Unknown64 = %4 * #x55555556;
+        [@0x001000.0004] %6: $Unknown64 = %5 >> #x20;
+        [@0x001000.0005] %7: $Unknown32 = Narrow32(%6);
+        [@0x001000.0006] %8: $Unknown64 = %4 >> #x1f;
+        [@0x001000.0007] %9: $Unknown32 = Narrow32(%8);
+        [@0x001000.0008] %3: $Unknown32 = %7 - %9;");
+        let input = ssa.inorder_walk()
+                       .find(|&n| {
+                           ssa.opcode(n) == Some(MOpcode::OpLsr) && width_of(&ssa, n) == Some(32)
+                       })
+                       .unwrap();
+        run(&mut ssa);
+        let root = last_op(&ssa);
+        assert_eq!(ssa.opcode(root), Some(MOpcode::OpDiv));
+        let operands = ssa.operands_of(root);
+        assert_eq!(operands[0], input);
+        assert_eq!(ssa.constant(operands[1]), Some(3));
+    }
+
+    #[test]
+    fn smod32_of_masked_dividend() {
+        // The sequence in `smod32` is only an unsigned remainder if the mask clears
+        // the sign bit of the dividend.
+        for &(mask, ref opcode) in &[("#x7fffffff", MOpcode::OpMod),
+                                     ("#x80000001", MOpcode::OpSMod(32))] {
+            let mut ssa = function(&format!("\
+        [@0x001000.0001] %15: $Unknown32 = %2 & {};
+        [@0x001000.0002] %4: $Unknown64 = SignExt64(%15);
+        [@0x001000.0003] %5: $Unknown64 = %4 * #xffffffff92492493;
+        [@0x001000.0004] %6: $Unknown64 = %5 >> #x20;
+        [@0x001000.0005] %7: $Unknown32 = Narrow32(%6);
+        [@0x001000.0006] %8: $Unknown32 = %7 + %15;
+        [@0x001000.0007] %9: $Unknown64 = SignExt64(%8);
+        [@0x001000.0008] %10: $Unknown64 = %9 >> #x2;
+        [@0x001000.0009] %11: $Unknown32 = Narrow32(%10);
+        [@0x001000.000a] %12: $Unknown32 = %15 >> #x1f;
+        [@0x001000.000b] %13: $Unknown32 = %11 + %12;
+        [@0x001000.000c] %14: $Unknown32 = %13 * #x7;
+        [@0x001000.000d] %3: $Unknown32 = %15 - %14;",
+                                            mask));
+            let masked = ssa.inorder_walk()
+                            .find(|&n| ssa.opcode(n) == Some(MOpcode::OpAnd))
+                            .unwrap();
+            run(&mut ssa);
+            let root = last_op(&ssa);
+            assert_eq!(ssa.opcode(root).as_ref(), Some(opcode), "mask {}", mask);
+            let operands = ssa.operands_of(root);
+            assert_eq!(operands[0], masked);
+            assert_eq!(ssa.constant(operands[1]), Some(7));
+        }
+    }
+
+    #[test]
+    fn imprecise_magic_is_kept() {
+        // 0x24924925 is the magic number for 7 but needs the fix-up above.
+        let mut ssa = function("\
+        [@0x001000.0001] %4: $Unknown64 = ZeroExt64(%2);
+        [@0x001000.0002] %5: $Unknown64 = %4 * #x24924925;
+        [@0x001000.0003] %6: $Unknown64 = %5 >> #x20;
+        [@0x001000.0004] %3: $Unknown32 = Narrow32(%6);");
+        run(&mut ssa);
+        let root = last_op(&ssa);
+        assert_eq!(ssa.opcode(root), Some(MOpcode::OpNarrow(32)));
+    }
+
+    #[test]
+    fn divisor_bounds() {
+        assert_eq!(divisor(0xcccccccd, 35, 32, false), Some(10));
+        assert_eq!(divisor(0x124924925, 35, 32, false), Some(7));
+        // The magic number for 7 without the extra bit is too small.
+        assert_eq!(divisor(0x24924925, 32, 32, false), None);
+        assert_eq!(divisor(0x55555556, 32, 32, true), Some(3));
+        assert_eq!(divisor(0x92492493, 34, 32, true), Some(7));
+        // Exact for unsigned dividends, but off by one on negative multiples of 4.
+        assert_eq!(divisor(0x40000000, 32, 32, false), Some(4));
+        assert_eq!(divisor(0x40000000, 32, 32, true), None);
+        assert_eq!(divisor(0xcccccccc, 35, 32, false), None);
+        assert_eq!(divisor(0xcccccccccccccccd, 67, 64, false), Some(10));
+        assert_eq!(divisor(0x12492492492492493, 67, 64, false), Some(7));
+        assert_eq!(divisor(0x5555555555555556, 64, 64, true), Some(3));
+    }
+
+    #[test]
+    fn other_arithmetic_is_kept() {
+        let mut ssa = function("\
+        [@0x001000.0001] %4: $Unknown64 = %1 * #xcccccccd;
+        [@0x001000.0002] %5: $Unknown64 = %4 >> #x23;
+        [@0x001000.0003] %3: $Unknown64 = %5 + %1;");
+        run(&mut ssa);
+        let root = last_op(&ssa);
+        assert_eq!(ssa.opcode(root), Some(MOpcode::OpAdd));
+    }
+}
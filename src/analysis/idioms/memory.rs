@@ -0,0 +1,1137 @@
+//! Recognises inlined `memcpy`, `memmove`, `memset` and `strlen` and replaces them by
+//! calls.
+//!
+//! Compilers inline these as runs of stores to adjacent addresses, as `rep` prefixed
+//! string instructions, which the frontend lifts as an `OpCustom` named after the
+//! instruction, and as loops loading one byte after another until they find a zero
+//! one. Each of them is replaced by a call to a pseudo-import of the same name, which
+//! takes its arguments in the argument registers of the calling convention and
+//! returns its result in the return register.
+//!
+//! A copy only becomes a `memcpy` if its source and destination are offsets from the
+//! same base which are far enough apart not to overlap. Any other copy may overlap and
+//! becomes a `memmove`.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+use frontend::imports::ImportInfo;
+use frontend::radeco_containers::RadecoModule;
+use middle::ir::{Endian, MAddress, MOpcode, WidthSpec};
+use middle::regfile::{RegisterId, RegisterMap};
+use middle::ssa::cfg_traits::*;
+use middle::ssa::ssa_traits::*;
+use middle::ssa::ssastorage::SSAStorage;
+use middle::ssa::utils;
+
+use super::{mask, width_of, SSAValue, Sequence};
+
+type SSABlock = <SSAStorage as CFG>::ActionRef;
+
+/// Address of the first pseudo-import, above any address code is loaded at.
+pub const PSEUDO_IMPORT_BASE: u64 = 0xffff_ffff_ffff_f000;
+
+/// Smallest number of bytes a run of stores has to write to be replaced by a call.
+const MIN_LENGTH: u64 = 32;
+
+/// Library functions which are recognised when inlined.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    Memcpy,
+    Memset,
+    Strlen,
+    Memmove,
+}
+
+impl Builtin {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Builtin::Memcpy => "memcpy",
+            Builtin::Memset => "memset",
+            Builtin::Strlen => "strlen",
+            Builtin::Memmove => "memmove",
+        }
+    }
+
+    /// Address of the pseudo-import calls to this function are made to.
+    pub fn address(&self) -> u64 {
+        PSEUDO_IMPORT_BASE + *self as u64
+    }
+
+    pub fn from_address(address: u64) -> Option<Builtin> {
+        [Builtin::Memcpy, Builtin::Memset, Builtin::Strlen, Builtin::Memmove]
+            .iter()
+            .cloned()
+            .find(|b| b.address() == address)
+    }
+}
+
+/// Replaces every inlined `memcpy`, `memmove`, `memset` and `strlen` in `ssa` by a call
+/// and returns the calls inserted. Values which are no longer used afterwards are left
+/// for `dce::collect`.
+pub fn run(ssa: &mut SSAStorage) -> Vec<(SSAValue, Builtin)> {
+    let mut calls = Vec::new();
+    for node in ssa.values() {
+        if let Some(MOpcode::OpCustom(name)) = ssa.opcode(node) {
+            calls.extend(string_op(ssa, node, &name));
+        }
+    }
+    for block in ssa.blocks() {
+        calls.extend(byte_scan(ssa, block));
+    }
+    for block in ssa.blocks() {
+        calls.extend(store_runs(ssa, block));
+    }
+    calls
+}
+
+/// Runs `run` on every function of `rmod` and adds the pseudo-imports called to its
+/// imports.
+pub fn run_module(rmod: &mut RadecoModule) {
+    let mut called = HashSet::new();
+    for rfn in rmod.functions.values_mut() {
+        called.extend(run(rfn.ssa_mut()).into_iter().map(|(_, builtin)| builtin));
+    }
+    for builtin in called {
+        let address = builtin.address();
+        rmod.imports
+            .entry(address)
+            .or_insert_with(|| ImportInfo::new_stub(address, Cow::from(builtin.name())));
+    }
+}
+
+/// A call inserted in place of an idiom.
+struct Call {
+    node: SSAValue,
+    /// Memory after the call, if it writes to it.
+    mem: Option<SSAValue>,
+    /// Value returned by the call, if it returns one.
+    ret: Option<SSAValue>,
+}
+
+/// Inserts a call to `builtin` passing `args` in the argument registers and `mem` as
+/// memory. Nothing is inserted if the calling convention does not name enough argument
+/// registers.
+fn insert_call(ssa: &mut SSAStorage,
+               builtin: Builtin,
+               args: &[SSAValue],
+               mem: SSAValue,
+               block: SSABlock,
+               addr: &mut MAddress)
+               -> Option<Call> {
+    let regs = ["A0", "A1", "A2"]
+        .iter()
+        .take(args.len())
+        .map(|alias| ssa.regfile.register_id_by_alias(alias))
+        .collect::<Option<Vec<_>>>()?;
+    let ret = match builtin {
+        Builtin::Strlen => {
+            let reg = ssa.regfile.register_id_by_alias("SN")?;
+            let vt = ssa.regfile.whole_registers[reg.to_usize()];
+            let name = ssa.regfile.get_name(reg)?.to_owned();
+            Some((reg, vt, name))
+        }
+        Builtin::Memcpy | Builtin::Memset | Builtin::Memmove => None,
+    };
+    let mem_id = ssa.regfile.mem_id();
+
+    let target = ssa.insert_const(builtin.address())?;
+    let node = ssa.insert_op(MOpcode::OpCall, ValueInfo::new_scalar(WidthSpec::Known(0)), None)?;
+    ssa.op_use(node, 0, target);
+    for (reg, &arg) in regs.iter().zip(args) {
+        ssa.op_use(node, reg.to_u8() + 1, arg);
+    }
+    ssa.op_use(node, mem_id.to_u8() + 1, mem);
+
+    let ret = match ret {
+        Some((reg, vt, name)) => {
+            let comment = ssa.insert_comment(vt, format!("{}@{}", name, addr))?;
+            ssa.op_use(comment, reg.to_u8(), node);
+            Some(comment)
+        }
+        None => None,
+    };
+    let mem = match builtin {
+        Builtin::Memcpy | Builtin::Memset | Builtin::Memmove => {
+            let vt = ValueInfo::new_scalar(WidthSpec::Known(0));
+            let comment = ssa.insert_comment(vt, format!("mem@{}", addr))?;
+            ssa.op_use(comment, mem_id.to_u8(), node);
+            Some(comment)
+        }
+        Builtin::Strlen => None,
+    };
+    ssa.insert_into_block(node, block, *addr);
+    addr.offset += 1;
+    radeco_trace!("memory|{:?} = CALL {}", node, builtin.name());
+    Some(Call { node, mem, ret })
+}
+
+fn insert_expr(ssa: &mut SSAStorage,
+               opcode: MOpcode,
+               operands: &[SSAValue],
+               width: u16,
+               block: SSABlock,
+               addr: &mut MAddress)
+               -> Option<SSAValue> {
+    let vt = ValueInfo::new_scalar(WidthSpec::Known(width));
+    let op = ssa.insert_op(opcode, vt, None)?;
+    for (i, &operand) in operands.iter().enumerate() {
+        ssa.op_use(op, i as u8, operand);
+    }
+    ssa.insert_into_block(op, block, *addr);
+    addr.offset += 1;
+    Some(op)
+}
+
+/// Whether `value` consists of `size` copies of its lowest byte.
+fn is_repeated_byte(value: u64, size: u64) -> bool {
+    let byte = value as u128 & 0xff;
+    let repeated = (0..size).fold(0, |acc, _| (acc << 8) | byte);
+    value as u128 & mask(size as u16 * 8) == repeated
+}
+
+/// Number of bytes taken by `count` elements of `size` bytes.
+fn byte_count(ssa: &mut SSAStorage,
+              count: SSAValue,
+              size: u64,
+              width: u16,
+              block: SSABlock,
+              addr: &mut MAddress)
+              -> Option<SSAValue> {
+    if size == 1 {
+        Some(count)
+    } else {
+        let size = ssa.insert_const(size)?;
+        insert_expr(ssa, MOpcode::OpMul, &[count, size], width, block, addr)
+    }
+}
+
+/// Replaces the `rep` prefixed string instruction `node`, which reads and writes
+/// registers like a call does, by a call.
+fn string_op(ssa: &mut SSAStorage, node: SSAValue, name: &str) -> Option<(SSAValue, Builtin)> {
+    let mut words = name.split_whitespace();
+    let (prefix, instr) = (words.next()?, words.next()?);
+    let size = match instr.chars().last()? {
+        'b' => 1,
+        'w' => 2,
+        'd' => 4,
+        'q' => 8,
+        _ => return None,
+    };
+    let (di, si, cx, ax) = {
+        let reg = |name: &str| ssa.regfile.register_id_by_name(name);
+        (reg("di")?, reg("si"), reg("cx")?, reg("ax"))
+    };
+    let mem_id = ssa.regfile.mem_id();
+    let ptr_width = ssa.regfile.get_width(di)? as u16;
+    let args = utils::register_args(node, ssa);
+    let rets = utils::call_rets(node, ssa);
+    let block = ssa.block_for(node)?;
+    let mut addr = ssa.address(node)?;
+
+    let (dst, count, mem) = (*args.get(di)?, *args.get(cx)?, *args.get(mem_id)?);
+    if !direction_clear(ssa, &args) {
+        return None;
+    }
+    let only_writes = |regs: &[RegisterId]| rets.iter().all(|(reg, _)| regs.contains(&reg));
+
+    let mut results = HashMap::new();
+    let replaced = match (prefix, &instr[..instr.len() - 1]) {
+        ("rep", "movs") => {
+            let si = si?;
+            let src = *args.get(si)?;
+            if !only_writes(&[di, si, cx, mem_id]) {
+                return None;
+            }
+            let length = ssa.constant(count).and_then(|c| c.checked_mul(size));
+            let builtin = copy_builtin(ssa, dst, src, length)?;
+            let zero = ssa.insert_const(0)?;
+            let len = byte_count(ssa, count, size, ptr_width, block, &mut addr)?;
+            let call = insert_call(ssa, builtin, &[dst, src, len], mem, block, &mut addr)?;
+            let end = insert_expr(ssa, MOpcode::OpAdd, &[dst, len], ptr_width, block, &mut addr)?;
+            let src_end =
+                insert_expr(ssa, MOpcode::OpAdd, &[src, len], ptr_width, block, &mut addr)?;
+            results.insert(di, end);
+            results.insert(si, src_end);
+            results.insert(cx, zero);
+            results.insert(mem_id, call.mem?);
+            (call.node, builtin)
+        }
+        ("rep", "stos") => {
+            let value = *args.get(ax?)?;
+            if !only_writes(&[di, cx, mem_id]) {
+                return None;
+            }
+            // `memset` only stores the lowest byte of its value.
+            let value = if size == 1 {
+                value
+            } else {
+                match ssa.constant(value) {
+                    Some(c) if is_repeated_byte(c, size) => ssa.insert_const(c & 0xff)?,
+                    _ => return None,
+                }
+            };
+            let zero = ssa.insert_const(0)?;
+            let len = byte_count(ssa, count, size, ptr_width, block, &mut addr)?;
+            let call = insert_call(ssa, Builtin::Memset, &[dst, value, len], mem, block, &mut addr)?;
+            let end = insert_expr(ssa, MOpcode::OpAdd, &[dst, len], ptr_width, block, &mut addr)?;
+            results.insert(di, end);
+            results.insert(cx, zero);
+            results.insert(mem_id, call.mem?);
+            (call.node, Builtin::Memset)
+        }
+        ("repne", "scas") if size == 1 => {
+            // Only a scan for a zero byte which is not bounded by the count is `strlen`.
+            let value = ssa.constant(*args.get(ax?)?)?;
+            let bound = ssa.constant(count)?;
+            if value & 0xff != 0 || bound as u128 != mask(ptr_width) {
+                return None;
+            }
+            let flags = ["cf", "pf", "af", "zf", "sf", "of"]
+                .iter()
+                .filter_map(|f| {
+                    let reg = ssa.regfile.register_id_by_name(f)?;
+                    if ssa.regfile.get_name(reg) == Some(*f) {
+                        Some((reg, *f))
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+            let mut written = vec![di, cx];
+            written.extend(flags.iter().map(|&(reg, _)| reg));
+            if !only_writes(&written) {
+                return None;
+            }
+            let one = ssa.insert_const(1)?;
+            let zero = ssa.insert_const(0)?;
+            let call = insert_call(ssa, Builtin::Strlen, &[dst], mem, block, &mut addr)?;
+            // The scan stops past the zero byte, which the count includes.
+            let scanned =
+                insert_expr(ssa, MOpcode::OpAdd, &[call.ret?, one], ptr_width, block, &mut addr)?;
+            let end = insert_expr(ssa, MOpcode::OpAdd, &[dst, scanned], ptr_width, block, &mut addr)?;
+            let left =
+                insert_expr(ssa, MOpcode::OpSub, &[count, scanned], ptr_width, block, &mut addr)?;
+            results.insert(di, end);
+            results.insert(cx, left);
+            // The last comparison is of two equal bytes.
+            for (reg, flag) in flags {
+                results.insert(reg, if flag == "zf" || flag == "pf" { one } else { zero });
+            }
+            (call.node, Builtin::Strlen)
+        }
+        _ => return None,
+    };
+
+    radeco_trace!("memory|{:?} `{}` replaced by {}", node, name, replaced.1.name());
+    for (reg, &(comment, _)) in rets.iter() {
+        ssa.replace_value(comment, results[&reg]);
+    }
+    ssa.remove_value(node);
+    Some(replaced)
+}
+
+/// Whether the direction flag read by the string instruction taking `args` is known to
+/// be clear, so that it counts addresses up. It is if the flag is a constant zero or
+/// still has its value on entry, where the calling convention requires it to be clear.
+fn direction_clear(ssa: &SSAStorage, args: &RegisterMap<SSAValue>) -> bool {
+    let df = match ssa.regfile.register_id_by_name("df") {
+        Some(df) => df,
+        None => return false,
+    };
+    let value = match args.get(df) {
+        Some(&value) => value,
+        None => return false,
+    };
+    if let Some(c) = ssa.constant(value) {
+        return c == 0;
+    }
+    ssa.entry_node()
+       .and_then(|entry| ssa.registers_in(entry))
+       .map_or(false, |state| utils::register_state_info(state, ssa)[df].0 == value)
+}
+
+/// The function a forward copy of `length` bytes, if known, from `src` to `dst` is a
+/// call to. It is `memcpy` if both are offsets from the same base far enough apart not
+/// to overlap. Otherwise it is `memmove`, which only differs from copying forward if
+/// `dst` starts inside the source, and there is none if it is known to.
+fn copy_builtin(ssa: &SSAStorage,
+                dst: SSAValue,
+                src: SSAValue,
+                length: Option<u64>)
+                -> Option<Builtin> {
+    let (dst_base, dst_offset) = split_address(ssa, dst);
+    let (src_base, src_offset) = split_address(ssa, src);
+    if dst_base != src_base {
+        return Some(Builtin::Memmove);
+    }
+    let distance = dst_offset as i128 - src_offset as i128;
+    match length {
+        Some(length) if distance.abs() >= length as i128 => Some(Builtin::Memcpy),
+        _ if distance <= 0 => Some(Builtin::Memmove),
+        _ => None,
+    }
+}
+
+/// Value at the exit of a byte scan loop of a value computed in it.
+#[derive(Clone, Copy, Debug)]
+enum Exit {
+    /// `init + step * len`, or `init + step * (len + 1)` for the incremented value.
+    Induction { init: SSAValue, step: u64, incremented: bool },
+    /// A value which does not change in the loop.
+    Invariant(SSAValue),
+    /// A value computed from the byte loaded only.
+    Constant(u64),
+}
+
+/// Replaces the loop branching back from `body` by a call to `strlen` if all it does
+/// is to load one byte after another until it finds a zero one.
+///
+/// The loop consists of `body` alone or of a header with nothing but phis, which
+/// flows into `body`. Values computed in it may only be used after it if their value
+/// at its exit follows from the length of the string.
+fn byte_scan(ssa: &mut SSAStorage, body: SSABlock) -> Option<(SSAValue, Builtin)> {
+    let edges = ssa.conditional_edges(body)?;
+    let blocks = ssa.conditional_blocks(body)?;
+    let is_header = |header: SSABlock| {
+        header == body ||
+        (ssa.unconditional_block(header) == Some(body) && ssa.exprs_in(header).is_empty() &&
+         ssa.preds_of(body) == vec![header] && ssa.phis_in(body).is_empty())
+    };
+    let (header, exit, back_edge, exit_on_true) = if is_header(blocks.true_side) {
+        (blocks.true_side, blocks.false_side, edges.true_side, false)
+    } else if is_header(blocks.false_side) {
+        (blocks.false_side, blocks.true_side, edges.false_side, true)
+    } else {
+        return None;
+    };
+    let preds = ssa.preds_of(header);
+    if exit == header || exit == body || preds.len() != 2 || !preds.contains(&body) {
+        return None;
+    }
+
+    let mut nodes = ssa.phis_in(header);
+    nodes.extend(ssa.exprs_in(body));
+    let in_loop = nodes.iter().cloned().collect::<HashSet<_>>();
+
+    let mut load = None;
+    for &node in &nodes {
+        match ssa.opcode(node) {
+            Some(MOpcode::OpLoad(8, _)) if load.is_none() => load = Some(node),
+            Some(MOpcode::OpLoad(..)) | Some(MOpcode::OpCustom(_)) | Some(MOpcode::OpCall) => {
+                return None
+            }
+            Some(ref op) if op.has_sideeffects() => return None,
+            _ => {}
+        }
+    }
+    let load = load?;
+
+    // Values which are computed from the previous iteration.
+    let mut exits = HashMap::new();
+    for phi in ssa.phis_in(header) {
+        let operands = ssa.operands_of(phi);
+        let outside = operands.iter().cloned().filter(|o| !in_loop.contains(o)).collect::<Vec<_>>();
+        let inside = operands.iter().cloned().filter(|o| in_loop.contains(o)).collect::<Vec<_>>();
+        if !outside.is_empty() && outside.iter().all(|&o| o == outside[0]) &&
+           inside.iter().all(|&o| o == phi) {
+            exits.insert(phi, Exit::Invariant(outside[0]));
+        } else if let (&[init], &[next]) = (&outside[..], &inside[..]) {
+            let step = match (ssa.opcode(next), &ssa.operands_of(next)[..]) {
+                (Some(MOpcode::OpAdd), &[a, b]) if a == phi => ssa.constant(b)?,
+                (Some(MOpcode::OpAdd), &[a, b]) if b == phi => ssa.constant(a)?,
+                _ => return None,
+            };
+            exits.insert(phi, Exit::Induction { init, step, incremented: false });
+            exits.insert(next, Exit::Induction { init, step, incremented: true });
+        } else {
+            return None;
+        }
+    }
+
+    // The byte loaded is at `base + init + offset` in the first iteration.
+    let operands = ssa.operands_of(load);
+    let (mem, address) = (*operands.get(0)?, *operands.get(1)?);
+    let mem = match exits.get(&mem) {
+        Some(&Exit::Invariant(mem)) => mem,
+        None if !in_loop.contains(&mem) => mem,
+        _ => return None,
+    };
+    let pointer = |node: SSAValue| match exits.get(&node) {
+        Some(&Exit::Induction { init, step: 1, incremented }) => Some((init, incremented)),
+        _ => None,
+    };
+    let (base, (init, offset)) = match pointer(address) {
+        Some(p) => (None, p),
+        None => {
+            match (ssa.opcode(address), &ssa.operands_of(address)[..]) {
+                (Some(MOpcode::OpAdd), &[a, b]) if !in_loop.contains(&b) => (Some(b), pointer(a)?),
+                (Some(MOpcode::OpAdd), &[a, b]) if !in_loop.contains(&a) => (Some(a), pointer(b)?),
+                _ => return None,
+            }
+        }
+    };
+
+    // The value of a loop node computed from the byte loaded only.
+    let byte_value = |node: SSAValue, byte: u128| if node == load {
+        Some(byte)
+    } else {
+        let seq = Sequence::collect(ssa, node, Some(load))?;
+        if seq.inputs.len() == 1 && seq.inputs.contains(&load) {
+            seq.evaluate(ssa, load, byte)
+        } else {
+            None
+        }
+    };
+    let selector = ssa.selector_in(body)?;
+    for byte in 0..256 {
+        let taken = byte_value(selector, byte)? != 0;
+        if (taken == exit_on_true) != (byte == 0) {
+            return None;
+        }
+    }
+
+    let states = [ssa.registers_in(header), ssa.registers_in(body)]
+        .iter()
+        .filter_map(|&s| s)
+        .collect::<HashSet<_>>();
+    let mut used = Vec::new();
+    for &node in &nodes {
+        let outside_uses = ssa.uses_of(node)
+                              .into_iter()
+                              .any(|u| !in_loop.contains(&u) && !states.contains(&u));
+        if outside_uses && !exits.contains_key(&node) {
+            exits.insert(node, Exit::Constant(byte_value(node, 0)? as u64));
+        }
+        if outside_uses {
+            used.push(node);
+        }
+    }
+    // The states at the start of the loop become those at the start of its only
+    // iteration.
+    let mut entry_states = Vec::new();
+    for &state in &states {
+        for (idx, operand) in ssa.sparse_operands_of(state) {
+            match exits.get(&operand) {
+                Some(&Exit::Invariant(value)) => entry_states.push((state, idx, operand, value)),
+                Some(&Exit::Induction { init, incremented: false, .. }) => {
+                    entry_states.push((state, idx, operand, init))
+                }
+                _ if in_loop.contains(&operand) => return None,
+                _ => {}
+            }
+        }
+    }
+
+    let mut addr = ssa.address(load)?;
+    let width = width_of(ssa, address).unwrap_or(64);
+    let one = ssa.insert_const(1)?;
+    let mut start = init;
+    if offset {
+        start = insert_expr(ssa, MOpcode::OpAdd, &[start, one], width, body, &mut addr)?;
+    }
+    if let Some(base) = base {
+        start = insert_expr(ssa, MOpcode::OpAdd, &[base, start], width, body, &mut addr)?;
+    }
+    let call = insert_call(ssa, Builtin::Strlen, &[start], mem, body, &mut addr)?;
+    let len = call.ret?;
+    let mut replacements = Vec::new();
+    for node in used {
+        let value = match exits[&node] {
+            Exit::Induction { init, step, incremented } => {
+                let width = width_of(ssa, node).unwrap_or(64);
+                let count = if incremented {
+                    insert_expr(ssa, MOpcode::OpAdd, &[len, one], width, body, &mut addr)?
+                } else {
+                    len
+                };
+                let distance = if step == 1 {
+                    count
+                } else {
+                    let step = ssa.insert_const(step)?;
+                    insert_expr(ssa, MOpcode::OpMul, &[count, step], width, body, &mut addr)?
+                };
+                insert_expr(ssa, MOpcode::OpAdd, &[init, distance], width, body, &mut addr)?
+            }
+            Exit::Invariant(value) => value,
+            Exit::Constant(c) => ssa.insert_const(c)?,
+        };
+        replacements.push((node, value));
+    }
+
+    radeco_trace!("memory|loop at {:?} replaced by strlen", body);
+    for (state, idx, old, new) in entry_states {
+        ssa.op_unuse(state, old);
+        ssa.op_use(state, idx, new);
+    }
+    ssa.remove_data_edge(back_edge);
+    for (node, value) in replacements {
+        if ssa.node_data(node).is_ok() {
+            ssa.replace_value(node, value);
+        }
+    }
+    for node in nodes {
+        if ssa.node_data(node).is_ok() {
+            ssa.remove_value(node);
+        }
+    }
+    Some((call.node, Builtin::Strlen))
+}
+
+/// What a run of stores writes.
+enum Contents {
+    /// The same byte everywhere.
+    Fill(u64),
+    /// The bytes at the given address, which are loaded by the run.
+    Copy(SSAValue, Vec<SSAValue>),
+}
+
+/// A run of stores to adjacent addresses, which together fill or copy a block of
+/// memory.
+struct Run {
+    stores: Vec<SSAValue>,
+    /// Address of the first byte written.
+    dst: SSAValue,
+    length: u64,
+    contents: Contents,
+}
+
+/// Splits an address into a base and a constant offset from it, the base being `None`
+/// for a constant address.
+fn split_address(ssa: &SSAStorage, address: SSAValue) -> (Option<SSAValue>, i64) {
+    if let Some(c) = ssa.constant(address) {
+        return (None, c as i64);
+    }
+    let operands = ssa.operands_of(address);
+    match (ssa.opcode(address), &operands[..]) {
+        (Some(MOpcode::OpAdd), &[a, b]) => {
+            match (ssa.constant(a), ssa.constant(b)) {
+                (_, Some(c)) => (Some(a), c as i64),
+                (Some(c), _) => (Some(b), c as i64),
+                _ => (Some(address), 0),
+            }
+        }
+        (Some(MOpcode::OpSub), &[a, b]) => {
+            match ssa.constant(b) {
+                Some(c) => (Some(a), (c as i64).wrapping_neg()),
+                None => (Some(address), 0),
+            }
+        }
+        _ => (Some(address), 0),
+    }
+}
+
+/// Replaces every run of stores in `block` which fills or copies at least `MIN_LENGTH`
+/// bytes by a call.
+fn store_runs(ssa: &mut SSAStorage, block: SSABlock) -> Vec<(SSAValue, Builtin)> {
+    // Sequences of stores, each to the memory written by the previous one.
+    let mut chains: Vec<Vec<SSAValue>> = Vec::new();
+    let mut ends = HashMap::new();
+    for node in ssa.exprs_in(block) {
+        match ssa.opcode(node) {
+            Some(MOpcode::OpStore(..)) => {}
+            _ => continue,
+        }
+        let mem = ssa.operands_of(node).get(0).cloned();
+        match mem.and_then(|mem| ends.remove(&mem)) {
+            Some(i) => {
+                chains[i].push(node);
+                ends.insert(node, i);
+            }
+            None => {
+                ends.insert(node, chains.len());
+                chains.push(vec![node]);
+            }
+        }
+    }
+
+    let mut calls = Vec::new();
+    for chain in chains {
+        let mut i = 0;
+        while i < chain.len() {
+            let run = (i + 2..chain.len() + 1)
+                .rev()
+                .filter_map(|j| store_run(ssa, &chain[i..j]).map(|run| (j, run)))
+                .next();
+            match run {
+                Some((j, run)) => {
+                    calls.extend(replace_run(ssa, run));
+                    i = j;
+                }
+                None => i += 1,
+            }
+        }
+    }
+    calls
+}
+
+/// A store of `width` bits to `offset` bytes past `base`.
+struct Part {
+    store: SSAValue,
+    address: SSAValue,
+    base: Option<SSAValue>,
+    offset: i64,
+    width: u16,
+    endian: Endian,
+    value: SSAValue,
+}
+
+/// Matches `stores`, each storing to the memory written by the previous one, as a run.
+fn store_run(ssa: &SSAStorage, stores: &[SSAValue]) -> Option<Run> {
+    let mem = *ssa.operands_of(stores[0]).get(0)?;
+    let mut parts = Vec::new();
+    for &store in stores {
+        let (width, endian) = match ssa.opcode(store) {
+            Some(MOpcode::OpStore(width, endian)) if width > 0 && width % 8 == 0 => {
+                (width, endian)
+            }
+            _ => return None,
+        };
+        let operands = ssa.operands_of(store);
+        let address = *operands.get(1)?;
+        let (base, offset) = split_address(ssa, address);
+        parts.push(Part {
+            store,
+            address,
+            base,
+            offset,
+            width,
+            endian,
+            value: *operands.get(2)?,
+        });
+    }
+    let base = parts[0].base;
+    if parts.iter().any(|p| p.base != base) {
+        return None;
+    }
+    let mut sorted = parts.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|p| p.offset);
+    let start = sorted[0].offset;
+    let mut length = 0;
+    for p in &sorted {
+        if p.offset != start.wrapping_add(length as i64) {
+            return None;
+        }
+        length += p.width as u64 / 8;
+    }
+    if length < MIN_LENGTH {
+        return None;
+    }
+
+    let fill = parts.iter()
+                    .map(|p| ssa.constant(p.value).filter(|&c| is_repeated_byte(c, p.width as u64 / 8)))
+                    .collect::<Option<Vec<_>>>();
+    let contents = match fill {
+        Some(ref values) if values.iter().all(|&c| c & 0xff == values[0] & 0xff) => {
+            Contents::Fill(values[0] & 0xff)
+        }
+        _ => {
+            // Each value is loaded from the source at the same distance from where it
+            // is stored, before anything but the run itself is stored.
+            let mut source = None;
+            for (k, p) in parts.iter().enumerate() {
+                if ssa.opcode(p.value) != Some(MOpcode::OpLoad(p.width, p.endian)) {
+                    return None;
+                }
+                let uses = ssa.uses_of(p.value).into_iter().collect::<HashSet<_>>();
+                let operands = ssa.operands_of(p.value);
+                let (load_mem, address) = (*operands.get(0)?, *operands.get(1)?);
+                if uses.len() != 1 || !uses.contains(&p.store) ||
+                   (load_mem != mem && !stores[..k].contains(&load_mem)) {
+                    return None;
+                }
+                let (src_base, src_offset) = split_address(ssa, address);
+                let distance = src_offset.wrapping_sub(p.offset);
+                match source {
+                    None => source = Some((src_base, distance)),
+                    Some(s) if s == (src_base, distance) => {}
+                    _ => return None,
+                }
+            }
+            let (src_base, distance) = source?;
+            if src_base == base && distance.abs() < length as i64 {
+                return None;
+            }
+            let src = value_address(ssa, sorted[0].value)?;
+            Contents::Copy(src, parts.iter().map(|p| p.value).collect())
+        }
+    };
+
+    // Nothing else may see the memory in between.
+    for (k, &store) in stores[..stores.len() - 1].iter().enumerate() {
+        for user in ssa.uses_of(store) {
+            let allowed = user == stores[k + 1] ||
+                          match contents {
+                              Contents::Copy(_, ref loads) => loads.contains(&user),
+                              Contents::Fill(_) => false,
+                          };
+            if !allowed {
+                return None;
+            }
+        }
+    }
+
+    Some(Run {
+        stores: stores.to_vec(),
+        dst: sorted[0].address,
+        length,
+        contents,
+    })
+}
+
+/// Address operand of a load.
+fn value_address(ssa: &SSAStorage, load: SSAValue) -> Option<SSAValue> {
+    ssa.operands_of(load).get(1).cloned()
+}
+
+fn replace_run(ssa: &mut SSAStorage, run: Run) -> Option<(SSAValue, Builtin)> {
+    let last = *run.stores.last()?;
+    let mem = *ssa.operands_of(run.stores[0]).get(0)?;
+    let block = ssa.block_for(last)?;
+    let mut addr = ssa.address(last)?;
+    let length = ssa.insert_const(run.length)?;
+    let (builtin, args, loads) = match run.contents {
+        Contents::Fill(byte) => (Builtin::Memset, vec![run.dst, ssa.insert_const(byte)?, length], Vec::new()),
+        Contents::Copy(src, loads) => {
+            (copy_builtin(ssa, run.dst, src, Some(run.length))?, vec![run.dst, src, length], loads)
+        }
+    };
+    let call = insert_call(ssa, builtin, &args, mem, block, &mut addr)?;
+
+    radeco_trace!("memory|{} stores before {:?} replaced by {}",
+                  run.stores.len(),
+                  last,
+                  builtin.name());
+    // Stores are never dead, so those replaced have to be removed here.
+    ssa.replace_value(last, call.mem?);
+    for &node in run.stores[..run.stores.len() - 1].iter().chain(&loads) {
+        ssa.remove_value(node);
+    }
+    Some((call.node, builtin))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::dce;
+    use middle::ir_reader::test_util::parse;
+    use middle::regfile::RegisterMap;
+
+    fn reg(ssa: &SSAStorage, name: &str) -> RegisterId {
+        ssa.regfile.register_id_by_name(name).unwrap()
+    }
+
+    // Turns the only call in `ssa` into the string instruction `name`, which reads and
+    // writes the same registers.
+    fn string_instruction(ssa: &mut SSAStorage, name: &str) {
+        let call = ssa.inorder_walk()
+                      .into_iter()
+                      .find(|&n| ssa.opcode(n) == Some(MOpcode::OpCall))
+                      .unwrap();
+        let (block, addr) = (ssa.block_for(call).unwrap(), ssa.address(call).unwrap());
+        let vt = ValueInfo::new_unresolved(WidthSpec::Unknown);
+        let custom = ssa.insert_op(MOpcode::OpCustom(name.to_owned()), vt, None).unwrap();
+        for (reg, arg) in utils::register_args(call, ssa).iter() {
+            ssa.op_use(custom, reg.to_u8() + 1, *arg);
+        }
+        for (reg, &(comment, _)) in utils::call_rets(call, ssa).iter() {
+            ssa.op_use(comment, reg.to_u8(), custom);
+        }
+        ssa.remove_value(call);
+        ssa.insert_into_block(custom, block, addr);
+    }
+
+    // The arguments of every call to `builtin`.
+    fn calls_to(ssa: &SSAStorage, builtin: Builtin) -> Vec<RegisterMap<SSAValue>> {
+        ssa.inorder_walk()
+           .into_iter()
+           .filter(|&n| ssa.opcode(n) == Some(MOpcode::OpCall))
+           .filter_map(|n| utils::call_info(n, ssa))
+           .filter(|info| ssa.constant(info.target) == Some(builtin.address()))
+           .map(|info| info.register_args)
+           .collect()
+    }
+
+    fn final_value(ssa: &SSAStorage, name: &str) -> SSAValue {
+        let state = ssa.registers_in(ssa.exit_node().unwrap()).unwrap();
+        let id = if name == "mem" { ssa.regfile.mem_id() } else { reg(ssa, name) };
+        utils::register_state_info(state, ssa)[id].0
+    }
+
+    fn opcodes(ssa: &SSAStorage) -> Vec<MOpcode> {
+        ssa.inorder_walk().into_iter().filter_map(|n| ssa.opcode(n)).collect()
+    }
+
+    // `body` stores to `%1` and loads from `%2`, `%3` being memory before it.
+    fn parse_stores(body: &str, mem: &str) -> SSAStorage {
+        parse(&format!("\
+define-fun main(unknown) -> unknown {{
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+        %2: $Unknown64 = $rsi;
+        %3: $Unknown0 = $mem;
+    bb_0x001000.0000(sz 0x20):
+{}
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $mem = {};
+}}
+",
+                       body,
+                       mem))
+    }
+
+    const MEMSET: &'static str = "\
+        [@0x001000.0000] %4: $Unknown0 = Store64(%3, %1, #x0);
+        [@0x001004.0000] %5: $Unknown64 = %1 + #x8;
+        [@0x001004.0001] %6: $Unknown0 = Store64(%4, %5, #x0);
+        [@0x001008.0000] %7: $Unknown64 = %1 + #x10;
+        [@0x001008.0001] %8: $Unknown0 = Store64(%6, %7, #x0);
+        [@0x00100C.0000] %9: $Unknown64 = %1 + #x18;
+        [@0x00100C.0001] %10: $Unknown0 = Store64(%8, %9, #x0);";
+
+    fn is_store(op: &MOpcode) -> bool {
+        if let MOpcode::OpStore(..) = *op { true } else { false }
+    }
+
+    fn is_load(op: &MOpcode) -> bool {
+        if let MOpcode::OpLoad(..) = *op { true } else { false }
+    }
+
+    #[test]
+    fn memset_unrolled() {
+        let mut ssa = parse_stores(MEMSET, "%10");
+        assert_eq!(run(&mut ssa).len(), 1);
+        dce::collect(&mut ssa);
+        let args = &calls_to(&ssa, Builtin::Memset)[0];
+        assert_eq!(ssa.comment(args[reg(&ssa, "rdi")]), Some("rdi".to_owned()));
+        assert_eq!(ssa.constant(args[reg(&ssa, "rsi")]), Some(0));
+        assert_eq!(ssa.constant(args[reg(&ssa, "rdx")]), Some(32));
+        assert!(!opcodes(&ssa).iter().any(is_store));
+        let mem = final_value(&ssa, "mem");
+        assert_eq!(ssa.comment(mem).map(|c| c.starts_with("mem@")), Some(true));
+    }
+
+    // Four loads from `src` plus `start` and 0 to 24 bytes, each stored to `%1` plus the
+    // latter.
+    fn copy_stores(src: &str, start: u64) -> SSAStorage {
+        let mut body = Vec::new();
+        for k in 0..4 {
+            let (n, offset) = (4 + 4 * k, 8 * k);
+            let mem = if k == 0 { 3 } else { n - 1 };
+            let addr = 0x1000 + offset;
+            body.push(format!("[@{:#08x}.0000] %{}: $Unknown64 = {} + #x{:x};",
+                              addr, n, src, start + offset));
+            body.push(format!("[@{:#08x}.0001] %{}: $Unknown64 = Load64(%{}, %{});",
+                              addr, n + 1, mem, n));
+            body.push(format!("[@{:#08x}.0002] %{}: $Unknown64 = %1 + #x{:x};",
+                              addr, n + 2, offset));
+            body.push(format!("[@{:#08x}.0003] %{}: $Unknown0 = Store64(%{}, %{}, %{});",
+                              addr, n + 3, mem, n + 2, n + 1));
+        }
+        parse_stores(&body.join("\n"), "%19")
+    }
+
+    #[test]
+    fn memmove_unrolled() {
+        let mut ssa = copy_stores("%2", 0);
+        assert_eq!(run(&mut ssa).len(), 1);
+        dce::collect(&mut ssa);
+        assert!(calls_to(&ssa, Builtin::Memcpy).is_empty());
+        let args = &calls_to(&ssa, Builtin::Memmove)[0];
+        assert_eq!(ssa.comment(args[reg(&ssa, "rdi")]), Some("rdi".to_owned()));
+        assert_eq!(ssa.opcode(args[reg(&ssa, "rsi")]), Some(MOpcode::OpAdd));
+        assert_eq!(ssa.constant(args[reg(&ssa, "rdx")]), Some(32));
+        let ops = opcodes(&ssa);
+        assert!(!ops.iter().any(is_store));
+        assert!(!ops.iter().any(is_load));
+    }
+
+    #[test]
+    fn memcpy_unrolled() {
+        // Only a copy between parts of the same object which do not overlap.
+        let mut ssa = copy_stores("%1", 0x40);
+        assert_eq!(run(&mut ssa).len(), 1);
+        dce::collect(&mut ssa);
+        assert!(calls_to(&ssa, Builtin::Memmove).is_empty());
+        let args = &calls_to(&ssa, Builtin::Memcpy)[0];
+        assert_eq!(ssa.constant(args[reg(&ssa, "rdx")]), Some(32));
+        assert!(!opcodes(&ssa).iter().any(is_load));
+
+        let mut ssa = copy_stores("%1", 0x10);
+        assert!(run(&mut ssa).is_empty());
+    }
+
+    #[test]
+    fn short_or_uneven_stores_are_kept() {
+        let short = MEMSET.lines().take(5).collect::<Vec<_>>().join("\n");
+        let uneven = MEMSET.replace("Store64(%6, %7, #x0)", "Store64(%6, %7, #x1)");
+        for &(body, mem, count) in &[(&*short, "%8", 3), (&*uneven, "%10", 4)] {
+            let mut ssa = parse_stores(body, mem);
+            assert!(run(&mut ssa).is_empty());
+            assert_eq!(opcodes(&ssa).iter().filter(|op| is_store(op)).count(), count);
+        }
+    }
+
+    const STRLEN: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+        %2: $Unknown0 = $mem;
+    bb_0x001000.0000(sz 0x4):
+        [@0x001000.0000] %3: $Unknown64 = %1 - #x1;
+        JMP 0x001004.0000
+    bb_0x001004.0000(sz 0x8):
+        %4: $Unknown64 = Phi(%3, %5);
+        [@0x001004.0000] %5: $Unknown64 = %4 + #x1;
+        [@0x001004.0001] %6: $Unknown8 = Load8(%2, %5);
+        [@0x001008.0000] %7: $Unknown1 = %6 == #x0;
+        JMP IF %7 0x00100C.0000 ELSE 0x001004.0000
+    bb_0x00100C.0000(sz 0x4):
+        [@0x00100C.0000] %8: $Unknown64 = %5 - %1;
+        JMP TO %8
+    exit-node:
+    final-register-state:
+        $rax = %8;
+        $mem = %2;
+}
+";
+
+    #[test]
+    fn strlen_loop() {
+        let mut ssa = parse(STRLEN);
+        let calls = run(&mut ssa);
+        dce::collect(&mut ssa);
+        assert_eq!(calls.len(), 1);
+        let args = &calls_to(&ssa, Builtin::Strlen)[0];
+        // The first byte loaded is at `%3 + 1`.
+        let start = args[reg(&ssa, "rdi")];
+        assert_eq!(ssa.opcode(start), Some(MOpcode::OpAdd));
+        assert_eq!(ssa.constant(ssa.operands_of(start)[1]), Some(1));
+        // `%5` is left pointing at the zero byte, `%3 + len + 1`.
+        let ret = final_value(&ssa, "rax");
+        assert_eq!(ssa.opcode(ret), Some(MOpcode::OpSub));
+        let end = ssa.operands_of(ret)[0];
+        assert_eq!(ssa.opcode(end), Some(MOpcode::OpAdd));
+        assert!(!opcodes(&ssa).iter().any(is_load));
+        assert!(ssa.inorder_walk().into_iter().all(|n| !ssa.is_phi(n)));
+        let body = ssa.block_for(calls[0].0).unwrap();
+        assert!(ssa.conditional_blocks(body).is_none());
+    }
+
+    #[test]
+    fn loop_with_a_store_is_kept() {
+        let il = STRLEN.replace("[@0x001008.0000] %7: $Unknown1 = %6 == #x0;",
+                                "[@0x001004.0002] %9: $Unknown0 = Store8(%2, %5, #x1);
+        [@0x001008.0000] %7: $Unknown1 = %6 == #x0;");
+        let mut ssa = parse(&il);
+        assert!(run(&mut ssa).is_empty());
+        assert!(ssa.inorder_walk().into_iter().any(|n| ssa.is_phi(n)));
+    }
+
+    #[test]
+    fn loop_not_ending_at_zero_is_kept() {
+        let mut ssa = parse(&STRLEN.replace("%6 == #x0", "%6 == #xa"));
+        assert!(run(&mut ssa).is_empty());
+    }
+
+    fn string_il(rets: &str, args: &str) -> String {
+        format!("\
+define-fun main(unknown) -> unknown {{
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+        %2: $Unknown64 = $rsi;
+        %3: $Unknown64 = $rcx;
+        %4: $Unknown0 = $mem;
+        %5: $Unknown64 = $rax;
+        %6: $Unknown1 = $df;
+    bb_0x001000.0000(sz 0x4):
+        [@0x001000.0000] ({}) = CALL #x0({});
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $rdi = %10;
+        $rcx = %12;
+}}
+",
+                rets,
+                args)
+    }
+
+    #[test]
+    fn rep_movsq() {
+        let mut ssa = parse(&string_il("%10: $Unknown64 = $rdi, %11: $Unknown64 = $rsi, \
+                                        %12: $Unknown64 = $rcx, %13: $Unknown0 = $mem",
+                                       "$rdi=%1, $rsi=%2, $rcx=%3, $df=%6, $mem=%4"));
+        string_instruction(&mut ssa, "rep movsq");
+        assert_eq!(run(&mut ssa).len(), 1);
+        dce::collect(&mut ssa);
+        // The source and destination may overlap.
+        assert!(calls_to(&ssa, Builtin::Memcpy).is_empty());
+        let args = &calls_to(&ssa, Builtin::Memmove)[0];
+        let len = args[reg(&ssa, "rdx")];
+        assert_eq!(ssa.opcode(len), Some(MOpcode::OpMul));
+        assert_eq!(ssa.constant(ssa.operands_of(len)[1]), Some(8));
+        let end = final_value(&ssa, "rdi");
+        assert_eq!(ssa.opcode(end), Some(MOpcode::OpAdd));
+        assert_eq!(ssa.operands_of(end)[1], len);
+        assert_eq!(ssa.constant(final_value(&ssa, "rcx")), Some(0));
+        assert!(!opcodes(&ssa).iter().any(|op| op.to_string().starts_with("OpCustom")));
+    }
+
+    #[test]
+    fn rep_stos() {
+        let rets = "%10: $Unknown64 = $rdi, %12: $Unknown64 = $rcx, %13: $Unknown0 = $mem";
+        let mut ssa = parse(&string_il(rets, "$rdi=%1, $rcx=%3, $rax=%5, $df=%6, $mem=%4"));
+        string_instruction(&mut ssa, "rep stosb");
+        run(&mut ssa);
+        let args = &calls_to(&ssa, Builtin::Memset)[0];
+        assert_eq!(ssa.comment(args[reg(&ssa, "rsi")]), Some("rax".to_owned()));
+        assert_eq!(ssa.comment(args[reg(&ssa, "rdx")]), Some("rcx".to_owned()));
+
+        // Wider stores are only a `memset` of a repeated byte.
+        let args = "$rdi=%1, $rcx=%3, $rax=#x2a2a2a2a, $df=%6, $mem=%4";
+        let mut ssa = parse(&string_il(rets, args));
+        string_instruction(&mut ssa, "rep stosd");
+        run(&mut ssa);
+        let args = &calls_to(&ssa, Builtin::Memset)[0];
+        assert_eq!(ssa.constant(args[reg(&ssa, "rsi")]), Some(0x2a));
+        let mut ssa = parse(&string_il(rets, &args.replace("2a2a2a2a", "2a2a2a2b")));
+        string_instruction(&mut ssa, "rep stosd");
+        assert!(run(&mut ssa).is_empty());
+    }
+
+    #[test]
+    fn repne_scasb() {
+        let rets = "%10: $Unknown64 = $rdi, %12: $Unknown64 = $rcx, %13: $Unknown1 = $zf, \
+                    %14: $Unknown1 = $cf";
+        let args = "$rdi=%1, $rcx=#xffffffffffffffff, $rax=#x0, $df=%6, $mem=%4";
+        let mut ssa = parse(&string_il(rets, args));
+        string_instruction(&mut ssa, "repne scasb");
+        assert_eq!(run(&mut ssa).len(), 1);
+        dce::collect(&mut ssa);
+        let args = &calls_to(&ssa, Builtin::Strlen)[0];
+        assert_eq!(ssa.comment(args[reg(&ssa, "rdi")]), Some("rdi".to_owned()));
+        let left = final_value(&ssa, "rcx");
+        assert_eq!(ssa.opcode(left), Some(MOpcode::OpSub));
+
+        // A scan bounded by the count is not `strlen`.
+        let mut ssa = parse(&string_il(rets, "$rdi=%1, $rcx=%3, $rax=#x0, $df=%6, $mem=%4"));
+        string_instruction(&mut ssa, "repne scasb");
+        assert!(run(&mut ssa).is_empty());
+    }
+
+    #[test]
+    fn direction_flag() {
+        // Only a forward store is `memset`, which it is if the flag is clear or still
+        // has its value on entry.
+        let rets = "%10: $Unknown64 = $rdi, %12: $Unknown64 = $rcx, %13: $Unknown0 = $mem";
+        for &(df, replaced) in &[("$df=#x0, ", true), ("$df=#x1, ", false), ("", false)] {
+            let args = format!("$rdi=%1, $rcx=%3, $rax=%5, {}$mem=%4", df);
+            let mut ssa = parse(&string_il(rets, &args));
+            string_instruction(&mut ssa, "rep stosb");
+            assert_eq!(run(&mut ssa).len(), replaced as usize, "{}", df);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Recovers operations which compilers expand into sequences of simpler ones.
+//!
+//! Rather than matching every variant a compiler may emit, the passes in this module
+//! collect the arithmetic computing a value from a single input and work out the
+//! function it computes from its operations and constants. A sequence which computes
+//! the same function as a single operation is replaced by that operation. Debug
+//! builds check the replacement by evaluating the sequence on sample inputs.
+//!
+//!     1. `magic_div` -- Divisions and remainders by constants.
+//!     2. `bits` -- Rotates, byte swaps and population counts.
+//!
+//! `memory` recognises inlined `memcpy`, `memmove`, `memset` and `strlen` instead, which
+//! are matched by the shape of the stores and loops they are written as.
+
+pub mod bits;
+pub mod magic_div;
+pub mod memory;
+
+use std::collections::{HashMap, HashSet};
+
+use middle::ir::{MOpcode, WidthSpec};
+use middle::ssa::ssa_traits::*;
+use middle::ssa::ssastorage::SSAStorage;
+
+type SSAValue = <SSAStorage as SSA>::ValueRef;
+
+/// Largest number of operations in a recognised sequence.
+const MAX_SEQUENCE: usize = 32;
+
+/// Replaces `node` by `opcode` applied to `operands`, keeping its width and its
+/// position in the block.
+fn replace(ssa: &mut SSAStorage,
+           node: SSAValue,
+           opcode: MOpcode,
+           operands: &[SSAValue])
+           -> Option<SSAValue> {
+    let width = width_of(ssa, node)?;
+    let (blk, addr) = match (ssa.block_for(node), ssa.address(node)) {
+        (Some(blk), Some(addr)) => (blk, addr),
+        _ => return None,
+    };
+    let vt = ValueInfo::new_scalar(WidthSpec::Known(width));
+    let op = ssa.insert_op(opcode, vt, None)?;
+    for (i, &operand) in operands.iter().enumerate() {
+        ssa.op_use(op, i as u8, operand);
+    }
+    ssa.replace_value(node, op);
+    ssa.insert_into_block(op, blk, addr);
+    Some(op)
+}
+
+fn width_of(ssa: &SSAStorage, node: SSAValue) -> Option<u16> {
+    ssa.node_data(node).ok()?.vt.width().get_width()
+}
+
+fn mask(width: u16) -> u128 {
+    if width >= 128 { !0 } else { (1 << width) - 1 }
+}
+
+fn sign_extend(value: u128, width: u16) -> i128 {
+    if width == 0 || width >= 128 {
+        return value as i128;
+    }
+    let shift = 128 - width as u32;
+    ((value << shift) as i128) >> shift
+}
+
+/// Operations a sequence may consist of, anything else is an input to it.
+fn is_arithmetic(opcode: &MOpcode) -> bool {
+    match *opcode {
+        MOpcode::OpAdd | MOpcode::OpSub | MOpcode::OpMul | MOpcode::OpDiv | MOpcode::OpMod |
+        MOpcode::OpAnd | MOpcode::OpOr | MOpcode::OpXor | MOpcode::OpNot | MOpcode::OpLsl |
+        MOpcode::OpLsr | MOpcode::OpRol | MOpcode::OpRor | MOpcode::OpNarrow(_) |
+        MOpcode::OpZeroExt(_) | MOpcode::OpSignExt(_) | MOpcode::OpSDiv(_) |
+        MOpcode::OpSMod(_) | MOpcode::OpBSwap(_) | MOpcode::OpPopCount | MOpcode::OpEq |
+        MOpcode::OpLt | MOpcode::OpGt | MOpcode::OpSLt(_) | MOpcode::OpSGt(_) |
+        MOpcode::OpMulHi(_) | MOpcode::OpSMulHi(_) => true,
+        _ => false,
+    }
+}
+
+/// Pseudo-random values to check a recognised sequence against, the same on every
+/// call.
+fn pseudo_random(count: usize) -> Vec<u128> {
+    let mut state = 0x2545f4914f6cdd1du64;
+    (0..count).map(|_| {
+                  state ^= state << 13;
+                  state ^= state >> 7;
+                  state ^= state << 17;
+                  state as u128
+              })
+              .collect()
+}
+
+/// The arithmetic operations computing `root`, operands first, and the values they
+/// depend on other than constants. `cut` is treated as an input.
+struct Sequence {
+    ops: Vec<SSAValue>,
+    inputs: HashSet<SSAValue>,
+}
+
+impl Sequence {
+    fn collect(ssa: &SSAStorage, root: SSAValue, cut: Option<SSAValue>) -> Option<Sequence> {
+        let mut seq = Sequence {
+            ops: Vec::new(),
+            inputs: HashSet::new(),
+        };
+        let mut visited = HashSet::new();
+        if seq.visit(ssa, root, cut, &mut visited) {
+            Some(seq)
+        } else {
+            None
+        }
+    }
+
+    fn visit(&mut self,
+             ssa: &SSAStorage,
+             node: SSAValue,
+             cut: Option<SSAValue>,
+             visited: &mut HashSet<SSAValue>)
+             -> bool {
+        if !visited.insert(node) || ssa.is_constant(node) {
+            return true;
+        }
+        match ssa.opcode(node) {
+            Some(ref op) if Some(node) != cut && is_arithmetic(op) => {
+                if self.ops.len() == MAX_SEQUENCE {
+                    return false;
+                }
+                for operand in ssa.operands_of(node) {
+                    if !self.visit(ssa, operand, cut, visited) {
+                        return false;
+                    }
+                }
+                self.ops.push(node);
+            }
+            _ => {
+                self.inputs.insert(node);
+            }
+        }
+        true
+    }
+
+    /// The values of the same width as `root` which it is computed from on their own,
+    /// each with the part of the sequence depending on it.
+    ///
+    /// Such a value is either an input to the whole sequence or computed by a part of
+    /// it, e.g. when the sequence starts with a `Narrow32`.
+    fn single_inputs(&self, ssa: &SSAStorage, root: SSAValue) -> Vec<(SSAValue, Sequence)> {
+        let width = width_of(ssa, root);
+        self.inputs
+            .iter()
+            .chain(self.ops.iter().rev())
+            .cloned()
+            .filter(|&n| n != root && width_of(ssa, n) == width)
+            .filter_map(|input| {
+                let seq = Sequence::collect(ssa, root, Some(input))?;
+                if seq.inputs.len() == 1 && seq.inputs.contains(&input) {
+                    Some((input, seq))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Value of the sequence when its only input is `value`, `None` if it cannot be
+    /// evaluated.
+    fn evaluate(&self, ssa: &SSAStorage, input: SSAValue, value: u128) -> Option<u128> {
+        let mut values = HashMap::new();
+        values.insert(input, value);
+        let mut result = None;
+        for &node in &self.ops {
+            let width = width_of(ssa, node)?;
+            if width > 128 {
+                return None;
+            }
+            let mut operands = Vec::new();
+            for op in ssa.operands_of(node) {
+                let v = match ssa.constant(op) {
+                    Some(c) => c as u128,
+                    None => *values.get(&op)?,
+                };
+                operands.push((v, width_of(ssa, op).unwrap_or(64)));
+            }
+            let (lhs, lhs_width) = *operands.get(0)?;
+            let rhs = operands.get(1).map_or(0, |o| o.0);
+            let v = match ssa.opcode(node)? {
+                MOpcode::OpAdd => lhs.wrapping_add(rhs),
+                MOpcode::OpSub => lhs.wrapping_sub(rhs),
+                MOpcode::OpMul => lhs.wrapping_mul(rhs),
+                MOpcode::OpMulHi(w) if w > 0 && w <= 64 => ((lhs & mask(w)) * (rhs & mask(w))) >> w,
+                MOpcode::OpSMulHi(w) if w > 0 && w <= 64 => {
+                    ((sign_extend(lhs, w) * sign_extend(rhs, w)) >> w) as u128
+                }
+                MOpcode::OpAnd => lhs & rhs,
+                MOpcode::OpOr => lhs | rhs,
+                MOpcode::OpXor => lhs ^ rhs,
+                MOpcode::OpNot => !lhs,
+                MOpcode::OpLsl => if rhs < 128 { lhs << rhs } else { 0 },
+                MOpcode::OpLsr => if rhs < 128 { lhs >> rhs } else { 0 },
+                MOpcode::OpRol | MOpcode::OpRor if width > 0 => {
+                    let lhs = lhs & mask(width);
+                    let amount = (rhs % width as u128) as u32;
+                    let amount = match ssa.opcode(node)? {
+                        MOpcode::OpRol => amount,
+                        _ => (width as u32 - amount) % width as u32,
+                    };
+                    if amount == 0 {
+                        lhs
+                    } else {
+                        (lhs << amount) | (lhs >> (width as u32 - amount))
+                    }
+                }
+                MOpcode::OpDiv => lhs.checked_div(rhs)?,
+                MOpcode::OpMod => lhs.checked_rem(rhs)?,
+                MOpcode::OpNarrow(_) | MOpcode::OpZeroExt(_) => lhs,
+                MOpcode::OpSignExt(_) => sign_extend(lhs, lhs_width) as u128,
+                MOpcode::OpSDiv(w) | MOpcode::OpSMod(w) => {
+                    let (lhs, rhs) = (sign_extend(lhs, w), sign_extend(rhs, w));
+                    if rhs == 0 {
+                        return None;
+                    }
+                    if let MOpcode::OpSDiv(_) = ssa.opcode(node)? {
+                        lhs.wrapping_div(rhs) as u128
+                    } else {
+                        lhs.wrapping_rem(rhs) as u128
+                    }
+                }
+                MOpcode::OpBSwap(w) if w > 0 && w <= 128 && w % 8 == 0 => {
+                    (lhs & mask(w)).swap_bytes() >> (128 - w as u32)
+                }
+                MOpcode::OpPopCount => (lhs & mask(lhs_width)).count_ones() as u128,
+                MOpcode::OpEq => (lhs == rhs & mask(lhs_width)) as u128,
+                MOpcode::OpLt => (lhs < rhs & mask(lhs_width)) as u128,
+                MOpcode::OpGt => (lhs > rhs & mask(lhs_width)) as u128,
+                MOpcode::OpSLt(w) => (sign_extend(lhs, w) < sign_extend(rhs, w)) as u128,
+                MOpcode::OpSGt(w) => (sign_extend(lhs, w) > sign_extend(rhs, w)) as u128,
+                _ => return None,
+            };
+            let v = v & mask(width);
+            values.insert(node, v);
+            result = Some(v);
+        }
+        result
+    }
+}
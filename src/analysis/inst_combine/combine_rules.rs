@@ -1,6 +1,7 @@
 use super::CombinableOpConstInfo as COCI;
 use super::CombinableOpInfo as COI;
 use middle::ir::MOpcode::*;
+use middle::ir::{width_mask, MOpcode};
 
 macro_rules! gen_rules {
     (
@@ -32,12 +33,29 @@ macro_rules! gen_rules {
 
     (@opcode +) => (OpAdd);
     (@opcode -) => (OpSub);
+    (@opcode *) => (OpMul);
     (@opcode &) => (OpAnd);
     (@opcode |) => (OpOr);
     (@opcode ^) => (OpXor);
 }
 
-pub(super) fn combine_opinfo(cur_opinfo: &COI, sub_opinfo: &COI) -> Option<COI> {
+/// Combines `sub_opinfo` followed by `cur_opinfo` into a single operation.
+/// `width` is the width of the operand of `sub_opinfo`, if known.
+/// Returns `Some(None)` if the two cancel out into a no-op.
+/// Returns `None` if no combination exists.
+pub(super) fn combine_opinfo(
+    cur_opinfo: &COI,
+    sub_opinfo: &COI,
+    width: Option<u16>,
+) -> Option<Option<COI>> {
+    combine_const_ops(cur_opinfo, sub_opinfo)
+        .or_else(|| combine_shifts(cur_opinfo, sub_opinfo))
+        .or_else(|| absorb_mask(cur_opinfo, sub_opinfo))
+        .map(Some)
+        .or_else(|| combine_unary_ops(cur_opinfo, sub_opinfo, width))
+}
+
+fn combine_const_ops(cur_opinfo: &COI, sub_opinfo: &COI) -> Option<COI> {
     // try to keep put the const on the left like `ssasort` does
     gen_rules!{
         . -> sub_opinfo -> cur_opinfo
@@ -77,6 +95,278 @@ pub(super) fn combine_opinfo(cur_opinfo: &COI, sub_opinfo: &COI) -> Option<COI>
             ((a^.)^b) => ((a^b)^.);
             (b^(.^a)) => ((a^b)^.);
             (b^(a^.)) => ((a^b)^.);
+            // mul/mul
+            ((.*a)*b) => ((a*b)*.);
+            ((a*.)*b) => ((a*b)*.);
+            (b*(.*a)) => ((a*b)*.);
+            (b*(a*.)) => ((a*b)*.);
+        }
+    }
+}
+
+/// Returns the constant of `info` if it is the commutative `opcode` applied
+/// with a constant on either side.
+fn commutative_const(info: &COI, opcode: MOpcode) -> Option<u64> {
+    match info.1 {
+        COCI::Left(c) | COCI::Right(c) if info.0 == opcode => Some(c),
+        _ => None,
+    }
+}
+
+/// Combines shifts with shifts in the same direction and with multiplications.
+/// Shift amounts are kept below 64, since `eval_binop` wraps larger ones.
+fn combine_shifts(cur_opinfo: &COI, sub_opinfo: &COI) -> Option<COI> {
+    match (sub_opinfo, cur_opinfo) {
+        // (x << a) << b, (x >> a) >> b
+        (&COI(OpLsl, COCI::Right(a)), &COI(OpLsl, COCI::Right(b)))
+        | (&COI(OpLsr, COCI::Right(a)), &COI(OpLsr, COCI::Right(b)))
+            if a < 64 && b < 64 && a + b < 64 =>
+        {
+            Some(COI(sub_opinfo.0.clone(), COCI::Right(a + b)))
+        }
+        // (x << a) * b
+        (&COI(OpLsl, COCI::Right(a)), _) if a < 64 => {
+            let b = commutative_const(cur_opinfo, OpMul)?;
+            Some(COI(OpMul, COCI::Left(b.wrapping_shl(a as u32))))
         }
+        // (x * a) << b
+        (_, &COI(OpLsl, COCI::Right(b))) if b < 64 => {
+            let a = commutative_const(sub_opinfo, OpMul)?;
+            Some(COI(OpMul, COCI::Left(a.wrapping_shl(b as u32))))
+        }
+        _ => None,
+    }
+}
+
+/// Drops the bits set by an `OpOr` or flipped by an `OpXor` that a following
+/// mask clears anyway, and masks that only clear bits which are already clear.
+fn absorb_mask(cur_opinfo: &COI, sub_opinfo: &COI) -> Option<COI> {
+    if let Some(b) = commutative_const(cur_opinfo, OpAnd) {
+        // (x | a) & b, (x ^ a) & b
+        let a =
+            commutative_const(sub_opinfo, OpOr).or_else(|| commutative_const(sub_opinfo, OpXor));
+        if let Some(a) = a {
+            if a & b == 0 {
+                return Some(COI(OpAnd, COCI::Left(b)));
+            }
+        }
+        // (x >> a) & b, (x << a) & b
+        let kept = match *sub_opinfo {
+            COI(OpLsr, COCI::Right(a)) if a < 64 => u64::max_value() >> a,
+            COI(OpLsl, COCI::Right(a)) if a < 64 => u64::max_value() << a,
+            _ => return None,
+        };
+        if kept & !b == 0 {
+            return Some(sub_opinfo.clone());
+        }
+    }
+    // (x & a) | b
+    if let Some(b) = commutative_const(cur_opinfo, OpOr) {
+        let a = commutative_const(sub_opinfo, OpAnd)?;
+        if a | b == u64::max_value() {
+            return Some(COI(OpOr, COCI::Left(b)));
+        }
+    }
+    None
+}
+
+/// Combines negations and chains of `OpNarrow`, `OpZeroExt` and `OpSignExt`.
+/// Some of the latter need the `width` of their operand.
+fn combine_unary_ops(
+    cur_opinfo: &COI,
+    sub_opinfo: &COI,
+    width: Option<u16>,
+) -> Option<Option<COI>> {
+    let unary = |opcode| Some(Some(COI(opcode, COCI::Unary)));
+    match (sub_opinfo, cur_opinfo) {
+        (&COI(OpNot, COCI::Unary), &COI(OpNot, COCI::Unary)) => Some(None),
+        // !x ^ c, !(x ^ c)
+        (&COI(OpNot, COCI::Unary), _) => {
+            let c = commutative_const(cur_opinfo, OpXor)?;
+            Some(Some(COI(OpXor, COCI::Left(!c))))
+        }
+        (_, &COI(OpNot, COCI::Unary)) => {
+            let c = commutative_const(sub_opinfo, OpXor)?;
+            Some(Some(COI(OpXor, COCI::Left(!c))))
+        }
+        (&COI(OpNarrow(_), COCI::Unary), &COI(OpNarrow(w), COCI::Unary)) => unary(OpNarrow(w)),
+        // the sign bit of a zero extended value is clear
+        (&COI(OpZeroExt(_), COCI::Unary), &COI(OpZeroExt(w), COCI::Unary))
+        | (&COI(OpZeroExt(_), COCI::Unary), &COI(OpSignExt(w), COCI::Unary)) => unary(OpZeroExt(w)),
+        (&COI(OpSignExt(_), COCI::Unary), &COI(OpSignExt(w), COCI::Unary)) => unary(OpSignExt(w)),
+        (&COI(OpZeroExt(_), COCI::Unary), &COI(OpNarrow(w), COCI::Unary))
+        | (&COI(OpSignExt(_), COCI::Unary), &COI(OpNarrow(w), COCI::Unary)) => {
+            let width = width?;
+            if w == width {
+                Some(None)
+            } else if w < width {
+                unary(OpNarrow(w))
+            } else if let OpZeroExt(_) = sub_opinfo.0 {
+                unary(OpZeroExt(w))
+            } else {
+                unary(OpSignExt(w))
+            }
+        }
+        // ZeroExt(Narrow(x)) back to the width of `x`
+        (&COI(OpNarrow(n), COCI::Unary), &COI(OpZeroExt(w), COCI::Unary)) if width == Some(w) => {
+            Some(Some(COI(OpAnd, COCI::Left(width_mask(n)))))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::simplify_opinfo;
+    use super::*;
+    use middle::ir::sign_extend;
+    use quickcheck::TestResult;
+
+    const WIDTHS: [u16; 4] = [8, 16, 32, 64];
+
+    /// Evaluates `info` on `x` of the given `width`, returning the result and
+    /// its width. Returns `None` if `info` is invalid for `width`.
+    fn eval(info: &COI, x: u64, width: u16) -> Option<(u64, u16)> {
+        let (res, res_width) = match info.1 {
+            COCI::Unary => match info.0 {
+                OpNot => (!x, width),
+                OpNarrow(w) if w < width => (x, w),
+                OpZeroExt(w) if w > width => (x, w),
+                OpSignExt(w) if w > width => (sign_extend(x, width) as u64, w),
+                _ => return None,
+            },
+            COCI::Left(c) => (info.0.eval_binop(c, x)?, width),
+            COCI::Right(c) => (info.0.eval_binop(x, c)?, width),
+        };
+        Some((res & width_mask(res_width), res_width))
+    }
+
+    /// Builds an operation from arbitrary values, preferring constants which
+    /// let the rules fire.
+    fn arbitrary_opinfo((op, c, left): (u8, u64, bool)) -> COI {
+        let c = match c % 4 {
+            0 => c,
+            1 => width_mask((c >> 8) as u16 % 65),
+            2 => !width_mask((c >> 8) as u16 % 65),
+            _ => (c >> 8) % 8,
+        };
+        let w = WIDTHS[(c % 4) as usize];
+        let coci = if left { COCI::Left(c) } else { COCI::Right(c) };
+        match op % 12 {
+            0 => COI(OpAdd, coci),
+            1 => COI(OpSub, coci),
+            2 => COI(OpMul, coci),
+            3 => COI(OpAnd, coci),
+            4 => COI(OpOr, coci),
+            5 => COI(OpXor, coci),
+            6 => COI(OpLsl, COCI::Right(c % 72)),
+            7 => COI(OpLsr, COCI::Right(c % 72)),
+            8 => COI(OpNot, COCI::Unary),
+            9 => COI(OpNarrow(w), COCI::Unary),
+            10 => COI(OpZeroExt(w), COCI::Unary),
+            _ => COI(OpSignExt(w), COCI::Unary),
+        }
+    }
+
+    /// Tests that a combined operation computes the same as the two operations
+    /// it was combined from.
+    #[quickcheck]
+    fn qc_combine_opinfo(x: u64, w: u8, sub: (u8, u64, bool), cur: (u8, u64, bool)) -> TestResult {
+        let width = WIDTHS[w as usize % WIDTHS.len()];
+        let x = x & width_mask(width);
+        let (sub_opinfo, cur_opinfo) = (arbitrary_opinfo(sub), arbitrary_opinfo(cur));
+        let expected = eval(&sub_opinfo, x, width).and_then(|(v, w)| eval(&cur_opinfo, v, w));
+        let expected = match expected {
+            Some(expected) => expected,
+            None => return TestResult::discard(),
+        };
+        let actual = match combine_opinfo(&cur_opinfo, &sub_opinfo, Some(width)) {
+            Some(Some(new_opinfo)) => eval(&new_opinfo, x, width),
+            Some(None) => Some((x, width)),
+            None => return TestResult::discard(),
+        };
+        if actual != Some(expected) {
+            println!("({:?} {:?}) on {:#x}:{}", sub_opinfo, cur_opinfo, x, width);
+            println!("  expected: {:?}", expected);
+            println!("  actual: {:?}", actual);
+            return TestResult::failed();
+        }
+        TestResult::passed()
+    }
+
+    /// Tests that a simplified operation computes the same as the original.
+    #[quickcheck]
+    fn qc_simplify_opinfo(x: u64, w: u8, op: (u8, u64, bool)) -> TestResult {
+        let width = WIDTHS[w as usize % WIDTHS.len()];
+        let x = x & width_mask(width);
+        let opinfo = arbitrary_opinfo(op);
+        let expected = match eval(&opinfo, x, width) {
+            Some(expected) => expected,
+            None => return TestResult::discard(),
+        };
+        let actual = match simplify_opinfo(&opinfo) {
+            Some(Some(new_opinfo)) => eval(&new_opinfo, x, width),
+            Some(None) => Some((x, width)),
+            None => return TestResult::discard(),
+        };
+        TestResult::from_bool(actual == Some(expected))
+    }
+
+    fn assert_combines(cur_opinfo: COI, sub_opinfo: COI, width: Option<u16>, expected: &str) {
+        let actual = combine_opinfo(&cur_opinfo, &sub_opinfo, width);
+        assert_eq!(format!("{:?}", actual), expected);
+    }
+
+    #[test]
+    fn combine_examples_test() {
+        // (x * 3) * 5
+        assert_combines(
+            COI(OpMul, COCI::Right(5)),
+            COI(OpMul, COCI::Right(3)),
+            None,
+            "Some(Some(-> (0xf OpMul .)))",
+        );
+        // (x << 2) << 3
+        assert_combines(
+            COI(OpLsl, COCI::Right(3)),
+            COI(OpLsl, COCI::Right(2)),
+            None,
+            "Some(Some(-> (. OpLsl 0x5)))",
+        );
+        // (x << 2) * 4
+        assert_combines(
+            COI(OpMul, COCI::Right(4)),
+            COI(OpLsl, COCI::Right(2)),
+            None,
+            "Some(Some(-> (0x10 OpMul .)))",
+        );
+        // ZeroExt64(Narrow8(x)) of a 64 bit x
+        assert_combines(
+            COI(OpZeroExt(64), COCI::Unary),
+            COI(OpNarrow(8), COCI::Unary),
+            Some(64),
+            "Some(Some(-> (0xff OpAnd .)))",
+        );
+        // the width of x is needed to combine the casts
+        assert_combines(
+            COI(OpZeroExt(64), COCI::Unary),
+            COI(OpNarrow(8), COCI::Unary),
+            None,
+            "None",
+        );
+        // !!x
+        assert_combines(
+            COI(OpNot, COCI::Unary),
+            COI(OpNot, COCI::Unary),
+            None,
+            "Some(None)",
+        );
+        // (x | 0xff00) & 0xff
+        assert_combines(
+            COI(OpAnd, COCI::Right(0xff)),
+            COI(OpOr, COCI::Right(0xff00)),
+            None,
+            "Some(Some(-> (0xff OpAnd .)))",
+        );
     }
 }
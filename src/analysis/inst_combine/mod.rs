@@ -1,5 +1,9 @@
 //! Combines sequences of arithmetic or logical instructions into single instructions.
+//!
+//! Operations whose result is known from the known bits of their operands are folded
+//! to constants, and masks which do not change their operand are dropped.
 
+use analysis::known_bits::{self, KnownBitsAnalysis};
 use middle::ir::MOpcode;
 use middle::ssa::ssa_traits::*;
 use middle::ssa::ssastorage::SSAStorage;
@@ -17,7 +21,7 @@ type SSAValue = <SSAStorage as SSA>::ValueRef;
 /// For every instruction, try to combine one of its operands into itself. This
 /// transforms linear data-dependency chains into trees.
 pub fn run(ssa: &mut SSAStorage) -> () {
-    Combiner::new().run(ssa);
+    Combiner::new(ssa).run(ssa);
 }
 
 /// Represents binary operations that are effectively unary because one of the
@@ -41,12 +45,15 @@ use self::CombinableOpConstInfo as COCI;
 struct Combiner {
     /// Nodes that could potentially be combined into another
     combine_candidates: HashMap<SSAValue, (SSAValue, CombinableOpInfo)>,
+    /// Known bits of the nodes from before any of them was replaced
+    known_bits: KnownBitsAnalysis,
 }
 
 impl Combiner {
-    fn new() -> Self {
+    fn new(ssa: &SSAStorage) -> Self {
         Combiner {
             combine_candidates: HashMap::new(),
+            known_bits: known_bits::analyze(ssa),
         }
     }
 
@@ -58,6 +65,7 @@ impl Combiner {
                 let blk = ssa.block_for(node).unwrap();
                 let addr = ssa.address(node).unwrap();
                 ssa.replace_value(node, repl_node);
+                self.known_bits.forget(node);
                 if !ssa.is_constant(repl_node) && ssa.address(repl_node).is_none() {
                     ssa.insert_into_block(repl_node, blk, addr);
                 }
@@ -70,6 +78,9 @@ impl Combiner {
     /// simplified, resulting in `new_node`.
     /// Returns `None` if no simplification can occur.
     fn visit_node(&mut self, cur_node: SSAValue, ssa: &mut SSAStorage) -> Option<SSAValue> {
+        if let Some(new_node) = self.visit_known_bits(cur_node, ssa) {
+            return Some(new_node);
+        }
         // bail if non-combinable
         let extracted = extract_opinfo(cur_node, ssa)?;
         match extracted {
@@ -115,6 +126,48 @@ impl Combiner {
         }
     }
 
+    /// Returns `Some(new_node)` if the known bits of `cur_node` make it a constant,
+    /// or if it is a mask which leaves its operand as it is.
+    fn visit_known_bits(&self, cur_node: SSAValue, ssa: &mut SSAStorage) -> Option<SSAValue> {
+        let (cur_opcode, _) = extract_opcode(cur_node, ssa)?;
+        if let MOpcode::OpConst(_) = cur_opcode {
+            return None;
+        }
+        if let Some(c_val) = self.known_bits.constant(cur_node) {
+            radeco_trace!("{:?} = {:#x} by known bits", cur_node, c_val);
+            return ssa.insert_const(c_val);
+        }
+        let is_and = match cur_opcode {
+            MOpcode::OpAnd => true,
+            MOpcode::OpOr => false,
+            _ => return None,
+        };
+        let (sub_node, c) = match ssa.operands_of(cur_node).as_slice() {
+            &[a, b] => match (ssa.constant(a), ssa.constant(b)) {
+                (None, Some(c)) => (a, c),
+                (Some(c), None) => (b, c),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let width = ssa.node_data(cur_node).ok()?.vt.width().get_width();
+        let sub_width = ssa.node_data(sub_node).ok()?.vt.width().get_width();
+        if width.is_none() || width != sub_width {
+            return None;
+        }
+        let redundant = if is_and {
+            self.known_bits.is_redundant_and(sub_node, c)
+        } else {
+            self.known_bits.is_redundant_or(sub_node, c)
+        };
+        if redundant {
+            radeco_trace!("{:?} ==> no-op by known bits", cur_node);
+            Some(sub_node)
+        } else {
+            None
+        }
+    }
+
     /// Returns `Left(new_node, new_sub_node, new_opinfo)` if `cur_opinfo`
     /// combined with an operand or if `cur_opinfo` was simplified.
     /// Returns `Right(new_node)` if `cur_opinfo` canceled with an
@@ -127,10 +180,11 @@ impl Combiner {
         sub_node: SSAValue,
         ssa: &mut SSAStorage,
     ) -> Option<Either<(SSAValue, SSAValue, CombinableOpInfo), SSAValue>> {
-        let (new_opinfo, new_sub_node) = self
-            .combine_opinfo(cur_opinfo, sub_node)
-            .map(|(oi, sn)| (Cow::Owned(oi), sn))
-            .unwrap_or((Cow::Borrowed(cur_opinfo), sub_node));
+        let (new_opinfo, new_sub_node) = match self.combine_opinfo(cur_opinfo, sub_node, ssa) {
+            Some((Some(oi), sn)) => (Cow::Owned(oi), sn),
+            Some((None, sn)) => return Some(Right(sn)),
+            None => (Cow::Borrowed(cur_opinfo), sub_node),
+        };
 
         // simplify
         match simplify_opinfo(&new_opinfo) {
@@ -166,13 +220,20 @@ impl Combiner {
 
     /// Tries to combine `sub_node` into `cur_opinfo`.
     /// Returns `None` if no combination exists.
+    /// Returns `Some((None, sub_sub_node))` if `cur_opinfo` cancels with
+    /// the operation computing `sub_node`.
     fn combine_opinfo(
         &self,
         cur_opinfo: &CombinableOpInfo,
         sub_node: SSAValue,
-    ) -> Option<(CombinableOpInfo, SSAValue)> {
+        ssa: &SSAStorage,
+    ) -> Option<(Option<CombinableOpInfo>, SSAValue)> {
         let &(sub_sub_node, ref sub_opinfo) = self.combine_candidates.get(&sub_node)?;
-        let new_opinfo = combine_rules::combine_opinfo(cur_opinfo, sub_opinfo)?;
+        let width = ssa
+            .node_data(sub_sub_node)
+            .ok()
+            .and_then(|nd| nd.vt.width().get_width());
+        let new_opinfo = combine_rules::combine_opinfo(cur_opinfo, sub_opinfo, width)?;
         radeco_trace!(
             "    combined ({:?} {:?}) into ({:?})",
             sub_opinfo,
@@ -229,7 +290,13 @@ fn simplify_opinfo(info: &CombinableOpInfo) -> Option<Option<CombinableOpInfo>>
         | COI(OpOr, COCI::Left(0))
         | COI(OpOr, COCI::Right(0))
         | COI(OpXor, COCI::Left(0))
-        | COI(OpXor, COCI::Right(0)) => Some(None),
+        | COI(OpXor, COCI::Right(0))
+        | COI(OpMul, COCI::Left(1))
+        | COI(OpMul, COCI::Right(1))
+        | COI(OpLsl, COCI::Right(0))
+        | COI(OpLsr, COCI::Right(0)) => Some(None),
+        COI(OpXor, COCI::Left(0xFFFFFFFFFFFFFFFF))
+        | COI(OpXor, COCI::Right(0xFFFFFFFFFFFFFFFF)) => Some(Some(COI(OpNot, COCI::Unary))),
         COI(OpAdd, COCI::Left(c)) | COI(OpAdd, COCI::Right(c)) if *c > u64::max_value() / 2 => {
             let c = OpSub.eval_binop(0, *c).unwrap();
             Some(Some(COI(OpSub, COCI::Right(c))))
@@ -302,3 +369,71 @@ impl fmt::Debug for CombinableOpInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir_reader::test_util::parse;
+
+    #[test]
+    fn known_bits_test() {
+        // `%5` is at most 0xffff, so neither the mask nor the comparison change
+        // anything.
+        let il = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+        %2: $Unknown64 = $rsi;
+    bb_0x001000.0000(sz 0x8):
+        [@0x001000.0000] %3: $Unknown64 = %1 & #xff;
+        [@0x001000.0001] %4: $Unknown64 = %2 & #xff00;
+        [@0x001000.0002] %5: $Unknown64 = %3 | %4;
+        [@0x001000.0003] %6: $Unknown64 = %5 & #xffff;
+        [@0x001000.0004] %7: $Unknown1 = %5 > #xffff;
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $rax = %6;
+        $rbx = %7;
+}
+";
+        let mut ssa = parse(il);
+        run(&mut ssa);
+
+        let count = |opcode: MOpcode| {
+            ssa.inorder_walk().filter(|&n| ssa.opcode(n) == Some(opcode.clone())).count()
+        };
+        assert_eq!(count(MOpcode::OpAnd), 2);
+        assert_eq!(count(MOpcode::OpGt), 0);
+    }
+
+    #[test]
+    fn known_low_bits_test() {
+        // The low four bits of `%3` are 0b0011, so `%4` is 3 and `%5` is `%3`.
+        let il = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+    bb_0x001000.0000(sz 0x8):
+        [@0x001000.0000] %2: $Unknown64 = %1 << #x4;
+        [@0x001000.0001] %3: $Unknown64 = %2 + #x3;
+        [@0x001000.0002] %4: $Unknown64 = %3 & #x7;
+        [@0x001000.0003] %5: $Unknown64 = %3 | #x1;
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $rax = %4;
+        $rbx = %5;
+}
+";
+        let mut ssa = parse(il);
+        run(&mut ssa);
+
+        let count = |opcode: MOpcode| {
+            ssa.inorder_walk().filter(|&n| ssa.opcode(n) == Some(opcode.clone())).count()
+        };
+        assert_eq!(count(MOpcode::OpAnd), 0);
+        assert_eq!(count(MOpcode::OpOr), 0);
+        assert_eq!(count(MOpcode::OpAdd), 1);
+    }
+}
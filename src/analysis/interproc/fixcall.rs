@@ -329,7 +329,7 @@ impl<'a> CallFixer<'a> {
                     Some(MOpcode::OpZeroExt(_)) => {
                         worklist.push_back(arg);    
                     }
-                    Some(MOpcode::OpLoad) => {
+                    Some(MOpcode::OpLoad(..)) => {
                         let operands = ssa.operands_of(arg);
                         if !exit_offset.contains_key(&operands[1]) {
                             continue;
@@ -377,7 +377,7 @@ impl<'a> CallFixer<'a> {
             let users = ssa.uses_of(*node);
             for reg_name in reg_names {
                 for user in &users {
-                    if Some(MOpcode::OpStore) == ssa.opcode(*user) {
+                    if let Some(MOpcode::OpStore(..)) = ssa.opcode(*user) {
                         let args = ssa.operands_of(*user);
                         if entry_offset.contains_key(&args[1]) {
                             let num = entry_offset.get(&args[1]).unwrap_or_else(|| {
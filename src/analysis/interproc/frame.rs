@@ -0,0 +1,716 @@
+// Copyright (c) 2015, The Radare Project. All rights preserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! This Module removes the code compilers wrap around the body of every function:
+//! stack protector checks, spills of callee-saved registers and frame setup.
+//!
+//! Registers are known to be callee-saved from the bindings marked by `CallFixer`,
+//! so it has to run on the module first. Stack slots are found with the offsets
+//! from `digstack`.
+//!
+//! Only the paths returning normally are kept, and on these every rewrite preserves
+//! the values of the function, so the SSA stays valid for the verifier.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use analysis::cse::ssasort::Sorter;
+use frontend::radeco_containers::{CGInfo, RadecoModule};
+use middle::ir::{MOpcode, WidthSpec};
+use middle::ssa::cfg_traits::{CFG, CFGMod};
+use middle::ssa::graph_traits::Graph;
+use middle::ssa::ssa_traits::{SSA, SSAMod, ValueInfo};
+use middle::ssa::ssastorage::SSAStorage;
+use middle::ssa::utils;
+
+use super::digstack;
+
+type LValueRef = <SSAStorage as SSA>::ValueRef;
+type LActionRef = <SSAStorage as CFG>::ActionRef;
+type LEdgeRef = <SSAStorage as CFG>::CFEdgeRef;
+
+/// Offsets in the thread local segment the stack protector canary is loaded from,
+/// `fs:0x28` on x86-64 and `gs:0x14` on x86.
+const CANARY_OFFSETS: [u64; 2] = [0x28, 0x14];
+
+/// Largest number of operations between a canary load and the branch checking it.
+const MAX_CHECK_DEPTH: usize = 8;
+
+
+#[derive(Debug)]
+pub struct FrameCleaner<'a> {
+    rmod: &'a mut RadecoModule,
+    sp_name: Option<String>,
+    bp_name: Option<String>,
+}
+
+impl<'a> FrameCleaner<'a> {
+    pub fn new(rmod: &'a mut RadecoModule, bp_name: Option<String>, sp_name: Option<String>) -> FrameCleaner<'a> {
+        FrameCleaner {
+            bp_name: bp_name,
+            sp_name: sp_name,
+            rmod: rmod,
+        }
+    }
+
+    /// Cleans every function of the RadecoModule.
+    pub fn run(&mut self) {
+        let matched_func_vec: Vec<u64> = self.rmod.functions.keys().cloned().collect();
+        for fn_addr in &matched_func_vec {
+            self.clean(fn_addr);
+        }
+    }
+
+    /// Cleans a single function of the RadecoModule.
+    pub fn clean(&mut self, rfn_addr: &u64) {
+        let fail_sites = self.stack_chk_fail_sites(rfn_addr);
+        let rfn = match self.rmod.functions.get_mut(rfn_addr) {
+            Some(rfn) => rfn,
+            None => {
+                radeco_err!("RadecoFunction Not Found!");
+                return;
+            }
+        };
+        radeco_trace!("FrameCleaner|RadecoFunction: {:?}", rfn.name);
+
+        let preserves: Vec<String> = rfn.bindings()
+                                        .into_iter()
+                                        .filter(|bind| bind.is_preserved())
+                                        .map(|bind| bind.name().to_owned())
+                                        .collect();
+        let ssa = rfn.ssa_mut();
+        let sp_name = self.sp_name.clone().or_else(|| {
+            ssa.regfile.get_name_by_alias(&"SP".to_owned()).map(|s| s.to_owned())
+        });
+        let bp_name = self.bp_name.clone().or_else(|| {
+            ssa.regfile.get_name_by_alias(&"BP".to_owned()).map(|s| s.to_owned())
+        });
+        clean(ssa,
+              &preserves,
+              &fail_sites,
+              &sp_name.unwrap_or(String::new()),
+              &bp_name.unwrap_or(String::new()));
+    }
+
+    // Addresses of the calls in the function to a function failing a stack protector
+    // check, according to the call graph.
+    fn stack_chk_fail_sites(&self, rfn_addr: &u64) -> HashSet<u64> {
+        let rfn = match self.rmod.functions.get(rfn_addr) {
+            Some(rfn) => rfn,
+            None => return HashSet::new(),
+        };
+        let callgraph = &self.rmod.callgraph;
+        callgraph.callees(rfn.cgid())
+                 .filter(|&(_, callee)| {
+                     let name = callgraph.node_weight(callee).and_then(|addr| {
+                         self.rmod.imports
+                             .get(addr)
+                             .map(|ifn| ifn.name.to_string())
+                             .or_else(|| self.rmod.functions.get(addr).map(|f| f.name.to_string()))
+                     });
+                     name.map_or(false, |name| is_stack_chk_fail(&name))
+                 })
+                 .map(|(csite, _)| csite)
+                 .collect()
+    }
+}
+
+/// Removes the stack protector checks, the spills of the callee-saved registers in
+/// `preserves` and the frame setup of a single function.
+///
+/// Calls failing a check are recognised by the name of their target, or by their
+/// address being in `fail_sites`.
+pub fn clean(ssa: &mut SSAStorage,
+             preserves: &[String],
+             fail_sites: &HashSet<u64>,
+             sp_name: &str,
+             bp_name: &str) {
+    // `digstack` expects the constant operand of an addition first.
+    {
+        let mut sorter = Sorter::new(ssa);
+        sorter.run();
+    }
+
+    remove_canary_checks(ssa, fail_sites, sp_name, bp_name);
+    remove_spills(ssa, preserves, sp_name, bp_name);
+    collapse_frame(ssa, sp_name, bp_name);
+}
+
+// Whether `name` is a function called when a stack protector check fails, given as a
+// symbol or as the disassembly of a call to it.
+fn is_stack_chk_fail(name: &str) -> bool {
+    let name = name.rsplit(|c: char| c == '.' || c.is_whitespace()).next().unwrap_or("");
+    name == "__stack_chk_fail" || name == "__stack_chk_fail_local"
+}
+
+fn is_fail_call(ssa: &SSAStorage, node: LValueRef, fail_sites: &HashSet<u64>) -> bool {
+    if ssa.opcode(node) != Some(MOpcode::OpCall) {
+        return false;
+    }
+    if ssa.address(node).map_or(false, |addr| fail_sites.contains(&addr.address)) {
+        return true;
+    }
+    utils::call_info(node, ssa)
+        .and_then(|info| ssa.comment(info.target))
+        .map_or(false, |target| is_stack_chk_fail(&target))
+}
+
+// Whether `node` loads from the offset in the thread local segment a canary is kept at.
+// This alone does not make it a canary, which is only known from the check it is used in.
+fn loads_canary_offset(ssa: &SSAStorage, node: LValueRef) -> bool {
+    match ssa.opcode(node) {
+        Some(MOpcode::OpLoad(..)) => {
+            let args = ssa.operands_of(node);
+            args.len() == 2 &&
+            ssa.constant(args[1]).map_or(false, |addr| CANARY_OFFSETS.contains(&addr))
+        }
+        _ => false,
+    }
+}
+
+// A stack protector check: the canary saved to the stack on entry is compared with the
+// one loaded again before returning, and unless they are equal the check branches to a
+// call failing it.
+struct CanaryCheck {
+    // Edge to the block failing the check.
+    fail_edge: LEdgeRef,
+    // Comparison of the two canaries, zero if they are equal.
+    compare: LValueRef,
+    // Store saving the canary to the stack.
+    save: LValueRef,
+}
+
+// Whether the stack addresses `a` and `b` are the same slot, computed alike or found at
+// the same offset by `digstack`.
+fn same_slot(ssa: &SSAStorage, offsets: &HashMap<LValueRef, i64>, a: LValueRef, b: LValueRef)
+             -> bool {
+    if a == b {
+        return true;
+    }
+    if ssa.opcode(a).is_some() && ssa.opcode(a) == ssa.opcode(b) &&
+       ssa.operands_of(a) == ssa.operands_of(b) {
+        return true;
+    }
+    match (stack_offset(ssa, offsets, a), stack_offset(ssa, offsets, b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+// The store saving a canary to the stack slot `reload` loads from.
+fn canary_save(ssa: &SSAStorage,
+               offsets: &HashMap<LValueRef, i64>,
+               reload: LValueRef)
+               -> Option<LValueRef> {
+    let slot = match ssa.opcode(reload) {
+        Some(MOpcode::OpLoad(..)) => *ssa.operands_of(reload).get(1)?,
+        _ => return None,
+    };
+    ssa.values()
+       .into_iter()
+       .filter(|&node| loads_canary_offset(ssa, node))
+       .flat_map(|canary| ssa.uses_of(canary).into_iter().map(move |user| (canary, user)))
+       .find(|&(canary, user)| {
+           let args = ssa.operands_of(user);
+           match ssa.opcode(user) {
+               Some(MOpcode::OpStore(..)) if args.len() == 3 && args[2] == canary => {
+                   same_slot(ssa, offsets, args[1], slot)
+               }
+               _ => false,
+           }
+       })
+       .map(|(_, save)| save)
+}
+
+// The comparison of a canary loaded again with the one saved to the stack, and the store
+// saving it, the selector `node` is computed from.
+fn canary_compare(ssa: &SSAStorage,
+                  offsets: &HashMap<LValueRef, i64>,
+                  node: LValueRef)
+                  -> Option<(LValueRef, LValueRef)> {
+    let mut worklist: VecDeque<(LValueRef, usize)> = VecDeque::new();
+    let mut visited: HashSet<LValueRef> = HashSet::new();
+    worklist.push_back((node, 0));
+    while let Some((node, depth)) = worklist.pop_front() {
+        if !visited.insert(node) {
+            continue;
+        }
+        let args = ssa.operands_of(node);
+        match ssa.opcode(node) {
+            Some(MOpcode::OpXor) | Some(MOpcode::OpSub) if args.len() == 2 => {
+                for &(canary, reload) in &[(args[0], args[1]), (args[1], args[0])] {
+                    if !loads_canary_offset(ssa, canary) {
+                        continue;
+                    }
+                    if let Some(save) = canary_save(ssa, offsets, reload) {
+                        return Some((node, save));
+                    }
+                }
+            }
+            _ => {}
+        }
+        if depth < MAX_CHECK_DEPTH && ssa.is_expr(node) {
+            worklist.extend(args.into_iter().map(|arg| (arg, depth + 1)));
+        }
+    }
+    None
+}
+
+fn reachable_blocks(ssa: &SSAStorage) -> HashSet<LActionRef> {
+    let mut reached: HashSet<LActionRef> = HashSet::new();
+    let mut worklist: Vec<LActionRef> = ssa.entry_node().into_iter().collect();
+    while let Some(block) = worklist.pop() {
+        if reached.insert(block) {
+            worklist.extend(ssa.succs_of(block));
+        }
+    }
+    reached
+}
+
+// Removes the branches to a failing stack protector check, the blocks only reachable
+// through them and the canary they compare.
+fn remove_canary_checks(ssa: &mut SSAStorage,
+                        fail_sites: &HashSet<u64>,
+                        sp_name: &str,
+                        bp_name: &str) {
+    let fail_blocks: HashSet<LActionRef> = ssa.blocks()
+                                              .into_iter()
+                                              .filter(|&block| {
+                                                  ssa.exprs_in(block)
+                                                     .into_iter()
+                                                     .any(|n| is_fail_call(ssa, n, fail_sites))
+                                              })
+                                              .collect();
+    if fail_blocks.is_empty() {
+        return;
+    }
+
+    let offsets = digstack::rounded_analysis(ssa, sp_name.to_owned(), bp_name.to_owned());
+    let mut checks = Vec::new();
+    for block in ssa.blocks() {
+        let failing = ssa.outgoing_edges(block).into_iter().find(|&(edge, _)| {
+            ssa.edge_info(edge).map_or(false, |info| fail_blocks.contains(&info.target))
+        });
+        let fail_edge = match failing {
+            Some((edge, _)) => edge,
+            None => continue,
+        };
+        let compare = ssa.selector_in(block).and_then(|sel| canary_compare(ssa, &offsets, sel));
+        if let Some((compare, save)) = compare {
+            radeco_trace!("FrameCleaner|Stack protector check in {:?}", block);
+            checks.push(CanaryCheck {
+                fail_edge: fail_edge,
+                compare: compare,
+                save: save,
+            });
+        }
+    }
+    if checks.is_empty() {
+        return;
+    }
+
+    let reachable = reachable_blocks(ssa);
+    for check in &checks {
+        ssa.remove_data_edge(check.fail_edge);
+    }
+    let reached = reachable_blocks(ssa);
+    for &block in reachable.difference(&reached) {
+        if Some(block) != ssa.exit_node() {
+            radeco_trace!("FrameCleaner|Remove block {:?}", block);
+            ssa.remove_block(block);
+        }
+    }
+
+    for check in checks {
+        remove_canary(ssa, &check);
+    }
+}
+
+// Replaces the comparison of the canaries of `check` by the result it has when the check
+// passes, which is now the only way out of the function, and removes the store saving
+// the canary, which nothing else reads. Other uses of the thread local segment are left
+// alone.
+fn remove_canary(ssa: &mut SSAStorage, check: &CanaryCheck) {
+    if ssa.block_for(check.compare).is_some() {
+        if let Some(zero) = insert_zero(ssa, check.compare) {
+            ssa.replace_value(check.compare, zero);
+        }
+    }
+    if ssa.block_for(check.save).is_some() {
+        let mem = ssa.operands_of(check.save)[0];
+        ssa.replace_value(check.save, mem);
+    }
+}
+
+// A zero as wide as `node`, placed next to it.
+fn insert_zero(ssa: &mut SSAStorage, node: LValueRef) -> Option<LValueRef> {
+    let zero = ssa.insert_const(0)?;
+    let width = ssa.node_data(node).ok()?.vt.width().get_width().unwrap_or(64);
+    if width == 64 {
+        return Some(zero);
+    }
+    let (block, addr) = (ssa.block_for(node)?, ssa.address(node)?);
+    let vt = ValueInfo::new_scalar(WidthSpec::Known(width));
+    let narrow = ssa.insert_op(MOpcode::OpNarrow(width), vt, None)?;
+    ssa.op_use(narrow, 0, zero);
+    ssa.insert_into_block(narrow, block, addr);
+    Some(narrow)
+}
+
+// The comment holding the value of register `name` on entry.
+fn entry_value(ssa: &SSAStorage, name: &str) -> Option<LValueRef> {
+    let reg_state = ssa.registers_in(ssa.entry_node()?)?;
+    ssa.operands_of(reg_state)
+       .into_iter()
+       .find(|&node| ssa.comment(node).map_or(false, |c| c == name))
+}
+
+// The offset of the stack address `addr`, which is either a value of SP or BP found by
+// `digstack` or a constant away from one.
+fn stack_offset(ssa: &SSAStorage, offsets: &HashMap<LValueRef, i64>, addr: LValueRef) -> Option<i64> {
+    if let Some(&offset) = offsets.get(&addr) {
+        return Some(offset);
+    }
+    let args = ssa.operands_of(addr);
+    if args.len() != 2 {
+        return None;
+    }
+    match ssa.opcode(addr)? {
+        MOpcode::OpAdd => {
+            match (ssa.constant(args[0]), ssa.constant(args[1])) {
+                (Some(num), None) => offsets.get(&args[1]).map(|base| base + num as i64),
+                (None, Some(num)) => offsets.get(&args[0]).map(|base| base + num as i64),
+                _ => None,
+            }
+        }
+        MOpcode::OpSub => {
+            let num = ssa.constant(args[1])?;
+            offsets.get(&args[0]).map(|base| base - num as i64)
+        }
+        _ => None,
+    }
+}
+
+// The loads giving `value` on every path, looking through phis. `None` if it may be
+// anything else than such a load or `entry`.
+fn restores_of(ssa: &SSAStorage, value: LValueRef, entry: LValueRef) -> Option<Vec<LValueRef>> {
+    let mut restores = Vec::new();
+    let mut worklist = vec![value];
+    let mut visited: HashSet<LValueRef> = HashSet::new();
+    while let Some(node) = worklist.pop() {
+        if node == entry || !visited.insert(node) {
+            continue;
+        }
+        if ssa.is_phi(node) {
+            worklist.extend(ssa.operands_of(node));
+            continue;
+        }
+        match ssa.opcode(node) {
+            Some(MOpcode::OpLoad(..)) => restores.push(node),
+            _ => return None,
+        }
+    }
+    if restores.is_empty() { None } else { Some(restores) }
+}
+
+fn width_of(ssa: &SSAStorage, node: LValueRef) -> Option<u16> {
+    ssa.node_data(node).ok().and_then(|d| d.vt.width().get_width())
+}
+
+// The stores of `entry` to the stack, with the offsets they store to.
+fn saves_of(ssa: &SSAStorage,
+            entry: LValueRef,
+            offsets: &HashMap<LValueRef, i64>)
+            -> Vec<(LValueRef, i64)> {
+    let mut saves = Vec::new();
+    for user in ssa.uses_of(entry) {
+        if let Some(MOpcode::OpStore(..)) = ssa.opcode(user) {
+            let args = ssa.operands_of(user);
+            if args.len() == 3 && args[2] == entry {
+                if let Some(offset) = stack_offset(ssa, offsets, args[1]) {
+                    saves.push((user, offset));
+                }
+            }
+        }
+    }
+    saves
+}
+
+// A register saved to the stack on entry and loaded back before returning.
+struct Spill {
+    entry: LValueRef,
+    save: LValueRef,
+    restores: Vec<LValueRef>,
+}
+
+// Replaces the restores of the registers in `preserves` by their values on entry and
+// removes the stores saving them. Like `CallFixer`, this assumes SP moves by the same
+// amount between every save and its restores, and gives up otherwise.
+fn remove_spills(ssa: &mut SSAStorage, preserves: &[String], sp_name: &str, bp_name: &str) {
+    let entry_offset = digstack::frontward_analysis(ssa, sp_name.to_owned(), bp_name.to_owned());
+    let exit_offset = digstack::backward_analysis(ssa, sp_name.to_owned());
+    let exit_values = match ssa.exit_node().and_then(|exit| ssa.registers_in(exit)) {
+        Some(reg_state) => utils::register_state_info(reg_state, ssa),
+        None => return,
+    };
+
+    let mut sp_offset: Option<i64> = None;
+    let mut spills = Vec::new();
+    for name in preserves {
+        let entry = match entry_value(ssa, name) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let width = width_of(ssa, entry);
+        let saves = saves_of(ssa, entry, &entry_offset);
+        if saves.len() != 1 {
+            radeco_trace!("FrameCleaner|{:?} is saved {} times", name, saves.len());
+            continue;
+        }
+        let (save, en_offset) = saves[0];
+
+        let exit = ssa.regfile
+                      .register_id_by_name(name)
+                      .and_then(|id| exit_values.get(id))
+                      .map(|&(node, _)| node);
+        let restores = match exit.and_then(|exit| restores_of(ssa, exit, entry)) {
+            Some(restores) => restores,
+            None => {
+                radeco_trace!("FrameCleaner|{:?} is not restored", name);
+                continue;
+            }
+        };
+        for &restore in &restores {
+            let args = ssa.operands_of(restore);
+            let ex_offset = args.get(1).and_then(|&addr| stack_offset(ssa, &exit_offset, addr));
+            let same_width = width_of(ssa, restore) == width;
+            match (ex_offset, sp_offset) {
+                (Some(ex_offset), None) if same_width => {
+                    sp_offset = Some(en_offset - ex_offset);
+                }
+                (Some(ex_offset), Some(off)) if same_width && en_offset - ex_offset == off => {}
+                _ => {
+                    radeco_warn!("FrameCleaner|{:?} is restored from another slot than saved",
+                                 name);
+                    return;
+                }
+            }
+        }
+        radeco_trace!("FrameCleaner|{:?} is saved at {}", name, en_offset);
+        spills.push(Spill {
+            entry: entry,
+            save: save,
+            restores: restores,
+        });
+    }
+
+    radeco_trace!("FrameCleaner|SP offset {:?}", sp_offset);
+    for spill in spills {
+        for restore in spill.restores {
+            ssa.replace_value(restore, spill.entry);
+        }
+        let mem = ssa.operands_of(spill.save)[0];
+        ssa.replace_value(spill.save, mem);
+    }
+}
+
+// Whether `node` moves a stack pointer by a constant, other than the entry one.
+fn is_frame_step(ssa: &SSAStorage, node: LValueRef, entry: LValueRef) -> bool {
+    match ssa.opcode(node) {
+        Some(MOpcode::OpAdd) | Some(MOpcode::OpSub) => {}
+        _ => return false,
+    }
+    let args = ssa.operands_of(node);
+    args.len() == 2 && !args.contains(&entry) && args.iter().any(|&arg| ssa.is_constant(arg))
+}
+
+// Computes every value of SP and BP directly from SP on entry, so the chains of
+// pushes, pops and adjustments building them, including the frame pointer setup,
+// become dead.
+fn collapse_frame(ssa: &mut SSAStorage, sp_name: &str, bp_name: &str) {
+    let entry = match entry_value(ssa, sp_name) {
+        Some(entry) => entry,
+        None => return,
+    };
+    let offsets = digstack::rounded_analysis(ssa, sp_name.to_owned(), bp_name.to_owned());
+    // The offsets are relative to the aligned SP instead if the function realigns it.
+    if offsets.get(&entry) != Some(&0) {
+        radeco_trace!("FrameCleaner|SP is realigned, {:?} has no offset", entry);
+        return;
+    }
+
+    let width = width_of(ssa, entry);
+    let mut steps: Vec<(LValueRef, i64)> =
+        offsets.into_iter()
+               .filter(|&(node, _)| is_frame_step(ssa, node, entry) && width_of(ssa, node) == width)
+               .collect();
+    steps.sort();
+    radeco_trace!("FrameCleaner|Frame steps: {:?}", steps);
+
+    for &(node, offset) in &steps {
+        if offset == 0 {
+            ssa.replace_value(node, entry);
+            continue;
+        }
+        let (block, addr) = match (ssa.block_for(node), ssa.address(node)) {
+            (Some(block), Some(addr)) => (block, addr),
+            _ => continue,
+        };
+        let vt = ssa.node_data(node).unwrap().vt;
+        let num = ssa.insert_const(offset.abs() as u64);
+        let (opcode, args) = match num {
+            Some(num) if offset > 0 => (MOpcode::OpAdd, [num, entry]),
+            Some(num) => (MOpcode::OpSub, [entry, num]),
+            None => continue,
+        };
+        let op = match ssa.insert_op(opcode, vt, None) {
+            Some(op) => op,
+            None => continue,
+        };
+        ssa.op_use(op, 0, args[0]);
+        ssa.op_use(op, 1, args[1]);
+        ssa.replace_value(node, op);
+        ssa.insert_into_block(op, block, addr);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json;
+    use r2api::structs::LFunctionInfo;
+    use std::fs::File;
+    use std::io::prelude::*;
+
+    use middle::regfile::SubRegisterFile;
+    use frontend::ssaconstructor::SSAConstruct;
+    use middle::dce;
+    use middle::ir_reader::test_util::{at, parse, register_profile};
+    use middle::ssa::verifier;
+
+    const STACK_PROTECTOR_INSTRUCTIONS: &'static str = "test_files/stack_protector_instructions.json";
+
+    // Checks the canary saved at rsp-0x10 before returning. The thread local segment is
+    // also read at the offset of the canary for another value, which is no canary.
+    const UNPAIRED: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rsp;
+        %2: $Unknown64 = $rdi;
+        %3: $Unknown0 = $mem;
+    bb_0x001000.0000(sz 0x18):
+        [@0x001000.0000] %4: $Unknown64 = Load64(%3, #x28);
+        [@0x001004.0000] %5: $Unknown64 = %1 - #x10;
+        [@0x001004.0001] %6: $Unknown0 = Store64(%3, %5, %4);
+        [@0x001008.0000] %7: $Unknown64 = Load64(%6, #x28);
+        [@0x001008.0001] %8: $Unknown64 = %7 ^ %2;
+        [@0x00100C.0000] %9: $Unknown64 = %1 - #x10;
+        [@0x00100C.0001] %10: $Unknown64 = Load64(%6, %9);
+        [@0x001010.0000] %11: $Unknown64 = Load64(%6, #x28);
+        [@0x001010.0001] %12: $Unknown64 = %10 - %11;
+        [@0x001010.0002] %13: $Unknown1 = %12 == #x0;
+        JMP IF %13 0x00101C.0000 ELSE 0x001018.0000
+    bb_0x001018.0000(sz 0x4):
+        [@0x001018.0000] (%14: $Unknown0 = $mem) = CALL #x2000($rdi=%2, $mem=%6);
+        JMP 0x00101C.0000
+    bb_0x00101C.0000(sz 0x4):
+        %15: $Unknown0 = Phi(%6, %14);
+        JMP TO %2
+    exit-node:
+    final-register-state:
+        $mem = %15;
+        $rax = %8;
+}
+";
+
+    fn construct() -> SSAStorage {
+        let mut instruction_file = File::open(STACK_PROTECTOR_INSTRUCTIONS).unwrap();
+        let mut s = String::new();
+        instruction_file.read_to_string(&mut s).unwrap();
+        let instructions: LFunctionInfo = serde_json::from_str(&*s).unwrap();
+        let mut ssa = SSAStorage::new();
+        {
+            let regfile = SubRegisterFile::new(&register_profile());
+            let mut constructor = SSAConstruct::new(&mut ssa, &regfile);
+            constructor.run(instructions.ops.unwrap().as_slice());
+        }
+        dce::collect(&mut ssa);
+        ssa
+    }
+
+    fn exit_value(ssa: &SSAStorage, name: &str) -> LValueRef {
+        let reg_state = ssa.registers_in(ssa.exit_node().unwrap()).unwrap();
+        let id = ssa.regfile.register_id_by_name(name).unwrap();
+        utils::register_state_info(reg_state, ssa)[id].0
+    }
+
+    fn count(ssa: &SSAStorage, f: &Fn(MOpcode) -> bool) -> usize {
+        ssa.values().into_iter().filter(|&n| ssa.opcode(n).map_or(false, f)).count()
+    }
+
+    fn is_store(op: MOpcode) -> bool {
+        if let MOpcode::OpStore(..) = op { true } else { false }
+    }
+
+    #[test]
+    fn canary_check_test() {
+        let mut ssa = construct();
+        clean(&mut ssa, &[], &HashSet::new(), "rsp", "rbp");
+        dce::collect(&mut ssa);
+
+        assert_eq!(count(&ssa, &|op| op == MOpcode::OpCall), 0);
+        let canaries = ssa.values().into_iter().filter(|&n| loads_canary_offset(&ssa, n)).count();
+        assert_eq!(canaries, 0);
+        // The pushes of RBP and RBX are left alone.
+        assert_eq!(count(&ssa, &is_store), 2);
+        verifier::verify(&ssa).expect("SSA verification failed");
+    }
+
+    #[test]
+    fn unpaired_canary_test() {
+        let mut ssa = parse(UNPAIRED);
+        let fail_sites = [0x1018].iter().cloned().collect();
+        clean(&mut ssa, &[], &fail_sites, "rsp", "rbp");
+        dce::collect(&mut ssa);
+
+        assert_eq!(count(&ssa, &|op| op == MOpcode::OpCall), 0);
+        assert_eq!(count(&ssa, &is_store), 0);
+        // Only the canary of the check is removed, the other load of its offset and the
+        // value computed from it are kept.
+        let loads = ssa.values().into_iter().filter(|&n| loads_canary_offset(&ssa, n));
+        assert_eq!(loads.collect::<Vec<_>>(), vec![at(&ssa, 0x1008, 0)]);
+        assert_eq!(count(&ssa, &|op| op == MOpcode::OpXor), 1);
+        verifier::verify(&ssa).expect("SSA verification failed");
+    }
+
+    #[test]
+    fn spill_and_frame_test() {
+        let mut ssa = construct();
+        let preserves = vec!["rbp".to_owned(), "rbx".to_owned()];
+        clean(&mut ssa, &preserves, &HashSet::new(), "rsp", "rbp");
+        dce::collect(&mut ssa);
+
+        assert_eq!(count(&ssa, &is_store), 0);
+        assert_eq!(ssa.comment(exit_value(&ssa, "rbx")), Some("rbx".to_owned()));
+        assert_eq!(ssa.comment(exit_value(&ssa, "rbp")), Some("rbp".to_owned()));
+
+        // RET pops the return address off the stack on entry.
+        let sp = exit_value(&ssa, "rsp");
+        assert_eq!(ssa.opcode(sp), Some(MOpcode::OpAdd));
+        let args = ssa.operands_of(sp);
+        assert_eq!(ssa.constant(args[0]), Some(8));
+        assert_eq!(ssa.comment(args[1]), Some("rsp".to_owned()));
+        verifier::verify(&ssa).expect("SSA verification failed");
+    }
+
+    #[test]
+    fn stack_chk_fail_names_test() {
+        assert!(is_stack_chk_fail("call sym.imp.__stack_chk_fail"));
+        assert!(is_stack_chk_fail("__stack_chk_fail_local"));
+        assert!(!is_stack_chk_fail("sym.imp.__stack_chk_fail_other"));
+    }
+}
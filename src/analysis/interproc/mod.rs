@@ -5,8 +5,9 @@
 // This file may not be copied, modified, or distributed
 // except according to those terms.
 
-mod digstack;
+pub(crate) mod digstack;
 pub mod summary;
 pub mod interproc;
 pub mod transfer;
 pub mod fixcall;
+pub mod frame;
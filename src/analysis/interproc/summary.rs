@@ -52,8 +52,8 @@ impl InterProcAnalysis for CallSummary {
                         let insert = uses.iter().any(|x| {
                             if let Ok(ref data) = ssa.node_data(*x) {
                                 match data.nt {
-                                    NodeType::Op(MOpcode::OpLoad) |
-                                    NodeType::Op(MOpcode::OpStore) => {
+                                    NodeType::Op(MOpcode::OpLoad(..)) |
+                                    NodeType::Op(MOpcode::OpStore(..)) => {
                                         // If the store is to a local variable, then it is still an
                                         // argument. Otherwise, it is part of preservation code and
                                         // must not be considered an argument to the function.
@@ -76,7 +76,7 @@ impl InterProcAnalysis for CallSummary {
                     let (insert_r, insert_m) = if let Ok(ref data) = ssa.node_data(*r) {
                         match data.nt {
                             NodeType::Comment(_) => (false, false),
-                            NodeType::Op(MOpcode::OpLoad) => {
+                            NodeType::Op(MOpcode::OpLoad(..)) => {
                                 (locals.contains(&i), true)
                             }
                             _ => (true, true),
@@ -0,0 +1,584 @@
+//! Sparse known-bits analysis over `SSAStorage`.
+//!
+//! Computes for every value of at most 64 bits which of its bits are known to be
+//! cleared and which are known to be set, whichever path led to it. Every value
+//! starts out as the empty set and is only ever joined with what its operation
+//! computes from its operands, so loops converge once each of their bits settled.
+//! Values computed by anything but the operations handled in `transfer` are unknown.
+//!
+//! `inst_combine` folds operations whose result is known and drops masks which do not
+//! change their operand, `idioms::magic_div` turns signed divisions of values known
+//! to be non-negative into unsigned ones.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use middle::ir::MOpcode;
+use middle::ssa::ssa_traits::*;
+use middle::ssa::ssastorage::SSAStorage;
+
+type SSAValue = <SSAStorage as SSA>::ValueRef;
+
+/// Bits of a value known to be cleared and known to be set. A bit which is both belongs
+/// to no value, so a value known like that has not been reached yet. `valueset` uses
+/// the same type for its value sets.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct KnownBits {
+    /// Bits that are cleared
+    pub zerobits: u64,
+    /// Bits that are set
+    pub onebits: u64,
+}
+
+impl KnownBits {
+    /// What is known about a value which is either `self` or `other`.
+    pub fn join(&self, other: &KnownBits) -> KnownBits {
+        KnownBits {
+            zerobits: self.zerobits & other.zerobits,
+            onebits: self.onebits & other.onebits,
+        }
+    }
+}
+
+/// The empty set, which every value is before anything reached it.
+const BOTTOM: KnownBits = KnownBits {
+    zerobits: !0,
+    onebits: !0,
+};
+
+/// Nothing is known, not even the bits above the width. Used for values which are
+/// not tracked.
+const NOTHING: KnownBits = KnownBits {
+    zerobits: 0,
+    onebits: 0,
+};
+
+/// Known bits of every value of at most 64 bits in an `SSAStorage`.
+#[derive(Debug)]
+pub struct KnownBitsAnalysis {
+    bits: HashMap<SSAValue, (u16, KnownBits)>,
+}
+
+/// Computes the known bits of every value in `ssa`.
+pub fn analyze(ssa: &SSAStorage) -> KnownBitsAnalysis {
+    let mut analysis = KnownBitsAnalysis { bits: HashMap::new() };
+    let mut worklist = ssa.values().into_iter().collect::<VecDeque<_>>();
+    let mut queued = worklist.iter().cloned().collect::<HashSet<_>>();
+    while let Some(node) = worklist.pop_front() {
+        queued.remove(&node);
+        let width = match width_of(ssa, node) {
+            Some(width) => width,
+            None => continue,
+        };
+        let old = analysis.bits.get(&node).map_or(BOTTOM, |&(_, bits)| bits);
+        let new = old.join(&analysis.transfer(ssa, node, width));
+        if new == old {
+            continue;
+        }
+        analysis.bits.insert(node, (width, new));
+        for user in ssa.uses_of(node) {
+            if queued.insert(user) {
+                worklist.push_back(user);
+            }
+        }
+    }
+    radeco_trace!("known_bits|{} values", analysis.bits.len());
+    analysis
+}
+
+impl KnownBitsAnalysis {
+    /// Bits of `value` known to be cleared and set. Bits above its width are known
+    /// to be cleared, nothing is known about values which are not tracked.
+    pub fn known_bits(&self, value: SSAValue) -> KnownBits {
+        self.bits.get(&value).map_or(NOTHING, |&(_, bits)| bits)
+    }
+
+    /// The value of `value`, if all of its bits are known.
+    pub fn constant(&self, value: SSAValue) -> Option<u64> {
+        let &(_, bits) = self.bits.get(&value)?;
+        fixed(bits)
+    }
+
+    /// Whether `value` is negative when read as a signed number, if that is known.
+    pub fn sign_bit(&self, value: SSAValue) -> Option<bool> {
+        let &(width, bits) = self.bits.get(&value)?;
+        let sign = 1 << (width - 1);
+        if is_empty(&bits) {
+            None
+        } else if bits.onebits & sign != 0 {
+            Some(true)
+        } else if bits.zerobits & sign != 0 {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Whether `value & mask` is `value`, i.e. every bit `mask` clears is known to
+    /// be cleared already.
+    pub fn is_redundant_and(&self, value: SSAValue, mask: u64) -> bool {
+        match self.bits.get(&value) {
+            Some(&(width, bits)) => !mask & low_bits(width as u32) & !bits.zerobits == 0,
+            None => false,
+        }
+    }
+
+    /// Whether `value | mask` is `value`, i.e. every bit `mask` sets is known to be
+    /// set already.
+    pub fn is_redundant_or(&self, value: SSAValue, mask: u64) -> bool {
+        match self.bits.get(&value) {
+            Some(&(width, bits)) => mask & low_bits(width as u32) & !bits.onebits == 0,
+            None => false,
+        }
+    }
+
+    /// Drops what is known about `value`, which has to be called before a removed
+    /// node's index is handed out to a new one.
+    pub fn forget(&mut self, value: SSAValue) {
+        self.bits.remove(&value);
+    }
+
+    /// Known bits of an operand, the empty set if nothing reached it yet.
+    fn operand(&self, ssa: &SSAStorage, value: SSAValue) -> KnownBits {
+        match width_of(ssa, value) {
+            Some(_) => self.bits.get(&value).map_or(BOTTOM, |&(_, bits)| bits),
+            None => NOTHING,
+        }
+    }
+
+    /// Known bits of what the operation computing `node` computes from what is known
+    /// about its operands so far.
+    fn transfer(&self, ssa: &SSAStorage, node: SSAValue, width: u16) -> KnownBits {
+        let operands = ssa.operands_of(node);
+        let known = operands.iter().map(|&op| self.operand(ssa, op)).collect::<Vec<_>>();
+        if ssa.is_phi(node) {
+            return truncate(known.iter().fold(BOTTOM, |acc, bits| acc.join(bits)), width);
+        }
+        let opcode = match ssa.opcode(node) {
+            Some(opcode) => opcode,
+            None => return unknown(width),
+        };
+        if known.iter().any(is_empty) {
+            return BOTTOM;
+        }
+        let (a, b) = match (known.get(0), known.get(1)) {
+            (Some(&a), Some(&b)) => (a, b),
+            (Some(&a), None) => (a, NOTHING),
+            _ => (NOTHING, NOTHING),
+        };
+        let bits = match opcode {
+            MOpcode::OpConst(c) => constant(c, width),
+            MOpcode::OpAnd => and(a, b),
+            MOpcode::OpOr => or(a, b),
+            MOpcode::OpXor => xor(a, b),
+            MOpcode::OpNot => not(a),
+            MOpcode::OpAdd => add(a, b, false),
+            MOpcode::OpSub => add(a, not(b), true),
+            MOpcode::OpMul => mul(a, b),
+            MOpcode::OpLsl => shl(a, b, width),
+            MOpcode::OpLsr => shr(a, b, width),
+            MOpcode::OpDiv if umin(b) != 0 => below(umax(a) / umin(b)),
+            MOpcode::OpMod if umin(b) != 0 => below(umax(a).min(umax(b) - 1)),
+            MOpcode::OpNarrow(_) | MOpcode::OpZeroExt(_) => a,
+            MOpcode::OpSignExt(_) => {
+                match operands.get(0).and_then(|&op| width_of(ssa, op)) {
+                    Some(from) => sign_extend(a, from),
+                    None => unknown(width),
+                }
+            }
+            MOpcode::OpEq => {
+                if (a.onebits & b.zerobits) | (a.zerobits & b.onebits) != 0 {
+                    constant(0, width)
+                } else if a.zerobits | a.onebits == !0 && b.zerobits | b.onebits == !0 {
+                    constant(1, width)
+                } else {
+                    unknown(width)
+                }
+            }
+            MOpcode::OpLt => compare(umax(a) < umin(b), umin(a) >= umax(b), width),
+            MOpcode::OpGt => compare(umin(a) > umax(b), umax(a) <= umin(b), width),
+            MOpcode::OpSLt(w) if w > 0 && w <= 64 => {
+                compare(smax(a, w) < smin(b, w), smin(a, w) >= smax(b, w), width)
+            }
+            MOpcode::OpSGt(w) if w > 0 && w <= 64 => {
+                compare(smin(a, w) > smax(b, w), smax(a, w) <= smin(b, w), width)
+            }
+            _ => unknown(width),
+        };
+        truncate(bits, width)
+    }
+}
+
+/// Width of `node` if it is tracked.
+fn width_of(ssa: &SSAStorage, node: SSAValue) -> Option<u16> {
+    match ssa.node_data(node).ok()?.vt.width().get_width() {
+        Some(width) if width > 0 && width <= 64 => Some(width),
+        _ => None,
+    }
+}
+
+/// The lowest `n` bits.
+fn low_bits(n: u32) -> u64 {
+    if n >= 64 { !0 } else { (1 << n) - 1 }
+}
+
+/// Known zero bits above the highest bit set in `max`.
+fn below(max: u64) -> KnownBits {
+    KnownBits {
+        zerobits: !low_bits(64 - max.leading_zeros()),
+        onebits: 0,
+    }
+}
+
+fn unknown(width: u16) -> KnownBits {
+    KnownBits {
+        zerobits: !low_bits(width as u32),
+        onebits: 0,
+    }
+}
+
+fn constant(value: u64, width: u16) -> KnownBits {
+    truncate(KnownBits {
+                 zerobits: !value,
+                 onebits: value,
+             },
+             width)
+}
+
+/// Known bits of the low `width` bits of `bits`.
+fn truncate(bits: KnownBits, width: u16) -> KnownBits {
+    let mask = low_bits(width as u32);
+    KnownBits {
+        zerobits: bits.zerobits | !mask,
+        onebits: bits.onebits & mask,
+    }
+}
+
+fn is_empty(bits: &KnownBits) -> bool {
+    bits.zerobits & bits.onebits != 0
+}
+
+/// Whether every bit of `bits` is known, and the value if so. Values which have not
+/// been reached have every bit both cleared and set, and no value.
+fn fixed(bits: KnownBits) -> Option<u64> {
+    if !is_empty(&bits) && bits.zerobits | bits.onebits == !0 {
+        Some(bits.onebits)
+    } else {
+        None
+    }
+}
+
+fn umin(bits: KnownBits) -> u64 {
+    bits.onebits
+}
+
+fn umax(bits: KnownBits) -> u64 {
+    !bits.zerobits
+}
+
+fn signed(value: u64, width: u16) -> i64 {
+    let shift = 64 - width as u32;
+    ((value << shift) as i64) >> shift
+}
+
+fn smin(bits: KnownBits, width: u16) -> i64 {
+    let sign = 1 << (width - 1);
+    signed(bits.onebits | (sign & !bits.zerobits), width)
+}
+
+fn smax(bits: KnownBits, width: u16) -> i64 {
+    let sign = 1 << (width - 1);
+    signed((!bits.zerobits & !sign) | (bits.onebits & sign), width)
+}
+
+/// A comparison which is known to hold if `always` and known not to if `never`.
+fn compare(always: bool, never: bool, width: u16) -> KnownBits {
+    if always {
+        constant(1, width)
+    } else if never {
+        constant(0, width)
+    } else {
+        unknown(width)
+    }
+}
+
+fn and(a: KnownBits, b: KnownBits) -> KnownBits {
+    KnownBits {
+        zerobits: a.zerobits | b.zerobits,
+        onebits: a.onebits & b.onebits,
+    }
+}
+
+fn or(a: KnownBits, b: KnownBits) -> KnownBits {
+    KnownBits {
+        zerobits: a.zerobits & b.zerobits,
+        onebits: a.onebits | b.onebits,
+    }
+}
+
+fn xor(a: KnownBits, b: KnownBits) -> KnownBits {
+    KnownBits {
+        zerobits: (a.zerobits & b.zerobits) | (a.onebits & b.onebits),
+        onebits: (a.zerobits & b.onebits) | (a.onebits & b.zerobits),
+    }
+}
+
+fn not(a: KnownBits) -> KnownBits {
+    KnownBits {
+        zerobits: a.onebits,
+        onebits: a.zerobits,
+    }
+}
+
+/// `a + b + carry`. A bit of the sum is known if the bits of both operands and the
+/// carry into it are, and the carry is known if the largest and the smallest sum
+/// agree on it.
+fn add(a: KnownBits, b: KnownBits, carry: bool) -> KnownBits {
+    let largest = (!a.zerobits).wrapping_add(!b.zerobits).wrapping_add(carry as u64);
+    let smallest = a.onebits.wrapping_add(b.onebits).wrapping_add(carry as u64);
+    let carry_zero = !(largest ^ a.zerobits ^ b.zerobits);
+    let carry_one = smallest ^ a.onebits ^ b.onebits;
+    let known = (a.zerobits | a.onebits) & (b.zerobits | b.onebits) & (carry_zero | carry_one);
+    KnownBits {
+        zerobits: !largest & known,
+        onebits: smallest & known,
+    }
+}
+
+/// Trailing zeros add up, the low bits known in both operands determine the low bits
+/// of the product, and a product which cannot overflow is at most the product of the
+/// largest operands.
+fn mul(a: KnownBits, b: KnownBits) -> KnownBits {
+    let zeros = (!a.zerobits).trailing_zeros() + (!b.zerobits).trailing_zeros();
+    let low = low_bits((!(a.zerobits | a.onebits))
+                           .trailing_zeros()
+                           .min((!(b.zerobits | b.onebits)).trailing_zeros()));
+    let product = a.onebits.wrapping_mul(b.onebits);
+    let mut bits = KnownBits {
+        zerobits: low_bits(zeros) | (!product & low),
+        onebits: product & low,
+    };
+    if let Some(max) = umax(a).checked_mul(umax(b)) {
+        bits.zerobits |= below(max).zerobits;
+    }
+    bits
+}
+
+/// Shifting by an unknown amount keeps the trailing zeros.
+fn shl(a: KnownBits, amount: KnownBits, width: u16) -> KnownBits {
+    match fixed(amount) {
+        Some(s) if s < width as u64 => KnownBits {
+            zerobits: (a.zerobits << s) | low_bits(s as u32),
+            onebits: a.onebits << s,
+        },
+        _ => KnownBits {
+            zerobits: low_bits((!a.zerobits).trailing_zeros()),
+            onebits: 0,
+        },
+    }
+}
+
+/// Shifting by an unknown amount keeps the leading zeros.
+fn shr(a: KnownBits, amount: KnownBits, width: u16) -> KnownBits {
+    match fixed(amount) {
+        Some(s) if s < width as u64 => KnownBits {
+            zerobits: (a.zerobits >> s) | !(!0u64 >> s),
+            onebits: a.onebits >> s,
+        },
+        _ => below(umax(a)),
+    }
+}
+
+/// Extends the sign bit of the low `from` bits of `a`.
+fn sign_extend(a: KnownBits, from: u16) -> KnownBits {
+    let sign = 1 << (from - 1);
+    let high = !low_bits(from as u32);
+    if a.zerobits & sign != 0 {
+        a
+    } else if a.onebits & sign != 0 {
+        KnownBits {
+            zerobits: a.zerobits & !high,
+            onebits: a.onebits | high,
+        }
+    } else {
+        KnownBits {
+            zerobits: a.zerobits & !high,
+            onebits: a.onebits,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir_reader::test_util::{at, parse};
+    use petgraph::graph::NodeIndex;
+
+    // Masks, shifts and extensions of an argument, and a loop counting in steps of
+    // four which wraps around at 256.
+    const IL: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+    bb_0x001000.0000(sz 0x10):
+        [@0x001000.0000] %2: $Unknown64 = %1 & #xff;
+        [@0x001000.0001] %3: $Unknown64 = %2 << #x4;
+        [@0x001000.0002] %4: $Unknown64 = %3 | #x3;
+        [@0x001000.0003] %5: $Unknown32 = Narrow32(%1);
+        [@0x001000.0004] %6: $Unknown64 = ZeroExt64(%5);
+        [@0x001000.0005] %7: $Unknown64 = %6 + %4;
+        [@0x001000.0006] %8: $Unknown1 = SLt64(%6, #x0);
+        [@0x001000.0007] %9: $Unknown64 = %4 | #x80;
+        [@0x001000.0008] %10: $Unknown8 = Narrow8(%9);
+        [@0x001000.0009] %11: $Unknown64 = SignExt64(%10);
+        JMP 0x001010.0000
+    bb_0x001010.0000(sz 0x8):
+        %12: $Unknown64 = Phi(#x0, %14);
+        [@0x001010.0000] %13: $Unknown64 = %12 + #x4;
+        [@0x001010.0001] %14: $Unknown64 = %13 & #xfc;
+        [@0x001014.0000] %15: $Unknown1 = %14 == #x0;
+        JMP IF %15 0x001018.0000 ELSE 0x001010.0000
+    bb_0x001018.0000(sz 0x4):
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $rax = %7;
+        $rbx = %8;
+        $rcx = %11;
+        $rdx = %12;
+}
+";
+
+    fn bits(zerobits: u64, onebits: u64) -> KnownBits {
+        KnownBits { zerobits, onebits }
+    }
+
+    /// Width of the values the transfer functions are checked on.
+    const WIDTH: u16 = 4;
+
+    /// Every way of knowing bits of a value of `WIDTH` bits.
+    fn patterns() -> Vec<KnownBits> {
+        let mut all = Vec::new();
+        for zerobits in 0..1 << WIDTH {
+            for onebits in (0..1 << WIDTH).filter(|&o| o & zerobits == 0) {
+                all.push(truncate(bits(zerobits, onebits), WIDTH));
+            }
+        }
+        all
+    }
+
+    fn contains(known: KnownBits, value: u64) -> bool {
+        value & known.zerobits == 0 && value & known.onebits == known.onebits
+    }
+
+    /// Checks that whatever `transfer` knows about `op` applied to values with the
+    /// known bits of its operands holds for every such value.
+    fn check(transfer: &Fn(KnownBits, KnownBits) -> KnownBits, op: &Fn(u64, u64) -> u64) {
+        let all = patterns();
+        for &a in &all {
+            for &b in &all {
+                let known = truncate(transfer(a, b), WIDTH);
+                for x in (0..1 << WIDTH).filter(|&x| contains(a, x)) {
+                    for y in (0..1 << WIDTH).filter(|&y| contains(b, y)) {
+                        let value = op(x, y) & low_bits(WIDTH as u32);
+                        assert!(contains(known, value),
+                                "{:?} and {:?} for {:#x} and {:#x}",
+                                a,
+                                b,
+                                x,
+                                y);
+                    }
+                }
+            }
+        }
+    }
+
+    fn known(value: u64, unknown: u64) -> KnownBits {
+        truncate(bits(!value & !unknown, value & !unknown), WIDTH)
+    }
+
+    #[test]
+    fn bitwise_transfer() {
+        check(&and, &|x, y| x & y);
+        check(&or, &|x, y| x | y);
+        check(&xor, &|x, y| x ^ y);
+
+        let (a, b) = (known(0b1100, 0b0001), known(0b0110, 0b1000));
+        assert_eq!(truncate(and(a, b), WIDTH), known(0b0100, 0b1000));
+        assert_eq!(truncate(or(a, b), WIDTH), known(0b1110, 0b0001));
+        assert_eq!(truncate(xor(a, b), WIDTH), known(0b1010, 0b1001));
+    }
+
+    #[test]
+    fn shift_transfer() {
+        check(&|a, b| shl(a, b, WIDTH), &|x, y| x << y);
+        check(&|a, b| shr(a, b, WIDTH), &|x, y| x >> y);
+
+        let a = known(0b0110, 0b0001);
+        assert_eq!(truncate(shl(a, known(2, 0), WIDTH), WIDTH), known(0b1000, 0b0100));
+        assert_eq!(truncate(shr(a, known(1, 0), WIDTH), WIDTH), known(0b0011, 0));
+        // An unknown amount keeps the trailing and the leading zeros.
+        let even = known(0b0100, 0b1010);
+        assert_eq!(truncate(shl(even, known(0, 0b1111), WIDTH), WIDTH), known(0, 0b1110));
+        assert_eq!(truncate(shr(even, known(0, 0b1111), WIDTH), WIDTH), known(0, 0b1111));
+        assert_eq!(truncate(shr(a, known(0, 0b1111), WIDTH), WIDTH), known(0, 0b0111));
+    }
+
+    #[test]
+    fn add_transfer() {
+        check(&|a, b| add(a, b, false), &|x, y| x + y);
+        check(&|a, b| add(a, not(b), true), &|x, y| x.wrapping_sub(y));
+        check(&mul, &|x, y| x * y);
+
+        // The carry out of the known low bits is known.
+        let a = known(0b0111, 0b1000);
+        assert_eq!(truncate(add(a, known(1, 0), false), WIDTH), known(0, 0b1000));
+        assert_eq!(truncate(add(a, known(0b0100, 0), false), WIDTH), known(0b0011, 0b1000));
+        // A carry out of an unknown bit is unknown, but stops at a bit known to be clear.
+        assert_eq!(truncate(add(known(0, 0b0001), known(1, 0), false), WIDTH),
+                   known(0, 0b0011));
+    }
+
+    #[test]
+    fn known_bits_test() {
+        let ssa = parse(IL);
+        let kb = analyze(&ssa);
+
+        assert_eq!(kb.known_bits(at(&ssa, 0x1000, 0)), bits(!0xff, 0));
+        assert_eq!(kb.known_bits(at(&ssa, 0x1000, 1)), bits(!0xff0, 0));
+        assert_eq!(kb.known_bits(at(&ssa, 0x1000, 2)), bits(!0xff3, 0x3));
+        assert_eq!(kb.known_bits(at(&ssa, 0x1000, 4)), bits(!0xffffffff, 0));
+        // At most 0xffffffff + 0xff3.
+        let sum = kb.known_bits(at(&ssa, 0x1000, 5));
+        assert_eq!(sum.zerobits, !0x1ffffffff);
+        assert_eq!(sum.onebits, 0);
+        assert_eq!(kb.constant(at(&ssa, 0x1000, 6)), Some(0));
+        let extended = at(&ssa, 0x1000, 9);
+        assert_eq!(kb.known_bits(extended), bits(0xc, 0xffffffffffffff83));
+        assert_eq!(kb.sign_bit(extended), Some(true));
+        assert_eq!(kb.sign_bit(at(&ssa, 0x1000, 4)), Some(false));
+        assert_eq!(kb.sign_bit(at(&ssa, 0x1000, 5)), Some(false));
+
+        let phi = ssa.inorder_walk().find(|&n| ssa.is_phi(n)).unwrap();
+        assert_eq!(kb.known_bits(phi), bits(!0xfc, 0));
+        assert_eq!(kb.constant(at(&ssa, 0x1014, 0)), None);
+
+        let masked = at(&ssa, 0x1010, 1);
+        assert!(kb.is_redundant_and(masked, 0xff));
+        assert!(kb.is_redundant_and(masked, 0xfffc));
+        assert!(!kb.is_redundant_and(masked, 0xf0));
+        assert!(kb.is_redundant_or(at(&ssa, 0x1000, 2), 0x3));
+        assert!(!kb.is_redundant_or(at(&ssa, 0x1000, 2), 0x4));
+    }
+
+    #[test]
+    fn unreached_value_is_unknown() {
+        let value = NodeIndex::new(1);
+        let mut kb = KnownBitsAnalysis { bits: HashMap::new() };
+        kb.bits.insert(value, (8, truncate(BOTTOM, 8)));
+        assert_eq!(kb.constant(value), None);
+        assert_eq!(kb.sign_bit(value), None);
+        kb.bits.insert(value, (8, constant(0x2a, 8)));
+        assert_eq!(kb.constant(value), Some(0x2a));
+        assert_eq!(kb.sign_bit(value), Some(false));
+    }
+}
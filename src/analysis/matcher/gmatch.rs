@@ -7,16 +7,30 @@
 //! Example of a query expression:
 //! (OpXor %1, %1) -> (OpConst(0))
 //!
-//! Example for comparison operation (JLE):
+//! Patterns are compiled into a [`Pattern`](struct.Pattern.html) once and may then be
+//! matched any number of times. Besides opcodes, bindings and `#x` constants they
+//! support:
 //!
-//! Example for matching x86 function prologue:
+//!  - Width guards: `%1:32` only binds values that are 32 bits wide and
+//!    `(OpAdd:32 %1, %2)` only matches a 32 bit addition.
+//!  - Constant predicates: `#pow2`, `#mask` (`2^n - 1`) and `#const` match any constant
+//!    with that property, `%1#pow2` also binds it.
+//!  - Commutativity: the operands of `OpAdd`, `OpMul`, `OpAnd`, `OpOr`, `OpXor` and
+//!    `OpEq` are matched in both orders, so `(OpAnd %1, #x1)` also finds
+//!    `(OpAnd #x1, %1)`.
+//!  - Repeated bindings: every occurrence of `%1` has to match the same subtree.
+//!
+//! Replacements use the same syntax and may build any number of new nodes, e.g.
+//! `(OpZeroExt64 (OpAnd:32 %1, #xff:32))`. A width suffix sets the width of the new
+//! node, otherwise it is inferred from the opcode and its operands.
+//!
+//! Unknown predicates, malformed constants and invalid widths are compile errors.
 //!
-
 use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::fmt;
 
-use middle::ir::{MAddress, MOpcode, WidthSpec};
+use middle::ir::{Endian, MAddress, MOpcode, WidthSpec};
 use middle::ssa::ssa_traits::{NodeType, SSA, SSAMod, SSAWalk, ValueInfo};
 
 #[derive(Clone, Debug)]
@@ -48,18 +62,238 @@ impl ParseToken {
     }
 }
 
+fn split_expression(expr: &str) -> ParseToken {
+    let mut depth = 0;
+    let mut sub_expr = Vec::new();
+    sub_expr.push(String::new());
+    for c in expr.chars() {
+        match c {
+            ' ' if depth == 1 => sub_expr.push(String::new()),
+            ',' if depth == 1 => { },
+            ')' => {
+                depth -= 1;
+                if depth <= 0 {
+                    continue
+                }
+                if let Some(s) = sub_expr.last_mut() {
+                    s.push(c);
+                }else {
+                    radeco_err!("parse error");
+                };
+            }
+            '(' => {
+                depth += 1;
+                if depth <= 1 {
+                    continue
+                }
+                if let Some(s) = sub_expr.last_mut() {
+                    s.push(c);
+                }else {
+                    radeco_err!("parse error");
+                };
+            }
+            _ => {
+                if let Some(s) = sub_expr.last_mut() {
+                    s.push(c);
+                }else {
+                    radeco_err!("parse error");
+                };
+            }
+        }
+    }
+    ParseToken {
+        parsed: sub_expr
+    }
+}
+
+/// Property a constant has to satisfy to be matched.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConstPred {
+    Any,
+    PowerOfTwo,
+    Mask,
+}
+
+impl ConstPred {
+    fn from_name(name: &str) -> Option<ConstPred> {
+        match name {
+            "const" => Some(ConstPred::Any),
+            "pow2" => Some(ConstPred::PowerOfTwo),
+            "mask" => Some(ConstPred::Mask),
+            _ => None,
+        }
+    }
+
+    pub fn holds(&self, value: u64) -> bool {
+        match *self {
+            ConstPred::Any => true,
+            ConstPred::PowerOfTwo => value.is_power_of_two(),
+            ConstPred::Mask => value != 0 && value & value.wrapping_add(1) == 0,
+        }
+    }
+}
+
+/// A compiled find or replace expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern {
+    /// `%<i>`, with an optional width guard and constant predicate.
+    Bind(String, Option<u16>, Option<ConstPred>),
+    /// `#x<hex>`, with an optional width.
+    Const(u64, Option<u16>),
+    /// `#pow2`, `#mask` or `#const`.
+    Pred(ConstPred),
+    /// An opcode (or comment) with an optional width and its operands.
+    Node(String, Option<u16>, Vec<Pattern>),
+}
+
+// Splits `<token>:<width>`.
+fn split_width(t: &str) -> Result<(&str, Option<u16>), String> {
+    match t.find(':') {
+        Some(i) => {
+            let width = u16::from_str_radix(&t[i + 1..], 10)
+                .map_err(|_| format!("invalid width in `{}`", t))?;
+            Ok((&t[..i], Some(width)))
+        }
+        None => Ok((t, None)),
+    }
+}
+
+impl Pattern {
+    pub fn compile(expr: &str) -> Result<Pattern, String> {
+        let t = split_expression(expr.trim());
+        let args = (1..t.len() + 1)
+            .filter_map(|i| t.op(i))
+            .map(|e| Pattern::compile(&e))
+            .collect::<Result<Vec<_>, _>>()?;
+        let current = t.current().unwrap_or_default();
+        let predicate = |name: &str| {
+            ConstPred::from_name(name)
+                .ok_or_else(|| format!("unknown constant predicate `#{}`", name))
+        };
+        if current.starts_with('%') {
+            let (binding, pred) = match current.find('#') {
+                Some(i) => (&current[..i], Some(predicate(&current[i + 1..])?)),
+                None => (&current[..], None),
+            };
+            let (name, width) = split_width(binding)?;
+            Ok(Pattern::Bind(name.to_owned(), width, pred))
+        } else if current.starts_with("#x") {
+            let (value, width) = split_width(&current[2..])?;
+            let value = u64::from_str_radix(value, 16)
+                .map_err(|_| format!("invalid hex integer in `{}`", current))?;
+            Ok(Pattern::Const(value, width))
+        } else if current.starts_with('#') {
+            Ok(Pattern::Pred(predicate(&current[1..])?))
+        } else {
+            let (op, width) = split_width(&current)?;
+            // Only checks the width an opcode ends in, other tokens are comments.
+            token_to_opcode(op)?;
+            Ok(Pattern::Node(op.to_owned(), width, args))
+        }
+    }
+
+    fn is_commutative(&self) -> bool {
+        match *self {
+            Pattern::Node(ref op, _, ref args) if args.len() == 2 => {
+                match op.as_str() {
+                    "OpAdd" | "OpMul" | "OpAnd" | "OpOr" | "OpXor" | "OpEq" => true,
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Opcode named by a pattern token, `None` for comments. Fails on opcodes with an
+/// invalid width or constant.
+pub(crate) fn token_to_opcode(t: &str) -> Result<Option<MOpcode>, String> {
+    let width = |prefix: &str| {
+        u16::from_str_radix(&t[prefix.len()..], 10)
+            .map_err(|_| format!("invalid width in `{}`", t))
+    };
+    let opcode = if t.starts_with("#x") {
+        let value = u64::from_str_radix(&t[2..], 16)
+            .map_err(|_| format!("invalid hex integer in `{}`", t))?;
+        MOpcode::OpConst(value)
+    } else if t.starts_with("OpNarrow") {
+        MOpcode::OpNarrow(width("OpNarrow")?)
+    } else if t.starts_with("OpLoadBE") {
+        MOpcode::OpLoad(width("OpLoadBE")?, Endian::Big)
+    } else if t.starts_with("OpLoad") {
+        MOpcode::OpLoad(width("OpLoad")?, Endian::Little)
+    } else if t.starts_with("OpStoreBE") {
+        MOpcode::OpStore(width("OpStoreBE")?, Endian::Big)
+    } else if t.starts_with("OpStore") {
+        MOpcode::OpStore(width("OpStore")?, Endian::Little)
+    } else if t.starts_with("OpZeroExt") {
+        MOpcode::OpZeroExt(width("OpZeroExt")?)
+    } else if t.starts_with("OpSignExt") {
+        MOpcode::OpSignExt(width("OpSignExt")?)
+    } else if t.starts_with("OpSGt") {
+        MOpcode::OpSGt(width("OpSGt")?)
+    } else if t.starts_with("OpSLt") {
+        MOpcode::OpSLt(width("OpSLt")?)
+    } else if t.starts_with("OpBSwap") {
+        MOpcode::OpBSwap(width("OpBSwap")?)
+    } else if t.starts_with("OpSDiv") {
+        MOpcode::OpSDiv(width("OpSDiv")?)
+    } else if t.starts_with("OpSMod") {
+        MOpcode::OpSMod(width("OpSMod")?)
+    } else if t.starts_with("OpMulHi") {
+        MOpcode::OpMulHi(width("OpMulHi")?)
+    } else if t.starts_with("OpSMulHi") {
+        MOpcode::OpSMulHi(width("OpSMulHi")?)
+    } else {
+        match t {
+            "OpAdd" => MOpcode::OpAdd,
+            "OpSub" => MOpcode::OpSub,
+            "OpMul" => MOpcode::OpMul,
+            "OpDiv" => MOpcode::OpDiv,
+            "OpMod" => MOpcode::OpMod,
+            "OpAnd" => MOpcode::OpAnd,
+            "OpOr" => MOpcode::OpOr,
+            "OpXor" => MOpcode::OpXor,
+            "OpNot" => MOpcode::OpNot,
+            "OpEq" => MOpcode::OpEq,
+            "OpGt" => MOpcode::OpGt,
+            "OpLt" => MOpcode::OpLt,
+            "OpLsl" => MOpcode::OpLsl,
+            "OpLsr" => MOpcode::OpLsr,
+            "OpRol" => MOpcode::OpRol,
+            "OpRor" => MOpcode::OpRor,
+            "OpPopCount" => MOpcode::OpPopCount,
+            "OpCall" => MOpcode::OpCall,
+            _ => return Ok(None),
+        }
+    };
+    Ok(Some(opcode))
+}
+
 #[derive(Clone, Debug)]
 pub struct Match<T: Clone + fmt::Debug> {
     root: T,
     bindings: Vec<(String, T)>,
 }
 
+impl<T: Clone + fmt::Debug> Match<T> {
+    /// Root of the matched subtree.
+    pub fn root(&self) -> T {
+        self.root.clone()
+    }
+
+    /// Value bound to `name`, e.g. `"%1"`.
+    pub fn binding(&self, name: &str) -> Option<T> {
+        self.bindings.iter().find(|b| b.0 == name).map(|b| b.1.clone())
+    }
+}
+
 pub struct GraphMatcher<'a, I, S>
     where I: Iterator<Item = S::ValueRef>,
           S: 'a + SSA + SSAMod + SSAWalk<I>
 {
     ssa: &'a mut S,
-    seen: HashMap<String, ParseToken>,
+    seen: HashMap<String, Pattern>,
     hash_subtrees: HashMap<S::ValueRef, String>,
     foo: PhantomData<I>,
 }
@@ -100,48 +334,18 @@ where I: Iterator<Item=S::ValueRef>,
     }
 
     // Some notes on parsing the expression (find / replace patterns).
+    #[allow(dead_code)]
     fn parse_expression(&self, expr: &str) -> ParseToken {
-        let mut depth = 0;
-        let mut sub_expr = Vec::new();
-        sub_expr.push(String::new());
-        for c in expr.chars() {
-            match c {
-                ' ' if depth == 1 => sub_expr.push(String::new()),
-                ',' if depth == 1 => { },
-                ')' => {
-                    depth -= 1;
-                    if depth <= 0 {
-                        continue
-                    }
-                    if let Some(s) = sub_expr.last_mut() {
-                        s.push(c);
-                    }else {
-                        radeco_err!("parse error");
-                    };
-                }
-                '(' => {
-                    depth += 1;
-                    if depth <= 1 {
-                        continue
-                    }
-                    if let Some(s) = sub_expr.last_mut() {
-                        s.push(c);
-                    }else {
-                        radeco_err!("parse error");
-                    };
-                }
-                _ => {
-                    if let Some(s) = sub_expr.last_mut() {
-                        s.push(c);
-                    }else {
-                        radeco_err!("parse error");
-                    };
-                }
-            }
-        }
-        ParseToken {
-            parsed: sub_expr
+        split_expression(expr)
+    }
+
+    fn compile(&mut self, expr: &str) -> Result<Pattern, String> {
+        if let Some(p) = self.seen.get(expr) {
+            return Ok(p.clone());
         }
+        let p = Pattern::compile(expr)?;
+        self.seen.insert(expr.to_owned(), p.clone());
+        Ok(p)
     }
 
     // Warning: This function uses too much time when the fucntion is huge,
@@ -201,14 +405,25 @@ where I: Iterator<Item=S::ValueRef>,
                     MOpcode::OpEq => "OpEq".to_owned(),
                     MOpcode::OpGt => "OpGt".to_owned(),
                     MOpcode::OpLt => "OpLt".to_owned(),
+                    MOpcode::OpSGt(w) => format!("OpSGt{}", w),
+                    MOpcode::OpSLt(w) => format!("OpSLt{}", w),
+                    MOpcode::OpSDiv(w) => format!("OpSDiv{}", w),
+                    MOpcode::OpBSwap(w) => format!("OpBSwap{}", w),
+                    MOpcode::OpSMod(w) => format!("OpSMod{}", w),
+                    MOpcode::OpMulHi(w) => format!("OpMulHi{}", w),
+                    MOpcode::OpSMulHi(w) => format!("OpSMulHi{}", w),
                     MOpcode::OpLsl => "OpLsl".to_owned(),
                     MOpcode::OpLsr => "OpLsr".to_owned(),
-                    MOpcode::OpLoad => "OpLoad".to_owned(),
-                    MOpcode::OpStore => "OpStore".to_owned(), 
+                    MOpcode::OpLoad(w, Endian::Little) => format!("OpLoad{}", w),
+                    MOpcode::OpLoad(w, Endian::Big) => format!("OpLoadBE{}", w),
+                    MOpcode::OpStore(w, Endian::Little) => format!("OpStore{}", w),
+                    MOpcode::OpStore(w, Endian::Big) => format!("OpStoreBE{}", w),
                     MOpcode::OpNarrow(w) => format!("OpNarrow{}", w),
                     MOpcode::OpZeroExt(w) => format!("OpZeroExt{}", w),
+                    MOpcode::OpSignExt(w) => format!("OpSignExt{}", w),
                     MOpcode::OpCall => "OpCall".to_owned(),
-                    _ => unreachable!(),
+                    // Never named by a pattern, so any distinct name will do.
+                    _ => opc.to_string().into_owned(),
                 });
             } else if let NodeType::Comment(s) = node_data.nt {
                 result.push_str(&s);
@@ -217,145 +432,172 @@ where I: Iterator<Item=S::ValueRef>,
         result
     }
 
-    /// Returns the root of the subtree that matches the given `find` expression.
-    // TODO:
-    //   - Handle commutativity
-    pub fn grep(&mut self, find: String) -> Vec<Match<S::ValueRef>> {
-        let mut worklist = Vec::<(S::ValueRef, Option<String>)>::new();
-        let mut bindings = HashMap::new();
-        let mut seen_exprs = HashMap::new();
-        let mut found = Vec::new();
-        let first_expr = self.parse_expression(&find);
-        for node in self.ssa.inorder_walk() {
-            // First level of filtering.
-            let nh = self.hash_data(node);
-            if first_expr.current().as_ref().is_none()
-                || first_expr.current().as_ref().unwrap() != &nh {
-                    continue;
+    fn width_of(&self, ni: S::ValueRef) -> Option<u16> {
+        self.ssa.node_data(ni).ok().and_then(|nd| nd.vt.width().get_width())
+    }
+
+    fn has_width(&self, ni: S::ValueRef, width: Option<u16>) -> bool {
+        width.map_or(true, |w| self.width_of(ni) == Some(w))
+    }
+
+    // Matches every `(pattern, node)` goal, backtracking over the operand orders of
+    // commutative operations until `accept` takes the bindings. On success `bindings`
+    // holds the bindings of all goals, on failure it is left as it was.
+    fn solve<'p>(&mut self,
+                 mut goals: Vec<(&'p Pattern, S::ValueRef)>,
+                 bindings: &mut Vec<(String, S::ValueRef)>,
+                 accept: &Fn(&S, &[(String, S::ValueRef)]) -> bool)
+                 -> bool {
+        let (pat, node) = match goals.pop() {
+            Some(goal) => goal,
+            None => return accept(&*self.ssa, bindings),
+        };
+        match *pat {
+            Pattern::Bind(ref name, width, pred) => {
+                if !self.has_width(node, width) {
+                    return false;
                 }
-            {
-                let args = self.ssa.operands_of(node);
-                // All the cases which can cause a mismatch in the node and it's arguments.
-                if args.len() != first_expr.len() {
-                    continue;
+                if let Some(p) = pred {
+                    if !self.ssa.constant(node).map_or(false, |c| p.holds(c)) {
+                        return false;
+                    }
                 }
-                worklist.clear();
-                bindings.clear();
-                for (i, argn) in args.iter().enumerate() {
-                    worklist.push((*argn, first_expr.op(i + 1)));
+                let bound = bindings.iter().find(|b| &b.0 == name).map(|b| b.1);
+                if let Some(old) = bound {
+                    // A repeated binding has to match the subtree bound before.
+                    if old != node && self.hash_subtree(old) != self.hash_subtree(node) {
+                        return false;
+                    }
+                    self.solve(goals, bindings, accept)
+                } else {
+                    bindings.push((name.clone(), node));
+                    if self.solve(goals, bindings, accept) {
+                        return true;
+                    }
+                    bindings.pop();
+                    false
                 }
             }
-
-            let mut viable = true;
-            while let Some((inner_node, match_str)) = worklist.pop() {
-                if match_str.is_none() {
-                    // Mismatch
-                    viable = false;
-                    break;
+            Pattern::Const(value, width) => {
+                self.ssa.constant(node) == Some(value) && self.has_width(node, width) &&
+                self.solve(goals, bindings, accept)
+            }
+            Pattern::Pred(p) => {
+                self.ssa.constant(node).map_or(false, |c| p.holds(c)) &&
+                self.solve(goals, bindings, accept)
+            }
+            Pattern::Node(ref op, width, ref args) => {
+                if self.hash_data(node) != *op || !self.has_width(node, width) {
+                    return false;
                 }
-                let current_h = self.hash_data(inner_node);
-                // Optimize by avoiding a regex match when an expression is already known/seen.
-                let t = if let Some(tk) = seen_exprs.get(match_str.as_ref().unwrap()).cloned() {
-                    tk
-                } else {
-                    let t_ = self.parse_expression(match_str.as_ref().unwrap());
-                    seen_exprs.insert(match_str.unwrap(), t_.clone());
-                    t_
-                };
-
-                if t.current().is_none() {
-                    // Mismatch
-                    viable = false;
-                    break;
+                let operands = self.ssa.operands_of(node);
+                if operands.len() != args.len() {
+                    return false;
                 }
-
-                let current = t.current().unwrap();
-                if current.starts_with('%') {
-                    // Match the current subtree to the subtree bound by the variable before. If
-                    // they do not match, then report as mismatch.
-                    let subtreeh = self.hash_subtree(inner_node);
-                    if let Some((old, _)) = bindings.insert(current.clone(),
-                                                            (subtreeh.clone(), inner_node)) {
-                        if old != subtreeh {
-                            // Mismatch
-                            viable = false;
-                            break;
-                        }
+                let mut orders = vec![operands.clone()];
+                if pat.is_commutative() {
+                    orders.push(vec![operands[1], operands[0]]);
+                }
+                for order in orders {
+                    let mut g = goals.clone();
+                    g.extend(args.iter().zip(order));
+                    let saved = bindings.len();
+                    if self.solve(g, bindings, accept) {
+                        return true;
                     }
-                } else if current_h != current {
-                    // Mismatch
-                    // No match case.
-                    viable = false;
-                    break;
+                    bindings.truncate(saved);
                 }
+                false
+            }
+        }
+    }
 
-                let args = self.ssa.operands_of(inner_node);
-                // All the cases which can cause a mismatch in the node and it's arguments.
-                // Since "%" binds the entire subtree, it will not have any arguments. So we skip
-                // this case.
-                if args.len() != t.len() && !current.starts_with('%') {
-                    viable = false;
-                    break;
-                }
+    /// Returns true if the nodes of `m` are still part of the SSA, which they might not
+    /// be after replacing an overlapping match.
+    pub fn is_live(&self, m: &Match<S::ValueRef>) -> bool {
+        self.ssa.node_data(m.root).is_ok() && self.ssa.block_for(m.root).is_some() &&
+        m.bindings.iter().all(|b| self.ssa.node_data(b.1).is_ok())
+    }
 
-                for (i, argn) in args.iter().enumerate() {
-                    if i >= t.len() { break; };
-                    worklist.push((*argn, t.op(i + 1)));
-                }
+    /// Returns the root of the subtree that matches the given `find` expression.
+    pub fn grep(&mut self, find: String) -> Vec<Match<S::ValueRef>> {
+        match self.compile(&find) {
+            Ok(pattern) => self.grep_pattern(&pattern),
+            Err(e) => {
+                radeco_err!("Invalid pattern {}: {}", find, e);
+                Vec::new()
             }
+        }
+    }
 
-            if viable {
-                let mut vbind = Vec::new();
-                for (k, v) in &bindings {
-                    vbind.push((k.clone(), v.1));
+    /// Returns the roots of all subtrees matching the compiled `pattern`.
+    pub fn grep_pattern(&mut self, pattern: &Pattern) -> Vec<Match<S::ValueRef>> {
+        self.grep_where(pattern, &|_, _| true)
+    }
+
+    /// Like `grep_pattern`, but only returns matches whose bindings are accepted by
+    /// `accept`. Other operand orders of commutative operations are tried before a
+    /// subtree is rejected.
+    pub fn grep_where(&mut self,
+                      pattern: &Pattern,
+                      accept: &Fn(&S, &[(String, S::ValueRef)]) -> bool)
+                      -> Vec<Match<S::ValueRef>> {
+        let mut found = Vec::new();
+        for node in self.ssa.inorder_walk() {
+            // First level of filtering.
+            if let Pattern::Node(ref op, _, _) = *pattern {
+                if self.hash_data(node) != *op {
+                    continue;
                 }
-                let nmatch = Match {
+            }
+            let mut bindings = Vec::new();
+            if self.solve(vec![(pattern, node)], &mut bindings, accept) {
+                found.push(Match {
                     root: node,
-                    bindings: vbind,
-                };
-                found.push(nmatch);
+                    bindings: bindings,
+                });
             }
         }
         found
     }
 
+    /// Width of the value produced by the template `tpl` once it is inserted. Unless
+    /// given explicitly it is derived from the opcode and, where that does not fix it,
+    /// from the first operand.
+    fn template_width(&self, tpl: &Pattern, bindings: &HashMap<String, S::ValueRef>) -> u16 {
+        match *tpl {
+            Pattern::Bind(ref name, _, _) => {
+                bindings.get(name).and_then(|&b| self.width_of(b)).unwrap_or(64)
+            }
+            Pattern::Const(_, width) => width.unwrap_or(64),
+            Pattern::Pred(_) => 64,
+            Pattern::Node(_, Some(width), _) => width,
+            Pattern::Node(ref op, None, ref args) => {
+                // The opcode was checked when the template was compiled.
+                match token_to_opcode(op).unwrap_or(None) {
+                    Some(MOpcode::OpNarrow(w)) |
+                    Some(MOpcode::OpZeroExt(w)) |
+                    Some(MOpcode::OpSignExt(w)) |
+                    Some(MOpcode::OpLoad(w, _)) => w,
+                    Some(ref op) if op.is_comparison() => 1,
+                    Some(MOpcode::OpConst(_)) | None => 64,
+                    Some(_) => {
+                        args.first().map_or(64, |lhs| self.template_width(lhs, bindings))
+                    }
+                }
+            }
+        }
+    }
+
     fn map_token_to_node(&mut self,
                          t: &str,
+                         width: u16,
                          block: &S::ActionRef,
                          addr: &mut MAddress)
                          -> S::ValueRef {
-        let opcode = if t.starts_with("#x") {
-            Some(MOpcode::OpConst(u64::from_str_radix(&t[2..], 16).expect("Invalid hex integer")))
-        } else if t.starts_with("OpNarrow") {
-            Some(MOpcode::OpNarrow(u16::from_str_radix(&t[8..], 10)
-                                       .expect("Invalid decimal integer")))
-        } else if t.starts_with("OpZeroExt") {
-            Some(MOpcode::OpZeroExt(u16::from_str_radix(&t[7..], 10)
-                                      .expect("Invalid decimal integer")))
-        } else {
-            match t {
-                "OpAdd" => Some(MOpcode::OpAdd),
-                "OpSub" => Some(MOpcode::OpSub),
-                "OpMul" => Some(MOpcode::OpMul),
-                "OpDiv" => Some(MOpcode::OpDiv),
-                "OpMod" => Some(MOpcode::OpMod),
-                "OpAnd" => Some(MOpcode::OpAnd),
-                "OpOr" => Some(MOpcode::OpOr),
-                "OpXor" => Some(MOpcode::OpXor),
-                "OpNot" => Some(MOpcode::OpNot),
-                "OpEq" => Some(MOpcode::OpEq),
-                "OpGt" => Some(MOpcode::OpGt),
-                "OpLt" => Some(MOpcode::OpLt),
-                "OpLsl" => Some(MOpcode::OpLsl),
-                "OpLsr" => Some(MOpcode::OpLsr),
-                "OpLoad" => Some(MOpcode::OpLoad),
-                "OpStore" => Some(MOpcode::OpStore),
-                "OpCall" => Some(MOpcode::OpCall),
-                _ => None,
-            }
-        };
+        let opcode = token_to_opcode(t).unwrap_or(None);
         if let Some(ref op) = opcode {
-            let node = self.ssa.insert_op(op.clone(), ValueInfo::new_unresolved(WidthSpec::from(64)), None)
+            let node = self.ssa.insert_op(op.clone(), ValueInfo::new_unresolved(WidthSpec::from(width)), None)
                                 .expect("Cannot insert new values");
             match *op {
                 MOpcode::OpConst(_) => {}
@@ -374,13 +616,56 @@ where I: Iterator<Item=S::ValueRef>,
         }
     }
 
+    // Inserts the nodes of the template `tpl` and returns its root.
+    fn build(&mut self,
+             tpl: &Pattern,
+             bindings: &HashMap<String, S::ValueRef>,
+             block: &S::ActionRef,
+             addr: &mut MAddress)
+             -> S::ValueRef {
+        match *tpl {
+            Pattern::Bind(ref name, _, _) => {
+                *bindings.get(name).unwrap_or_else(|| {
+                    radeco_err!("Unknown Binding {}", name);
+                    panic!("Replacement uses a binding missing from the pattern")
+                })
+            }
+            Pattern::Const(value, _) => {
+                let width = self.template_width(tpl, bindings);
+                self.map_token_to_node(&format!("#x{:x}", value), width, block, addr)
+            }
+            Pattern::Pred(p) => {
+                radeco_err!("{:?} used in a replacement", p);
+                panic!("Constant predicates cannot be inserted")
+            }
+            Pattern::Node(ref op, _, ref args) => {
+                let width = self.template_width(tpl, bindings);
+                let node = self.map_token_to_node(op, width, block, addr);
+                for (i, arg) in args.iter().enumerate() {
+                    let operand = self.build(arg, bindings, block, addr);
+                    self.ssa.op_use(node, i as u8, operand);
+                }
+                node
+            }
+        }
+    }
+
     /// Replaces the subtree rooted at `S::ValueRef` by the `replace` expression.
     /// Returns the root of the replaced expression.
     pub fn replace_value(&mut self, found: Match<S::ValueRef>, replace: String) -> S::ValueRef {
+        match self.compile(&replace) {
+            Ok(template) => self.replace_pattern(found, &template),
+            Err(e) => {
+                radeco_err!("Invalid pattern {}: {}", replace, e);
+                found.root()
+            }
+        }
+    }
+
+    /// Replaces the subtree rooted at `S::ValueRef` by the compiled `template`, which may
+    /// span several new nodes. Returns the root of the replaced expression.
+    pub fn replace_pattern(&mut self, found: Match<S::ValueRef>, template: &Pattern) -> S::ValueRef {
         let bindings = found.bindings.iter().cloned().collect::<HashMap<_, _>>();
-        // Vec of (parent, lhs/rhs (0/1), parse_expression)
-        let mut worklist = Vec::new();
-        // Remove operands to the root node and prepare for replace.
         let root = found.root;
         let mut address = self.ssa.address(root).expect("No address information found");
         let block = self.ssa.block_for(root).expect("Value node doesn't belong to any block");
@@ -391,70 +676,12 @@ where I: Iterator<Item=S::ValueRef>,
             self.ssa.op_unuse(root, *arg);
         }
 
-        let r = if let Some(tk) = self.seen.get(&replace).cloned() {
-            tk
-        } else {
-            let t_ = self.parse_expression(&replace);
-            self.seen.insert(replace, t_.clone());
-            t_
-        };
-
-        let replace_root = if r.current().as_ref().map_or(false, |x| x.starts_with('%')) {
-                                *bindings.get(r.current().as_ref().unwrap()).unwrap_or_else(|| {
-                                    radeco_err!("Unknown Binding");
-                                    &found.root
-                                })
-                            } else if r.current().as_ref().is_some() {
-                                self.map_token_to_node(r.current().as_ref().unwrap(),
-                                   &block,
-                                   &mut address)
-                            } else {
-                                radeco_err!("r.current().as_ref() == None");
-                                found.root
-                            };
-
+        let replace_root = self.build(template, &bindings, &block, &mut address);
         self.ssa.replace_value(root, replace_root);
-        for i in 0..r.len() {
-            let p = r.op(i + 1).unwrap_or_else(|| {
-                radeco_err!("parse error");
-                String::new()
-            });
-            worklist.push((replace_root, i as u8, p));
-        }
-
-        while let Some((parent, edge_idx, subexpr)) = worklist.pop() {
-            let pt = if let Some(tk) = self.seen.get(&subexpr).cloned() {
-                tk
-            } else {
-                let t_ = self.parse_expression(&subexpr);
-                self.seen.insert(subexpr, t_.clone());
-                t_
-            };
-            let current = pt.current().unwrap_or_else(|| {
-                radeco_err!("parse error");
-                String::new()
-            });
-            let inner_node = if current.starts_with('%') {
-                *bindings.get(&current).unwrap_or_else(|| {
-                    radeco_err!("Unknown Binding");
-                    //TODO
-                    None.unwrap()
-                })
-            } else {
-                self.map_token_to_node(&current, &block, &mut address)
-            };
-            self.ssa.op_use(parent, edge_idx, inner_node);
-            for i in 0..pt.len() {
-                let _p = r.op(i + 1).unwrap_or_else(|| {
-                    radeco_err!("parse error");
-                    String::new()
-                });
-                worklist.push((inner_node, i as u8, pt.op(i + 1).unwrap()));
-            }
+        if let Pattern::Node(..) = *template {
+            address.offset += 1;
+            self.ssa.set_address(replace_root, address);
         }
-
-        address.offset += 1;
-        self.ssa.set_address(replace_root, address);
         replace_root
     }
 }
@@ -467,6 +694,7 @@ mod test {
     use middle::ssa::ssa_traits::{SSA, SSAMod, ValueType, SSAWalk};
     use middle::ssa::cfg_traits::CFGMod;
     use middle::ir::{MOpcode, MAddress};
+    use petgraph::graph::NodeIndex;
 
     #[test]
     fn parse_expr() {
@@ -606,4 +834,112 @@ mod test {
             }
         });
     }
+
+    // Inserts `(op lhs, rhs)` of the given width into a fresh block of `ssa`.
+    fn binop(ssa: &mut SSAStorage, op: MOpcode, width: u16, lhs: u64, rhs: u64) -> NodeIndex {
+        let blk = ssa.insert_dynamic().expect("Cannot insert new dynamics");
+        let vt = ValueInfo::new_unresolved(WidthSpec::from(width));
+        let node = ssa.insert_op(op, vt, None).expect("Cannot insert new expressions");
+        let lhs = ssa.insert_const(lhs).expect("Cannot insert new constants");
+        let rhs = ssa.insert_const(rhs).expect("Cannot insert new constants");
+        let addr = MAddress::new(0, 0);
+        ssa.insert_into_block(node, blk, addr);
+        ssa.op_use(node, 0, lhs);
+        ssa.op_use(node, 1, rhs);
+        ssa.set_entry_node(blk);
+        node
+    }
+
+    #[test]
+    fn compile_pattern() {
+        assert_eq!(Pattern::compile("(OpAnd:32 %1:8, #mask)"),
+                   Ok(Pattern::Node("OpAnd".to_owned(), Some(32),
+                                    vec![Pattern::Bind("%1".to_owned(), Some(8), None),
+                                         Pattern::Pred(ConstPred::Mask)])));
+        assert_eq!(Pattern::compile("(OpMul %1, %2#pow2)"),
+                   Ok(Pattern::Node("OpMul".to_owned(), None,
+                                    vec![Pattern::Bind("%1".to_owned(), None, None),
+                                         Pattern::Bind("%2".to_owned(), None, Some(ConstPred::PowerOfTwo))])));
+        assert_eq!(Pattern::compile("#xff:16"), Ok(Pattern::Const(0xff, Some(16))));
+    }
+
+    #[test]
+    fn compile_errors() {
+        assert!(Pattern::compile("(OpAnd %1, #odd)").is_err());
+        assert!(Pattern::compile("(OpAnd %1#odd, %2)").is_err());
+        assert!(Pattern::compile("(OpAnd %1, #xfg)").is_err());
+        assert!(Pattern::compile("(OpAnd:x %1, %2)").is_err());
+        assert!(Pattern::compile("(OpNot %1:64k)").is_err());
+        assert!(Pattern::compile("(OpNarrow3x %1)").is_err());
+        assert!(token_to_opcode("OpSMulHi").is_err());
+        assert_eq!(token_to_opcode("OpSMulHi64"), Ok(Some(MOpcode::OpSMulHi(64))));
+        assert_eq!(token_to_opcode("mem@0x1000"), Ok(None));
+    }
+
+    #[test]
+    fn const_predicates() {
+        assert!(ConstPred::PowerOfTwo.holds(0x1000));
+        assert!(!ConstPred::PowerOfTwo.holds(0x1001));
+        assert!(ConstPred::Mask.holds(0xff));
+        assert!(ConstPred::Mask.holds(!0));
+        assert!(!ConstPred::Mask.holds(0xfe));
+        assert!(!ConstPred::Mask.holds(0));
+    }
+
+    #[test]
+    fn commutative_grep() {
+        let mut ssa = SSAStorage::new();
+        let and = binop(&mut ssa, MOpcode::OpAnd, 64, 1, 7);
+        let m = grep!(&mut ssa, "(OpAnd %1, #x1)");
+        assert_eq!(m.len(), 1);
+        assert_eq!(m[0].root, and);
+        assert_eq!(ssa.constant(m[0].bindings[0].1), Some(7));
+        // Subtraction is not commutative.
+        let mut ssa = SSAStorage::new();
+        binop(&mut ssa, MOpcode::OpSub, 64, 1, 7);
+        assert!(grep!(&mut ssa, "(OpSub %1, #x1)").is_empty());
+    }
+
+    #[test]
+    fn width_guard_grep() {
+        let mut ssa = SSAStorage::new();
+        binop(&mut ssa, MOpcode::OpAdd, 32, 1, 2);
+        assert_eq!(grep!(&mut ssa, "(OpAdd:32 %1, %2)").len(), 1);
+        assert!(grep!(&mut ssa, "(OpAdd:64 %1, %2)").is_empty());
+    }
+
+    #[test]
+    fn const_predicate_grep() {
+        let mut ssa = SSAStorage::new();
+        binop(&mut ssa, MOpcode::OpMul, 64, 5, 8);
+        let m = grep!(&mut ssa, "(OpMul %1, %2#pow2)");
+        assert_eq!(m.len(), 1);
+        let pow2 = m[0].bindings.iter().find(|b| b.0 == "%2").unwrap().1;
+        assert_eq!(ssa.constant(pow2), Some(8));
+        assert!(grep!(&mut ssa, "(OpMul %1, #mask)").is_empty());
+    }
+
+    #[test]
+    fn repeated_binding_grep() {
+        let mut ssa = SSAStorage::new();
+        binop(&mut ssa, MOpcode::OpXor, 64, 1, 2);
+        assert!(grep!(&mut ssa, "(OpXor %1, %1)").is_empty());
+    }
+
+    #[test]
+    fn multi_node_replace() {
+        let mut ssa = SSAStorage::new();
+        binop(&mut ssa, MOpcode::OpMul, 32, 5, 8);
+        grep_and_replace!(&mut ssa, "(OpMul %1, #x8)" => "(OpZeroExt64 (OpLsl:32 %1, #x3))");
+        let ext = ssa.values()
+                     .into_iter()
+                     .find(|&v| ssa.opcode(v) == Some(MOpcode::OpZeroExt(64)))
+                     .expect("No extension inserted");
+        let shift = ssa.operands_of(ext)[0];
+        assert_eq!(ssa.opcode(shift), Some(MOpcode::OpLsl));
+        assert_eq!(ssa.node_data(shift).unwrap().vt.width().get_width(), Some(32));
+        let ops = ssa.operands_of(shift);
+        assert_eq!(ssa.constant(ops[0]), Some(5));
+        assert_eq!(ssa.constant(ops[1]), Some(3));
+    }
 }
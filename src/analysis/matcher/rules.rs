@@ -0,0 +1,446 @@
+//! Rewrite rules loaded from text files.
+//!
+//! A rule file holds one rule per line:
+//!
+//! ```text
+//! // Comments take a whole line.
+//! xor_self: (OpXor %1, %1) => #x0
+//! mul8: (OpMul %1, %2) => (OpLsl %1, #x3) where %2 == #x8
+//! and_low: (OpAnd %1, %2) => %1 where %1:8, %2#mask
+//! ```
+//!
+//! Every rule has a unique name, a find pattern, a replacement and optionally a list
+//! of guards on the bindings of the find pattern. Patterns use the syntax of
+//! [`gmatch`](../gmatch/index.html). The guards are:
+//!
+//!  - `%1:32`, `%1` is 32 bits wide,
+//!  - `%1#pow2`, `%1` is a constant satisfying the predicate,
+//!  - `%1 != %2`, `%1` and `%2` are bound to different values,
+//!  - `%1 == #x8`, `%1` is the given constant.
+//!
+//! Rules are validated when they are loaded, a [`RuleSet`](struct.RuleSet.html) is
+//! then applied to a function until none of its rules matches anymore.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use analysis::matcher::gmatch::{self, ConstPred, GraphMatcher, Pattern};
+use middle::dce;
+use middle::ir::{MArity, MOpcode};
+use middle::ssa::ssa_traits::SSA;
+use middle::ssa::ssastorage::SSAStorage;
+
+type SSAValue = <SSAStorage as SSA>::ValueRef;
+
+/// Rounds after which `RuleSet::apply` gives up on reaching a fixpoint.
+const MAX_ROUNDS: usize = 64;
+
+#[derive(Debug)]
+pub enum RuleError {
+    Io(io::Error),
+    /// The rule on the given line (counting from 1) is invalid.
+    Invalid(usize, String),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RuleError::Io(ref e) => write!(f, "{}", e),
+            RuleError::Invalid(line, ref reason) => write!(f, "line {}: {}", line, reason),
+        }
+    }
+}
+
+impl From<io::Error> for RuleError {
+    fn from(e: io::Error) -> RuleError {
+        RuleError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Guard {
+    Width(String, u16),
+    Const(String, ConstPred),
+    Equals(String, u64),
+    Distinct(String, String),
+}
+
+impl Guard {
+    fn parse(s: &str) -> Result<Guard, String> {
+        let s = s.trim();
+        if let Some(i) = s.find("!=") {
+            return Ok(Guard::Distinct(s[..i].trim().to_owned(), s[i + 2..].trim().to_owned()));
+        }
+        if let Some(i) = s.find("==") {
+            let value = s[i + 2..].trim();
+            if !value.starts_with("#x") {
+                return Err(format!("expected a constant in guard `{}`", s));
+            }
+            let value = u64::from_str_radix(&value[2..], 16)
+                .map_err(|_| format!("invalid constant in guard `{}`", s))?;
+            return Ok(Guard::Equals(s[..i].trim().to_owned(), value));
+        }
+        match Pattern::compile(s)? {
+            Pattern::Bind(name, Some(width), None) => Ok(Guard::Width(name, width)),
+            Pattern::Bind(name, None, Some(pred)) => Ok(Guard::Const(name, pred)),
+            _ => Err(format!("invalid guard `{}`", s)),
+        }
+    }
+
+    fn bindings(&self) -> Vec<&str> {
+        match *self {
+            Guard::Width(ref b, _) |
+            Guard::Const(ref b, _) |
+            Guard::Equals(ref b, _) => vec![b.as_str()],
+            Guard::Distinct(ref b1, ref b2) => vec![b1.as_str(), b2.as_str()],
+        }
+    }
+
+    fn holds(&self, ssa: &SSAStorage, bindings: &[(String, SSAValue)]) -> bool {
+        let value = |b: &str| bindings.iter().find(|v| v.0 == b).map(|v| v.1);
+        match *self {
+            Guard::Width(ref b, width) => {
+                value(b).and_then(|v| ssa.node_data(v).ok())
+                        .and_then(|nd| nd.vt.width().get_width()) == Some(width)
+            }
+            Guard::Const(ref b, pred) => {
+                value(b).and_then(|v| ssa.constant(v)).map_or(false, |c| pred.holds(c))
+            }
+            Guard::Equals(ref b, c) => value(b).and_then(|v| ssa.constant(v)) == Some(c),
+            Guard::Distinct(ref b1, ref b2) => value(b1) != value(b2),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub name: String,
+    pub find: Pattern,
+    pub replace: Pattern,
+    pub guards: Vec<Guard>,
+}
+
+// Names of the bindings in `p`, in order of appearance.
+fn bindings_of<'a>(p: &'a Pattern, out: &mut Vec<&'a str>) {
+    match *p {
+        Pattern::Bind(ref name, _, _) => out.push(name),
+        Pattern::Node(_, _, ref args) => {
+            for arg in args {
+                bindings_of(arg, out);
+            }
+        }
+        Pattern::Const(..) | Pattern::Pred(_) => {}
+    }
+}
+
+fn operands(opcode: &MOpcode) -> usize {
+    match opcode.arity() {
+        MArity::Zero => 0,
+        MArity::Unary => 1,
+        MArity::Binary => 2,
+        MArity::Ternary => 3,
+    }
+}
+
+// Checks the opcodes of `p` and, for replacements, that it can be built. Their widths
+// were already checked when `p` was compiled.
+fn validate(p: &Pattern, is_replace: bool) -> Result<(), String> {
+    match *p {
+        Pattern::Node(ref op, _, ref args) => {
+            match gmatch::token_to_opcode(op)? {
+                Some(ref opcode) if operands(opcode) != args.len() => {
+                    Err(format!("`{}` takes {} operands", op, operands(opcode)))
+                }
+                None if op.starts_with("Op") => Err(format!("unknown opcode `{}`", op)),
+                None if !args.is_empty() => Err(format!("`{}` is not an opcode", op)),
+                _ => args.iter().map(|a| validate(a, is_replace)).collect(),
+            }
+        }
+        Pattern::Pred(p) if is_replace => Err(format!("{:?} cannot be inserted", p)),
+        _ => Ok(()),
+    }
+}
+
+fn is_balanced(s: &str) -> bool {
+    let mut depth = 0i32;
+    for c in s.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth < 0 {
+            return false;
+        }
+    }
+    depth == 0
+}
+
+impl Rule {
+    /// Parses a single `name: find => replace [where guards]` rule. Patterns which do
+    /// not compile are reported like any other invalid rule.
+    pub fn parse(line: &str) -> Result<Rule, String> {
+        let colon = line.find(':').ok_or("missing rule name")?;
+        let name = line[..colon].trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(format!("invalid rule name `{}`", name));
+        }
+        let rest = &line[colon + 1..];
+        let arrow = rest.find("=>").ok_or("missing `=>`")?;
+        let find = rest[..arrow].trim();
+        let (replace, guards) = match rest[arrow + 2..].find(" where ") {
+            Some(i) => (&rest[arrow + 2..arrow + 2 + i], Some(&rest[arrow + 2 + i + 7..])),
+            None => (&rest[arrow + 2..], None),
+        };
+        let replace = replace.trim();
+        for side in &[find, replace] {
+            if side.is_empty() || !is_balanced(side) {
+                return Err(format!("malformed pattern `{}`", side));
+            }
+        }
+
+        let find = Pattern::compile(find)?;
+        let replace = Pattern::compile(replace)?;
+        if let Pattern::Node(..) = find {
+        } else {
+            return Err("the find pattern has to start with an opcode".to_owned());
+        }
+        validate(&find, false)?;
+        validate(&replace, true)?;
+        let guards = match guards {
+            Some(g) => g.split(',').map(Guard::parse).collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+
+        let mut bound = Vec::new();
+        bindings_of(&find, &mut bound);
+        let bound = bound.into_iter().collect::<HashSet<_>>();
+        let mut used = Vec::new();
+        bindings_of(&replace, &mut used);
+        used.extend(guards.iter().flat_map(|g| g.bindings()));
+        if let Some(b) = used.into_iter().find(|b| !bound.contains(b)) {
+            return Err(format!("`{}` is not bound by the find pattern", b));
+        }
+
+        Ok(Rule {
+            name: name.to_owned(),
+            find: find,
+            replace: replace,
+            guards: guards,
+        })
+    }
+}
+
+/// Number of times every rule was applied.
+#[derive(Clone, Debug, Default)]
+pub struct RuleStats {
+    hits: Vec<(String, usize)>,
+    /// Rounds over all rules, including the last one which did not change anything.
+    pub rounds: usize,
+}
+
+impl RuleStats {
+    pub fn hits(&self, rule: &str) -> usize {
+        self.hits.iter().find(|h| h.0 == rule).map_or(0, |h| h.1)
+    }
+
+    pub fn total(&self) -> usize {
+        self.hits.iter().map(|h| h.1).sum()
+    }
+}
+
+impl fmt::Display for RuleStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(ref name, hits) in &self.hits {
+            writeln!(f, "{}: {}", name, hits)?;
+        }
+        write!(f, "{} rewrites in {} rounds", self.total(), self.rounds)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    /// Parses and validates the rules in `text`.
+    pub fn parse(text: &str) -> Result<RuleSet, RuleError> {
+        let mut rules = Vec::<Rule>::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let rule = Rule::parse(line).map_err(|e| RuleError::Invalid(i + 1, e))?;
+            if rules.iter().any(|r| r.name == rule.name) {
+                return Err(RuleError::Invalid(i + 1, format!("duplicate rule `{}`", rule.name)));
+            }
+            rules.push(rule);
+        }
+        Ok(RuleSet { rules: rules })
+    }
+
+    /// Reads, parses and validates the rule file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<RuleSet, RuleError> {
+        RuleSet::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Rewrites `ssa` with every rule, in file order, until none of them matches.
+    pub fn apply(&self, ssa: &mut SSAStorage) -> RuleStats {
+        let mut stats = RuleStats {
+            hits: self.rules.iter().map(|r| (r.name.clone(), 0)).collect(),
+            rounds: 0,
+        };
+        loop {
+            if stats.rounds == MAX_ROUNDS {
+                radeco_warn!("Rewrite rules did not reach a fixpoint after {} rounds", MAX_ROUNDS);
+                break;
+            }
+            stats.rounds += 1;
+            let mut changed = false;
+            for (i, rule) in self.rules.iter().enumerate() {
+                let accept = |ssa: &SSAStorage, bindings: &[(String, SSAValue)]| {
+                    rule.guards.iter().all(|g| g.holds(ssa, bindings))
+                };
+                let found = GraphMatcher::new(ssa).grep_where(&rule.find, &accept);
+                let mut matcher = GraphMatcher::new(ssa);
+                for m in found {
+                    if !matcher.is_live(&m) {
+                        continue;
+                    }
+                    radeco_trace!("rules|{}|{:?}", rule.name, m.root());
+                    matcher.replace_pattern(m, &rule.replace);
+                    stats.hits[i].1 += 1;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+            dce::collect(ssa);
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir_reader::test_util::parse;
+    use middle::ssa::ssa_traits::SSAWalk;
+
+    const SSA_TXT: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+    bb_0x001000.0000(sz 0x4):
+        [@0x001000.0001] %2: $Unknown64 = !%1;
+        [@0x001000.0002] %3: $Unknown64 = !%2;
+        [@0x001000.0003] %4: $Unknown64 = !%3;
+        [@0x001000.0004] %5: $Unknown64 = !%4;
+        [@0x001000.0005] %6: $Unknown64 = #x8 * %5;
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $rax = %6;
+}
+";
+
+    fn load_ssa() -> SSAStorage {
+        parse(SSA_TXT)
+    }
+
+    fn opcodes(ssa: &SSAStorage) -> Vec<MOpcode> {
+        ssa.inorder_walk().filter_map(|n| ssa.opcode(n)).collect()
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rules = RuleSet::parse("
+            // Comment
+            not_not: (OpNot (OpNot %1)) => %1
+
+            mul8: (OpMul %1, %2) => (OpLsl %1, #x3) where %2 == #x8, %1:64
+        ").unwrap();
+        assert_eq!(rules.rules().len(), 2);
+        assert_eq!(rules.rules()[1].name, "mul8");
+        assert_eq!(rules.rules()[1].guards,
+                   vec![Guard::Equals("%2".to_owned(), 8), Guard::Width("%1".to_owned(), 64)]);
+    }
+
+    #[test]
+    fn invalid_rules() {
+        let invalid = |text: &str| match RuleSet::parse(text) {
+            Err(RuleError::Invalid(line, _)) => line,
+            _ => panic!("`{}` was accepted", text),
+        };
+        assert_eq!(invalid("a: (OpNot %1 => %1"), 1);
+        assert_eq!(invalid("\na: (OpNot %1) => %2"), 2);
+        assert_eq!(invalid("a: (OpFoo %1) => %1"), 1);
+        assert_eq!(invalid("a: (OpZeroExt %1) => %1"), 1);
+        assert_eq!(invalid("a: (OpNot %1, %2) => %1"), 1);
+        assert_eq!(invalid("a: (OpAdd %1, #pow2) => (OpSub %1, #pow2)"), 1);
+        assert_eq!(invalid("a: (OpNot %1) => %1 where %3:8"), 1);
+        assert_eq!(invalid("(OpNot %1) => %1"), 1);
+        assert_eq!(invalid("a: (OpNot %1) => %1\na: (OpNot %1) => %1"), 2);
+        // Compile errors of the patterns and guards.
+        assert_eq!(invalid("\na: (OpAnd %1, #odd) => %1"), 2);
+        assert_eq!(invalid("a: (OpAnd %1, #xfg) => %1"), 1);
+        assert_eq!(invalid("a: (OpNot %1) => (OpZeroExt6x %1)"), 1);
+        assert_eq!(invalid("a: (OpNot:6x %1) => %1"), 1);
+        assert_eq!(invalid("a: (OpNot %1) => %1 where %1#odd"), 1);
+        assert_eq!(invalid("a: (OpNot %1) => %1 where %1:x"), 1);
+    }
+
+    #[test]
+    fn apply_to_fixpoint() {
+        let rules = RuleSet::parse("
+            not_not: (OpNot (OpNot %1)) => %1
+            mul8: (OpMul %1, %2) => (OpLsl %1, #x3) where %2 == #x8
+            mul16: (OpMul %1, %2) => (OpLsl %1, #x4) where %2 == #x10
+        ").unwrap();
+        let mut ssa = load_ssa();
+        let stats = rules.apply(&mut ssa);
+        // `!!%1` and `!!%2` are rewritten in the first round, the `!!%1` left behind
+        // by the second rewrite only in the next one.
+        assert_eq!(stats.hits("not_not"), 3);
+        assert_eq!(stats.hits("mul8"), 1);
+        assert_eq!(stats.hits("mul16"), 0);
+        assert_eq!(stats.total(), 4);
+        assert_eq!(stats.rounds, 3);
+        let ops = opcodes(&ssa);
+        assert!(ops.contains(&MOpcode::OpLsl));
+        assert!(!ops.contains(&MOpcode::OpNot) && !ops.contains(&MOpcode::OpMul));
+    }
+
+    #[test]
+    fn guards() {
+        let rules = RuleSet::parse("
+            not_not: (OpNot (OpNot %1)) => %1 where %1:32
+            mul: (OpMul %1, %2) => (OpLsl %1, #x3) where %1 != %2, %2#pow2
+        ").unwrap();
+        let mut ssa = load_ssa();
+        let stats = rules.apply(&mut ssa);
+        assert_eq!(stats.hits("not_not"), 0);
+        assert_eq!(stats.hits("mul"), 1);
+        assert_eq!(stats.rounds, 2);
+    }
+
+    #[test]
+    fn load_rule_file() {
+        let rules = RuleSet::load("test_files/rules/simplify.rules").unwrap();
+        assert!(rules.rules().iter().any(|r| r.name == "xor_self"));
+        match RuleSet::load("test_files/rules/missing.rules") {
+            Err(RuleError::Io(_)) => {}
+            _ => panic!("Missing rule file loaded"),
+        }
+    }
+}
@@ -14,6 +14,7 @@ pub mod dom;
 pub mod sccp;
 pub mod cse {
     pub mod cse;
+    pub mod gvn;
     pub mod ssasort;
 }
 
@@ -21,12 +22,18 @@ pub mod cse {
 pub mod matcher {
     #[macro_use]
     pub mod gmatch;
+    pub mod rules;
 }
 
 pub mod vsa;
+pub mod known_bits;
+pub mod ranges;
 pub mod interproc;
 pub mod tie;
+pub mod records;
+pub mod arrays;
 pub mod reference_marking;
 pub mod constraint_set;
 pub mod functions;
 pub mod inst_combine;
+pub mod idioms;
@@ -0,0 +1,725 @@
+//! Range analysis over `SSAStorage`.
+//!
+//! Computes the unsigned interval every value of at most 64 bits lies in. Conditional
+//! branches refine the ranges of the values they compare: in the blocks only entered
+//! over one side of a branch, and for phi operands flowing in over it, the condition
+//! is known to hold. A block behind a condition which cannot hold is never reached,
+//! so its values do not flow into any phi.
+//!
+//! Phis which keep growing are widened to the next constant compared against, and
+//! all ranges are narrowed again afterwards. A loop counter compared against a
+//! constant thus ends up in the range the comparison allows.
+//!
+//! Branches whose condition always holds, or never does, are handed to `sccp`, which
+//! then only marks the side taken as executable.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use petgraph::graph::Graph;
+
+use analysis::dom::DomTree;
+use middle::ir::MOpcode;
+use middle::ssa::cfg_traits::CFG;
+use middle::ssa::ssa_traits::*;
+use middle::ssa::ssastorage::SSAStorage;
+
+type SSAValue = <SSAStorage as SSA>::ValueRef;
+type SSABlock = <SSAStorage as CFG>::ActionRef;
+
+/// Number of times a phi may grow before it is widened.
+const WIDEN_AFTER: usize = 3;
+/// Number of rounds over all values narrowing their ranges after widening.
+const NARROW_ROUNDS: usize = 2;
+
+/// Unsigned values from `lo` to `hi`, both included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Range {
+    pub lo: u64,
+    pub hi: u64,
+}
+
+impl Range {
+    pub fn new(lo: u64, hi: u64) -> Range {
+        Range { lo, hi }
+    }
+
+    fn full(width: u16) -> Range {
+        Range::new(0, mask(width))
+    }
+
+    /// The only value in the range, if there is one.
+    pub fn singleton(&self) -> Option<u64> {
+        if self.lo == self.hi {
+            Some(self.lo)
+        } else {
+            None
+        }
+    }
+
+    /// The values in both ranges, `None` if there are none.
+    fn intersect(&self, other: &Range) -> Option<Range> {
+        let (lo, hi) = (self.lo.max(other.lo), self.hi.min(other.hi));
+        if lo <= hi {
+            Some(Range::new(lo, hi))
+        } else {
+            None
+        }
+    }
+
+    /// The smallest range containing both.
+    fn union(&self, other: &Range) -> Range {
+        Range::new(self.lo.min(other.lo), self.hi.max(other.hi))
+    }
+
+    /// Whether all values are in the same half when read as signed `width` bit
+    /// numbers, in which case signed and unsigned comparisons agree on them.
+    fn same_sign(&self, width: u16) -> bool {
+        let smax = mask(width) >> 1;
+        self.hi <= smax || self.lo > smax
+    }
+
+    /// The smallest signed range containing the values read as `width` bit numbers.
+    fn signed(&self, width: u16) -> (i64, i64) {
+        let smax = mask(width) >> 1;
+        if self.same_sign(width) {
+            (sign_extend(self.lo, width), sign_extend(self.hi, width))
+        } else {
+            (sign_extend(smax + 1, width), smax as i64)
+        }
+    }
+}
+
+/// A condition holding in a block, as the selector of a branch and the side taken.
+type Condition = (SSAValue, bool);
+
+/// Ranges of the values in an `SSAStorage`, and the branches always taking the same
+/// side.
+#[derive(Debug)]
+pub struct RangeAnalysis {
+    ranges: HashMap<SSAValue, Range>,
+    branches: HashMap<SSABlock, bool>,
+}
+
+/// Computes the ranges of the values in `ssa`.
+pub fn analyze(ssa: &SSAStorage) -> RangeAnalysis {
+    let mut analyzer = Analyzer::new(ssa);
+    analyzer.run();
+    let branches = analyzer.branches();
+    radeco_trace!("ranges|{} values, {} branches decided",
+                  analyzer.ranges.len(),
+                  branches.len());
+    RangeAnalysis {
+        ranges: analyzer.ranges,
+        branches: branches,
+    }
+}
+
+impl RangeAnalysis {
+    /// Range of `value` wherever it is used. `None` if it is wider than 64 bits or
+    /// never computed.
+    pub fn range(&self, value: SSAValue) -> Option<Range> {
+        self.ranges.get(&value).cloned()
+    }
+
+    /// The value of `value`, if its range holds a single one.
+    pub fn constant(&self, value: SSAValue) -> Option<u64> {
+        self.range(value).and_then(|r| r.singleton())
+    }
+
+    /// The side the conditional branch at the end of `block` always takes, if any.
+    pub fn branch(&self, block: SSABlock) -> Option<bool> {
+        self.branches.get(&block).cloned()
+    }
+
+    /// Every block whose conditional branch always takes the same side, and that side.
+    pub fn branches(&self) -> &HashMap<SSABlock, bool> {
+        &self.branches
+    }
+}
+
+struct Analyzer<'a> {
+    ssa: &'a SSAStorage,
+    /// Ranges computed so far, values missing are not reached yet
+    ranges: HashMap<SSAValue, Range>,
+    /// Dominators of each block reachable from the entry, including itself
+    doms: HashMap<SSABlock, HashSet<SSABlock>>,
+    /// Conditions holding in each block reachable from the entry
+    scopes: HashMap<SSABlock, Vec<Condition>>,
+    /// Constants compared against and their neighbours, in ascending order
+    thresholds: Vec<u64>,
+}
+
+impl<'a> Analyzer<'a> {
+    fn new(ssa: &'a SSAStorage) -> Analyzer<'a> {
+        let mut analyzer = Analyzer {
+            ssa: ssa,
+            ranges: HashMap::new(),
+            doms: ssa.entry_node().map_or_else(HashMap::new, |entry| dominators(ssa, entry)),
+            scopes: HashMap::new(),
+            thresholds: Vec::new(),
+        };
+        let scopes = analyzer.doms
+                             .iter()
+                             .map(|(&block, doms)| {
+                                 let scope = doms.iter()
+                                                 .filter_map(|&d| analyzer.entry_condition(d))
+                                                 .collect();
+                                 (block, scope)
+                             })
+                             .collect();
+        analyzer.scopes = scopes;
+
+        for value in ssa.values() {
+            match ssa.opcode(value) {
+                Some(MOpcode::OpEq) | Some(MOpcode::OpLt) | Some(MOpcode::OpGt) |
+                Some(MOpcode::OpSLt(_)) | Some(MOpcode::OpSGt(_)) => {}
+                _ => continue,
+            }
+            for c in ssa.operands_of(value).into_iter().filter_map(|op| ssa.constant(op)) {
+                analyzer.thresholds.extend(&[c.wrapping_sub(1), c, c.wrapping_add(1)]);
+            }
+        }
+        analyzer.thresholds.sort();
+        analyzer.thresholds.dedup();
+        analyzer
+    }
+
+    /// Computes the ranges in an ascending phase, widening phis which keep growing,
+    /// followed by a few rounds of narrowing.
+    fn run(&mut self) {
+        let values = self.ssa
+                         .values()
+                         .into_iter()
+                         .filter(|&v| width_of(self.ssa, v).is_some())
+                         .collect::<Vec<_>>();
+        let dependents = self.dependents(&values);
+        let mut grown = HashMap::new();
+        let mut worklist = values.iter().cloned().collect::<VecDeque<_>>();
+        let mut queued = values.iter().cloned().collect::<HashSet<_>>();
+        while let Some(value) = worklist.pop_front() {
+            queued.remove(&value);
+            let width = match width_of(self.ssa, value) {
+                Some(width) => width,
+                None => continue,
+            };
+            let new = match self.transfer(value, width) {
+                Some(new) => new,
+                None => continue,
+            };
+            let old = self.ranges.get(&value).cloned();
+            let mut range = old.map_or(new, |old| old.union(&new));
+            if Some(range) == old {
+                continue;
+            }
+            if let (Some(old), true) = (old, self.ssa.is_phi(value)) {
+                let count = grown.entry(value).or_insert(0);
+                *count += 1;
+                if *count > WIDEN_AFTER {
+                    range = self.widen(old, range, width);
+                }
+            }
+            self.ranges.insert(value, range);
+            let users = self.ssa.uses_of(value);
+            let others = dependents.get(&value).map_or(&[][..], |d| &d[..]);
+            for &user in users.iter().chain(others) {
+                if queued.insert(user) {
+                    worklist.push_back(user);
+                }
+            }
+        }
+
+        for _ in 0..NARROW_ROUNDS {
+            for &value in &values {
+                let old = match self.ranges.get(&value) {
+                    Some(&old) => old,
+                    None => continue,
+                };
+                let width = width_of(self.ssa, value).unwrap_or(64);
+                match self.transfer(value, width).and_then(|new| new.intersect(&old)) {
+                    Some(range) => self.ranges.insert(value, range),
+                    None => self.ranges.remove(&value),
+                };
+            }
+        }
+    }
+
+    /// Values whose ranges depend on more than their operands, i.e. on the selectors
+    /// of the conditions holding where they are computed and on what these compare.
+    fn dependents(&self, values: &[SSAValue]) -> HashMap<SSAValue, Vec<SSAValue>> {
+        let mut dependents = HashMap::new();
+        for &value in values {
+            let block = match self.ssa.block_for(value) {
+                Some(block) => block,
+                None => continue,
+            };
+            let mut conditions = self.scope(block).to_vec();
+            if self.ssa.is_phi(value) {
+                for pred in self.ssa.preds_of(block) {
+                    conditions.extend(self.scope(pred));
+                    conditions.extend(self.edge_condition(pred, block));
+                }
+            }
+            for (selector, _) in conditions {
+                for v in Some(selector).into_iter().chain(self.ssa.operands_of(selector)) {
+                    dependents.entry(v).or_insert_with(Vec::new).push(value);
+                }
+            }
+        }
+        dependents
+    }
+
+    fn scope(&self, block: SSABlock) -> &[Condition] {
+        self.scopes.get(&block).map_or(&[][..], |s| &s[..])
+    }
+
+    /// The condition under which `block` is entered, if it is only entered over one
+    /// side of a branch.
+    fn entry_condition(&self, block: SSABlock) -> Option<Condition> {
+        let preds = self.ssa.preds_of(block);
+        if preds.len() != 1 || self.ssa.incoming_edges(block).len() != 1 {
+            return None;
+        }
+        self.edge_condition(preds[0], block)
+    }
+
+    /// The condition under which control flows from `pred` to `block`.
+    fn edge_condition(&self, pred: SSABlock, block: SSABlock) -> Option<Condition> {
+        let selector = self.ssa.selector_in(pred)?;
+        let sides = self.ssa.conditional_blocks(pred)?;
+        if sides.true_side == sides.false_side {
+            None
+        } else if sides.true_side == block {
+            Some((selector, true))
+        } else if sides.false_side == block {
+            Some((selector, false))
+        } else {
+            None
+        }
+    }
+
+    /// Range of `value` so far, `None` if it is not reached yet. Values which are not
+    /// tracked may be anything.
+    fn range(&self, value: SSAValue) -> Option<Range> {
+        match width_of(self.ssa, value) {
+            Some(_) => self.ranges.get(&value).cloned(),
+            None => Some(Range::new(0, !0)),
+        }
+    }
+
+    /// Whether all of `conditions` may hold, as far as the ranges of their selectors go.
+    fn feasible(&self, conditions: &[Condition]) -> bool {
+        conditions.iter().all(|&(selector, taken)| {
+            match self.range(selector) {
+                Some(r) if taken => r.hi != 0,
+                Some(r) => r.lo == 0,
+                None => false,
+            }
+        })
+    }
+
+    /// Range of `value` where all of `conditions` hold, `None` if it cannot be
+    /// computed there.
+    fn refined(&self, value: SSAValue, conditions: &[Condition]) -> Option<Range> {
+        let mut range = self.range(value)?;
+        for &(selector, taken) in conditions {
+            range = self.refine(range, value, selector, taken)?;
+        }
+        Some(range)
+    }
+
+    /// Narrows `range` of `value` to the values for which `selector` is `taken`.
+    fn refine(&self, range: Range, value: SSAValue, selector: SSAValue, taken: bool)
+              -> Option<Range> {
+        if value == selector {
+            let holds = if taken { Range::new(1, !0) } else { Range::new(0, 0) };
+            return range.intersect(&holds);
+        }
+        let operands = self.ssa.operands_of(selector);
+        if operands.len() != 2 {
+            return Some(range);
+        }
+        let (other, left) = if operands[0] == value {
+            (operands[1], true)
+        } else if operands[1] == value {
+            (operands[0], false)
+        } else {
+            return Some(range);
+        };
+        let other = match self.range(other) {
+            Some(other) => other,
+            None => return Some(range),
+        };
+        let width = width_of(self.ssa, value).unwrap_or(64);
+        // Whether `value < other` is what holds when the selector is true.
+        let less = match self.ssa.opcode(selector) {
+            Some(MOpcode::OpEq) => {
+                return if taken {
+                    range.intersect(&other)
+                } else {
+                    match other.singleton() {
+                        Some(c) if c == range.lo && c == range.hi => None,
+                        Some(c) if c == range.lo => Some(Range::new(c + 1, range.hi)),
+                        Some(c) if c == range.hi => Some(Range::new(range.lo, c - 1)),
+                        _ => Some(range),
+                    }
+                };
+            }
+            Some(MOpcode::OpLt) => left,
+            Some(MOpcode::OpGt) => !left,
+            // Signed comparisons only order values in the same half like unsigned ones.
+            Some(MOpcode::OpSLt(_)) | Some(MOpcode::OpSGt(_))
+                if !range.same_sign(width) || !other.same_sign(width) ||
+                   (range.hi > mask(width) >> 1) != (other.hi > mask(width) >> 1) => {
+                return Some(range);
+            }
+            Some(MOpcode::OpSLt(_)) => left,
+            Some(MOpcode::OpSGt(_)) => !left,
+            _ => return Some(range),
+        };
+        let holds = match (less, taken) {
+            (true, true) if other.hi == 0 => return None,
+            (true, true) => Range::new(0, other.hi - 1),
+            (false, true) if other.lo == !0 => return None,
+            (false, true) => Range::new(other.lo + 1, !0),
+            (true, false) => Range::new(other.lo, !0),
+            (false, false) => Range::new(0, other.hi),
+        };
+        range.intersect(&holds)
+    }
+
+    /// Range of what `value` computes from the ranges of its operands where it is
+    /// computed, `None` if none of them is reached yet.
+    fn transfer(&self, value: SSAValue, width: u16) -> Option<Range> {
+        if self.ssa.is_phi(value) {
+            return self.transfer_phi(value, width);
+        }
+        let opcode = match self.ssa.opcode(value) {
+            Some(opcode) => opcode,
+            None => return Some(Range::full(width)),
+        };
+        if let MOpcode::OpConst(c) = opcode {
+            let c = c & mask(width);
+            return Some(Range::new(c, c));
+        }
+        let conditions = self.ssa.block_for(value).map_or(&[][..], |b| self.scope(b));
+        if !self.feasible(conditions) {
+            return None;
+        }
+        let mut operands = Vec::new();
+        for op in self.ssa.operands_of(value) {
+            let range = self.refined(op, conditions)?;
+            operands.push((range, width_of(self.ssa, op).unwrap_or(64)));
+        }
+        Some(evaluate(&opcode, &operands, width))
+    }
+
+    /// The union of the operands of `phi`, each refined on the edges it may flow in
+    /// over. An operand flows in from the predecessors it is available in.
+    fn transfer_phi(&self, phi: SSAValue, width: u16) -> Option<Range> {
+        let block = self.ssa.block_for(phi)?;
+        let preds = self.ssa.preds_of(block);
+        let mut result: Option<Range> = None;
+        for op in self.ssa.operands_of(phi) {
+            let def = self.ssa.block_for(op);
+            let mut available = false;
+            for &pred in &preds {
+                match (def, self.doms.get(&pred)) {
+                    (Some(def), Some(doms)) if !doms.contains(&def) => continue,
+                    (_, None) => continue,
+                    _ => available = true,
+                }
+                let mut conditions = self.scope(pred).to_vec();
+                conditions.extend(self.edge_condition(pred, block));
+                if !self.feasible(&conditions) {
+                    continue;
+                }
+                if let Some(range) = self.refined(op, &conditions) {
+                    result = Some(result.map_or(range, |r| r.union(&range)));
+                }
+            }
+            if !available {
+                if let Some(range) = self.range(op) {
+                    result = Some(result.map_or(range, |r| r.union(&range)));
+                }
+            }
+        }
+        result.map(|r| clamp(r, width))
+    }
+
+    /// Widens the bounds of `new` which moved beyond `old` to the next threshold.
+    fn widen(&self, old: Range, new: Range, width: u16) -> Range {
+        let max = mask(width);
+        let lo = if new.lo < old.lo {
+            self.thresholds.iter().rev().cloned().find(|&t| t <= new.lo).unwrap_or(0)
+        } else {
+            old.lo
+        };
+        let hi = if new.hi > old.hi {
+            self.thresholds
+                .iter()
+                .cloned()
+                .chain(Some(max >> 1))
+                .filter(|&t| t >= new.hi && t <= max)
+                .min()
+                .unwrap_or(max)
+        } else {
+            old.hi
+        };
+        Range::new(lo, hi)
+    }
+
+    /// Branches whose selector is known within their block.
+    fn branches(&self) -> HashMap<SSABlock, bool> {
+        let mut branches = HashMap::new();
+        for (&block, conditions) in &self.scopes {
+            let selector = match self.ssa.selector_in(block) {
+                Some(selector) => selector,
+                None => continue,
+            };
+            if self.ssa.conditional_blocks(block).is_none() ||
+               width_of(self.ssa, selector).is_none() || !self.feasible(conditions) {
+                continue;
+            }
+            match self.refined(selector, conditions) {
+                Some(r) if r.hi == 0 => {
+                    branches.insert(block, false);
+                }
+                Some(r) if r.lo != 0 => {
+                    branches.insert(block, true);
+                }
+                _ => {}
+            }
+        }
+        branches
+    }
+}
+
+/// Computes the dominators of the blocks reachable from `entry`, each including the
+/// block itself.
+fn dominators(ssa: &SSAStorage, entry: SSABlock) -> HashMap<SSABlock, HashSet<SSABlock>> {
+    // `DomTree` expects every node to be reachable from the start node.
+    let mut g = Graph::<SSABlock, ()>::new();
+    let mut indices = HashMap::new();
+    indices.insert(entry, g.add_node(entry));
+    let mut worklist = vec![entry];
+    while let Some(block) = worklist.pop() {
+        for succ in ssa.succs_of(block) {
+            if !indices.contains_key(&succ) {
+                indices.insert(succ, g.add_node(succ));
+                worklist.push(succ);
+            }
+            g.add_edge(indices[&block], indices[&succ], ());
+        }
+    }
+    let tree = DomTree::build_dom_tree(&g, indices[&entry]);
+    indices.values()
+           .map(|&i| (g[i], tree.doms(i).into_iter().map(|d| g[d]).collect()))
+           .collect()
+}
+
+/// Width of `value` if it is tracked.
+fn width_of(ssa: &SSAStorage, value: SSAValue) -> Option<u16> {
+    match ssa.node_data(value).ok()?.vt.width().get_width() {
+        Some(width) if width > 0 && width <= 64 => Some(width),
+        _ => None,
+    }
+}
+
+fn mask(width: u16) -> u64 {
+    if width >= 64 { !0 } else { (1 << width) - 1 }
+}
+
+fn sign_extend(value: u64, width: u16) -> i64 {
+    let shift = 64 - width as u32;
+    ((value << shift) as i64) >> shift
+}
+
+/// All bits up to the highest one set in `value`.
+fn smear(value: u64) -> u64 {
+    if value == 0 { 0 } else { !0 >> value.leading_zeros() }
+}
+
+/// `range` if it fits in `width` bits, all of them otherwise.
+fn clamp(range: Range, width: u16) -> Range {
+    if range.hi <= mask(width) {
+        range
+    } else {
+        Range::full(width)
+    }
+}
+
+/// Values from `lo` to `hi` modulo `2^width`, all of them if these wrap around in
+/// between.
+fn wrap(lo: i128, hi: i128, width: u16) -> Range {
+    let (size, span) = (1i128 << width, hi - lo);
+    if span >= size {
+        return Range::full(width);
+    }
+    let lo = ((lo % size) + size) % size;
+    let hi = lo + span;
+    if hi < size {
+        Range::new(lo as u64, hi as u64)
+    } else {
+        Range::full(width)
+    }
+}
+
+/// Range of what `opcode` computes from operands in the given ranges and of the given
+/// widths, as a `width` bit value.
+fn evaluate(opcode: &MOpcode, operands: &[(Range, u16)], width: u16) -> Range {
+    let max = mask(width);
+    let full = Range::full(width);
+    let (a, wa) = match operands.get(0) {
+        Some(&operand) => operand,
+        None => return full,
+    };
+    let b = operands.get(1).map_or(Range::new(0, !0), |o| o.0);
+    let flag = |always: bool, never: bool| {
+        if always {
+            Range::new(1, 1)
+        } else if never {
+            Range::new(0, 0)
+        } else {
+            Range::new(0, 1)
+        }
+    };
+    let range = match *opcode {
+        MOpcode::OpAdd => wrap(a.lo as i128 + b.lo as i128, a.hi as i128 + b.hi as i128, width),
+        MOpcode::OpSub => wrap(a.lo as i128 - b.hi as i128, a.hi as i128 - b.lo as i128, width),
+        MOpcode::OpMul => {
+            if a.hi as u128 * b.hi as u128 <= max as u128 {
+                Range::new(a.lo * b.lo, a.hi * b.hi)
+            } else {
+                full
+            }
+        }
+        MOpcode::OpDiv => Range::new(a.lo / b.hi.max(1), a.hi / b.lo.max(1)),
+        MOpcode::OpMod if a.hi < b.lo => a,
+        MOpcode::OpMod => Range::new(0, a.hi.min(b.hi.max(1) - 1)),
+        MOpcode::OpAnd => Range::new(0, a.hi.min(b.hi)),
+        MOpcode::OpOr => Range::new(a.lo.max(b.lo), smear(a.hi | b.hi)),
+        MOpcode::OpXor => Range::new(0, smear(a.hi | b.hi)),
+        MOpcode::OpNot if a.hi <= max => Range::new(max - a.hi, max - a.lo),
+        MOpcode::OpLsl => {
+            match b.singleton() {
+                Some(s) if s < width as u64 && (a.hi as u128) << s <= max as u128 => {
+                    Range::new(a.lo << s, a.hi << s)
+                }
+                _ => full,
+            }
+        }
+        MOpcode::OpLsr if b.hi < width as u64 => Range::new(a.lo >> b.hi, a.hi >> b.lo),
+        MOpcode::OpLsr => Range::new(0, a.hi),
+        MOpcode::OpNarrow(_) if a.lo & !max == a.hi & !max => Range::new(a.lo & max, a.hi & max),
+        MOpcode::OpZeroExt(_) => a,
+        MOpcode::OpSignExt(_) if a.hi <= mask(wa) && a.same_sign(wa) => {
+            let (lo, hi) = a.signed(wa);
+            Range::new(lo as u64 & max, hi as u64 & max)
+        }
+        MOpcode::OpEq => {
+            let equal = a.singleton().is_some() && a.singleton() == b.singleton();
+            flag(equal, a.intersect(&b).is_none())
+        }
+        MOpcode::OpLt => flag(a.hi < b.lo, a.lo >= b.hi),
+        MOpcode::OpGt => flag(a.lo > b.hi, a.hi <= b.lo),
+        MOpcode::OpSLt(_) | MOpcode::OpSGt(_) if a.hi <= mask(wa) && b.hi <= mask(wa) => {
+            let ((alo, ahi), (blo, bhi)) = (a.signed(wa), b.signed(wa));
+            if let MOpcode::OpSLt(_) = *opcode {
+                flag(ahi < blo, alo >= bhi)
+            } else {
+                flag(alo > bhi, ahi <= blo)
+            }
+        }
+        MOpcode::OpITE => {
+            match (operands.get(1), operands.get(2)) {
+                (Some(&(t, _)), Some(&(e, _))) => t.union(&e),
+                _ => full,
+            }
+        }
+        MOpcode::OpPopCount => Range::new(0, wa as u64),
+        _ => full,
+    };
+    clamp(range, width)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir::MAddress;
+    use middle::ir_reader::test_util::{at, parse};
+
+    // Counts `%2` from 0 to 16, then checks whether it exceeds 32, which it never
+    // does.
+    const LOOP: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+    bb_0x001000.0000(sz 0x4):
+        JMP 0x001004.0000
+    bb_0x001004.0000(sz 0x8):
+        %2: $Unknown64 = Phi(#x0, %3);
+        [@0x001004.0000] %3: $Unknown64 = %2 + #x1;
+        [@0x001008.0000] %4: $Unknown1 = %3 < #x10;
+        JMP IF %4 0x001004.0000 ELSE 0x00100C.0000
+    bb_0x00100C.0000(sz 0x4):
+        [@0x00100C.0000] %5: $Unknown1 = %2 > #x20;
+        [@0x00100C.0001] %6: $Unknown64 = #x7 + #x0;
+        JMP IF %5 0x001010.0000 ELSE 0x001014.0000
+    bb_0x001010.0000(sz 0x4):
+        [@0x001010.0000] %7: $Unknown64 = %1 + #x1;
+        JMP 0x001014.0000
+    bb_0x001014.0000(sz 0x4):
+        %8: $Unknown64 = Phi(%6, %7);
+        [@0x001014.0000] %9: $Unknown64 = %8 + %3;
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $rax = %9;
+}
+";
+
+    fn block(ssa: &SSAStorage, address: u64) -> SSABlock {
+        ssa.blocks()
+           .into_iter()
+           .find(|&b| ssa.starting_address(b) == Some(MAddress::new(address, 0)))
+           .unwrap()
+    }
+
+    #[test]
+    fn ranges_test() {
+        let ssa = parse(LOOP);
+        let ranges = analyze(&ssa);
+
+        let counter = ssa.block_for(at(&ssa, 0x1004, 0))
+                         .and_then(|b| ssa.phis_in(b).into_iter().next())
+                         .unwrap();
+        assert_eq!(ranges.range(counter), Some(Range::new(0, 15)));
+        assert_eq!(ranges.range(at(&ssa, 0x1004, 0)), Some(Range::new(1, 16)));
+        assert_eq!(ranges.range(at(&ssa, 0x1008, 0)), Some(Range::new(0, 1)));
+        assert_eq!(ranges.constant(at(&ssa, 0x100C, 0)), Some(0));
+        assert_eq!(ranges.branch(block(&ssa, 0x1004)), None);
+        assert_eq!(ranges.branch(block(&ssa, 0x100C)), Some(false));
+        assert_eq!(ranges.branches().len(), 1);
+
+        // The block after the branch which is never taken does not reach the phi,
+        // and the counter leaves the loop at 16.
+        assert_eq!(ranges.range(at(&ssa, 0x1010, 0)), None);
+        assert_eq!(ranges.constant(at(&ssa, 0x1014, 0)), Some(23));
+    }
+
+    #[test]
+    fn evaluate_test() {
+        let r = |lo, hi| (Range::new(lo, hi), 8);
+        assert_eq!(evaluate(&MOpcode::OpAdd, &[r(250, 255), r(1, 2)], 8), Range::full(8));
+        assert_eq!(evaluate(&MOpcode::OpAdd, &[r(250, 252), r(10, 12)], 8), Range::new(4, 8));
+        assert_eq!(evaluate(&MOpcode::OpSub, &[r(1, 2), r(3, 3)], 8), Range::new(254, 255));
+        assert_eq!(evaluate(&MOpcode::OpAnd, &[r(0, 255), r(15, 15)], 8), Range::new(0, 15));
+        assert_eq!(evaluate(&MOpcode::OpLsr, &[r(16, 255), r(4, 4)], 8), Range::new(1, 15));
+        assert_eq!(evaluate(&MOpcode::OpSignExt(16), &[r(0x80, 0x90)], 16),
+                   Range::new(0xff80, 0xff90));
+        assert_eq!(evaluate(&MOpcode::OpSLt(8), &[r(0x80, 0xff), r(0, 0x10)], 1),
+                   Range::new(1, 1));
+        assert_eq!(evaluate(&MOpcode::OpLt, &[r(0x80, 0xff), r(0, 0x10)], 1),
+                   Range::new(0, 0));
+    }
+}
@@ -0,0 +1,409 @@
+//! Recovers the layout of records from memory accesses at constant offsets from a
+//! common base pointer.
+//!
+//! Every load and store address is split into a base value and a constant offset.
+//! Bases are clustered across a module: the operands of a phi are the same pointer
+//! as the phi, and a value passed in an argument register is the same pointer as
+//! the value of that register on entry to the function called. Each cluster accessed
+//! at two or more distinct offsets becomes a record with one field per offset, which
+//! the C backend prints as `base->field_<offset>`.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+
+use petgraph::unionfind::UnionFind;
+
+use analysis::vsa::abstract_set::strided_interval::StridedInterval;
+use analysis::vsa::analyzer;
+use analysis::vsa::value_set::MemRegion;
+use frontend::radeco_containers::RadecoModule;
+use middle::ir::MOpcode;
+use middle::ssa::cfg_traits::CFG;
+use middle::ssa::ssa_traits::*;
+use middle::ssa::ssastorage::SSAStorage;
+use middle::ssa::utils;
+
+pub type SSAValue = <SSAStorage as SSA>::ValueRef;
+
+/// Accesses further than this from their base are not taken to be fields.
+const MAX_RECORD_SIZE: u64 = 0x1000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub offset: u64,
+    /// Width in bits.
+    pub width: u16,
+}
+
+impl Field {
+    pub fn name(&self) -> String {
+        format!("field_{:x}", self.offset)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    /// Fields sorted by offset, none of them overlapping.
+    pub fields: Vec<Field>,
+}
+
+impl Record {
+    pub fn field_at(&self, offset: u64) -> Option<&Field> {
+        self.fields.iter().find(|f| f.offset == offset)
+    }
+
+    /// C definition of the record, padding the gaps between fields.
+    pub fn to_c(&self) -> String {
+        let mut s = format!("struct {} {{\n", self.name);
+        let mut end = 0;
+        for field in &self.fields {
+            if field.offset > end {
+                let _ = writeln!(s, "    char pad_{:x}[{}];", end, field.offset - end);
+            }
+            let _ = writeln!(s, "    uint{}_t {};", field.width, field.name());
+            end = field.offset + field.width as u64 / 8;
+        }
+        s.push_str("};");
+        s
+    }
+}
+
+/// Access to a field of a record, recorded for the value of its address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldAccess {
+    /// Pointer to the record.
+    pub base: SSAValue,
+    /// Name of the record.
+    pub record: String,
+    /// Name of the field.
+    pub field: String,
+}
+
+/// Recovers the records accessed by the functions of `rmod`, storing the field
+/// accesses of each function in it.
+pub fn run(rmod: &mut RadecoModule) -> Vec<Record> {
+    let (records, mut accesses) = {
+        let ssas = rmod.functions
+                       .iter()
+                       .map(|(&addr, rfn)| (addr, rfn.ssa()))
+                       .collect::<Vec<_>>();
+        recover(&ssas)
+    };
+    for (addr, rfn) in rmod.functions.iter_mut() {
+        rfn.set_field_accesses(accesses.remove(addr).unwrap_or_default());
+    }
+    radeco_trace!("records|Recovered {} records", records.len());
+    records
+}
+
+/// Recovers the records accessed by `functions`, given by their address, and the field
+/// accesses of each of them.
+pub fn recover(functions: &[(u64, &SSAStorage)])
+               -> (Vec<Record>, HashMap<u64, HashMap<SSAValue, FieldAccess>>) {
+    // Every value of every function gets a key in the union-find.
+    let mut keys = HashMap::new();
+    for &(addr, ssa) in functions {
+        for value in ssa.values() {
+            let key = keys.len();
+            keys.insert((addr, value), key);
+        }
+    }
+    let mut clusters = UnionFind::new(keys.len());
+
+    let mut memory_accesses = Vec::new();
+    for &(addr, ssa) in functions {
+        let frame = frame_pointers(ssa);
+        for value in ssa.values() {
+            if ssa.is_phi(value) {
+                for operand in ssa.operands_of(value) {
+                    clusters.union(keys[&(addr, value)], keys[&(addr, operand)]);
+                }
+            }
+            let width = match ssa.opcode(value) {
+                Some(MOpcode::OpLoad(width, _)) | Some(MOpcode::OpStore(width, _)) => width,
+                Some(MOpcode::OpCall) => {
+                    union_arguments(functions, &keys, &mut clusters, addr, ssa, value);
+                    continue;
+                }
+                _ => continue,
+            };
+            let address = match ssa.operands_of(value).get(1) {
+                Some(&address) => address,
+                None => continue,
+            };
+            if let Some((base, offset)) = base_offset(ssa, &frame, address) {
+                memory_accesses.push((addr, address, base, offset, width));
+            }
+        }
+    }
+
+    // Fields of each cluster, ordered by cluster for stable names.
+    let mut layouts = BTreeMap::new();
+    for &(addr, _, base, offset, width) in &memory_accesses {
+        let cluster = clusters.find(keys[&(addr, base)]);
+        let layout = layouts.entry(cluster).or_insert_with(BTreeMap::new);
+        let field_width = layout.entry(offset).or_insert(width);
+        *field_width = (*field_width).max(width);
+    }
+    let mut records = Vec::new();
+    let mut record_of = HashMap::new();
+    for (cluster, layout) in layouts {
+        if layout.len() < 2 {
+            continue;
+        }
+        let mut fields: Vec<Field> = Vec::new();
+        for (offset, width) in layout {
+            let overlaps = fields.last()
+                                 .map_or(false, |f| f.offset + f.width as u64 / 8 > offset);
+            if overlaps {
+                radeco_trace!("records|Dropping field at {:#x} overlapping another", offset);
+                continue;
+            }
+            fields.push(Field {
+                offset: offset,
+                width: width,
+            });
+        }
+        record_of.insert(cluster, records.len());
+        records.push(Record {
+            name: format!("struct_{}", records.len()),
+            fields: fields,
+        });
+    }
+
+    let mut accesses: HashMap<u64, HashMap<SSAValue, FieldAccess>> = HashMap::new();
+    for (addr, address, base, offset, width) in memory_accesses {
+        let record = match record_of.get(&clusters.find(keys[&(addr, base)])) {
+            Some(&record) => &records[record],
+            None => continue,
+        };
+        match record.field_at(offset) {
+            Some(field) if field.width == width => {
+                let access = FieldAccess {
+                    base: base,
+                    record: record.name.clone(),
+                    field: field.name(),
+                };
+                accesses.entry(addr).or_insert_with(HashMap::new).insert(address, access);
+            }
+            _ => {}
+        }
+    }
+    (records, accesses)
+}
+
+/// Splits `address` into a base pointer and a constant offset from it. `None` if the
+/// address is constant, too far from the base, or relative to the stack frame, i.e.
+/// the base is one of `frame`.
+fn base_offset(ssa: &SSAStorage, frame: &HashSet<SSAValue>, address: SSAValue)
+               -> Option<(SSAValue, u64)> {
+    let mut base = address;
+    let mut offset = 0u64;
+    loop {
+        let operands = ssa.operands_of(base);
+        match ssa.opcode(base) {
+            Some(MOpcode::OpAdd) => {
+                if let Some(c) = ssa.constant(operands[1]) {
+                    offset = offset.wrapping_add(c);
+                    base = operands[0];
+                } else if let Some(c) = ssa.constant(operands[0]) {
+                    offset = offset.wrapping_add(c);
+                    base = operands[1];
+                } else {
+                    break;
+                }
+            }
+            Some(MOpcode::OpSub) => {
+                match ssa.constant(operands[1]) {
+                    Some(c) => {
+                        offset = offset.wrapping_sub(c);
+                        base = operands[0];
+                    }
+                    None => break,
+                }
+            }
+            _ => break,
+        }
+    }
+    if ssa.is_constant(base) || offset >= MAX_RECORD_SIZE || frame.contains(&base) {
+        None
+    } else {
+        Some((base, offset))
+    }
+}
+
+/// Values which point into the stack frame by value set analysis: the stack and frame
+/// pointers as they are on entry, restored by calls or merged by phis, and the addresses
+/// computed from them.
+fn frame_pointers(ssa: &SSAStorage) -> HashSet<SSAValue> {
+    let vsa = analyzer::analyze::<StridedInterval>(ssa, &HashSet::new());
+    vsa.value_sets()
+       .iter()
+       .filter(|&(_, vs)| vs.offsets(MemRegion::Stack).is_some())
+       .map(|(&value, _)| value)
+       .collect()
+}
+
+/// Unions the arguments passed by `call` with the values of their registers on entry
+/// to the function called, if it is one of `functions`.
+fn union_arguments(functions: &[(u64, &SSAStorage)],
+                   keys: &HashMap<(u64, SSAValue), usize>,
+                   clusters: &mut UnionFind<usize>,
+                   caller: u64,
+                   ssa: &SSAStorage,
+                   call: SSAValue) {
+    let info = match utils::call_info(call, ssa) {
+        Some(info) => info,
+        None => return,
+    };
+    let (callee, callee_ssa) = match ssa.constant(info.target)
+                                        .and_then(|t| functions.iter().find(|f| f.0 == t)) {
+        Some(&(callee, callee_ssa)) => (callee, callee_ssa),
+        None => return,
+    };
+    let entry_regs = match callee_ssa.entry_node().and_then(|e| callee_ssa.registers_in(e)) {
+        Some(regstate) => utils::register_state_info(regstate, callee_ssa),
+        None => return,
+    };
+    for (reg, &arg) in info.register_args.iter() {
+        if let (Some(&(param, _)), Some(&arg_key)) = (entry_regs.get(reg),
+                                                       keys.get(&(caller, arg))) {
+            clusters.union(arg_key, keys[&(callee, param)]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir_reader::test_util::{at, parse};
+
+    // Reads the fields at 0x10 and 0x18 of the record `%1` points to and passes the
+    // pointer on to the function at 0x2000.
+    const CALLER: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+        %2: $Unknown64 = $rsp;
+        %3: $Unknown0 = $mem;
+    bb_0x001000.0000(sz 0x14):
+        [@0x001000.0000] %4: $Unknown64 = %1 + #x10;
+        [@0x001000.0001] %5: $Unknown64 = Load64(%3, %4);
+        [@0x001004.0000] %6: $Unknown64 = %1 + #x18;
+        [@0x001004.0001] %7: $Unknown32 = Load32(%3, %6);
+        [@0x001008.0000] %8: $Unknown64 = %2 - #x8;
+        [@0x001008.0001] %9: $Unknown64 = Load64(%3, %8);
+        [@0x00100C.0000] %10: $Unknown64 = %2 + #x10;
+        [@0x00100C.0001] %11: $Unknown64 = Load64(%3, %10);
+        [@0x001010.0000] (%12: $Unknown0 = $mem) = CALL #x2000($rdi=%1, $mem=%3);
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $mem = %12;
+        $rax = %5;
+}
+";
+
+    // Writes the field at 0x8 of the record its argument points to.
+    const CALLEE: &'static str = "\
+define-fun callee(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+        %2: $Unknown0 = $mem;
+    bb_0x002000.0000(sz 0x4):
+        [@0x002000.0000] %3: $Unknown64 = %1 + #x8;
+        [@0x002000.0001] %4: $Unknown0 = Store16(%2, %3, #x0);
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $mem = %4;
+}
+";
+
+    // Reads at constant offsets from the stack pointer restored by a call and from a phi
+    // of it, which are slots of the stack frame rather than fields.
+    const FRAME: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+        %2: $Unknown64 = $rsp;
+        %3: $Unknown0 = $mem;
+    bb_0x001000.0000(sz 0x8):
+        [@0x001000.0000] %4: $Unknown64 = %2 - #x20;
+        [@0x001004.0000] (%5: $Unknown64 = $rsp, %6: $Unknown0 = $mem) = CALL #x2000($rdi=%1, $rsp=%4, $mem=%3);
+        [@0x001004.0001] %7: $Unknown1 = %1 == #x0;
+        JMP IF %7 0x001008.0000 ELSE 0x00100C.0000
+    bb_0x001008.0000(sz 0x4):
+        [@0x001008.0000] %8: $Unknown64 = %5 - #x10;
+        JMP 0x00100C.0000
+    bb_0x00100C.0000(sz 0x10):
+        %9: $Unknown64 = Phi(%5, %8);
+        [@0x00100C.0000] %10: $Unknown64 = %5 + #x8;
+        [@0x00100C.0001] %11: $Unknown64 = Load64(%6, %10);
+        [@0x001010.0000] %12: $Unknown64 = %5 + #x10;
+        [@0x001010.0001] %13: $Unknown64 = Load64(%6, %12);
+        [@0x001014.0000] %14: $Unknown64 = %9 + #x8;
+        [@0x001014.0001] %15: $Unknown64 = Load64(%6, %14);
+        [@0x001018.0000] %16: $Unknown64 = %9 + #x18;
+        [@0x001018.0001] %17: $Unknown64 = Load64(%6, %16);
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $mem = %6;
+        $rax = %17;
+}
+";
+
+    fn field(offset: u64, width: u16) -> Field {
+        Field {
+            offset: offset,
+            width: width,
+        }
+    }
+
+    #[test]
+    fn intraprocedural_test() {
+        let ssa = parse(CALLER);
+        let (records, accesses) = recover(&[(0x1000, &ssa)]);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].fields, vec![field(0x10, 64), field(0x18, 32)]);
+        let accesses = &accesses[&0x1000];
+        assert_eq!(accesses.len(), 2);
+        assert_eq!(accesses[&at(&ssa, 0x1004, 0)].field, "field_18");
+        // Stack slots are no fields.
+        assert!(!accesses.contains_key(&at(&ssa, 0x100C, 0)));
+    }
+
+    #[test]
+    fn frame_pointer_test() {
+        let ssa = parse(FRAME);
+        let (records, accesses) = recover(&[(0x1000, &ssa)]);
+        assert!(records.is_empty());
+        assert!(accesses.get(&0x1000).map_or(true, |a| a.is_empty()));
+    }
+
+    #[test]
+    fn interprocedural_test() {
+        let caller = parse(CALLER);
+        let callee = parse(CALLEE);
+        let (records, accesses) = recover(&[(0x1000, &caller), (0x2000, &callee)]);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].fields,
+                   vec![field(0x8, 16), field(0x10, 64), field(0x18, 32)]);
+        let access = &accesses[&0x2000][&at(&callee, 0x2000, 0)];
+        assert_eq!(access.record, records[0].name);
+        assert_eq!(access.field, "field_8");
+    }
+
+    #[test]
+    fn to_c_test() {
+        let record = Record {
+            name: "struct_0".to_owned(),
+            fields: vec![field(0, 32), field(0x8, 64)],
+        };
+        assert_eq!(record.to_c(),
+                   "struct struct_0 {\n    uint32_t field_0;\n    char pad_4[4];\n    \
+                    uint64_t field_8;\n};");
+    }
+}
@@ -16,6 +16,7 @@ pub mod reference_marking_inter;
 
 use frontend::radeco_containers::{RadecoFunction, CallContextInfo};
 use middle::regfile::SubRegisterFile;
+use middle::ssa::cfg_traits::CFG;
 use middle::ssa::ssa_traits::{SSA, ValueType};
 use petgraph::graph::NodeIndex;
 use r2api::structs::LSectionInfo;
@@ -116,26 +117,114 @@ impl Propagate for ReferenceMarker {
         }
     }
 
+    // The values of the registers at entry and exit are the ones that callsites map to.
     fn summary(analyzer: &mut ReferenceMarker,
                rfn: &RadecoFunction)
                -> Option<ReferenceMarkerInfo> {
-        Some(ReferenceMarkerInfo(rfn.bindings()
-            .into_iter()
-            .filter(|x| x.btype.is_argument() || x.btype.is_return())
-            .map(|x| (x.idx, analyzer.cs.bvalue(x.idx)))
+        let ssa = rfn.ssa();
+        let interface = [ssa.entry_node(), ssa.exit_node()]
+            .iter()
+            .filter_map(|&node| node.and_then(|n| ssa.registers_in(n)))
+            .flat_map(|regstate| ssa.operands_of(regstate))
+            .collect::<Vec<_>>();
+        Some(ReferenceMarkerInfo(interface.into_iter()
+            .map(|idx| (idx, analyzer.cs.bvalue(idx)))
             .collect()))
     }
 
-    fn union(_analyzer: &mut ReferenceMarker, _infov: &[ReferenceMarkerInfo]) -> Option<Self::Info> {
-        unimplemented!()
+    fn union(_analyzer: &mut ReferenceMarker, infov: &[ReferenceMarkerInfo]) -> Option<Self::Info> {
+        if infov.is_empty() {
+            None
+        } else {
+            Some(infov.iter()
+                .fold(ReferenceMarkerInfo::default(),
+                      |acc, x| ReferenceMarkerInfo::eval(&acc, x)))
+        }
     }
 
     fn push(analyzer: &mut ReferenceMarker, info: Option<&ReferenceMarkerInfo>) -> bool {
+        let mut changed = false;
         if let Some(refinfo) = info {
             for (idx, vt) in &refinfo.0 {
-                analyzer.cs.add_eq(*idx, *vt);
+                // Nothing is learnt from unresolved values, and adding a value that is already
+                // known would keep the analyzer from converging.
+                if *vt != ValueType::Unresolved && analyzer.cs.bvalue(*idx) != *vt {
+                    analyzer.cs.add_eq(*idx, *vt);
+                    changed = true;
+                }
             }
         }
-        true
+        changed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir::MOpcode;
+    use middle::ir_reader::test_util::parse;
+
+    // Returns its first argument plus eight.
+    const OFFSET: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+    bb_0x001000.0000(sz 0x4):
+        [@0x001000.0000] %2: $Unknown64 = %1 + #x8;
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $rax = %2;
+}
+";
+
+    fn marker(rfn: &mut RadecoFunction) -> ReferenceMarker {
+        *rfn.ssa_mut() = parse(OFFSET);
+        let regfile = Arc::clone(&rfn.ssa().regfile);
+        ReferenceMarker::transfer(rfn, regfile, Arc::new(Vec::new()))
+    }
+
+    fn info(values: &[(NodeIndex, ValueType)]) -> ReferenceMarkerInfo {
+        ReferenceMarkerInfo(values.iter().cloned().collect())
+    }
+
+    #[test]
+    fn propagate_return_test() {
+        let mut rfn = RadecoFunction::default();
+        let mut marker = marker(&mut rfn);
+
+        let ret = rfn.ssa()
+                     .values()
+                     .into_iter()
+                     .find(|&n| rfn.ssa().opcode(n) == Some(MOpcode::OpAdd))
+                     .unwrap();
+        let arg = rfn.ssa().operands_of(ret)[0];
+        assert_eq!(marker.cs.bvalue(arg), ValueType::Unresolved);
+        let summary = ReferenceMarker::summary(&mut marker, &rfn).unwrap();
+        assert_eq!(summary.0.get(&ret), Some(&ValueType::Unresolved));
+
+        // A caller uses the returned value as a reference.
+        let callsites = [summary, info(&[(ret, ValueType::Reference)])];
+        let union = ReferenceMarker::union(&mut marker, &callsites);
+        assert!(ReferenceMarker::push(&mut marker, union.as_ref()));
+        assert!(!ReferenceMarker::transfer_iterative(&mut marker, &mut rfn));
+        assert!(rfn.ssa().node_data(ret).unwrap().vt.is_reference());
+        assert!(rfn.ssa().node_data(arg).unwrap().vt.is_reference());
+
+        // Nothing new is learnt from the same information.
+        assert!(!ReferenceMarker::push(&mut marker, union.as_ref()));
+        assert!(ReferenceMarker::transfer_iterative(&mut marker, &mut rfn));
+    }
+
+    #[test]
+    fn union_conflict_test() {
+        let (a, b) = (NodeIndex::new(0), NodeIndex::new(1));
+        let callsites = [info(&[(a, ValueType::Reference), (b, ValueType::Unresolved)]),
+                         info(&[(a, ValueType::Scalar), (b, ValueType::Scalar)])];
+        let mut marker = marker(&mut RadecoFunction::default());
+        let union = ReferenceMarker::union(&mut marker, &callsites).unwrap();
+        assert_eq!(union.0[&a], ValueType::Invalid);
+        assert_eq!(union.0[&b], ValueType::Scalar);
+        assert!(ReferenceMarker::union(&mut marker, &[]).is_none());
     }
 }
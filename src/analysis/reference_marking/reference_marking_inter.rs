@@ -205,22 +205,15 @@ impl<T: InterProcAnalysis> InterProceduralAnalyzer<T> {
                                              &mut infos);
             }
 
-            // Union all infos collected for a function. TODO: Parallelize as there is no
-            // dependency.
-            for infov in infos.values_mut() {
-                // XXX: Avoid allocation
-                *infov = vec![infov.iter()
-                                  .fold(T::Info::default(), |acc, x| T::Info::eval(&acc, &x))];
-            }
-
-            // Push the information down to the analyzers.
+            // Union all infos collected for a function and push the result down to its
+            // analyzer. TODO: Parallelize as there is no dependency.
             for analyzer_wrapper in analyzers.iter_mut() {
                 let offset = analyzer_wrapper.offset();
                 analyzer_wrapper.should_run = {
-                    let info = infos.get(&offset);
                     let analyzer = analyzer_wrapper.analyzer_mut();
-                    if let Some(ref inf) = info {
-                        T::push(analyzer, Some(&inf[0]))
+                    if let Some(infov) = infos.get(&offset) {
+                        let info = T::union(analyzer, infov);
+                        T::push(analyzer, info.as_ref())
                     } else {
                         // Assume that the analyzer should be run.
                         true
@@ -228,7 +221,9 @@ impl<T: InterProcAnalysis> InterProceduralAnalyzer<T> {
                 }
             }
 
-            // Continue analysis. TODO: Parallelize
+            // Continue analysis. The analysis reached a fixpoint once no analyzer can make
+            // further progress. TODO: Parallelize
+            fixpoint = true;
             for (wrapper, aw) in rmod.iter_mut().zip(analyzers.iter_mut()) {
                 // Check if the analyzer should be run for the current function.
                 if !aw.should_run() {
@@ -238,10 +233,17 @@ impl<T: InterProcAnalysis> InterProceduralAnalyzer<T> {
                 let analyzer = aw.analyzer_mut();
                 let (_, current_fn) = wrapper.function;
                 let fp = T::transfer_iterative(analyzer, current_fn);
-                fixpoint = fixpoint || fp;
+                fixpoint = fixpoint && fp;
             }
 
             infos.clear();
         }
+
+        if fixpoint {
+            radeco_trace!("reference_marking|Reached a fixpoint");
+        } else {
+            radeco_warn!("reference_marking|No fixpoint after {} iterations",
+                         n_iters.unwrap_or(u64::max_value()));
+        }
     }
 }
@@ -20,7 +20,7 @@
 //!   - Return from functions                           -- Added from inter-function propagation
 //!     + Returns from 'well-known' functions that return references, such as malloc.
 
-use analysis::constraint_set::ConstraintSet;
+use analysis::constraint_set::{ConstraintSet, Lattice};
 use frontend::radeco_containers::RadecoFunction;
 use middle::ir::MOpcode;
 use middle::regfile::SubRegisterFile;
@@ -31,9 +31,61 @@ use r2api::structs::LSectionInfo;
 use std::collections::HashSet;
 use std::sync::Arc;
 
+// The union of values is the result of an operation on them. Following assumption 1, it is a
+// reference if exactly one of them is a reference, and a scalar if all of them are scalars.
+impl Lattice for ValueType {
+    fn bottom() -> ValueType {
+        ValueType::Unresolved
+    }
+
+    fn top() -> ValueType {
+        ValueType::Invalid
+    }
+
+    fn join(&self, other: &ValueType) -> ValueType {
+        match (*self, *other) {
+            (ValueType::Unresolved, vt) |
+            (vt, ValueType::Unresolved) => vt,
+            (vt1, vt2) if vt1 == vt2 => vt1,
+            (_, _) => ValueType::Invalid,
+        }
+    }
+
+    fn union(lhs: ValueType, op1: ValueType, op2: ValueType) -> (ValueType, ValueType, ValueType) {
+        match (lhs, op1, op2) {
+            // Both, op1 and op2, are scalars
+            (ValueType::Scalar, _, _) => (ValueType::Scalar, ValueType::Scalar, ValueType::Scalar),
+            // Exactly one of op1 and op2 is a reference
+            (ValueType::Reference, ValueType::Reference, _) |
+            (ValueType::Reference, _, ValueType::Scalar) => {
+                (ValueType::Reference, ValueType::Reference, ValueType::Scalar)
+            }
+            (ValueType::Reference, _, ValueType::Reference) |
+            (ValueType::Reference, ValueType::Scalar, _) => {
+                (ValueType::Reference, ValueType::Scalar, ValueType::Reference)
+            }
+            (ValueType::Unresolved, ValueType::Invalid, _) |
+            (ValueType::Unresolved, _, ValueType::Invalid) |
+            (ValueType::Unresolved, ValueType::Reference, ValueType::Reference) => {
+                (ValueType::Invalid, op1, op2)
+            }
+            (ValueType::Unresolved, ValueType::Reference, _) => {
+                (ValueType::Reference, ValueType::Reference, ValueType::Scalar)
+            }
+            (ValueType::Unresolved, _, ValueType::Reference) => {
+                (ValueType::Reference, ValueType::Scalar, ValueType::Reference)
+            }
+            (ValueType::Unresolved, ValueType::Scalar, ValueType::Scalar) => {
+                (ValueType::Scalar, ValueType::Scalar, ValueType::Scalar)
+            }
+            (_, _, _) => (lhs, op1, op2),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ReferenceMarker {
-    pub cs: ConstraintSet<NodeIndex>,
+    pub cs: ConstraintSet<NodeIndex, ValueType>,
     regfile: Arc<SubRegisterFile>,
     sections: Arc<Vec<LSectionInfo>>,
 }
@@ -68,6 +120,14 @@ impl ReferenceMarker {
                         &MOpcode::OpAdd |
                         &MOpcode::OpGt |
                         &MOpcode::OpLt |
+                        &MOpcode::OpSGt(_) |
+                        &MOpcode::OpSLt(_) |
+                        &MOpcode::OpSDiv(_) |
+                        &MOpcode::OpSMod(_) |
+                        &MOpcode::OpMulHi(_) |
+                        &MOpcode::OpSMulHi(_) |
+                        &MOpcode::OpBSwap(_) |
+                        &MOpcode::OpPopCount |
                         &MOpcode::OpNot |
                         &MOpcode::OpOr |
                         &MOpcode::OpNarrow(_) |
@@ -120,8 +180,8 @@ impl ReferenceMarker {
                                 //self.cs.add_eq(*op2, ValueType::Scalar);
                             //}
                         //}
-                        &MOpcode::OpLoad |
-                        &MOpcode::OpStore => {
+                        &MOpcode::OpLoad(..) |
+                        &MOpcode::OpStore(..) => {
                             // Special case for load/store
                             let operands = ssa.operands_of(idx);
                             // Operand 0 is "mem", this is not a reference
@@ -189,19 +249,31 @@ impl ReferenceMarker {
         }
     }
 
+    // Returns true if no binding changed, i.e. the analysis of this function reached a fixpoint.
     pub fn resolve_references_iterative(&mut self, rfn: &mut RadecoFunction) -> bool {
-        let progress = self.cs.solve();
+        let before = self.cs.bindings.clone();
+        self.cs.solve();
+        self.mark(rfn);
+        // Bindings to `Unresolved` are only placeholders for nodes looked up while solving.
+        self.cs
+            .iter_bindings()
+            .all(|(ni, vt)| *vt == ValueType::Unresolved || before.get(ni) == Some(vt))
+    }
+
+    /// Stores the value types resolved so far in the `ValueInfo` of the nodes of `rfn`.
+    /// Nodes that are still unresolved keep their current value type.
+    pub fn mark(&self, rfn: &mut RadecoFunction) {
+        let ssa = rfn.ssa_mut();
         for (ni, vt) in self.cs.iter_bindings() {
-            let ssa = rfn.ssa_mut();
-            if let Some(ref mut nd) = ssa.g.node_weight_mut(*ni) {
-                if let Some(vi) = nd.valueinfo_mut() {
-                    vi.set_value_type(*vt);
+            if let Some(vi) = ssa.g.node_weight_mut(*ni).and_then(|nd| nd.valueinfo_mut()) {
+                match *vt {
+                    ValueType::Reference => vi.mark_as_reference(),
+                    ValueType::Scalar => vi.mark_as_scalar(),
+                    ValueType::Invalid => vi.mark_as_invalid(),
+                    ValueType::Unresolved => {}
                 }
             }
         }
-
-        // fixpoint = !progress
-        !progress
     }
 
     // Used for calling to resolve references the first time. Future calls should call
@@ -11,13 +11,23 @@
 //! perform the propagation. For more details, please refer:
 //!    * https://www.cs.utexas.edu/~lin/cs380c/wegman.pdf.
 //!
+//! Operations are folded at the width of the value they compute, see `fold_constants`.
+//! Values of registers on entry, results of calls and memory are never constant,
+//! except for loads reading back a store of a constant to the same address.
+//!
+//! Branches proven to always take the same side by `ranges` may be handed over with
+//! `Analyzer::ranges`, only that side is then marked executable.
+//!
 
 use std::collections::{HashMap, VecDeque};
-use std::u64;
+use std::sync::Arc;
+use analysis::ranges::RangeAnalysis;
+use frontend::rodata::ReadOnlyData;
 use middle::ssa::ssa_traits::{SSA, SSAMod};
 use middle::ssa::ssa_traits::{NodeData, NodeType, ValueInfo, ValueType};
-use middle::ssa::graph_traits::{Graph, ConditionInfo};
-use middle::ir::{MArity, MOpcode, WidthSpec, MAddress};
+use middle::ssa::graph_traits::Graph;
+use middle::ssa::ssastorage::SSAStorage;
+use middle::ir::{self, Endian, MOpcode, WidthSpec, MAddress};
 
 #[macro_export]
 macro_rules! node_data_from_g {
@@ -79,6 +89,55 @@ fn meet(v1: &LatticeValue, v2: &LatticeValue) -> LatticeValue {
     *v1
 }
 
+/// Evaluates `opcode` on constant `operands`, each given with its width, to a value of
+/// `width` bits. Returns `None` if the result is not defined, e.g. for a division by
+/// zero, or cannot be represented.
+///
+/// Arithmetic is done at `width` bits and wraps around, comparisons and extensions
+/// look at each operand at its own width. Shifts by at least `width` give zero.
+fn fold_constants(opcode: &MOpcode, operands: &[(u64, u16)], width: u16) -> Option<u64> {
+    if width == 0 || width > 64 || operands.iter().any(|&(_, w)| w == 0 || w > 64) {
+        return None;
+    }
+    let mask = ir::width_mask(width);
+    let arg = |i: usize| operands.get(i).map(|&(v, w)| v & ir::width_mask(w));
+    let val = match *opcode {
+        MOpcode::OpNarrow(_) | MOpcode::OpZeroExt(_) => arg(0)?,
+        MOpcode::OpSignExt(_) => {
+            let (v, w) = *operands.get(0)?;
+            ir::sign_extend(v, w) as u64
+        }
+        MOpcode::OpNot | MOpcode::OpPopCount | MOpcode::OpBSwap(_) => opcode.eval_unop(arg(0)?)?,
+        MOpcode::OpEq | MOpcode::OpGt | MOpcode::OpLt | MOpcode::OpSGt(_) |
+        MOpcode::OpSLt(_) | MOpcode::OpSDiv(_) | MOpcode::OpSMod(_) | MOpcode::OpMulHi(_) |
+        MOpcode::OpSMulHi(_) => {
+            opcode.eval_binop(arg(0)?, arg(1)?)?
+        }
+        MOpcode::OpRol | MOpcode::OpRor => opcode.eval_rotate(arg(0)?, arg(1)?, width)?,
+        MOpcode::OpLsl | MOpcode::OpLsr => {
+            let (lhs, amount) = (arg(0)? & mask, arg(1)?);
+            if amount >= width as u64 {
+                0
+            } else if *opcode == MOpcode::OpLsl {
+                lhs << amount
+            } else {
+                lhs >> amount
+            }
+        }
+        MOpcode::OpAdd | MOpcode::OpSub | MOpcode::OpMul | MOpcode::OpDiv |
+        MOpcode::OpMod | MOpcode::OpAnd | MOpcode::OpOr | MOpcode::OpXor => {
+            let (lhs, rhs) = (arg(0)? & mask, arg(1)? & mask);
+            match *opcode {
+                MOpcode::OpDiv | MOpcode::OpMod if rhs == 0 => return None,
+                _ => opcode.eval_binop(lhs, rhs)?,
+            }
+        }
+        MOpcode::OpITE => if arg(0)? != 0 { arg(1)? } else { arg(2)? },
+        _ => return None,
+    };
+    Some(val & mask)
+}
+
 pub struct Analyzer<T> 
     where T: Clone +
         SSAMod<ActionRef=<T as Graph>::GraphNodeRef, 
@@ -90,6 +149,9 @@ pub struct Analyzer<T>
     cfg_worklist: VecDeque<T::CFEdgeRef>,
     executable: HashMap<T::CFEdgeRef, bool>,
     expr_val: HashMap<T::ValueRef, LatticeValue>,
+    rodata: Option<Arc<ReadOnlyData>>,
+    /// Blocks whose branch always takes the same side, and that side
+    decided: HashMap<T::ActionRef, bool>,
     g: T,
 }
 
@@ -106,10 +168,18 @@ impl<T> Analyzer<T>
             cfg_worklist: VecDeque::new(),
             executable: HashMap::new(),
             expr_val: HashMap::new(),
+            rodata: None,
+            decided: HashMap::new(),
             g: g.clone(),
         }
     }
 
+    /// Folds loads from constant addresses in `rodata` to the values stored there.
+    pub fn rodata(mut self, rodata: Arc<ReadOnlyData>) -> Analyzer<T> {
+        self.rodata = Some(rodata);
+        self
+    }
+
     pub fn dump(&self) {
         println!("{:?}", self.expr_val);
     }
@@ -141,6 +211,10 @@ impl<T> Analyzer<T>
 
             let edge = self.g.find_edges_between(operand_block, parent_block);
             if edge.len() == 0 {
+                // Defined further up, which edge it flows in over is not known.
+                if operand_block == invalid_block || self.is_block_executable(&operand_block) {
+                    phi_val = meet(&phi_val, &op_val);
+                }
                 continue;
             }
             assert_eq!(edge.len(), 1);
@@ -155,182 +229,138 @@ impl<T> Analyzer<T>
         phi_val
     }
 
-    fn evaluate_control_flow(&mut self, i: &T::ValueRef) {
-        assert!(self.g.is_selector(*i));
-
-        let cond_val = self.get_value(i);
-        let block = self.g.selector_for(*i).unwrap_or_else(|| {
-                        radeco_err!("Victim value is not a selector");
-                        self.g.invalid_action().unwrap()
-        });
-        let invalid_edge = self.g.invalid_edge().expect("Invalid Edge is not defined");
-        let conditional_branches = 
-            if let Some(branches) = self.g.conditional_edges(block) {
-                branches
+    // Adds the successors of `block` which may be taken according to its selector. A
+    // selector need not be computed in the block it selects for, so this is done both
+    // when the block becomes executable and when its selector changes.
+    fn evaluate_control_flow(&mut self, block: &T::ActionRef) {
+        if !self.is_block_executable(block) {
+            return;
+        }
+        let selector = match self.g.selector_in(*block) {
+            Some(selector) => selector,
+            None => return,
+        };
+        let branches = match self.g.conditional_edges(*block) {
+            Some(branches) => branches,
+            None => return,
+        };
+        if let Some(&taken) = self.decided.get(block) {
+            if taken {
+                self.cfgwl_push(&branches.true_side);
             } else {
-                ConditionInfo::new(invalid_edge, invalid_edge)
-            };
-        let true_branch = conditional_branches.true_side;
-        let false_branch = conditional_branches.false_side;
-        match cond_val {
+                self.cfgwl_push(&branches.false_side);
+            }
+            return;
+        }
+        match self.get_value(&selector) {
             LatticeValue::Bottom => {
-                self.cfg_worklist.push_back(true_branch);
-                self.cfg_worklist.push_back(false_branch);
+                self.cfgwl_push(&branches.true_side);
+                self.cfgwl_push(&branches.false_side);
             }
             LatticeValue::Top => {
-                // TODO: Not really sure what to do here.
+                // Not known yet, the branch is taken once the selector is.
             }
             LatticeValue::Const(cval) => {
-                if cval == 1 {
-                    self.cfgwl_push(&true_branch);
+                if cval != 0 {
+                    self.cfgwl_push(&branches.true_side);
                 } else {
-                    self.cfgwl_push(&false_branch);
+                    self.cfgwl_push(&branches.false_side);
                 }
             }
         }
     }
 
-    fn evaluate_unary_op(&mut self, i: &T::ValueRef, opcode: MOpcode) -> LatticeValue {
-        let operand = self.g.operands_of(*i);
-        let operand = if operand.is_empty() {
-            return LatticeValue::Top;
-        } else {
-            operand[0]
-        };
-
-        let val = self.get_value(&operand);
-        let const_val = if let LatticeValue::Const(cval) = val {
-            cval
-        } else {
-            return val;
-        };
-
-
-        let mut val: u64 = match opcode {
-            MOpcode::OpZeroExt(_) | MOpcode::OpSignExt(_) => {
-                // Nothing to do in case of widen as the value cannot change.
-                const_val
-            }
-            MOpcode::OpNarrow(size) => {
-                // Max size is 64. Therefore, we can _never_ narrow to 64.
-                assert!(size < 64);
-                let mask = (1 << (size)) - 1;
-                const_val & mask
-            }
-            MOpcode::OpNot => {
-                !const_val as u64
-            }
-            MOpcode::OpCall => {
-                return LatticeValue::Bottom;
+    // Folds an operation whose operands are all constants.
+    fn evaluate_op(&mut self, i: &T::ValueRef, opcode: MOpcode) -> LatticeValue {
+        let mut operands = Vec::new();
+        let mut undefined = false;
+        for op in self.g.operands_of(*i) {
+            match self.get_value(&op) {
+                LatticeValue::Const(cval) => operands.push((cval, self.width_of(&op))),
+                LatticeValue::Bottom => return LatticeValue::Bottom,
+                LatticeValue::Top => undefined = true,
             }
-            _ => unreachable!(),
-        };
-
-        // We should consider width.
-        let ndata = node_data_from_g!(self, i);
-        let w = ndata.vt.width().get_width().unwrap_or(64);
-        if w < 64 {
-            val = val & ((1 << (w)) - 1);
         }
-
-        LatticeValue::Const(val)
+        if undefined {
+            return LatticeValue::Top;
+        }
+        match fold_constants(&opcode, &operands, self.width_of(i)) {
+            Some(val) => LatticeValue::Const(val),
+            None => LatticeValue::Bottom,
+        }
     }
 
-    fn evaluate_binary_op(&mut self, i: &T::ValueRef, opcode: MOpcode) -> LatticeValue {
-        // Do not reason about load/stores.
-        match opcode {
-            MOpcode::OpLoad | MOpcode::OpStore => return LatticeValue::Bottom,
-            _ => { },
+    // If - Then - Else is known as soon as its condition is, even if the other side
+    // is not.
+    fn evaluate_ite(&mut self, i: &T::ValueRef) -> LatticeValue {
+        let operands = self.g.operands_of(*i);
+        if operands.len() != 3 {
+            return LatticeValue::Bottom;
         }
+        let then_val = self.get_value(&operands[1]);
+        let else_val = self.get_value(&operands[2]);
+        match self.get_value(&operands[0]) {
+            LatticeValue::Const(0) => else_val,
+            LatticeValue::Const(_) => then_val,
+            LatticeValue::Top => LatticeValue::Top,
+            LatticeValue::Bottom => meet(&then_val, &else_val),
+        }
+    }
 
-        let operands = self.g.operands_of(*i).iter().map(|x| self.get_value(x)).collect::<Vec<_>>();
-
-        let lhs = operands[0];
-        let rhs = operands[1];
-
-        let lhs_val = if let LatticeValue::Const(cval) = lhs {
-            cval
-        } else {
-            return lhs;
-        };
-        let rhs_val = if let LatticeValue::Const(cval) = rhs {
-            cval
-        } else {
-            return rhs;
-        };
-
-        let mut val: u64 = match opcode {
-            MOpcode::OpAdd => {
-                // lhs_val + rhs_val
-                // we should avoid integer overflow panic
-                let remained: u64 = u64::MAX - lhs_val;
-                if remained >= rhs_val {
-                    lhs_val + rhs_val
-                } else {
-                    (rhs_val - remained - 1) as u64
-                }
-            }
-            MOpcode::OpSub => {
-                if lhs_val >= rhs_val {
-                    lhs_val - rhs_val
-                } else {
-                    // rhs_val - lhs_val
-                    // This will never integer overflow 
-                    (u64::MAX - rhs_val + lhs_val + 1) as u64
+    // Folds a load whose memory operand is a store of a constant to the same constant
+    // address. When the store was done in the other byte order, the value read back is
+    // byte swapped.
+    //
+    // Loads from read-only data are folded whatever the memory operand is, as
+    // nothing can have been stored there.
+    fn evaluate_load(&mut self, i: &T::ValueRef, ld_w: u16, endian: Endian) -> LatticeValue {
+        let operands = self.g.operands_of(*i);
+        if operands.len() != 2 {
+            return LatticeValue::Bottom;
+        }
+        if self.rodata.is_some() {
+            match self.get_value(&operands[1]) {
+                LatticeValue::Const(addr) => {
+                    let rodata = self.rodata.as_ref().unwrap();
+                    if let Some(val) = rodata.read(addr, ld_w, endian) {
+                        return LatticeValue::Const(val);
+                    }
                 }
+                LatticeValue::Top => return LatticeValue::Top,
+                LatticeValue::Bottom => {}
             }
-            MOpcode::OpMul => {
-                // TODO: how to handle integer overflow.
-                lhs_val * rhs_val
-            }
-            MOpcode::OpDiv => {
-                lhs_val / rhs_val
-            }
-            MOpcode::OpMod => {
-                lhs_val % rhs_val
-            }
-            MOpcode::OpAnd => {
-                lhs_val & rhs_val
-            }
-            MOpcode::OpOr => {
-                lhs_val | rhs_val
-            }
-            MOpcode::OpXor => {
-                lhs_val ^ rhs_val
-            }
-            MOpcode::OpEq => {
-                (lhs_val == rhs_val) as u64
-            }
-            MOpcode::OpGt => {
-                (lhs_val > rhs_val) as u64
-            }
-            MOpcode::OpLt => {
-                (lhs_val < rhs_val) as u64
-            }
-            MOpcode::OpLsl => {
-                lhs_val << rhs_val
-            }
-            MOpcode::OpLsr => {
-                lhs_val >> rhs_val
-            }
-            _ => unreachable!(),
+        }
+        let store = operands[0];
+        let (st_w, store_endian) = match self.g.opcode(store) {
+            Some(MOpcode::OpStore(w, e)) => (w, e),
+            _ => return LatticeValue::Bottom,
         };
-
-        // We should consider width.
-        let ndata = node_data_from_g!(self, i);
-        let w = ndata.vt.width().get_width().unwrap_or(64);
-        if w < 64 {
-            val = val & ((1 << (w)) - 1);
+        let store_ops = self.g.operands_of(store);
+        if store_ops.len() != 3 {
+            return LatticeValue::Bottom;
         }
 
-        LatticeValue::Const(val)
-    }
+        let ld_addr = self.get_value(&operands[1]);
+        let st_addr = self.get_value(&store_ops[1]);
+        let st_val = self.get_value(&store_ops[2]);
+        let val = match (ld_addr, st_addr, st_val) {
+            (LatticeValue::Const(a), LatticeValue::Const(b), LatticeValue::Const(v)) if a == b => v,
+            (LatticeValue::Bottom, _, _) |
+            (_, LatticeValue::Bottom, _) |
+            (_, _, LatticeValue::Bottom) |
+            (LatticeValue::Const(_), LatticeValue::Const(_), LatticeValue::Const(_)) => {
+                return LatticeValue::Bottom;
+            }
+            _ => return LatticeValue::Top,
+        };
 
-    fn evaluate_ternary_op(&mut self, i: &T::ValueRef, opcode: MOpcode) -> LatticeValue {
-        // Do not reason about stores.
-        match opcode {
-            MOpcode::OpStore => return LatticeValue::Bottom,
-            _ => unimplemented!(),
+        // A partial overlap cannot be folded.
+        if ld_w != st_w || ld_w > 64 {
+            LatticeValue::Bottom
+        } else if endian == store_endian {
+            LatticeValue::Const(val)
+        } else {
+            LatticeValue::Const(ir::swap_bytes(val, ld_w))
         }
     }
 
@@ -349,26 +379,15 @@ impl<T> Analyzer<T>
             MOpcode::OpInvalid
         };
 
-        if let MOpcode::OpConst(v) = opcode {
-            return LatticeValue::Const(v as u64);
-        }
-
-        let val = match opcode.arity() {
-            MArity::Unary => self.evaluate_unary_op(i, opcode),
-            MArity::Binary => self.evaluate_binary_op(i, opcode),
-            _ => self.evaluate_ternary_op(i, opcode),
-        };
-
-        // If expression is a `Selector` it means that it's value can affect the
-        // control flow.
-        // Hence evaluate the control flow to add edges to the cfgwl.
-        // TODO: Handle the case where the selector of a block may belong to a
-        // different block.
-        if self.g.is_selector(*i) {
-            self.evaluate_control_flow(i);
+        match opcode {
+            MOpcode::OpConst(v) => LatticeValue::Const(v as u64),
+            // Only loads reading back a known store are reasoned about.
+            MOpcode::OpLoad(w, endian) => self.evaluate_load(i, w, endian),
+            MOpcode::OpITE => self.evaluate_ite(i),
+            // Neither memory nor the results of calls are known.
+            MOpcode::OpStore(..) | MOpcode::OpCall => LatticeValue::Bottom,
+            _ => self.evaluate_op(i, opcode),
         }
-
-        val
     }
 
     pub fn analyze(&mut self) {
@@ -391,7 +410,9 @@ impl<T> Analyzer<T>
                     let phis = self.g.phis_in(block);
                     for phi in &phis {
                         let v = self.visit_phi(phi);
-                        self.set_value(phi, v);
+                        if v != self.get_value(phi) {
+                            self.update_value(phi, v);
+                        }
                     }
 
                     let visits = self.g.incoming_edges(block).iter().fold(0, |acc, &e| {
@@ -406,11 +427,9 @@ impl<T> Analyzer<T>
                     if visits == 1 {
                         for expr in self.g.exprs_in(block) {
                             let val = self.visit_expression(&expr);
-                            self.set_value(&expr, val);
-                            for use_ in self.g.uses_of(expr) {
-                                self.ssawl_push(&use_);
-                            }
+                            self.update_value(&expr, val);
                         }
+                        self.evaluate_control_flow(&block);
                     }
 
                     if let Some(next_edge) = self.g.unconditional_edge(block) {
@@ -436,10 +455,7 @@ impl<T> Analyzer<T>
                 };
 
                 if t != self.get_value(&e) {
-                    self.set_value(&e, t);
-                    for use_ in &self.g.uses_of(e) {
-                        self.ssawl_push(use_);
-                    }
+                    self.update_value(&e, t);
                 }
             } // End of ssawl
         } // End of while-loop
@@ -532,7 +548,8 @@ impl<T> Analyzer<T>
         let node_data = self.g.node_data(*i).unwrap();
         match node_data.nt {
             NodeType::Op(MOpcode::OpConst(v)) => LatticeValue::Const(v),
-            NodeType::Undefined => LatticeValue::Bottom,
+            // Comments are the values of registers on entry and the results of calls.
+            NodeType::Undefined | NodeType::Comment(_) => LatticeValue::Bottom,
             _ => LatticeValue::Top,
         }
     }
@@ -552,6 +569,20 @@ impl<T> Analyzer<T>
         *n = v;
     }
 
+    // Sets the value of `i` and revisits everything depending on it, i.e. its uses and
+    // the successors of the block it is the selector of.
+    fn update_value(&mut self, i: &T::ValueRef, v: LatticeValue) {
+        self.set_value(i, v);
+        self.push_uses(i);
+        if let Some(block) = self.g.selector_for(*i) {
+            self.evaluate_control_flow(&block);
+        }
+    }
+
+    fn width_of(&self, i: &T::ValueRef) -> u16 {
+        node_data_from_g!(self, i).vt.width().get_width().unwrap_or(64)
+    }
+
     fn is_block_executable(&self, i: &T::ActionRef) -> bool {
         // entry_node is always reachable.
         if *i == entry_node_err!(&self.g) {
@@ -567,7 +598,7 @@ impl<T> Analyzer<T>
     }
 
     fn ssawl_push(&mut self, i: &T::ValueRef) {
-        if !self.g.is_expr(*i) {
+        if !self.g.is_expr(*i) && !self.g.is_phi(*i) {
             return;
         }
         let owner_block = self.g.block_for(*i)
@@ -580,14 +611,325 @@ impl<T> Analyzer<T>
         }
     }
 
+    // Loads are folded through the operands of the store they read from, so a
+    // change in a stored value has to reach the loads using that store as well.
+    fn push_uses(&mut self, i: &T::ValueRef) {
+        for use_ in self.g.uses_of(*i) {
+            self.ssawl_push(&use_);
+            if let Some(MOpcode::OpStore(..)) = self.g.opcode(use_) {
+                for load in self.g.uses_of(use_) {
+                    if let Some(MOpcode::OpLoad(..)) = self.g.opcode(load) {
+                        self.ssawl_push(&load);
+                    }
+                }
+            }
+        }
+    }
+
     fn cfgwl_push(&mut self, i: &T::CFEdgeRef) {
         self.cfg_worklist.push_back(*i);
     }
 }
 
+impl Analyzer<SSAStorage> {
+    /// Only marks the side taken executable for the branches `ranges` proves to always
+    /// take the same side.
+    pub fn ranges(mut self, ranges: &RangeAnalysis) -> Analyzer<SSAStorage> {
+        self.decided.extend(ranges.branches());
+        self
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{LatticeValue, meet};
+    use super::{Analyzer, LatticeValue, meet};
+    use analysis::ranges;
+    use frontend::rodata::ReadOnlyData;
+    use middle::ir::MOpcode;
+    use middle::ir_reader::test_util::{at, parse};
+    use middle::ssa::ssa_traits::{SSA, SSAWalk};
+    use middle::ssa::ssastorage::SSAStorage;
+    use quickcheck::TestResult;
+    use std::sync::Arc;
+
+    const WIDTHS: [u16; 4] = [8, 16, 32, 64];
+
+    // Evaluates `opcode` on `a` and `b` of `wa` bits to a value of `w` bits, on 128
+    // bit integers so nothing overflows.
+    fn reference(opcode: &MOpcode, a: u64, b: u64, wa: u16, w: u16) -> Option<u64> {
+        let signed = |v: u64| -> i128 {
+            let v = v as i128;
+            if (v >> (wa - 1)) & 1 == 1 { v - (1 << wa) } else { v }
+        };
+        let (x, y) = (a as u128, b as u128);
+        let r = match *opcode {
+            MOpcode::OpAdd => x + y,
+            MOpcode::OpSub => x + (1 << wa) - y,
+            MOpcode::OpMul => x * y,
+            MOpcode::OpDiv => x.checked_div(y)?,
+            MOpcode::OpMod => x.checked_rem(y)?,
+            MOpcode::OpAnd => x & y,
+            MOpcode::OpOr => x | y,
+            MOpcode::OpXor => x ^ y,
+            MOpcode::OpEq => (x == y) as u128,
+            MOpcode::OpGt => (x > y) as u128,
+            MOpcode::OpLt => (x < y) as u128,
+            MOpcode::OpLsl => if y >= wa as u128 { 0 } else { x << y },
+            MOpcode::OpLsr => if y >= wa as u128 { 0 } else { x >> y },
+            MOpcode::OpRol => (x << (y % wa as u128)) | (x >> (wa as u128 - y % wa as u128)),
+            MOpcode::OpRor => (x >> (y % wa as u128)) | (x << (wa as u128 - y % wa as u128)),
+            MOpcode::OpSGt(_) => (signed(a) > signed(b)) as u128,
+            MOpcode::OpSLt(_) => (signed(a) < signed(b)) as u128,
+            MOpcode::OpSDiv(_) => signed(a).checked_div(signed(b))? as u128,
+            MOpcode::OpSMod(_) => signed(a).checked_rem(signed(b))? as u128,
+            MOpcode::OpMulHi(_) => (x * y) >> wa,
+            MOpcode::OpSMulHi(_) => ((signed(a) * signed(b)) >> wa) as u128,
+            MOpcode::OpNarrow(_) | MOpcode::OpZeroExt(_) => x,
+            MOpcode::OpSignExt(_) => signed(a) as u128,
+            MOpcode::OpNot => !x,
+            MOpcode::OpPopCount => x.count_ones() as u128,
+            MOpcode::OpBSwap(_) => {
+                (0..wa / 8).fold(0, |acc, i| acc | ((x >> (8 * i)) & 0xff) << (wa - 8 - 8 * i))
+            }
+            _ => unreachable!(),
+        };
+        Some((r & ((1 << w) - 1)) as u64)
+    }
+
+    // The opcode, its result width and the IR computing it from `%2` and `%3`.
+    fn operation(op: usize, wa: u16, wb: u16) -> Option<(MOpcode, u16, String)> {
+        let infix = |opcode: MOpcode, sym: &str, w: u16| {
+            Some((opcode, w, format!("%2 {} %3", sym)))
+        };
+        match op % 27 {
+            0 => infix(MOpcode::OpAdd, "+", wa),
+            1 => infix(MOpcode::OpSub, "-", wa),
+            2 => infix(MOpcode::OpMul, "*", wa),
+            3 => infix(MOpcode::OpDiv, "/", wa),
+            4 => infix(MOpcode::OpMod, "%", wa),
+            5 => infix(MOpcode::OpAnd, "&", wa),
+            6 => infix(MOpcode::OpOr, "|", wa),
+            7 => infix(MOpcode::OpXor, "^", wa),
+            8 => infix(MOpcode::OpEq, "==", 1),
+            9 => infix(MOpcode::OpGt, ">", 1),
+            10 => infix(MOpcode::OpLt, "<", 1),
+            11 => infix(MOpcode::OpLsl, "<<", wa),
+            12 => infix(MOpcode::OpLsr, ">>", wa),
+            13 => Some((MOpcode::OpRol, wa, "Rol(%2, %3)".to_owned())),
+            14 => Some((MOpcode::OpRor, wa, "Ror(%2, %3)".to_owned())),
+            15 => Some((MOpcode::OpSGt(wa), 1, format!("SGt{}(%2, %3)", wa))),
+            16 => Some((MOpcode::OpSLt(wa), 1, format!("SLt{}(%2, %3)", wa))),
+            17 => Some((MOpcode::OpSDiv(wa), wa, format!("SDiv{}(%2, %3)", wa))),
+            18 => Some((MOpcode::OpSMod(wa), wa, format!("SMod{}(%2, %3)", wa))),
+            19 if wb < wa => Some((MOpcode::OpNarrow(wb), wb, format!("Narrow{}(%2)", wb))),
+            20 if wb > wa => Some((MOpcode::OpZeroExt(wb), wb, format!("ZeroExt{}(%2)", wb))),
+            21 if wb > wa => Some((MOpcode::OpSignExt(wb), wb, format!("SignExt{}(%2)", wb))),
+            22 => Some((MOpcode::OpNot, wa, "!%2".to_owned())),
+            23 => Some((MOpcode::OpPopCount, wa, "PopCount(%2)".to_owned())),
+            24 => Some((MOpcode::OpBSwap(wa), wa, format!("BSwap{}(%2)", wa))),
+            25 => Some((MOpcode::OpMulHi(wa), wa, format!("MulHi{}(%2, %3)", wa))),
+            26 => Some((MOpcode::OpSMulHi(wa), wa, format!("SMulHi{}(%2, %3)", wa))),
+            _ => None,
+        }
+    }
+
+    #[quickcheck]
+    fn qc_fold_constants(op: usize, wa: usize, wb: usize, a: u64, b: u64) -> TestResult {
+        let (wa, wb) = (WIDTHS[wa % WIDTHS.len()], WIDTHS[wb % WIDTHS.len()]);
+        let (opcode, w, expr) = match operation(op, wa, wb) {
+            Some(operation) => operation,
+            None => return TestResult::discard(),
+        };
+        // Keep shift and rotate amounts mostly in range.
+        let b = match opcode {
+            MOpcode::OpLsl | MOpcode::OpLsr | MOpcode::OpRol | MOpcode::OpRor => {
+                b % (2 * wa as u64)
+            }
+            _ => b,
+        };
+        let il = format!("\
+define-fun main(unknown) -> unknown {{
+    entry-register-state:
+        %1: $Unknown64(*?) = $rsp;
+    bb_0x000100.0000(sz 0x0):
+        [@0x000100.0001] %2: $Unknown{wa} = Narrow{wa}(#x{a:x});
+        [@0x000100.0002] %3: $Unknown{wa} = Narrow{wa}(#x{b:x});
+        [@0x000100.0003] %4: $Unknown{w} = {expr};
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $rax = %4;
+}}
+", wa = wa, a = a, b = b, w = w, expr = expr);
+        let mut ssa = parse(&il);
+        let node = at(&ssa, 0x100, 3);
+        let mut analyzer = Analyzer::new(&mut ssa);
+        analyzer.analyze();
+
+        let mask = if wa == 64 { !0 } else { (1 << wa) - 1 };
+        let expected = reference(&opcode, a & mask, b & mask, wa, w)
+            .map_or(LatticeValue::Bottom, LatticeValue::Const);
+        if analyzer.expr_val[&node] != expected {
+            println!("{:?} on {:#x}, {:#x}: {:?}, expected {:?}",
+                     opcode, a & mask, b & mask, analyzer.expr_val[&node], expected);
+        }
+        TestResult::from_bool(analyzer.expr_val[&node] == expected)
+    }
+
+    // The selector of the second block is computed in the first one, its true side
+    // is never taken and does not reach the phi.
+    #[test]
+    fn test_selector_in_other_block() {
+        let mut ssa = parse("\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64(*?) = $rsp;
+    bb_0x000100.0000(sz 0x4):
+        [@0x000100.0000] %2: $Unknown1 = #x5 == #x6;
+        JMP 0x000104.0000
+    bb_0x000104.0000(sz 0x4):
+        JMP IF %2 0x000108.0000 ELSE 0x00010C.0000
+    bb_0x000108.0000(sz 0x4):
+        [@0x000108.0000] %3: $Unknown64 = %1 + #x1;
+        JMP 0x000110.0000
+    bb_0x00010C.0000(sz 0x4):
+        [@0x00010C.0000] %4: $Unknown64 = #x7 + #x1;
+        JMP 0x000110.0000
+    bb_0x000110.0000(sz 0x4):
+        %5: $Unknown64 = Phi(%3, %4);
+        JMP TO %5
+    exit-node:
+    final-register-state:
+        $rax = %5;
+}
+");
+        let phi = ssa.inorder_walk().find(|&n| ssa.is_phi(n)).expect("No phi found");
+        let mut analyzer = Analyzer::new(&mut ssa);
+        analyzer.analyze();
+        assert_eq!(analyzer.expr_val[&phi], LatticeValue::Const(8));
+    }
+
+    // `%2` counts up to 15 at most, so the branch at 0x10C is never taken, which only the
+    // ranges prove.
+    #[test]
+    fn test_ranges_decide_branch() {
+        let il = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64(*?) = $rsp;
+    bb_0x000100.0000(sz 0x4):
+        JMP 0x000104.0000
+    bb_0x000104.0000(sz 0x8):
+        %2: $Unknown64 = Phi(#x0, %3);
+        [@0x000104.0000] %3: $Unknown64 = %2 + #x1;
+        [@0x000108.0000] %4: $Unknown1 = %3 < #x10;
+        JMP IF %4 0x000104.0000 ELSE 0x00010C.0000
+    bb_0x00010C.0000(sz 0x4):
+        [@0x00010C.0000] %5: $Unknown1 = %2 > #x20;
+        [@0x00010C.0001] %6: $Unknown64 = #x7 + #x0;
+        JMP IF %5 0x000110.0000 ELSE 0x000114.0000
+    bb_0x000110.0000(sz 0x4):
+        [@0x000110.0000] %7: $Unknown64 = %1 + #x1;
+        JMP 0x000114.0000
+    bb_0x000114.0000(sz 0x4):
+        %8: $Unknown64 = Phi(%6, %7);
+        JMP TO %8
+    exit-node:
+    final-register-state:
+        $rax = %8;
+}
+";
+        let phi = |ssa: &SSAStorage| {
+            ssa.inorder_walk()
+                .filter(|&n| ssa.is_phi(n))
+                .find(|&n| ssa.operands_of(n).iter().all(|&op| ssa.constant(op).is_none()))
+                .expect("No phi found")
+        };
+
+        let mut ssa = parse(il);
+        let joined = phi(&ssa);
+        let mut analyzer = Analyzer::new(&mut ssa);
+        analyzer.analyze();
+        assert_eq!(analyzer.expr_val[&joined], LatticeValue::Bottom);
+
+        let mut ssa = parse(il);
+        let joined = phi(&ssa);
+        let ranges = ranges::analyze(&ssa);
+        let mut analyzer = Analyzer::new(&mut ssa).ranges(&ranges);
+        analyzer.analyze();
+        assert_eq!(analyzer.expr_val[&joined], LatticeValue::Const(7));
+    }
+
+    fn fold_load(store: &str, load: &str, width: u16) -> LatticeValue {
+        let il = format!("\
+define-fun main(unknown) -> unknown {{
+    entry-register-state:
+        %1: $Unknown64(*?) = $rsp;
+        %2: $Unknown0 = $mem;
+    bb_0x000100.0000(sz 0x0):
+        [@0x000100.0001] %3: $Unknown32 = Narrow32(#x11223344);
+        [@0x000100.0002] %4: $Unknown0 = {}(%2, #x1000, %3);
+        [@0x000100.0003] %5: $Unknown{} = {}(%4, #x1000);
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $rax = %5;
+        $mem = %4;
+}}
+", store, width, load);
+        let mut ssa = parse(&il);
+        let load = at(&ssa, 0x100, 3);
+        let mut analyzer = Analyzer::new(&mut ssa);
+        analyzer.analyze();
+        analyzer.expr_val[&load]
+    }
+
+    #[test]
+    fn test_fold_load_same_endian() {
+        assert_eq!(fold_load("Store32", "Load32", 32), LatticeValue::Const(0x11223344));
+        assert_eq!(fold_load("StoreBE32", "LoadBE32", 32), LatticeValue::Const(0x11223344));
+    }
+
+    #[test]
+    fn test_fold_load_mixed_endian() {
+        assert_eq!(fold_load("Store32", "LoadBE32", 32), LatticeValue::Const(0x44332211));
+        assert_eq!(fold_load("StoreBE32", "Load32", 32), LatticeValue::Const(0x44332211));
+    }
+
+    #[test]
+    fn test_fold_load_rodata() {
+        let il = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64(*?) = $rsp;
+        %2: $Unknown0 = $mem;
+    bb_0x000100.0000(sz 0x0):
+        [@0x000100.0001] %3: $Unknown64 = #x2000 + #x8;
+        [@0x000100.0002] %4: $Unknown0 = Store64(%2, %1, #x0);
+        [@0x000100.0003] %5: $Unknown32 = Load32(%4, %3);
+        [@0x000100.0004] %6: $Unknown32 = Load32(%4, #x3000);
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $rax = %5;
+        $rbx = %6;
+        $mem = %4;
+}
+";
+        let mut rodata = ReadOnlyData::new();
+        rodata.add_section(0x2000, vec![0, 0, 0, 0, 0, 0, 0, 0, 0x78, 0x56, 0x34, 0x12]);
+        let mut ssa = parse(il);
+        let (rodata_load, other_load) = (at(&ssa, 0x100, 3), at(&ssa, 0x100, 4));
+        let mut analyzer = Analyzer::new(&mut ssa).rodata(Arc::new(rodata));
+        analyzer.analyze();
+        assert_eq!(analyzer.expr_val[&rodata_load], LatticeValue::Const(0x12345678));
+        assert_eq!(analyzer.expr_val[&other_load], LatticeValue::Bottom);
+    }
+
+    #[test]
+    fn test_fold_load_partial() {
+        assert_eq!(fold_load("Store32", "Load16", 16), LatticeValue::Bottom);
+    }
 
     #[test]
     fn test_meet() {
@@ -0,0 +1,305 @@
+//! Generates type constraints from the values of an `SSAStorage`.
+//!
+//! Every value which is not a constant gets a type variable, which is a subtype of
+//! the register it fits in. Operations then constrain their operands and result:
+//! multiplications and bitwise operations work on numbers, unsigned and signed ones on
+//! integers of that kind, the address of a load or store points to the value, and
+//! the operands of a phi are subtypes of it. Calls to functions with a known
+//! prototype constrain the arguments passed and the value returned.
+
+use std::collections::HashMap;
+
+use petgraph::graph::NodeIndex;
+
+use middle::ir::MOpcode;
+use middle::regfile::SubRegisterFile;
+use middle::ssa::ssa_traits::*;
+use middle::ssa::ssastorage::SSAStorage;
+use middle::ssa::utils;
+
+use super::structs::{ConstraintSet, RType};
+
+pub type SSAValue = <SSAStorage as SSA>::ValueRef;
+
+/// Types of the arguments and return value of a function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prototype {
+    pub args: Vec<RType>,
+    pub ret: Option<RType>,
+}
+
+impl Prototype {
+    pub fn new(args: Vec<RType>, ret: Option<RType>) -> Prototype {
+        Prototype {
+            args: args,
+            ret: ret,
+        }
+    }
+
+    /// Prototype of the C library function `name`, ignoring any prefix up to the
+    /// last `.` as in `sym.imp.strlen`. `size_t` is as wide as a pointer, `ptr_width`.
+    pub fn of_libc(name: &str, ptr_width: u16) -> Option<Prototype> {
+        let name = name.rsplit('.').next().unwrap_or(name);
+        let size_t = RType::uint(ptr_width);
+        let char_ptr = || RType::Ptr(Box::new(RType::Int8));
+        let void_ptr = || RType::Ptr(Box::new(RType::Undefined));
+        let prototype = match name {
+            "strlen" => Prototype::new(vec![char_ptr()], Some(size_t.clone())),
+            "strcmp" => Prototype::new(vec![char_ptr(), char_ptr()], Some(RType::Int32)),
+            "strncmp" => {
+                Prototype::new(vec![char_ptr(), char_ptr(), size_t.clone()], Some(RType::Int32))
+            }
+            "strcpy" | "strcat" => Prototype::new(vec![char_ptr(), char_ptr()], Some(char_ptr())),
+            "strchr" | "strrchr" => {
+                Prototype::new(vec![char_ptr(), RType::Int32], Some(char_ptr()))
+            }
+            "strdup" => Prototype::new(vec![char_ptr()], Some(char_ptr())),
+            "memcpy" | "memmove" => {
+                Prototype::new(vec![void_ptr(), void_ptr(), size_t.clone()], Some(void_ptr()))
+            }
+            "memset" => {
+                Prototype::new(vec![void_ptr(), RType::Int32, size_t.clone()], Some(void_ptr()))
+            }
+            "memcmp" => {
+                Prototype::new(vec![void_ptr(), void_ptr(), size_t.clone()], Some(RType::Int32))
+            }
+            "malloc" => Prototype::new(vec![size_t.clone()], Some(void_ptr())),
+            "calloc" => Prototype::new(vec![size_t.clone(), size_t.clone()], Some(void_ptr())),
+            "realloc" => Prototype::new(vec![void_ptr(), size_t.clone()], Some(void_ptr())),
+            "free" => Prototype::new(vec![void_ptr()], None),
+            "puts" | "atoi" => Prototype::new(vec![char_ptr()], Some(RType::Int32)),
+            "printf" => Prototype::new(vec![char_ptr()], Some(RType::Int32)),
+            "putchar" | "exit" => Prototype::new(vec![RType::Int32], None),
+            _ => return None,
+        };
+        Some(prototype)
+    }
+}
+
+/// Width of the pointers of the architecture of `regfile`, that of its stack pointer.
+pub fn ptr_width(regfile: &SubRegisterFile) -> Option<u16> {
+    regfile.register_id_by_alias("SP")
+           .and_then(|sp| regfile.get_width(sp))
+           .map(|width| width as u16)
+}
+
+/// Builds a `ConstraintSet` for the values of `ssa`. `prototypes` gives the prototype
+/// of the functions at the addresses they are called at.
+pub struct ConstraintGenerator<'a> {
+    ssa: &'a SSAStorage,
+    prototypes: &'a HashMap<u64, Prototype>,
+    cs: ConstraintSet,
+    vars: HashMap<SSAValue, NodeIndex>,
+}
+
+impl<'a> ConstraintGenerator<'a> {
+    pub fn new(ssa: &'a SSAStorage,
+               prototypes: &'a HashMap<u64, Prototype>)
+               -> ConstraintGenerator<'a> {
+        let mut cs = ConstraintSet::default();
+        if let Some(width) = ptr_width(&ssa.regfile) {
+            cs.set_ptr_width(width);
+        }
+        ConstraintGenerator {
+            ssa: ssa,
+            prototypes: prototypes,
+            cs: cs,
+            vars: HashMap::new(),
+        }
+    }
+
+    /// Constrains every value, returning the constraints and the type variable of each
+    /// value which is not a constant.
+    pub fn generate(mut self) -> (ConstraintSet, HashMap<SSAValue, NodeIndex>) {
+        let values = self.ssa.values();
+        for &value in &values {
+            if let Some(width) = self.width_of(value) {
+                let var = self.var(value);
+                let reg = if width == 1 { RType::Bool } else { RType::reg(width) };
+                self.bound(var, reg);
+            }
+        }
+        for &value in &values {
+            if self.ssa.is_phi(value) {
+                self.phi(value);
+            } else if let Some(opcode) = self.ssa.opcode(value) {
+                self.operation(value, opcode);
+            }
+        }
+        (self.cs, self.vars)
+    }
+
+    fn width_of(&self, value: SSAValue) -> Option<u16> {
+        if self.ssa.is_constant(value) {
+            return None;
+        }
+        let width = self.ssa.node_data(value).ok()?.vt.width().get_width()?;
+        if width == 0 {
+            None
+        } else {
+            Some(width)
+        }
+    }
+
+    // Type variable of `value`, `None` for constants and memory.
+    fn var(&mut self, value: SSAValue) -> Option<NodeIndex> {
+        if let Some(&var) = self.vars.get(&value) {
+            return Some(var);
+        }
+        self.width_of(value)?;
+        let var = self.cs.insert_type_var(Some(format!("{:?}", value)));
+        self.vars.insert(value, var);
+        Some(var)
+    }
+
+    // `var <: ty`
+    fn bound(&mut self, var: Option<NodeIndex>, ty: RType) {
+        if let (Some(var), false) = (var, ty == RType::Undefined) {
+            let ty = self.cs.insert_base_type(ty);
+            self.cs.subtype(&var, &ty);
+        }
+    }
+
+    // `ty <: var`
+    fn lower_bound(&mut self, var: Option<NodeIndex>, ty: RType) {
+        if let (Some(var), false) = (var, ty == RType::Undefined) {
+            let ty = self.cs.insert_base_type(ty);
+            self.cs.subtype(&ty, &var);
+        }
+    }
+
+    // The value at `address` has the type of `value`.
+    fn points_to(&mut self, address: SSAValue, value: SSAValue) {
+        let address = match self.var(address) {
+            Some(address) => address,
+            None => return,
+        };
+        let inner = match self.var(value) {
+            Some(inner) => inner,
+            None => self.cs.insert_type_var(None),
+        };
+        let ptr = self.cs.ptr(&inner);
+        self.cs.equal(&[address, ptr]);
+    }
+
+    fn phi(&mut self, phi: SSAValue) {
+        let var = self.var(phi);
+        for operand in self.ssa.operands_of(phi) {
+            if let (Some(var), Some(op)) = (var, self.var(operand)) {
+                self.cs.subtype(&op, &var);
+            }
+        }
+    }
+
+    fn operation(&mut self, value: SSAValue, opcode: MOpcode) {
+        let operands = self.ssa.operands_of(value);
+        let width = self.width_of(value).unwrap_or(0);
+        let result = self.var(value);
+        match opcode {
+            MOpcode::OpMul | MOpcode::OpXor | MOpcode::OpNot | MOpcode::OpLsl |
+            MOpcode::OpRol | MOpcode::OpRor | MOpcode::OpBSwap(_) | MOpcode::OpPopCount => {
+                self.bound(result, RType::num(width));
+                for &operand in &operands {
+                    let ty = RType::num(self.width_of(operand).unwrap_or(0));
+                    let op = self.var(operand);
+                    self.bound(op, ty);
+                }
+            }
+            MOpcode::OpDiv | MOpcode::OpMod | MOpcode::OpMulHi(_) | MOpcode::OpLsr => {
+                self.bound(result, RType::uint(width));
+                let operands = if opcode == MOpcode::OpLsr { &operands[..1] } else { &operands[..] };
+                for &operand in operands {
+                    let ty = RType::uint(self.width_of(operand).unwrap_or(0));
+                    let op = self.var(operand);
+                    self.bound(op, ty);
+                }
+            }
+            MOpcode::OpSDiv(w) | MOpcode::OpSMod(w) | MOpcode::OpSMulHi(w) => {
+                self.bound(result, RType::int(width));
+                for &operand in &operands {
+                    let op = self.var(operand);
+                    self.bound(op, RType::int(w));
+                }
+            }
+            MOpcode::OpSGt(w) | MOpcode::OpSLt(w) => {
+                for &operand in &operands {
+                    let op = self.var(operand);
+                    self.bound(op, RType::int(w));
+                }
+            }
+            MOpcode::OpGt | MOpcode::OpLt => {
+                for &operand in &operands {
+                    let ty = RType::uint(self.width_of(operand).unwrap_or(0));
+                    let op = self.var(operand);
+                    self.bound(op, ty);
+                }
+            }
+            MOpcode::OpSignExt(_) => {
+                self.bound(result, RType::int(width));
+                if let Some(&operand) = operands.first() {
+                    let ty = RType::int(self.width_of(operand).unwrap_or(0));
+                    let op = self.var(operand);
+                    self.bound(op, ty);
+                }
+            }
+            MOpcode::OpZeroExt(_) => {
+                self.bound(result, RType::uint(width));
+                if let Some(&operand) = operands.first() {
+                    let ty = RType::uint(self.width_of(operand).unwrap_or(0));
+                    let op = self.var(operand);
+                    self.bound(op, ty);
+                }
+            }
+            MOpcode::OpEq => {
+                if let (Some(&a), Some(&b)) = (operands.get(0), operands.get(1)) {
+                    if let (Some(a), Some(b)) = (self.var(a), self.var(b)) {
+                        self.cs.equal(&[a, b]);
+                    }
+                }
+            }
+            MOpcode::OpLoad(..) => {
+                if let Some(&address) = operands.get(1) {
+                    self.points_to(address, value);
+                }
+            }
+            MOpcode::OpStore(..) => {
+                if let (Some(&address), Some(&stored)) = (operands.get(1), operands.get(2)) {
+                    self.points_to(address, stored);
+                }
+            }
+            MOpcode::OpCall => self.call(value),
+            _ => {}
+        }
+    }
+
+    fn call(&mut self, call: SSAValue) {
+        let info = match utils::call_info(call, self.ssa) {
+            Some(info) => info,
+            None => return,
+        };
+        let prototype = match self.ssa
+                                  .constant(info.target)
+                                  .and_then(|target| self.prototypes.get(&target)) {
+            Some(prototype) => prototype.clone(),
+            None => return,
+        };
+        for (alias, ty) in ["A0", "A1", "A2", "A3"].iter().zip(prototype.args) {
+            let arg = self.ssa
+                          .regfile
+                          .register_id_by_alias(alias)
+                          .and_then(|reg| info.register_args.get(reg).cloned());
+            if let Some(arg) = arg {
+                let var = self.var(arg);
+                self.bound(var, ty);
+            }
+        }
+        let ret_reg = match self.ssa.regfile.register_id_by_alias("SN") {
+            Some(reg) => reg,
+            None => return,
+        };
+        if let (Some(ty), Some(&(ret, _))) = (prototype.ret, utils::call_rets(call, self.ssa).get(ret_reg)) {
+            let var = self.var(ret);
+            self.lower_bound(var, ty);
+        }
+    }
+}
@@ -6,4 +6,126 @@
 
 #![allow(dead_code)]
 
+pub mod constraints;
 pub mod structs;
+
+use std::collections::HashMap;
+
+use analysis::idioms::memory::Builtin;
+use frontend::radeco_containers::{RadecoFunction, RadecoModule};
+use middle::regfile::SubRegisterFile;
+use middle::ssa::ssastorage::SSAStorage;
+
+use self::constraints::{ConstraintGenerator, Prototype, SSAValue};
+use self::structs::{RType, DEFAULT_PTR_WIDTH};
+
+/// Prototypes of the imports of `rmod` and of the builtins calls can be made to,
+/// by the address they are called at. `size_t` is as wide as the pointers of `regfile`.
+pub fn prototypes(rmod: &RadecoModule, regfile: &SubRegisterFile) -> HashMap<u64, Prototype> {
+    let ptr_width = constraints::ptr_width(regfile).unwrap_or(DEFAULT_PTR_WIDTH);
+    let mut prototypes = HashMap::new();
+    for (&addr, import) in &rmod.imports {
+        if let Some(prototype) = Prototype::of_libc(&import.name, ptr_width) {
+            prototypes.insert(addr, prototype);
+        }
+    }
+    for builtin in &[Builtin::Memcpy, Builtin::Memset, Builtin::Strlen, Builtin::Memmove] {
+        if let Some(prototype) = Prototype::of_libc(builtin.name(), ptr_width) {
+            prototypes.insert(builtin.address(), prototype);
+        }
+    }
+    prototypes
+}
+
+/// Infers the type of every value of `ssa` which is not a constant.
+pub fn infer_types(ssa: &SSAStorage,
+                   prototypes: &HashMap<u64, Prototype>)
+                   -> HashMap<SSAValue, RType> {
+    let (mut cs, vars) = ConstraintGenerator::new(ssa, prototypes).generate();
+    cs.solve();
+    vars.into_iter()
+        .filter_map(|(value, var)| cs.type_of(&var).map(|ty| (value, ty.clone())))
+        .collect()
+}
+
+/// Infers the types of the values of `rfn` and stores them in it.
+pub fn run(rfn: &mut RadecoFunction, prototypes: &HashMap<u64, Prototype>) {
+    let types = infer_types(rfn.ssa(), prototypes);
+    radeco_trace!("tie|Inferred {} types for {}", types.len(), rfn.name);
+    rfn.set_types(types);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir_reader::test_util::{at, parse, regfile};
+    use middle::ssa::ssa_traits::*;
+
+    // `%1` points to a byte which is sign extended, `%2` is divided unsigned and the
+    // pointer loaded from `%3` is passed to the function at 0x2000.
+    const TYPES: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+        %2: $Unknown64 = $rsi;
+        %3: $Unknown64 = $rdx;
+        %4: $Unknown0 = $mem;
+    bb_0x001000.0000(sz 0x14):
+        [@0x001000.0000] %5: $Unknown8 = Load8(%4, %1);
+        [@0x001000.0001] %6: $Unknown32 = SignExt32(%5);
+        [@0x001004.0000] %7: $Unknown64 = %2 / #x3;
+        [@0x001008.0000] %8: $Unknown0 = Store32(%4, %7, %6);
+        [@0x00100C.0000] %9: $Unknown64 = Load64(%8, %3);
+        [@0x001010.0000] (%10: $Unknown0 = $mem) = CALL #x2000($rdi=%9, $mem=%8);
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $mem = %10;
+        $rax = %6;
+}
+";
+
+    fn entry(ssa: &SSAStorage, reg: &str) -> SSAValue {
+        ssa.values()
+           .into_iter()
+           .find(|&n| ssa.comment(n).as_ref().map(String::as_str) == Some(reg))
+           .unwrap()
+    }
+
+    fn ptr(ty: RType) -> RType {
+        RType::Ptr(Box::new(ty))
+    }
+
+    #[test]
+    fn infer_types_test() {
+        let ssa = parse(TYPES);
+        let types = infer_types(&ssa, &HashMap::new());
+        assert_eq!(types[&at(&ssa, 0x1000, 0)], RType::Int8);
+        assert_eq!(types[&at(&ssa, 0x1000, 1)], RType::Int32);
+        assert_eq!(types[&entry(&ssa, "rdi")], ptr(RType::Int8));
+        assert_eq!(types[&entry(&ssa, "rsi")], RType::UInt64);
+        assert_eq!(types[&at(&ssa, 0x100C, 0)], RType::Reg64);
+        // `%7` is both divided unsigned and dereferenced.
+        assert_eq!(types[&at(&ssa, 0x1004, 0)], RType::Overdefined);
+    }
+
+    #[test]
+    fn infer_prototype_test() {
+        let ssa = parse(TYPES);
+        let mut prototypes = HashMap::new();
+        prototypes.insert(0x2000, Prototype::of_libc("sym.imp.strlen", 64).unwrap());
+        let types = infer_types(&ssa, &prototypes);
+        assert_eq!(types[&at(&ssa, 0x100C, 0)], ptr(RType::Int8));
+        assert_eq!(types[&entry(&ssa, "rdx")], ptr(ptr(RType::Int8)));
+    }
+
+    #[test]
+    fn libc_size_t_width() {
+        assert_eq!(constraints::ptr_width(&regfile()), Some(64));
+        let malloc = Prototype::of_libc("malloc", 32).unwrap();
+        assert_eq!(malloc.args, vec![RType::UInt32]);
+        let memset = Prototype::of_libc("sym.imp.memset", 64).unwrap();
+        assert_eq!(memset.args[2], RType::UInt64);
+        assert_eq!(memset.ret, Some(ptr(RType::Undefined)));
+    }
+}
@@ -1,15 +1,36 @@
 //! Defines valid types.
+//!
+//! Types are ordered by how specific they are, `Undefined` being the most general type
+//! and `Overdefined` the result of conflicting constraints. Apart from `Overdefined`
+//! they form a tree:
+//!
+//! ```text
+//!                 Undefined
+//!         /           |         \
+//!      RegN        Reg<ptr>     Bool
+//!       |          /   |  \
+//!      NumN     NumN Ptr(_) Code
+//!     /    \
+//!  IntN   UIntN
+//! ```
+//!
+//! where `Ptr(a)` is below `Ptr(b)` if `a` is below `b`.
 
 use std::fmt;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use petgraph::graph::{EdgeIndex, Graph, NodeIndex};
+use petgraph::graph::{Graph, NodeIndex};
+use petgraph::unionfind::UnionFind;
 use petgraph::visit::EdgeRef;
 use petgraph::EdgeDirection;
 
-type TIEResult<T> = Result<T, String>;
+/// Pointer width assumed if none is set.
+pub const DEFAULT_PTR_WIDTH: u16 = 64;
 
-#[derive(Clone, Debug)]
+/// Rounds of bound propagation before giving up on a fixed point.
+const MAX_ROUNDS: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum RType {
     Overdefined,
     Reg8,
@@ -36,6 +57,52 @@ pub enum RType {
     Undefined,
 }
 
+impl RType {
+    /// Register of `width` bits, `Undefined` if there is none.
+    pub fn reg(width: u16) -> RType {
+        match width {
+            8 => RType::Reg8,
+            16 => RType::Reg16,
+            32 => RType::Reg32,
+            64 => RType::Reg64,
+            _ => RType::Undefined,
+        }
+    }
+
+    /// Number of `width` bits, `Undefined` if there is none.
+    pub fn num(width: u16) -> RType {
+        match width {
+            8 => RType::Num8,
+            16 => RType::Num16,
+            32 => RType::Num32,
+            64 => RType::Num64,
+            _ => RType::Undefined,
+        }
+    }
+
+    /// Unsigned integer of `width` bits, `Undefined` if there is none.
+    pub fn uint(width: u16) -> RType {
+        match width {
+            8 => RType::UInt8,
+            16 => RType::UInt16,
+            32 => RType::UInt32,
+            64 => RType::UInt64,
+            _ => RType::Undefined,
+        }
+    }
+
+    /// Signed integer of `width` bits, `Undefined` if there is none.
+    pub fn int(width: u16) -> RType {
+        match width {
+            8 => RType::Int8,
+            16 => RType::Int16,
+            32 => RType::Int32,
+            64 => RType::Int64,
+            _ => RType::Undefined,
+        }
+    }
+}
+
 impl fmt::Display for RType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if let RType::Ptr(ref ty) = *self {
@@ -194,6 +261,7 @@ pub struct ConstraintSet {
     upper_bound: HashMap<NodeIndex, RType>,
     // B_
     lower_bound: HashMap<NodeIndex, RType>,
+    ptr_width: u16,
 }
 
 impl ConstraintSet {
@@ -201,10 +269,10 @@ impl ConstraintSet {
     pub fn operands(&self, n: &NodeIndex) -> Vec<NodeIndex> {
         let mut result = Vec::new();
         for edge in self.g.edges_directed(*n, EdgeDirection::Outgoing) {
-            result.push ((edge.id(), n));
+            result.push((edge.id(), edge.target()));
         }
         result.sort_by(|a, b| a.0.cmp(&b.0));
-        result.iter().map(|a| *a.1).collect()
+        result.iter().map(|a| a.1).collect()
     }
 
     pub fn insert_type_var(&mut self, named: Option<String>) -> NodeIndex {
@@ -266,16 +334,173 @@ impl ConstraintSet {
         intersection
     }
 
-    pub fn solve(&mut self) {
-        unimplemented!()
+    /// Inserts a pointer to `inner`.
+    pub fn ptr(&mut self, inner: &NodeIndex) -> NodeIndex {
+        let ptr = self.g.add_node(ConstraintNode::Ptr);
+        self.g.update_edge(ptr, *inner, ConstraintEdge::Ptr);
+        ptr
+    }
+
+    /// Sets the width of pointers, which are registers of that width.
+    pub fn set_ptr_width(&mut self, width: u16) {
+        self.ptr_width = width;
+    }
+
+    /// Type of `var` found by `solve`.
+    pub fn type_of(&self, var: &NodeIndex) -> Option<&RType> {
+        self.binding_map.get(var)
+    }
+
+    /// Most general type `var` may have according to the constraints.
+    pub fn upper_bound(&self, var: &NodeIndex) -> Option<&RType> {
+        self.upper_bound.get(var)
     }
 
-    fn solve_equality_constraint(&mut self, _constraint_edge: &EdgeIndex) -> TIEResult<()> {
-        unimplemented!()
+    /// Most specific type `var` must at least have according to the constraints.
+    pub fn lower_bound(&self, var: &NodeIndex) -> Option<&RType> {
+        self.lower_bound.get(var)
+    }
+
+    /// Solves the constraints, giving a type to each type variable.
+    ///
+    /// Equal nodes are unified first, together with the inner types of pointers
+    /// unified that way. The subtype relations between the resulting classes are
+    /// decomposed according to section 6.3.2 of the paper and the bounds of each
+    /// class propagated along them until they no longer change. A variable gets its
+    /// lower bound as type if it has one, its upper bound otherwise, and
+    /// `Overdefined` if they conflict.
+    pub fn solve(&mut self) {
+        let classes = self.unify();
+
+        let mut inner = HashMap::new();
+        let mut base = HashMap::new();
+        for node in self.g.node_indices() {
+            let class = classes.find(node.index());
+            match self.g[node] {
+                ConstraintNode::Ptr => {
+                    inner.insert(class, classes.find(self.inner_type(node).index()));
+                }
+                ConstraintNode::Type(ref ty) => {
+                    let ty = match base.get(&class) {
+                        Some(other) => self.meet(&[self.normalize(ty), RType::clone(other)]),
+                        None => self.normalize(ty),
+                    };
+                    base.insert(class, ty);
+                }
+                _ => {}
+            }
+        }
+
+        let mut relations = HashSet::new();
+        for edge in self.g.edge_references() {
+            if let ConstraintEdge::SubType = *edge.weight() {
+                self.decompose(&classes, edge.source(), edge.target(), &mut relations);
+            }
+        }
+        // Pointers are covariant in their inner type.
+        let mut worklist = relations.iter().cloned().collect::<Vec<_>>();
+        while let Some((lhs, rhs)) = worklist.pop() {
+            if let (Some(&lhs), Some(&rhs)) = (inner.get(&lhs), inner.get(&rhs)) {
+                if relations.insert((lhs, rhs)) {
+                    worklist.push((lhs, rhs));
+                }
+            }
+        }
+
+        let any_ptr = RType::Ptr(Box::new(RType::Undefined));
+        let mut upper = HashMap::new();
+        let mut lower = HashMap::new();
+        for node in self.g.node_indices() {
+            let class = classes.find(node.index());
+            let (ub, lb) = match base.get(&class) {
+                Some(ty) => (ty.clone(), ty.clone()),
+                None => (RType::Undefined, RType::Overdefined),
+            };
+            let ub = if inner.contains_key(&class) { self.meet(&[ub, any_ptr.clone()]) } else { ub };
+            upper.insert(class, ub);
+            lower.insert(class, lb);
+        }
+
+        let mut stable = false;
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = false;
+            for &(lhs, rhs) in &relations {
+                let ub = self.meet(&[upper[&lhs].clone(), upper[&rhs].clone()]);
+                if ub != upper[&lhs] {
+                    upper.insert(lhs, ub);
+                    changed = true;
+                }
+                let lb = self.join(&[lower[&lhs].clone(), lower[&rhs].clone()]);
+                if lb != lower[&rhs] {
+                    lower.insert(rhs, lb);
+                    changed = true;
+                }
+            }
+            for (&class, &inner_class) in &inner {
+                if let RType::Ptr(ref ty) = upper[&class].clone() {
+                    let ub = self.meet(&[upper[&inner_class].clone(), RType::clone(ty)]);
+                    if ub != upper[&inner_class] {
+                        upper.insert(inner_class, ub);
+                        changed = true;
+                    }
+                }
+                if let RType::Ptr(ref ty) = lower[&class].clone() {
+                    let lb = self.join(&[lower[&inner_class].clone(), RType::clone(ty)]);
+                    if lb != lower[&inner_class] {
+                        lower.insert(inner_class, lb);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                stable = true;
+                break;
+            }
+        }
+        if !stable {
+            radeco_warn!("tie|No fixpoint after {} rounds, types may be too general",
+                         MAX_ROUNDS);
+        }
+
+        for var in self.type_vars.values().cloned().collect::<Vec<_>>() {
+            let class = classes.find(var.index());
+            let ty = self.resolve(class, &upper, &lower, &inner, &mut HashSet::new());
+            self.binding_map.insert(var, ty);
+            self.upper_bound.insert(var, upper[&class].clone());
+            self.lower_bound.insert(var, lower[&class].clone());
+        }
     }
 
-    fn solve_subtype_constraint(&mut self, _constraint_edge: &EdgeIndex) -> TIEResult<()> {
-        unimplemented!()
+    // Unifies nodes which are equal, including the inner types of unified pointers.
+    fn unify(&self) -> UnionFind<usize> {
+        let mut classes = UnionFind::new(self.g.node_count());
+        for edge in self.g.edge_references() {
+            if let ConstraintEdge::Equal = *edge.weight() {
+                classes.union(edge.source().index(), edge.target().index());
+            }
+        }
+        let ptrs = self.g
+                       .node_indices()
+                       .filter(|&n| match self.g[n] {
+                           ConstraintNode::Ptr => true,
+                           _ => false,
+                       })
+                       .collect::<Vec<_>>();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            let mut inner = HashMap::new();
+            for &ptr in &ptrs {
+                let inner_node = self.inner_type(ptr).index();
+                match inner.get(&classes.find(ptr.index())).cloned() {
+                    Some(other) => changed |= classes.union(other, inner_node),
+                    None => {
+                        inner.insert(classes.find(ptr.index()), inner_node);
+                    }
+                }
+            }
+        }
+        classes
     }
 
     fn inner_type(&self, ptr_node: NodeIndex) -> NodeIndex {
@@ -288,65 +513,232 @@ impl ConstraintSet {
                 }
             })
             .expect("Inner type of `Ptr` cannot be `None`")
-            .source() // TODO .source() or .target() ?
+            .target()
     }
 
-    // This function decomposes the first node and adds the appropriate edges to
-    // the other node.
-    fn decompose_constraint_node(&mut self, lhs: NodeIndex, rhs: NodeIndex) {
-        let rhs_is_ptr = match self.g[rhs] {
-            ConstraintNode::Intersect => {
-                let operands = self.operands(&rhs);
-                for op in &operands {
-                    self.g.update_edge(lhs, *op, ConstraintEdge::SubType);
-                }
-                false
+    // Decomposes `lhs <: rhs` into relations between classes. A union is a subtype if
+    // all of its operands are, an intersection a supertype if all of its operands are.
+    fn decompose(&self,
+                 classes: &UnionFind<usize>,
+                 lhs: NodeIndex,
+                 rhs: NodeIndex,
+                 relations: &mut HashSet<(usize, usize)>) {
+        if let ConstraintNode::Union = self.g[lhs] {
+            for op in self.operands(&lhs) {
+                self.decompose(classes, op, rhs, relations);
+            }
+        } else if let ConstraintNode::Intersect = self.g[rhs] {
+            for op in self.operands(&rhs) {
+                self.decompose(classes, lhs, op, relations);
             }
-            ConstraintNode::Ptr => true,
-            _ => false,
+        } else {
+            let (lhs, rhs) = (classes.find(lhs.index()), classes.find(rhs.index()));
+            if lhs != rhs {
+                relations.insert((lhs, rhs));
+            }
+        }
+    }
+
+    // Type of `class` from its bounds, with pointers to the type of their inner class.
+    fn resolve(&self,
+               class: usize,
+               upper: &HashMap<usize, RType>,
+               lower: &HashMap<usize, RType>,
+               inner: &HashMap<usize, usize>,
+               visited: &mut HashSet<usize>)
+               -> RType {
+        let (ub, lb) = (&upper[&class], &lower[&class]);
+        let ty = if !self.is_subtype(lb, ub) {
+            return RType::Overdefined;
+        } else if *lb != RType::Overdefined {
+            lb.clone()
+        } else {
+            ub.clone()
         };
+        let inner_class = match inner.get(&class) {
+            Some(&inner_class) if visited.insert(class) => inner_class,
+            _ => return ty,
+        };
+        let pointee = match ty {
+            RType::Ptr(pointee) => *pointee,
+            RType::Undefined => RType::Undefined,
+            ref ty if *ty == RType::reg(self.ptr_width()) => RType::Undefined,
+            ty => return ty,
+        };
+        let inner_ty = self.resolve(inner_class, upper, lower, inner, visited);
+        let pointee = match self.meet(&[pointee.clone(), inner_ty]) {
+            RType::Overdefined => pointee,
+            ty => ty,
+        };
+        RType::Ptr(Box::new(pointee))
+    }
 
-        let lhs_is_ptr = match self.g[lhs] {
-            ConstraintNode::Union => {
-                let lhs_operands = self.operands(&lhs);
-                let mut subtype_relations = Vec::new();
-                let mut edges = self.g.neighbors_directed(lhs, EdgeDirection::Incoming).detach();
-                while let Some(edge) = edges.next_edge(&self.g) {
-                    if let ConstraintEdge::SubType = self.g[edge] {
-                        let (_, target) = self.g.edge_endpoints(edge).expect("");
-                        self.g.remove_edge(edge);
-                        subtype_relations.push(target);
-                    }
-                }
-                for rhs_op in subtype_relations {
-                    for lhs_op in &lhs_operands {
-                        self.g.update_edge(*lhs_op, rhs_op, ConstraintEdge::SubType);
-                    }
+    fn ptr_width(&self) -> u16 {
+        if self.ptr_width == 0 {
+            DEFAULT_PTR_WIDTH
+        } else {
+            self.ptr_width
+        }
+    }
+
+    // Replaces unions and intersections by the types they stand for.
+    fn normalize(&self, ty: &RType) -> RType {
+        match *ty {
+            RType::Union(ref tys) => self.join(tys),
+            RType::Intersect(ref tys) => self.meet(tys),
+            RType::Ptr(ref inner) => RType::Ptr(Box::new(self.normalize(inner))),
+            ref ty => ty.clone(),
+        }
+    }
+
+    // The next more general type, `None` for `Undefined` and `Overdefined`.
+    fn parent(&self, ty: &RType) -> Option<RType> {
+        let parent = match *ty {
+            RType::Overdefined | RType::Undefined => return None,
+            RType::Reg8 | RType::Reg16 | RType::Reg32 | RType::Reg64 | RType::Bool => {
+                RType::Undefined
+            }
+            RType::Num8 => RType::Reg8,
+            RType::Num16 => RType::Reg16,
+            RType::Num32 => RType::Reg32,
+            RType::Num64 => RType::Reg64,
+            RType::UInt8 | RType::Int8 => RType::Num8,
+            RType::UInt16 | RType::Int16 => RType::Num16,
+            RType::UInt32 | RType::Int32 => RType::Num32,
+            RType::UInt64 | RType::Int64 => RType::Num64,
+            RType::Code => RType::reg(self.ptr_width()),
+            RType::Ptr(ref inner) => {
+                match self.parent(inner) {
+                    Some(inner) => RType::Ptr(Box::new(inner)),
+                    None => RType::reg(self.ptr_width()),
                 }
-                false
             }
-            ConstraintNode::Ptr => true,
-            _ => false,
+            RType::Union(_) | RType::Intersect(_) => return self.parent(&self.normalize(ty)),
         };
+        Some(parent)
+    }
 
-        if lhs_is_ptr && rhs_is_ptr {
-            let lhs_inner = self.inner_type(lhs);
-            let rhs_inner = self.inner_type(rhs);
-            self.g.update_edge(lhs_inner, rhs_inner, ConstraintEdge::SubType);
+    // `ty` followed by all of its more general types.
+    fn ancestors(&self, ty: &RType) -> Vec<RType> {
+        let mut ancestors = vec![self.normalize(ty)];
+        while let Some(parent) = self.parent(&ancestors[ancestors.len() - 1]) {
+            ancestors.push(parent);
         }
+        ancestors
+    }
+
+    fn is_subtype(&self, lhs: &RType, rhs: &RType) -> bool {
+        *lhs == RType::Overdefined || *rhs == RType::Undefined ||
+        self.ancestors(lhs).contains(&self.normalize(rhs))
+    }
+
+    // Most general type which is a subtype of all `operands`.
+    fn meet(&self, operands: &[RType]) -> RType {
+        operands.iter().fold(RType::Undefined, |acc, ty| {
+            if self.is_subtype(&acc, ty) {
+                acc
+            } else if self.is_subtype(ty, &acc) {
+                self.normalize(ty)
+            } else {
+                RType::Overdefined
+            }
+        })
+    }
+
+    // Most specific type which is a supertype of all `operands`.
+    fn join(&self, operands: &[RType]) -> RType {
+        operands.iter().fold(RType::Overdefined, |acc, ty| {
+            if acc == RType::Overdefined {
+                return self.normalize(ty);
+            } else if *ty == RType::Overdefined {
+                return acc;
+            }
+            let ancestors = self.ancestors(&acc);
+            self.ancestors(ty)
+                .into_iter()
+                .find(|t| ancestors.contains(t))
+                .unwrap_or(RType::Undefined)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ptr(ty: RType) -> RType {
+        RType::Ptr(Box::new(ty))
+    }
+
+    #[test]
+    fn lattice_test() {
+        let cs = ConstraintSet::default();
+        assert_eq!(cs.join(&[RType::Int32, RType::UInt32]), RType::Num32);
+        assert_eq!(cs.join(&[RType::Int32, RType::Int64]), RType::Undefined);
+        assert_eq!(cs.join(&[ptr(RType::Int8), RType::Num64]), RType::Reg64);
+        assert_eq!(cs.join(&[ptr(RType::Int8), ptr(RType::UInt8)]), ptr(RType::Num8));
+        assert_eq!(cs.join(&[RType::Overdefined, RType::Bool]), RType::Bool);
+        assert_eq!(cs.meet(&[RType::Reg32, RType::UInt32]), RType::UInt32);
+        assert_eq!(cs.meet(&[RType::Int32, RType::UInt32]), RType::Overdefined);
+        assert_eq!(cs.meet(&[ptr(RType::Undefined), ptr(RType::Int8)]), ptr(RType::Int8));
+        assert_eq!(cs.meet(&[RType::Code, RType::Reg64]), RType::Code);
+        assert_eq!(cs.meet(&[RType::Union(vec![RType::Int8, RType::UInt8]), RType::Reg8]),
+                   RType::Num8);
     }
 
-    fn decompose_subtype_relation(&mut self, subtype_edge: EdgeIndex) {
-        // According to section 6.3.2 - Decomposition Rules.
-        let (lhs, rhs) = self.g.edge_endpoints(subtype_edge).expect("This cannot be None");
-        self.decompose_constraint_node(lhs, rhs);
+    #[test]
+    fn solve_test() {
+        let mut cs = ConstraintSet::default();
+        let a = cs.insert_type_var(None);
+        let b = cs.insert_type_var(None);
+        let c = cs.insert_type_var(None);
+        let d = cs.insert_type_var(None);
+        let int = cs.insert_base_type(RType::Int32);
+        let reg = cs.insert_base_type(RType::Reg32);
+        // a = int32, a <: b <: reg32, c <: reg32, d = *a
+        cs.equal(&[a, int]);
+        cs.subtype(&a, &b);
+        cs.subtype(&b, &reg);
+        cs.subtype(&c, &reg);
+        let pa = cs.ptr(&a);
+        cs.equal(&[d, pa]);
+        cs.solve();
+        assert_eq!(cs.type_of(&a), Some(&RType::Int32));
+        assert_eq!(cs.type_of(&b), Some(&RType::Int32));
+        assert_eq!(cs.upper_bound(&b), Some(&RType::Reg32));
+        assert_eq!(cs.type_of(&c), Some(&RType::Reg32));
+        assert_eq!(cs.type_of(&d), Some(&ptr(RType::Int32)));
     }
 
-    fn meet(&mut self, _operands: &[RType]) -> RType {
-        unimplemented!()
+    #[test]
+    fn solve_ptr_test() {
+        let mut cs = ConstraintSet::default();
+        let p = cs.insert_type_var(None);
+        let q = cs.insert_type_var(None);
+        let x = cs.insert_type_var(None);
+        let y = cs.insert_type_var(None);
+        // *p = x, *q = y, p = q, q <: *i8
+        let px = cs.ptr(&x);
+        let qy = cs.ptr(&y);
+        cs.equal(&[p, px]);
+        cs.equal(&[q, qy]);
+        cs.equal(&[p, q]);
+        let char_ptr = cs.insert_base_type(ptr(RType::Int8));
+        cs.subtype(&q, &char_ptr);
+        cs.solve();
+        assert_eq!(cs.type_of(&x), Some(&RType::Int8));
+        assert_eq!(cs.type_of(&p), Some(&ptr(RType::Int8)));
     }
 
-    fn join(&mut self, _operands: &[RType]) -> RType {
-        unimplemented!()
+    #[test]
+    fn solve_conflict_test() {
+        let mut cs = ConstraintSet::default();
+        let a = cs.insert_type_var(None);
+        let int = cs.insert_base_type(RType::Int64);
+        let uint = cs.insert_base_type(RType::UInt64);
+        cs.subtype(&int, &a);
+        cs.subtype(&a, &uint);
+        cs.solve();
+        assert_eq!(cs.type_of(&a), Some(&RType::Overdefined));
     }
 }
@@ -161,7 +161,7 @@ where RFn: RFunction + Clone,
                     },
                     MArity::Binary => {
                         match opcode {
-                            MOpcode::OpLoad => {
+                            MOpcode::OpLoad(..) => {
                                 //format!("{}({}, {})",
                                 //        opcode.to_string(),
                                 //        self.print_node_as_comp(ops[0]),
@@ -171,7 +171,7 @@ where RFn: RFunction + Clone,
                                          opcode.to_string(),
                                          self.print_node_as_comp(ops[1]))
                             },
-                            MOpcode::OpStore => {
+                            MOpcode::OpStore(..) => {
                                 // FIXME probably has wrong arity
                                 //format!("{}({}, {}, {})",
                                 //        opcode.to_string(),
@@ -244,7 +244,7 @@ where RFn: RFunction + Clone,
                 // We are loading from a memory region of which we don't know the value
                 StridedInterval_u::new()
             },
-            NodeType::Op(MOpcode::OpStore) => {
+            NodeType::Op(MOpcode::OpStore(..)) => {
                 let mem_state   = operands[0];
                 let target_node = operands[1];
                 let value_node  = operands[2];
@@ -305,10 +305,10 @@ where RFn: RFunction + Clone,
             },
             //NodeType::Comment(ref c) if is_base_pointer(c) => {},
             //NodeType::Comment(ref c) if is_gen_purpose_reg(c) => {},
-            NodeType::Op(MOpcode::OpStore) => {
+            NodeType::Op(MOpcode::OpStore(..)) => {
                 self.compute_abstract_value(operands[2])
             },
-            NodeType::Op(MOpcode::OpLoad) => {
+            NodeType::Op(MOpcode::OpLoad(..)) => {
                 let (a_loc_base, a_loc_offs) =
                     self.compute_a_loc(operands[1])
                     .expect("No base a-loc found");
@@ -395,7 +395,7 @@ where RFn: RFunction + Clone,
                 }, 0))
             },
             NodeType::Comment(c) => None, // TODO
-            NodeType::Op(MOpcode::OpStore) => { //TODO
+            NodeType::Op(MOpcode::OpStore(..)) => { //TODO
                 // should return set of all a-loc that have been written to
                 // up to 'now'
                 // value that will be stored in the a-loc
@@ -424,7 +424,7 @@ where RFn: RFunction + Clone,
                             } else {None}
                 } else {None}
             },
-            NodeType::Op(MOpcode::OpLoad) => {None}, // TODO
+            NodeType::Op(MOpcode::OpLoad(..)) => {None}, // TODO
             NodeType::Op(m_opcode) => {
                 if operands.len() >= 2 {
                     let update = self.compute_abstract_value(operands[1]).as_const();
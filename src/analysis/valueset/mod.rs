@@ -28,14 +28,9 @@ mod strided_interval;
 pub mod mem_structs;
 pub mod analyzer_wysinwyx;
 
-/// Value set of u64 integers with certain bits set/cleared.
-#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
-pub struct KnownBits {
-    /// Bits that are cleared
-    pub zerobits: u64,
-    /// Bits that are set
-    pub onebits: u64,
-}
+/// Value set of u64 integers with certain bits set/cleared, shared with the known-bits
+/// analysis.
+pub use analysis::known_bits::KnownBits;
 
 /// Value set of unsigned values that satisfy value % modulus = residue.
 #[derive(Clone, Copy, Debug)]
@@ -7,10 +7,1045 @@
 
 //! Module that implements math operation on the BDD.
 //!
+//! A BDD (on k bits' space) is the set of k-bits numbers which satisfy a reduced
+//! ordered binary decision diagram, with one variable for each bit of the numbers,
+//! from the least significant bit to the most significant one.
+//! Set operations, bitwise operations, addition, subtraction and shifts by constants
+//! are exact. Other operations are exact on small sets, and over-approximated
+//! otherwise.
+//!
+//! Nodes of all BDDs are shared in one table, thus a BDD is only an index of its
+//! root node. Nodes are never freed.
+//!
 //! More information are available from "BDD-based Value Analysis for X86 Executables".
 //! Please refer here:
 //!     * https://tubdok.tub.tuhh.de/bitstream/11420/1510/1/dis.pdf
 //!
 
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::{Neg, Add, Sub, Div, Rem, Mul};
+use std::ops::{BitAnd, BitOr, BitXor, Not, Shl, Shr};
+use std::sync::{Mutex, MutexGuard};
+
+use super::abstract_set::{inum, unum, _bits};
+use super::abstract_set::{AbstractSet, Container};
+
+// Operations which are not exact on BDDs enumerate pairs of numbers of sets up to
+// this size.
+const ENUMERATION_LIMIT: u128 = 4096;
+
+type NodeRef = u32;
+
+const FALSE: NodeRef = 0;
+const TRUE: NodeRef = 1;
+// Variable of the terminal nodes, which comes after any bit.
+const TERMINAL: u8 = u8::max_value();
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct Node {
+    var: u8,
+    low: NodeRef,
+    high: NodeRef,
+}
+
+// Unique table of the nodes of all BDDs.
+struct Table {
+    nodes: Vec<Node>,
+    unique: HashMap<Node, NodeRef>,
+}
+
+lazy_static! {
+    static ref TABLE: Mutex<Table> = Mutex::new(Table::new());
+}
+
+fn table() -> MutexGuard<'static, Table> {
+    TABLE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Returns the mask of k bits.
+fn mask(k: u8) -> unum {
+    if k >= _bits {
+        unum::max_value()
+    } else {
+        ((1 as unum) << k) - 1
+    }
+}
+
+/// Returns the bits of n in k bits.
+fn to_bits(n: inum, k: u8) -> unum {
+    (n as unum) & mask(k)
+}
+
+/// Returns the signed number of k bits v.
+fn from_bits(v: unum, k: u8) -> inum {
+    let shift = _bits - k;
+    ((v << shift) as inum) >> shift
+}
+
+impl Table {
+    fn new() -> Table {
+        let terminal = |n| Node { var: TERMINAL, low: n, high: n };
+        Table {
+            nodes: vec![terminal(FALSE), terminal(TRUE)],
+            unique: HashMap::new(),
+        }
+    }
+
+    fn var(&self, n: NodeRef) -> u8 {
+        self.nodes[n as usize].var
+    }
+
+    // Returns the level of n, k for terminal nodes.
+    fn level(&self, n: NodeRef, k: u8) -> u8 {
+        if n == FALSE || n == TRUE { k } else { self.var(n) }
+    }
+
+    fn mk(&mut self, var: u8, low: NodeRef, high: NodeRef) -> NodeRef {
+        if low == high {
+            return low;
+        }
+        let node = Node { var: var, low: low, high: high };
+        if let Some(&n) = self.unique.get(&node) {
+            return n;
+        }
+        let n = self.nodes.len() as NodeRef;
+        self.nodes.push(node);
+        self.unique.insert(node, n);
+        n
+    }
+
+    // Returns the cofactors of n for the bit var.
+    fn cofactors(&self, n: NodeRef, var: u8) -> (NodeRef, NodeRef) {
+        let node = self.nodes[n as usize];
+        if node.var == var { (node.low, node.high) } else { (n, n) }
+    }
+
+    // Returns the bits from..to set to bit, followed by tail.
+    fn chain(&mut self, from: u8, to: u8, bit: bool, tail: NodeRef) -> NodeRef {
+        (from..to).rev().fold(tail, |n, i| {
+            if bit { self.mk(i, FALSE, n) } else { self.mk(i, n, FALSE) }
+        })
+    }
+
+    fn constant(&mut self, v: unum, k: u8) -> NodeRef {
+        (0..k).rev().fold(TRUE, |n, i| {
+            if (v >> i) & 1 == 1 { self.mk(i, FALSE, n) } else { self.mk(i, n, FALSE) }
+        })
+    }
+
+    fn contains(&self, root: NodeRef, v: unum) -> bool {
+        let mut n = root;
+        while n != FALSE && n != TRUE {
+            let node = self.nodes[n as usize];
+            n = if (v >> node.var) & 1 == 1 { node.high } else { node.low };
+        }
+        n == TRUE
+    }
+
+    // Returns the set operation op on a and b.
+    fn apply<F>(&mut self, a: NodeRef, b: NodeRef, op: &F,
+                memo: &mut HashMap<(NodeRef, NodeRef), NodeRef>) -> NodeRef
+        where F: Fn(bool, bool) -> bool
+    {
+        if (a == FALSE || a == TRUE) && (b == FALSE || b == TRUE) {
+            return if op(a == TRUE, b == TRUE) { TRUE } else { FALSE };
+        }
+        if let Some(&n) = memo.get(&(a, b)) {
+            return n;
+        }
+        let var = ::std::cmp::min(self.var(a), self.var(b));
+        let (a0, a1) = self.cofactors(a, var);
+        let (b0, b1) = self.cofactors(b, var);
+        let low = self.apply(a0, b0, op, memo);
+        let high = self.apply(a1, b1, op, memo);
+        let n = self.mk(var, low, high);
+        memo.insert((a, b), n);
+        n
+    }
+
+    fn or(&mut self, a: NodeRef, b: NodeRef) -> NodeRef {
+        self.apply(a, b, &|x, y| x || y, &mut HashMap::new())
+    }
+
+    fn and(&mut self, a: NodeRef, b: NodeRef) -> NodeRef {
+        self.apply(a, b, &|x, y| x && y, &mut HashMap::new())
+    }
+
+    // Returns the numbers of a which are not in b.
+    fn diff(&mut self, a: NodeRef, b: NodeRef) -> NodeRef {
+        self.apply(a, b, &|x, y| x && !y, &mut HashMap::new())
+    }
+
+    // Returns the number of numbers of k bits in root.
+    fn count(&self, root: NodeRef, k: u8) -> u128 {
+        fn count_from(t: &Table, n: NodeRef, k: u8, memo: &mut HashMap<NodeRef, u128>) -> u128 {
+            if n == FALSE || n == TRUE {
+                return if n == TRUE { 1 } else { 0 };
+            }
+            if let Some(&c) = memo.get(&n) {
+                return c;
+            }
+            let node = t.nodes[n as usize];
+            let low = count_from(t, node.low, k, memo) << (t.level(node.low, k) - node.var - 1);
+            let high = count_from(t, node.high, k, memo) << (t.level(node.high, k) - node.var - 1);
+            memo.insert(n, low + high);
+            low + high
+        }
+        count_from(self, root, k, &mut HashMap::new()) << self.level(root, k)
+    }
+
+    // Returns the numbers of k bits in root, if there are at most limit of them.
+    fn elements(&self, root: NodeRef, k: u8, limit: u128) -> Option<Vec<unum>> {
+        fn collect(t: &Table, n: NodeRef, level: u8, k: u8, prefix: unum, out: &mut Vec<unum>) {
+            if n == FALSE {
+                return;
+            }
+            if level == k {
+                out.push(prefix);
+                return;
+            }
+            let (low, high) = t.cofactors(n, level);
+            collect(t, low, level + 1, k, prefix, out);
+            collect(t, high, level + 1, k, prefix | ((1 as unum) << level), out);
+        }
+        if self.count(root, k) > limit {
+            return None;
+        }
+        let mut out = Vec::new();
+        collect(self, root, 0, k, 0, &mut out);
+        Some(out)
+    }
+
+    // Returns {x op y | x in a, y in b} for an operation op working bit by bit.
+    fn bitwise<F>(&mut self, a: NodeRef, b: NodeRef, op: &F,
+                  memo: &mut HashMap<(NodeRef, NodeRef), NodeRef>) -> NodeRef
+        where F: Fn(bool, bool) -> bool
+    {
+        if a == FALSE || b == FALSE {
+            return FALSE;
+        }
+        if a == TRUE && b == TRUE {
+            return TRUE;
+        }
+        if let Some(&n) = memo.get(&(a, b)) {
+            return n;
+        }
+        let var = ::std::cmp::min(self.var(a), self.var(b));
+        let (a0, a1) = self.cofactors(a, var);
+        let (b0, b1) = self.cofactors(b, var);
+        let (mut low, mut high) = (FALSE, FALSE);
+        for &(x, ax) in &[(false, a0), (true, a1)] {
+            for &(y, by) in &[(false, b0), (true, b1)] {
+                let n = self.bitwise(ax, by, op, memo);
+                if op(x, y) {
+                    high = self.or(high, n);
+                } else {
+                    low = self.or(low, n);
+                }
+            }
+        }
+        let n = self.mk(var, low, high);
+        memo.insert((a, b), n);
+        n
+    }
+
+    // Returns {x + y + carry | x in a, y in b} on the bits level..k.
+    fn add(&mut self, a: NodeRef, b: NodeRef, carry: bool, level: u8, k: u8,
+           memo: &mut HashMap<(NodeRef, NodeRef, bool, u8), NodeRef>) -> NodeRef {
+        if a == FALSE || b == FALSE {
+            return FALSE;
+        }
+        // Adding any number to anything results in any number
+        if level == k || a == TRUE || b == TRUE {
+            return TRUE;
+        }
+        if let Some(&n) = memo.get(&(a, b, carry, level)) {
+            return n;
+        }
+        // The carry of a bit which is free in both numbers may be anything, so the
+        // bits are added one at a time, even those on which neither a nor b depend.
+        let (a0, a1) = self.cofactors(a, level);
+        let (b0, b1) = self.cofactors(b, level);
+        let (mut low, mut high) = (FALSE, FALSE);
+        for &(x, ax) in &[(false, a0), (true, a1)] {
+            for &(y, by) in &[(false, b0), (true, b1)] {
+                let sum = x ^ y ^ carry;
+                let next = (x && y) || (carry && (x ^ y));
+                let n = self.add(ax, by, next, level + 1, k, memo);
+                if sum {
+                    high = self.or(high, n);
+                } else {
+                    low = self.or(low, n);
+                }
+            }
+        }
+        let n = self.mk(level, low, high);
+        memo.insert((a, b, carry, level), n);
+        n
+    }
+
+    // Returns {!x | x in a}.
+    fn not(&mut self, a: NodeRef, memo: &mut HashMap<NodeRef, NodeRef>) -> NodeRef {
+        if a == FALSE || a == TRUE {
+            return a;
+        }
+        if let Some(&n) = memo.get(&a) {
+            return n;
+        }
+        let node = self.nodes[a as usize];
+        let low = self.not(node.high, memo);
+        let high = self.not(node.low, memo);
+        let n = self.mk(node.var, low, high);
+        memo.insert(a, n);
+        n
+    }
+
+    // Returns a without the bits from k on.
+    fn truncate(&mut self, a: NodeRef, k: u8, memo: &mut HashMap<NodeRef, NodeRef>) -> NodeRef {
+        if a == FALSE || a == TRUE {
+            return a;
+        }
+        if self.var(a) >= k {
+            return TRUE;
+        }
+        if let Some(&n) = memo.get(&a) {
+            return n;
+        }
+        let node = self.nodes[a as usize];
+        let low = self.truncate(node.low, k, memo);
+        let high = self.truncate(node.high, k, memo);
+        let n = self.mk(node.var, low, high);
+        memo.insert(a, n);
+        n
+    }
+
+    // Returns a without the bits before k.
+    fn exists_below(&mut self, a: NodeRef, k: u8, memo: &mut HashMap<NodeRef, NodeRef>) -> NodeRef {
+        if a == FALSE || a == TRUE || self.var(a) >= k {
+            return a;
+        }
+        if let Some(&n) = memo.get(&a) {
+            return n;
+        }
+        let node = self.nodes[a as usize];
+        let low = self.exists_below(node.low, k, memo);
+        let high = self.exists_below(node.high, k, memo);
+        let n = self.or(low, high);
+        memo.insert(a, n);
+        n
+    }
+
+    // Returns a with every bit moved by delta, followed by tail.
+    fn relabel(&mut self, a: NodeRef, delta: i16, tail: NodeRef,
+               memo: &mut HashMap<NodeRef, NodeRef>) -> NodeRef {
+        if a == FALSE {
+            return FALSE;
+        }
+        if a == TRUE {
+            return tail;
+        }
+        if let Some(&n) = memo.get(&a) {
+            return n;
+        }
+        let node = self.nodes[a as usize];
+        let low = self.relabel(node.low, delta, tail, memo);
+        let high = self.relabel(node.high, delta, tail, memo);
+        let n = self.mk((node.var as i16 + delta) as u8, low, high);
+        memo.insert(a, n);
+        n
+    }
+
+    // Returns a of k bits followed by zeroes (tail0) or ones (tail1) after its sign bit.
+    fn sign_extend(&mut self, a: NodeRef, k: u8, tail0: NodeRef, tail1: NodeRef,
+                   memo: &mut HashMap<NodeRef, NodeRef>) -> NodeRef {
+        if a == FALSE {
+            return FALSE;
+        }
+        if a == TRUE {
+            return self.mk(k - 1, tail0, tail1);
+        }
+        if let Some(&n) = memo.get(&a) {
+            return n;
+        }
+        let node = self.nodes[a as usize];
+        let n = if node.var == k - 1 {
+            // Both children are terminal nodes
+            let low = if node.low == TRUE { tail0 } else { FALSE };
+            let high = if node.high == TRUE { tail1 } else { FALSE };
+            self.mk(node.var, low, high)
+        } else {
+            let low = self.sign_extend(node.low, k, tail0, tail1, memo);
+            let high = self.sign_extend(node.high, k, tail0, tail1, memo);
+            self.mk(node.var, low, high)
+        };
+        memo.insert(a, n);
+        n
+    }
+
+    // Returns the numbers of k bits v with v <= c (or v >= c if not le), signed.
+    fn compare(&mut self, c: unum, k: u8, le: bool) -> NodeRef {
+        // Nodes of a bit by whether the relation holds for the bits before.
+        let mut next = [FALSE, TRUE];
+        for i in (0..k).rev() {
+            let ci = (c >> i) & 1 == 1;
+            let mut cur = [FALSE, FALSE];
+            for s in 0..2 {
+                let state = |x: bool| -> usize {
+                    // The sign bit of negative numbers is set
+                    let (x, ci) = if i == k - 1 { (!x, !ci) } else { (x, ci) };
+                    if x == ci {
+                        s
+                    } else if (x < ci) == le {
+                        1
+                    } else {
+                        0
+                    }
+                };
+                let (low, high) = (next[state(false)], next[state(true)]);
+                cur[s] = self.mk(i, low, high);
+            }
+            next = cur;
+        }
+        next[1]
+    }
+
+    // Returns the bits of the numbers of k bits in root which may be 0 and 1.
+    fn bit_values(&self, root: NodeRef, k: u8) -> (unum, unum) {
+        let range = |from: u8, to: u8| mask(to) & !mask(from);
+        let (mut zeroes, mut ones) = (0, 0);
+        if root == FALSE {
+            return (zeroes, ones);
+        }
+        let free = range(0, self.level(root, k));
+        zeroes |= free;
+        ones |= free;
+        let mut visited = HashSet::new();
+        let mut stack = vec![root];
+        while let Some(n) = stack.pop() {
+            if n == FALSE || n == TRUE || !visited.insert(n) {
+                continue;
+            }
+            let node = self.nodes[n as usize];
+            for &(child, bit) in &[(node.low, false), (node.high, true)] {
+                if child == FALSE {
+                    continue;
+                }
+                if bit {
+                    ones |= 1 << node.var;
+                } else {
+                    zeroes |= 1 << node.var;
+                }
+                let free = range(node.var + 1, self.level(child, k));
+                zeroes |= free;
+                ones |= free;
+                stack.push(child);
+            }
+        }
+        (zeroes, ones)
+    }
+}
+
+/// A set of k-bits numbers as a BDD.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BDD {
+    // Used for indicate radix of BDD (k-bits)
+    // k == 0 means this BDD is an empty set
+    pub k: u8,
+    root: NodeRef,
+}
+
+impl BDD {
+    fn make(k: u8, root: NodeRef) -> BDD {
+        if root == FALSE || k == 0 {
+            BDD::null()
+        } else {
+            BDD { k: k, root: root }
+        }
+    }
+
+    /// Returns the empty set of BDD.
+    pub fn null() -> BDD {
+        BDD { k: 0, root: FALSE }
+    }
+
+    /// Returns the BDD of all numbers in k bits.
+    pub fn default_k(k: u8) -> BDD {
+        BDD::make(if k > _bits { _bits } else { k }, TRUE)
+    }
+
+    /// Returns the BDD of the numbers in k bits.
+    pub fn from_numbers(k: u8, numbers: &[inum]) -> BDD {
+        let k = if k > _bits { _bits } else { k };
+        let mut t = table();
+        let root = numbers.iter().fold(FALSE, |root, &n| {
+            let c = t.constant(to_bits(n, k), k);
+            t.or(root, c)
+        });
+        BDD::make(k, root)
+    }
+
+    /// Returns the numbers of the BDD, if there are at most `limit` of them.
+    pub fn numbers(&self, limit: u128) -> Option<Vec<inum>> {
+        if self.is_empty() {
+            return Some(Vec::new());
+        }
+        let k = self.k;
+        table().elements(self.root, k, limit)
+               .map(|vs| vs.into_iter().map(|v| from_bits(v, k)).collect())
+    }
+
+    // Returns {f(a, b) | a in self, b in other} for small sets.
+    fn pairwise<F>(&self, other: &BDD, f: F) -> Option<BDD>
+        where F: Fn(inum, inum) -> inum
+    {
+        let xs = self.numbers(ENUMERATION_LIMIT)?;
+        let ys = other.numbers(ENUMERATION_LIMIT)?;
+        if (xs.len() * ys.len()) as u128 > ENUMERATION_LIMIT {
+            return None;
+        }
+        let numbers: Vec<inum> = xs.iter()
+                                   .flat_map(|&x| ys.iter().map(move |&y| (x, y)))
+                                   .map(|(x, y)| f(x, y))
+                                   .collect();
+        Some(BDD::from_numbers(self.k, &numbers))
+    }
+
+    // Checks the operands of an operation, returning the result of invalid ones.
+    fn check(&self, other: &BDD, name: &str) -> Option<BDD> {
+        if self.is_empty() || other.is_empty() {
+            radeco_err!("Empty set cannot be used in arithmetical operation");
+            Some(BDD::default())
+        } else if self.k != other.k {
+            radeco_err!("{} between two BDDs with different radices", name);
+            Some(BDD::default())
+        } else {
+            None
+        }
+    }
+
+    fn shl_constant(&self, i: u8) -> BDD {
+        let mut t = table();
+        let truncated = t.truncate(self.root, self.k - i, &mut HashMap::new());
+        let shifted = t.relabel(truncated, i as i16, TRUE, &mut HashMap::new());
+        let root = t.chain(0, i, false, shifted);
+        BDD::make(self.k, root)
+    }
+
+    fn shr_constant(&self, i: u8) -> BDD {
+        let mut t = table();
+        let tail = t.chain(self.k - i, self.k, false, TRUE);
+        let truncated = t.exists_below(self.root, i, &mut HashMap::new());
+        let root = t.relabel(truncated, -(i as i16), tail, &mut HashMap::new());
+        BDD::make(self.k, root)
+    }
+
+    // Returns the join of f(self, i) for every amount i to shift by in other.
+    fn shift<F>(&self, other: &BDD, f: F) -> BDD
+        where F: Fn(&BDD, u8) -> BDD
+    {
+        if other.lower_bound().map_or(true, |lb| lb < 0) {
+            radeco_err!("Bitwise shift's operation cannot be negative");
+            return BDD::default_k(self.k);
+        }
+        let mut res = (0..self.k).filter(|&i| other.contains(&(i as inum)))
+                                 .fold(BDD::null(), |res, i| res.join(&f(self, i)));
+        if other.upper_bound().map_or(false, |ub| ub >= self.k as inum) {
+            // Shifting by k bits or more results in 0
+            res = res.join(&BDD::from((self.k, 0)));
+        }
+        res
+    }
+}
+
+impl fmt::Display for BDD {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.numbers(8) {
+            Some(numbers) => write!(f, "{}-bits: {:?}", self.k, numbers),
+            None => write!(f, "{}-bits: {} numbers", self.k, self.capacity()),
+        }
+    }
+}
+
+impl fmt::Debug for BDD {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BDD({})", self)
+    }
+}
+
+impl Default for BDD {
+    fn default() -> Self {
+        BDD::default_k(_bits)
+    }
+}
+
+// Generate BDD by a constant
+impl From<inum> for BDD {
+    fn from(number: inum) -> Self {
+        BDD::from((_bits, number))
+    }
+}
+
+impl From<(u8, inum)> for BDD {
+    fn from(number_k: (u8, inum)) -> Self {
+        BDD::from_numbers(number_k.0, &[number_k.1])
+    }
+}
+
+impl Container<inum> for BDD {
+    fn contains(&self, object: &inum) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let v = to_bits(*object, self.k);
+        // object must be a number in k bits
+        from_bits(v, self.k) == *object && table().contains(self.root, v)
+    }
+}
+
+impl Container<BDD> for BDD {
+    fn contains(&self, object: &BDD) -> bool {
+        if object.is_empty() {
+            true
+        } else if self.k != object.k {
+            false
+        } else {
+            table().diff(object.root, self.root) == FALSE
+        }
+    }
+}
+
+impl Container<Vec<inum>> for BDD {
+    fn contains(&self, object: &Vec<inum>) -> bool {
+        object.iter().all(|x| self.contains(x))
+    }
+}
+
+impl Neg for BDD {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        if let Some(res) = self.check(&self, "Neg") {
+            return res;
+        }
+        // -x == !x + 1
+        let mut t = table();
+        let not = t.not(self.root, &mut HashMap::new());
+        let zero = t.constant(0, self.k);
+        let root = t.add(not, zero, true, 0, self.k, &mut HashMap::new());
+        BDD::make(self.k, root)
+    }
+}
+
+impl Add for BDD {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "Addition") {
+            return res;
+        }
+        let root = table().add(self.root, other.root, false, 0, self.k, &mut HashMap::new());
+        BDD::make(self.k, root)
+    }
+}
+
+impl Sub for BDD {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "Subtraction") {
+            return res;
+        }
+        // x - y == x + !y + 1
+        let mut t = table();
+        let not = t.not(other.root, &mut HashMap::new());
+        let root = t.add(self.root, not, true, 0, self.k, &mut HashMap::new());
+        BDD::make(self.k, root)
+    }
+}
+
+impl Mul for BDD {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "Multiplication") {
+            return res;
+        }
+        if let Some(res) = self.pairwise(&other, |x, y| x.wrapping_mul(y)) {
+            return res;
+        }
+        let (set, n) = match (self.constant(), other.constant()) {
+            (_, Some(n)) => (self, n),
+            (Some(n), _) => (other, n),
+            (None, None) => return BDD::default_k(self.k),
+        };
+        // x * n is the sum of x shifted by every bit set in n
+        let n = to_bits(n, self.k);
+        (0..self.k).filter(|&i| (n >> i) & 1 == 1)
+                   .fold(BDD::from((self.k, 0)), |res, i| res + set.shl_constant(i))
+    }
+}
+
+impl Div for BDD {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "Division") {
+            return res;
+        }
+        if other.contains(&0) {
+            radeco_err!("Divied by zero");
+            return BDD::default_k(self.k);
+        }
+        self.pairwise(&other, |x, y| x.wrapping_div(y))
+            .unwrap_or_else(|| BDD::default_k(self.k))
+    }
+}
+
+impl Rem for BDD {
+    type Output = Self;
+
+    fn rem(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "Remainder") {
+            return res;
+        }
+        if other.contains(&0) {
+            radeco_err!("Divied by zero");
+            return BDD::default_k(self.k);
+        }
+        self.pairwise(&other, |x, y| x.wrapping_rem(y))
+            .unwrap_or_else(|| BDD::default_k(self.k))
+    }
+}
+
+impl BitAnd for BDD {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "BitAnd") {
+            return res;
+        }
+        let root = table().bitwise(self.root, other.root, &|x, y| x && y, &mut HashMap::new());
+        BDD::make(self.k, root)
+    }
+}
+
+impl BitOr for BDD {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "BitOr") {
+            return res;
+        }
+        let root = table().bitwise(self.root, other.root, &|x, y| x || y, &mut HashMap::new());
+        BDD::make(self.k, root)
+    }
+}
+
+impl BitXor for BDD {
+    type Output = Self;
+
+    fn bitxor(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "BitXor") {
+            return res;
+        }
+        let root = table().bitwise(self.root, other.root, &|x, y| x ^ y, &mut HashMap::new());
+        BDD::make(self.k, root)
+    }
+}
+
+impl Not for BDD {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        if let Some(res) = self.check(&self, "Not") {
+            return res;
+        }
+        let root = table().not(self.root, &mut HashMap::new());
+        BDD::make(self.k, root)
+    }
+}
+
+impl Shr for BDD {
+    type Output = Self;
+
+    // All shift right in ESIl is logical shift
+    fn shr(self, other: Self) -> Self {
+        if self.is_empty() || other.is_empty() {
+            radeco_err!("Empty set cannot be used in arithmetical operation");
+            return BDD::default();
+        }
+        self.shift(&other, BDD::shr_constant)
+    }
+}
+
+impl Shl for BDD {
+    type Output = Self;
+
+    fn shl(self, other: Self) -> Self {
+        if self.is_empty() || other.is_empty() {
+            radeco_err!("Empty set cannot be used in arithmetical operation");
+            return BDD::default();
+        }
+        self.shift(&other, BDD::shl_constant)
+    }
+}
+
+// Implement trait AbstractSet for BDD
+impl AbstractSet for BDD {
+    fn null() -> Self {
+        BDD::null()
+    }
+
+    fn default_k(k: u8) -> Self {
+        BDD::default_k(k)
+    }
+
+    fn radix(&self) -> u8 {
+        self.k
+    }
+
+    fn meet(&self, other: &Self) -> Self {
+        if self.is_empty() || other.is_empty() {
+            BDD::null()
+        } else if self.k != other.k {
+            radeco_err!("Meet two BDDs with different radices");
+            BDD::default()
+        } else {
+            BDD::make(self.k, table().and(self.root, other.root))
+        }
+    }
+
+    fn join(&self, other: &Self) -> Self {
+        if self.is_empty() {
+            *other
+        } else if other.is_empty() {
+            *self
+        } else if self.k != other.k {
+            radeco_err!("Join two BDDs with different radices");
+            BDD::default()
+        } else {
+            BDD::make(self.k, table().or(self.root, other.root))
+        }
+    }
+
+    // The join is widened to the numbers whose bits match the bits all numbers of the
+    // join agree on, up to the first bit which was known in `self` but is not anymore.
+    // Every widening which does not stabilize loses a known bit.
+    fn widen(&self, other: &Self) -> Self {
+        let join = self.join(other);
+        if self.is_empty() || other.is_empty() || join == *self || join.k != self.k {
+            return join;
+        }
+        let mut t = table();
+        let (self_zeroes, self_ones) = t.bit_values(self.root, self.k);
+        let (zeroes, ones) = t.bit_values(join.root, self.k);
+        let known = zeroes ^ ones;
+        let lost = (self_zeroes ^ self_ones) & !known;
+        let limit = if lost == 0 { self.k } else { lost.trailing_zeros() as u8 };
+        let root = (0..limit).rev().fold(TRUE, |n, i| {
+            if (known >> i) & 1 == 0 {
+                n
+            } else if (ones >> i) & 1 == 1 {
+                t.mk(i, FALSE, n)
+            } else {
+                t.mk(i, n, FALSE)
+            }
+        });
+        BDD::make(self.k, root)
+    }
+
+    fn remove_lower_bound(&self) -> Self {
+        match self.upper_bound() {
+            Some(ub) => {
+                let root = table().compare(to_bits(ub, self.k), self.k, true);
+                BDD::make(self.k, root)
+            }
+            None => *self,
+        }
+    }
+
+    fn set_lower_bound(&self, x: inum) -> Self {
+        if self.is_empty() || x <= from_bits(1 << (self.k - 1), self.k) {
+            *self
+        } else if x > from_bits(mask(self.k - 1), self.k) {
+            radeco_warn!("Set a lower bound which is bigger than upper bound");
+            BDD::null()
+        } else {
+            let mut t = table();
+            let ge = t.compare(to_bits(x, self.k), self.k, false);
+            BDD::make(self.k, t.and(self.root, ge))
+        }
+    }
+
+    fn remove_upper_bound(&self) -> Self {
+        match self.lower_bound() {
+            Some(lb) => {
+                let root = table().compare(to_bits(lb, self.k), self.k, false);
+                BDD::make(self.k, root)
+            }
+            None => *self,
+        }
+    }
+
+    fn set_upper_bound(&self, x: inum) -> Self {
+        if self.is_empty() || x >= from_bits(mask(self.k - 1), self.k) {
+            *self
+        } else if x < from_bits(1 << (self.k - 1), self.k) {
+            radeco_warn!("Set a upper bound which is smaller than lower bound");
+            BDD::null()
+        } else {
+            let mut t = table();
+            let le = t.compare(to_bits(x, self.k), self.k, true);
+            BDD::make(self.k, t.and(self.root, le))
+        }
+    }
+
+    // Binary search of the smallest x with a number <= x in the BDD.
+    fn lower_bound(&self) -> Option<inum> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut t = table();
+        let (mut lb, mut ub) = (from_bits(1 << (self.k - 1), self.k) as i128,
+                                from_bits(mask(self.k - 1), self.k) as i128);
+        while lb < ub {
+            let mid = lb + (ub - lb) / 2;
+            let le = t.compare(to_bits(mid as inum, self.k), self.k, true);
+            if t.and(self.root, le) != FALSE {
+                ub = mid;
+            } else {
+                lb = mid + 1;
+            }
+        }
+        Some(lb as inum)
+    }
+
+    // Binary search of the biggest x with a number >= x in the BDD.
+    fn upper_bound(&self) -> Option<inum> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut t = table();
+        let (mut lb, mut ub) = (from_bits(1 << (self.k - 1), self.k) as i128,
+                                from_bits(mask(self.k - 1), self.k) as i128);
+        while lb < ub {
+            let mid = ub - (ub - lb) / 2;
+            let ge = t.compare(to_bits(mid as inum, self.k), self.k, false);
+            if t.and(self.root, ge) != FALSE {
+                lb = mid;
+            } else {
+                ub = mid - 1;
+            }
+        }
+        Some(ub as inum)
+    }
+
+    fn narrow(&self, k: u8) -> Self {
+        if self.is_empty() {
+            radeco_warn!("Empty BDD cannot be narrow");
+            *self
+        } else if k > self.k {
+            radeco_warn!("BDD cannot be narrowed to a bigger bits");
+            *self
+        } else {
+            BDD::make(k, table().truncate(self.root, k, &mut HashMap::new()))
+        }
+    }
+
+    fn sign_extend(&self, k: u8) -> Self {
+        let k = if k > _bits { _bits } else { k };
+        if self.is_empty() {
+            radeco_warn!("Empty BDD cannot be extended");
+            *self
+        } else if k < self.k {
+            radeco_warn!("BDD cannot be extended to a smaller bits");
+            *self
+        } else {
+            let mut t = table();
+            let zeroes = t.chain(self.k, k, false, TRUE);
+            let ones = t.chain(self.k, k, true, TRUE);
+            BDD::make(k, t.sign_extend(self.root, self.k, zeroes, ones, &mut HashMap::new()))
+        }
+    }
+
+    fn zero_extend(&self, k: u8) -> Self {
+        let k = if k > _bits { _bits } else { k };
+        if self.is_empty() {
+            radeco_warn!("Empty BDD cannot be extended");
+            *self
+        } else if k < self.k {
+            radeco_warn!("BDD cannot be extended to a smaller bits");
+            *self
+        } else {
+            let mut t = table();
+            let zeroes = t.chain(self.k, k, false, TRUE);
+            BDD::make(k, t.relabel(self.root, 0, zeroes, &mut HashMap::new()))
+        }
+    }
+
+    fn constant(&self) -> Option<inum> {
+        match self.numbers(1) {
+            Some(ref numbers) if numbers.len() == 1 => Some(numbers[0]),
+            _ => None,
+        }
+    }
+
+    fn capacity(&self) -> inum {
+        if self.is_empty() {
+            0
+        } else {
+            let count = table().count(self.root, self.k);
+            if count > inum::max_value() as u128 { inum::max_value() } else { count as inum }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.k == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bdd_test_arith() {
+        let op1 = BDD::from_numbers(8, &[1, 3, 5]);
+        let op2 = BDD::from_numbers(8, &[0x10, 0x20]);
+        assert_eq!(BDD::from_numbers(8, &[0x11, 0x13, 0x15, 0x21, 0x23, 0x25]), op1 + op2);
+        assert_eq!(BDD::from_numbers(8, &[-0xf, -0xd, -0xb, -0x1f, -0x1d, -0x1b]), op1 - op2);
+        assert_eq!(BDD::from_numbers(8, &[-1, -3, -5]), -op1);
+        assert_eq!(BDD::from_numbers(8, &[0x10, 0x30, 0x50, 0x20, 0x60, -0x60]), op1 * op2);
+        assert_eq!(BDD::from_numbers(8, &[2, 6, 10, 4, 12, 20]),
+                   op1 << BDD::from_numbers(8, &[1, 2]));
+        assert_eq!(BDD::from_numbers(8, &[0, 1, 2]), op1 >> BDD::from((8, 1)));
+        assert_eq!(BDD::from_numbers(8, &[0x11, 0x13, 0x15, 0x21, 0x23, 0x25]), op1 | op2);
+        assert_eq!(BDD::from((8, 0)), op1 & op2);
+
+        // Overflow wraps around
+        let op1 = BDD::from_numbers(8, &[0x7f]);
+        assert_eq!(BDD::from((8, -0x80)), op1 + BDD::from((8, 1)));
+    }
+
+    #[test]
+    fn bdd_test_setop() {
+        let op1 = BDD::from_numbers(8, &[-4, 1, 7]);
+        assert_eq!(op1.capacity(), 3);
+        assert_eq!((op1.lower_bound(), op1.upper_bound()), (Some(-4), Some(7)));
+        assert_eq!(BDD::from_numbers(8, &[1, 7]), op1.set_lower_bound(0));
+        assert_eq!(BDD::from_numbers(8, &[-4]), op1.set_upper_bound(0));
+        assert!(op1.remove_lower_bound().contains(&-128));
+        assert!(!op1.remove_lower_bound().contains(&8));
+
+        assert_eq!(BDD::from_numbers(4, &[-4, 1, 7]), op1.narrow(4));
+        assert_eq!(BDD::from_numbers(16, &[-4, 1, 7]), op1.sign_extend(16));
+        assert_eq!(BDD::from_numbers(16, &[0xfc, 1, 7]), op1.zero_extend(16));
 
-// TODO
+        // The stride of the numbers survives the widening
+        let op2 = BDD::from_numbers(8, &[0, 4]);
+        let widened = BDD::from((8, 0)).widen(&op2);
+        assert!(widened.contains(&0x7c) && !widened.contains(&2));
+        assert_eq!(widened, widened.widen(&BDD::from_numbers(8, &[8, 12])));
+    }
+}
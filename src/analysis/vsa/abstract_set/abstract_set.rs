@@ -39,10 +39,8 @@ pub const _bits: u8 = 64;
 
 /// Trait used to indicate this is a container which contains something
 pub trait Container<T: Clone> {
-    // Returns true if the Container `self` contains `object`, false otherwise.
-    fn contains(&self, object: &T) -> bool {
-        unimplemented!();
-    }
+    /// Returns true if the Container `self` contains `object`, false otherwise.
+    fn contains(&self, object: &T) -> bool;
 }
 
 /// Type of abstract set. 
@@ -54,74 +52,225 @@ pub enum AbstractSetType {
     Polynomial,
 }
 
+/// Abstract set of k-bits numbers.
+///
+/// Numbers are held as signed numbers in k bits, and operations wrap around on the
+/// k-bits-filed, except that `>>` is a logical shift. The soundness of an operation op
+/// goes like:
+///     {a op b | set1.contains(a) && set2.contains(b)} is a subset of `set1 op set2`
 pub trait AbstractSet: Copy + Clone + Debug + PartialEq + Eq + Hash +
-                        Neg + Add + Sub + Div + Rem + Mul +
-                        BitAnd + BitOr + BitXor + Not + Shl + Shr +
+                        Neg<Output = Self> + Add<Output = Self> + Sub<Output = Self> +
+                        Div<Output = Self> + Rem<Output = Self> + Mul<Output = Self> +
+                        BitAnd<Output = Self> + BitOr<Output = Self> +
+                        BitXor<Output = Self> + Not<Output = Self> +
+                        Shl<Output = Self> + Shr<Output = Self> +
                         Container<inum> + Container<Self> + Container<Vec<inum>> +
                         From<inum> + From<(u8, inum)> + Default
 {
+    /// Returns the empty AbstractSet.
+    fn null() -> Self;
+
+    /// Returns the AbstractSet of all numbers in k bits.
+    fn default_k(k: u8) -> Self;
+
+    /// Returns the number of bits k of the numbers in the AbstractSet, 0 if it is empty.
+    fn radix(&self) -> u8;
+
     /// Returns the meet (intersection) of AbstractSet `self` and `other`.
-    fn meet(&self, other: &Self) -> Self {
-        unimplemented!();
-    }
+    fn meet(&self, other: &Self) -> Self;
 
     /// Returns the join (union) of AbstractSet `self` and `other`.
-    fn join(&self, other: &Self) -> Self {
-        unimplemented!();
-    }
+    fn join(&self, other: &Self) -> Self;
 
-    /// Returns the AbstractSet obtained by widening `self` with respect to `other`
-    fn widen(&self, other: &Self) -> Self {
-        unimplemented!();
-    }
+    /// Returns the AbstractSet obtained by widening `self` with respect to `other`.
+    /// A chain of widenings must be stable after finite steps.
+    fn widen(&self, other: &Self) -> Self;
 
     /// Returns the AbstractSet obtained by remoing lower bound for `self`
-    fn remove_lower_bound(&self) -> Self {
-        unimplemented!()
-    }
+    fn remove_lower_bound(&self) -> Self;
 
     /// Returns the AbstractSet obtained by setting lower bound as x for `self`
-    fn set_lower_bound(&self, x: inum) -> Self {
-        unimplemented!()
-    }
+    fn set_lower_bound(&self, x: inum) -> Self;
 
     /// Returns the AbstractSet obtained by remoing upper bound for `self`
-    fn remove_upper_bound(&self) -> Self {
-        unimplemented!()
-    }
+    fn remove_upper_bound(&self) -> Self;
 
     /// Returns the AbstractSet obtained by setting upper bound as x for `self`
-    fn set_upper_bound(&self, x: inum) -> Self {
-        unimplemented!()
-    }
+    fn set_upper_bound(&self, x: inum) -> Self;
+
+    /// Returns a lower bound of the numbers in the AbstractSet, None if it is empty.
+    fn lower_bound(&self) -> Option<inum>;
+
+    /// Returns an upper bound of the numbers in the AbstractSet, None if it is empty.
+    fn upper_bound(&self) -> Option<inum>;
 
     /// Returns the AbstractSet narrowed into a new k-bit-filed, for OpNarrow.
-    fn narrow(&self, k: u8) -> Self {
-        unimplemented!();
-    }
+    fn narrow(&self, k: u8) -> Self;
 
     /// Returns the AbstractSet sign extended into a new k-bit-filed, for OpSignExt.
-    fn sign_extend(&self, k: u8) -> Self {
-        unimplemented!();
-    }
+    fn sign_extend(&self, k: u8) -> Self;
 
     /// Returns the AbstractSet zero extended into a new k-bit-filed, for OpZeroExt.
-    fn zero_extend(&self, k: u8) -> Self {
-        unimplemented!();
-    }
+    fn zero_extend(&self, k: u8) -> Self;
 
     /// Returns Some(cons) if the AbstractSet only contains one constant, None otherwise.
     fn constant(&self) -> Option<inum> {
-        unimplemented!()
+        match (self.lower_bound(), self.upper_bound()) {
+            (Some(lb), Some(ub)) if lb == ub => Some(lb),
+            _ => None,
+        }
     }
      
     /// Returns capacity of AbstractSet. 
-    fn capacity(&self) -> inum {
-        unimplemented!()
-    }
+    fn capacity(&self) -> inum;
      
     /// Returns whether the AbstractSet is empty. 
     fn is_empty(&self) -> bool {
-        unimplemented!()
+        self.radix() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::strided_interval::StridedInterval;
+    use super::super::BDD::BDD;
+    use super::super::polynomial::Polynomial;
+
+    // Numbers are enumerated in K bits.
+    const K: u8 = 4;
+
+    // Returns n in K bits.
+    fn wrap(n: inum) -> inum {
+        (n << (_bits - K)) >> (_bits - K)
+    }
+
+    fn numbers() -> Vec<inum> {
+        (-(1 << (K - 1))..(1 << (K - 1))).collect()
+    }
+
+    fn sets() -> Vec<Vec<inum>> {
+        vec![vec![0], vec![1], vec![-8], vec![7], vec![-3], vec![1, 3], vec![-2, 5],
+             vec![0, 2, 4, 6], vec![-3, -1, 7], vec![1, 2, 3, 4, 5], vec![-8, 0, 4],
+             vec![-1, 0, 1], vec![2, 3, 5, 7], numbers()]
+    }
+
+    fn abstract_set<A: AbstractSet>(ns: &[inum]) -> A {
+        ns.iter().fold(A::null(), |a, &n| a.join(&A::from((K, n))))
+    }
+
+    fn check_unary<A, F, G>(name: &str, op: F, concrete: G)
+        where A: AbstractSet, F: Fn(A) -> A, G: Fn(inum) -> Option<inum>
+    {
+        for xs in sets() {
+            let a: A = abstract_set(&xs);
+            let r = op(a);
+            for &x in &xs {
+                if let Some(c) = concrete(x) {
+                    assert!(r.contains(&c), "{}({:?}) = {:?} misses {}", name, a, r, c);
+                }
+            }
+        }
+    }
+
+    fn check_binary<A, F, G>(name: &str, op: F, concrete: G)
+        where A: AbstractSet, F: Fn(A, A) -> A, G: Fn(inum, inum) -> Option<inum>
+    {
+        for xs in sets() {
+            for ys in sets() {
+                let (a, b): (A, A) = (abstract_set(&xs), abstract_set(&ys));
+                let r = op(a, b);
+                for &x in &xs {
+                    for &y in &ys {
+                        if let Some(c) = concrete(x, y) {
+                            assert!(r.contains(&c), "{:?} {} {:?} = {:?} misses {}",
+                                    a, name, b, r, c);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Checks every operation of A against the operation on the numbers A contains.
+    fn check_soundness<A: AbstractSet>() {
+        for xs in sets() {
+            let a: A = abstract_set(&xs);
+            assert!(a.contains(&xs), "{:?} misses numbers of {:?}", a, xs);
+            assert!(a.capacity() >= xs.len() as inum);
+            assert!(a.lower_bound().map_or(false, |lb| xs.iter().all(|&x| lb <= x)));
+            assert!(a.upper_bound().map_or(false, |ub| xs.iter().all(|&x| x <= ub)));
+            if let Some(n) = a.constant() {
+                assert_eq!(xs, vec![n]);
+            }
+        }
+        assert!(A::null().is_empty());
+        assert!(A::default_k(K).contains(&numbers()));
+
+        check_binary::<A, _, _>("+", |a, b| a + b, |x, y| Some(wrap(x + y)));
+        check_binary::<A, _, _>("-", |a, b| a - b, |x, y| Some(wrap(x - y)));
+        check_binary::<A, _, _>("*", |a, b| a * b, |x, y| Some(wrap(x * y)));
+        check_binary::<A, _, _>("/", |a, b| a / b,
+                                |x, y| if y == 0 { None } else { Some(wrap(x / y)) });
+        check_binary::<A, _, _>("%", |a, b| a % b,
+                                |x, y| if y == 0 { None } else { Some(wrap(x % y)) });
+        check_binary::<A, _, _>("&", |a, b| a & b, |x, y| Some(x & y));
+        check_binary::<A, _, _>("|", |a, b| a | b, |x, y| Some(x | y));
+        check_binary::<A, _, _>("^", |a, b| a ^ b, |x, y| Some(x ^ y));
+        check_binary::<A, _, _>("<<", |a, b| a << b,
+                                |x, y| if y < 0 { None } else { Some(wrap(x << y)) });
+        check_binary::<A, _, _>(">>", |a, b| a >> b, |x, y| {
+            if y < 0 { None } else { Some(wrap((x & ((1 << K) - 1)) >> y)) }
+        });
+        check_unary::<A, _, _>("-", |a| -a, |x| Some(wrap(-x)));
+        check_unary::<A, _, _>("!", |a| !a, |x| Some(!x));
+        check_unary::<A, _, _>("narrow", |a| a.narrow(2), |x| Some((x << 62) >> 62));
+        check_unary::<A, _, _>("sign_extend", |a| a.sign_extend(8), |x| Some(x));
+        check_unary::<A, _, _>("zero_extend", |a| a.zero_extend(8), |x| Some(x & 0xf));
+        check_unary::<A, _, _>("set_lower_bound", |a| a.set_lower_bound(-1),
+                               |x| if x >= -1 { Some(x) } else { None });
+        check_unary::<A, _, _>("set_upper_bound", |a| a.set_upper_bound(2),
+                               |x| if x <= 2 { Some(x) } else { None });
+        check_unary::<A, _, _>("remove_lower_bound", |a| a.remove_lower_bound(), Some);
+        check_unary::<A, _, _>("remove_upper_bound", |a| a.remove_upper_bound(), Some);
+
+        for xs in sets() {
+            for ys in sets() {
+                let (a, b): (A, A) = (abstract_set(&xs), abstract_set(&ys));
+                assert!(a.join(&b).contains(&xs) && a.join(&b).contains(&ys));
+                assert!(a.widen(&b).contains(&xs) && a.widen(&b).contains(&ys));
+                let both: Vec<inum> = xs.iter().cloned().filter(|x| ys.contains(x)).collect();
+                assert!(a.meet(&b).contains(&both), "{:?} meet {:?} misses {:?}", a, b, both);
+                if a.contains(&b) {
+                    assert!(a.contains(&ys), "{:?} does not contain {:?}", a, b);
+                }
+            }
+        }
+
+        // Widening a growing chain is stable after finite steps.
+        let mut a: A = A::from((K, 0));
+        for (i, &n) in numbers().iter().cycle().take(64).enumerate() {
+            let next = a.widen(&a.join(&A::from((K, n))));
+            if next == a && i >= numbers().len() {
+                break;
+            }
+            assert!(i < 48, "widening of {:?} does not stabilize", a);
+            a = next;
+        }
+    }
+
+    #[test]
+    fn strided_interval_soundness_test() {
+        check_soundness::<StridedInterval>();
+    }
+
+    #[test]
+    fn bdd_soundness_test() {
+        check_soundness::<BDD>();
+    }
+
+    #[test]
+    fn polynomial_soundness_test() {
+        check_soundness::<Polynomial>();
     }
 }
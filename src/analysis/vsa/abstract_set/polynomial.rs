@@ -10,7 +10,986 @@
 //! A polynomial abstract set goes like:
 //!     base + [a1]x1 + [a2]x2 + ... + [an]xn
 //! which means:
-//!     {base + k1 * x1 + k2 * x2 + ... + kn * xn | 0 <= ki <= ai}     
+//!     {base + k1 * x1 + k2 * x2 + ... + kn * xn | 0 <= ki <= ai}
+//! where all operations wrap around on the k-bits-filed.
+//!
+//! Negation, addition, subtraction, multiplication by a constant, shifts by
+//! constants and narrowing are exact, as long as the result has at most
+//! `MAX_TERMS` terms. Other operations are exact on small sets, and
+//! over-approximated otherwise.
+
+use std::cmp;
+use std::fmt;
+use std::ops::{Neg, Add, Sub, Div, Rem, Mul};
+use std::ops::{BitAnd, BitOr, BitXor, Not, Shl, Shr};
+
+use super::abstract_set::{inum, unum, _bits};
+use super::abstract_set::{AbstractSet, Container};
+
+// Terms beyond this number are merged, over-approximating the polynomial.
+const MAX_TERMS: usize = 4;
+
+// Operations which are not exact on polynomials enumerate pairs of numbers of sets
+// up to this size.
+const ENUMERATION_LIMIT: u128 = 4096;
+
+/// Returns the mask of k bits.
+fn mask(k: u8) -> unum {
+    if k >= _bits {
+        unum::max_value()
+    } else {
+        ((1 as unum) << k) - 1
+    }
+}
+
+/// Returns the bits of n in k bits.
+fn to_bits(n: inum, k: u8) -> unum {
+    (n as unum) & mask(k)
+}
+
+/// Returns the signed number of k bits v.
+fn from_bits(v: unum, k: u8) -> inum {
+    let shift = _bits - k;
+    ((v << shift) as inum) >> shift
+}
+
+/// Returns the number of trailing zeroes of v in k bits.
+fn tz(v: unum, k: u8) -> u8 {
+    cmp::min(v.trailing_zeros() as u8, k)
+}
+
+/// Returns the inverse of the odd number x modulo 2^64.
+fn inverse(x: unum) -> unum {
+    // Each step of Newton's method doubles the number of correct bits
+    (0..6).fold(x, |inv, _| inv.wrapping_mul((2 as unum).wrapping_sub(x.wrapping_mul(inv))))
+}
+
+/// Returns whether some 0 <= c <= a has c * x == r in k bits.
+fn solve(x: unum, a: unum, r: unum, k: u8) -> bool {
+    let t = tz(x, k);
+    if r & mask(t) != 0 {
+        return false;
+    }
+    let c = ((r >> t).wrapping_mul(inverse(x >> t))) & mask(k - t);
+    c <= a
+}
+
+/// A set of k-bits numbers as a polynomial.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Polynomial {
+    // Used for indicate radix of Polynomial (k-bits)
+    // k == 0 means this Polynomial is an empty set
+    pub k: u8,
+    base: unum,
+    // Terms (x, a) sorted by x, unused ones are (0, 0).
+    terms: [(unum, unum); MAX_TERMS],
+    len: usize,
+}
+
+impl Polynomial {
+    /// Returns the empty set of Polynomial.
+    pub fn null() -> Polynomial {
+        Polynomial { k: 0, base: 0, terms: [(0, 0); MAX_TERMS], len: 0 }
+    }
+
+    /// Returns the Polynomial of all numbers in k bits.
+    pub fn default_k(k: u8) -> Polynomial {
+        let k = if k > _bits { _bits } else { k };
+        Polynomial::new(k, 0, &[(1, mask(k))])
+    }
+
+    /// Returns the Polynomial base + [a1]x1 + ... + [an]xn of k bits, for terms
+    /// (xi, ai). Terms beyond `MAX_TERMS` are merged.
+    pub fn new(k: u8, base: inum, terms: &[(inum, unum)]) -> Polynomial {
+        let terms: Vec<(unum, unum)> = terms.iter().map(|&(x, a)| (x as unum, a)).collect();
+        Polynomial::make(k, base as unum, terms)
+    }
+
+    /// Returns the Polynomial of the numbers in k bits.
+    pub fn from_numbers(k: u8, numbers: &[inum]) -> Polynomial {
+        let mut numbers = numbers.to_vec();
+        numbers.sort();
+        numbers.dedup();
+        numbers.iter().fold(Polynomial::null(), |res, &n| res.join(&Polynomial::from((k, n))))
+    }
+
+    /// Returns the numbers of the Polynomial, if there are at most `limit` of them.
+    pub fn numbers(&self, limit: u128) -> Option<Vec<inum>> {
+        if self.is_empty() {
+            return Some(Vec::new());
+        }
+        if self.terms().iter().fold(1, |p: u128, &(_, a)| p.saturating_mul(a as u128 + 1)) > limit {
+            return None;
+        }
+        let m = mask(self.k);
+        let mut values = vec![self.base];
+        for &(x, a) in self.terms() {
+            values = values.iter()
+                           .flat_map(|&v| (0..a + 1).map(move |c| v.wrapping_add(c.wrapping_mul(x)) & m))
+                           .collect();
+            values.sort();
+            values.dedup();
+        }
+        Some(values.into_iter().map(|v| from_bits(v, self.k)).collect())
+    }
+
+    fn terms(&self) -> &[(unum, unum)] {
+        &self.terms[..self.len]
+    }
+
+    fn make(k: u8, base: unum, terms: Vec<(unum, unum)>) -> Polynomial {
+        let k = if k > _bits { _bits } else { k };
+        if k == 0 {
+            return Polynomial::null();
+        }
+        let mut base = base & mask(k);
+        let mut terms = terms;
+        loop {
+            let (next_base, next_terms) = Polynomial::reduce(k, base, &terms);
+            if next_base == base && next_terms == terms {
+                break;
+            }
+            base = next_base;
+            terms = next_terms;
+        }
+        while terms.len() > MAX_TERMS {
+            terms = Polynomial::merge(k, &terms);
+            let (next_base, next_terms) = Polynomial::reduce(k, base, &terms);
+            base = next_base;
+            terms = next_terms;
+        }
+        let mut res = Polynomial { k: k, base: base, terms: [(0, 0); MAX_TERMS], len: terms.len() };
+        res.terms[..terms.len()].copy_from_slice(&terms);
+        res
+    }
+
+    // One step of the normalization of base + terms, which keeps the numbers.
+    // A full term [2^(k-t)-1]2^t, holding all multiples of 2^t, absorbs the multiples
+    // of 2^t of the base and the other terms.
+    fn reduce(k: u8, base: unum, terms: &[(unum, unum)]) -> (unum, Vec<(unum, unum)>) {
+        let period = |x: unum, k: u8| ((1 as u128) << (k - tz(x, k))) - 1;
+        let is_full = |&(x, a): &(unum, unum)| {
+            x & mask(k) != 0 && a != 0 && a as u128 >= period(x & mask(k), k)
+        };
+        // Bits of the modulus of the other terms
+        let t = terms.iter().filter(|term| is_full(term))
+                     .map(|&(x, _)| tz(x, k)).min().unwrap_or(k);
+        let m = mask(t);
+        let mut base = base & m;
+        let mut reduced: Vec<(unum, unum)> = Vec::new();
+        for &(x, a) in terms {
+            let x = x & m;
+            if x == 0 || a == 0 {
+                continue;
+            }
+            let a = cmp::min(a as u128, period(x, t)) as unum;
+            let term = if a as u128 == period(x, t) {
+                (1 << tz(x, k), period(x, k) as unum)
+            } else if t > 0 && x > (1 << (t - 1)) {
+                // [a]x == a * x + [a](-x)
+                base = base.wrapping_add(a.wrapping_mul(x)) & m;
+                (x.wrapping_neg() & m, a)
+            } else {
+                (x, a)
+            };
+            match reduced.iter().position(|&(y, _)| y == term.0) {
+                Some(i) => reduced[i].1 = reduced[i].1.saturating_add(term.1),
+                None => reduced.push(term),
+            }
+        }
+        if t < k {
+            reduced.push((1 << t, period(1 << t, k) as unum));
+        }
+        reduced.sort();
+        (base, reduced)
+    }
+
+    // Merges the two terms whose merged term holds the fewest numbers.
+    fn merge(k: u8, terms: &[(unum, unum)]) -> Vec<(unum, unum)> {
+        let merged = |(x1, a1): (unum, unum), (x2, a2): (unum, unum)| {
+            // [a1]x1 + [a2](m * x1) is in [a1 + m * a2]x1, or else in the multiples of
+            // the biggest power of 2 dividing x1 and x2
+            if x2 % x1 == 0 {
+                let a = (a1 as u128).saturating_add((x2 / x1) as u128 * a2 as u128);
+                (x1, cmp::min(a, mask(k) as u128) as unum)
+            } else {
+                let t = cmp::min(tz(x1, k), tz(x2, k));
+                (1 << t, mask(k - t))
+            }
+        };
+        let mut best: Option<(usize, usize, (unum, unum))> = None;
+        for i in 0..terms.len() {
+            for j in i + 1..terms.len() {
+                let term = merged(terms[i], terms[j]);
+                let size = |(x, a): (unum, unum)| {
+                    cmp::min(a as u128, (1 as u128) << (k - tz(x, k)))
+                };
+                if best.map_or(true, |(_, _, b)| size(term) < size(b)) {
+                    best = Some((i, j, term));
+                }
+            }
+        }
+        let (i, j, term) = best.expect("Merging a polynomial with less than two terms");
+        let mut res: Vec<(unum, unum)> = terms.iter().enumerate()
+                                              .filter(|&(l, _)| l != i && l != j)
+                                              .map(|(_, &t)| t)
+                                              .collect();
+        res.push(term);
+        res
+    }
+
+    // Returns the polynomial with its base and every term mapped by f.
+    fn map<F>(&self, k: u8, f: F) -> Polynomial
+        where F: Fn(unum) -> unum
+    {
+        let terms = self.terms().iter().map(|&(x, a)| (f(x), a)).collect();
+        Polynomial::make(k, f(self.base), terms)
+    }
+
+    // Returns Some(true) if v is in the polynomial, Some(false) if it is not, and None
+    // if it is too costly to decide.
+    fn member(&self, v: unum) -> Option<bool> {
+        if self.is_empty() {
+            return Some(false);
+        }
+        let (k, m) = (self.k, mask(self.k));
+        let r = v.wrapping_sub(self.base) & m;
+        if self.len == 0 {
+            return Some(r == 0);
+        }
+        // The term with the most coefficients is solved, the other ones enumerated
+        let last = (0..self.len).max_by_key(|&i| self.terms[i].1).unwrap();
+        let (x, a) = self.terms[last];
+        let others: Vec<(unum, unum)> = self.terms().iter().enumerate()
+                                            .filter(|&(i, _)| i != last)
+                                            .map(|(_, &t)| t)
+                                            .collect();
+        if others.iter().fold(1, |p: u128, &(_, a)| p.saturating_mul(a as u128 + 1))
+           > ENUMERATION_LIMIT {
+            return None;
+        }
+        let mut sums = vec![0 as unum];
+        for &(y, b) in &others {
+            sums = sums.iter()
+                       .flat_map(|&s| (0..b + 1).map(move |c| s.wrapping_add(c.wrapping_mul(y)) & m))
+                       .collect();
+            sums.sort();
+            sums.dedup();
+        }
+        Some(sums.iter().any(|&s| solve(x, a, r.wrapping_sub(s) & m, k)))
+    }
+
+    // Returns the smallest and biggest numbers of the polynomial, taking the base as
+    // a signed or unsigned number and the factors of the terms as signed numbers,
+    // if no number wraps around.
+    fn range(&self, signed: bool) -> Option<(i128, i128)> {
+        let k = self.k;
+        let base = if signed { from_bits(self.base, k) as i128 } else { self.base as i128 };
+        let (lb, ub) = self.terms().iter().fold(Some((base, base)), |range, &(x, a)| {
+            let (lb, ub) = range?;
+            let v = (from_bits(x, k) as i128).checked_mul(a as i128)?;
+            Some((lb.checked_add(cmp::min(v, 0))?, ub.checked_add(cmp::max(v, 0))?))
+        })?;
+        let (min, max) = if signed {
+            (-((1 as i128) << (k - 1)), ((1 as i128) << (k - 1)) - 1)
+        } else {
+            (0, ((1 as i128) << k) - 1)
+        };
+        if min <= lb && ub <= max { Some((lb, ub)) } else { None }
+    }
+
+    // Returns the numbers of k bits in [lb, ub] which are congruent to r modulo 2^t.
+    fn congruence(k: u8, r: inum, t: u8, lb: Option<inum>, ub: Option<inum>) -> Polynomial {
+        let modulus = (1 as i128) << t;
+        let (min, max) = (-((1 as i128) << (k - 1)), ((1 as i128) << (k - 1)) - 1);
+        let r = r as i128;
+        let lb = lb.map_or(min, |lb| lb as i128);
+        let ub = ub.map_or(max, |ub| ub as i128);
+        let lb = lb + (r - lb).rem_euclid(modulus);
+        let ub = ub - (ub - r).rem_euclid(modulus);
+        if lb > ub {
+            return Polynomial::null();
+        }
+        Polynomial::make(k, lb as unum, vec![(1 << t, ((ub - lb) >> t) as unum)])
+    }
+
+    // Returns the numbers in [lb, ub] of a progression which does not wrap around.
+    fn clamp(&self, lb: i128, ub: i128) -> Option<Polynomial> {
+        let (step, a) = match self.terms() {
+            &[] => (0, 0),
+            &[(x, a)] => (from_bits(x, self.k) as i128, a as i128),
+            _ => return None,
+        };
+        self.range(true)?;
+        let base = from_bits(self.base, self.k) as i128;
+        let floor = |n: i128, d: i128| n.div_euclid(d);
+        let ceil = |n: i128, d: i128| -(-n).div_euclid(d);
+        // Coefficients of the numbers in [lb, ub]
+        let (lo, hi) = if step > 0 {
+            (ceil(lb - base, step), floor(ub - base, step))
+        } else if step < 0 {
+            (ceil(base - ub, -step), floor(base - lb, -step))
+        } else if lb <= base && base <= ub {
+            (0, 0)
+        } else {
+            (1, 0)
+        };
+        let (lo, hi) = (cmp::max(lo, 0), cmp::min(hi, a));
+        if lo > hi {
+            return Some(Polynomial::null());
+        }
+        let base = base + lo * step;
+        Some(Polynomial::make(self.k, base as unum, vec![(step as unum, (hi - lo) as unum)]))
+    }
+
+    // Returns the biggest t such that every number is congruent modulo 2^t.
+    fn modulus(&self) -> u8 {
+        self.terms().iter().map(|&(x, _)| tz(x, self.k)).min().unwrap_or(0)
+    }
+
+    // Returns {f(a, b) | a in self, b in other} for small sets.
+    fn pairwise<F>(&self, other: &Polynomial, f: F) -> Option<Polynomial>
+        where F: Fn(inum, inum) -> inum
+    {
+        let xs = self.numbers(ENUMERATION_LIMIT)?;
+        let ys = other.numbers(ENUMERATION_LIMIT)?;
+        if (xs.len() * ys.len()) as u128 > ENUMERATION_LIMIT {
+            return None;
+        }
+        let numbers: Vec<inum> = xs.iter()
+                                   .flat_map(|&x| ys.iter().map(move |&y| (x, y)))
+                                   .map(|(x, y)| f(x, y))
+                                   .collect();
+        Some(Polynomial::from_numbers(self.k, &numbers))
+    }
+
+    // Returns {f(a, n) | a in self} when the bits of n not below the modulus are those
+    // of `high`, as the low bits of the numbers are the ones of the base.
+    fn bitwise_constant<F>(&self, n: inum, high: unum, f: F) -> Option<Polynomial>
+        where F: Fn(unum, unum) -> unum
+    {
+        let low = mask(self.modulus());
+        let n = to_bits(n, self.k);
+        if n & !low != high & !low & mask(self.k) {
+            return None;
+        }
+        let base = (self.base & !low) | (f(self.base, n) & low);
+        Some(Polynomial::make(self.k, base, self.terms().to_vec()))
+    }
+
+    // Checks the operands of an operation, returning the result of invalid ones.
+    fn check(&self, other: &Polynomial, name: &str) -> Option<Polynomial> {
+        if self.is_empty() || other.is_empty() {
+            radeco_err!("Empty set cannot be used in arithmetical operation");
+            Some(Polynomial::default())
+        } else if self.k != other.k {
+            radeco_err!("{} between two Polynomials with different radices", name);
+            Some(Polynomial::default())
+        } else {
+            None
+        }
+    }
+
+    fn shl_constant(&self, i: u8) -> Polynomial {
+        self.map(self.k, |x| x << i)
+    }
+
+    fn shr_constant(&self, i: u8) -> Polynomial {
+        if i == 0 {
+            *self
+        } else if self.modulus() >= i {
+            // The low bits of all numbers are those of the base
+            self.map(self.k - i, |x| x >> i).zero_extend(self.k)
+        } else {
+            Polynomial::default_k(self.k - i).zero_extend(self.k)
+        }
+    }
+
+    // Returns the join of f(self, i) for every amount i to shift by in other.
+    fn shift<F>(&self, other: &Polynomial, f: F) -> Polynomial
+        where F: Fn(&Polynomial, u8) -> Polynomial
+    {
+        if other.lower_bound().map_or(true, |lb| lb < 0) {
+            radeco_err!("Bitwise shift's operation cannot be negative");
+            return Polynomial::default_k(self.k);
+        }
+        let mut res = (0..self.k).filter(|&i| other.contains(&(i as inum)))
+                                 .fold(Polynomial::null(), |res, i| res.join(&f(self, i)));
+        if other.upper_bound().map_or(false, |ub| ub >= self.k as inum) {
+            // Shifting by k bits or more results in 0
+            res = res.join(&Polynomial::from((self.k, 0)));
+        }
+        res
+    }
+}
+
+impl fmt::Display for Polynomial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-bits: {}", self.k, from_bits(self.base, self.k))?;
+        for &(x, a) in self.terms() {
+            write!(f, " + [{}]{}", a, x)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Polynomial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Polynomial({})", self)
+    }
+}
+
+impl Default for Polynomial {
+    fn default() -> Self {
+        Polynomial::default_k(_bits)
+    }
+}
+
+// Generate Polynomial by a constant
+impl From<inum> for Polynomial {
+    fn from(number: inum) -> Self {
+        Polynomial::from((_bits, number))
+    }
+}
+
+impl From<(u8, inum)> for Polynomial {
+    fn from(number_k: (u8, inum)) -> Self {
+        Polynomial::new(number_k.0, number_k.1, &[])
+    }
+}
+
+// Membership of big polynomials may not be decided, they may then contain any number.
+impl Container<inum> for Polynomial {
+    fn contains(&self, object: &inum) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let v = to_bits(*object, self.k);
+        // object must be a number in k bits
+        from_bits(v, self.k) == *object && self.member(v).unwrap_or(true)
+    }
+}
+
+impl Container<Polynomial> for Polynomial {
+    fn contains(&self, object: &Polynomial) -> bool {
+        if object.is_empty() {
+            return true;
+        }
+        if self.k != object.k {
+            return false;
+        }
+        if self == object {
+            return true;
+        }
+        if let Some(numbers) = object.numbers(ENUMERATION_LIMIT) {
+            return numbers.iter().all(|&n| self.member(to_bits(n, self.k)) == Some(true));
+        }
+        // The multiples of 2^t from the base
+        match self.terms() {
+            &[(x, a)] if a == mask(self.k - tz(x, self.k)) => {
+                let low = mask(tz(x, self.k));
+                (object.base & low) == (self.base & low) &&
+                    object.terms().iter().all(|&(y, _)| y & low == 0)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Container<Vec<inum>> for Polynomial {
+    fn contains(&self, object: &Vec<inum>) -> bool {
+        object.iter().all(|x| self.contains(x))
+    }
+}
+
+impl Neg for Polynomial {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        if let Some(res) = self.check(&self, "Neg") {
+            return res;
+        }
+        self.map(self.k, |x| x.wrapping_neg())
+    }
+}
+
+impl Add for Polynomial {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "Addition") {
+            return res;
+        }
+        let terms = self.terms().iter().chain(other.terms().iter()).cloned().collect();
+        Polynomial::make(self.k, self.base.wrapping_add(other.base), terms)
+    }
+}
+
+impl Sub for Polynomial {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "Subtraction") {
+            return res;
+        }
+        self + (-other)
+    }
+}
+
+impl Mul for Polynomial {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "Multiplication") {
+            return res;
+        }
+        if let Some(res) = self.pairwise(&other, |x, y| x.wrapping_mul(y)) {
+            return res;
+        }
+        match (self.constant(), other.constant()) {
+            (_, Some(n)) => self.map(self.k, |x| x.wrapping_mul(n as unum)),
+            (Some(n), _) => other.map(self.k, |x| x.wrapping_mul(n as unum)),
+            (None, None) => Polynomial::default_k(self.k),
+        }
+    }
+}
+
+impl Div for Polynomial {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "Division") {
+            return res;
+        }
+        if other.contains(&0) {
+            radeco_err!("Divied by zero");
+            return Polynomial::default_k(self.k);
+        }
+        self.pairwise(&other, |x, y| x.wrapping_div(y))
+            .unwrap_or_else(|| Polynomial::default_k(self.k))
+    }
+}
+
+impl Rem for Polynomial {
+    type Output = Self;
+
+    fn rem(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "Remainder") {
+            return res;
+        }
+        if other.contains(&0) {
+            radeco_err!("Divied by zero");
+            return Polynomial::default_k(self.k);
+        }
+        self.pairwise(&other, |x, y| x.wrapping_rem(y))
+            .unwrap_or_else(|| Polynomial::default_k(self.k))
+    }
+}
+
+impl BitAnd for Polynomial {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "BitAnd") {
+            return res;
+        }
+        if let Some(res) = self.pairwise(&other, |x, y| x & y) {
+            return res;
+        }
+        let (set, n) = match (self.constant(), other.constant()) {
+            (_, Some(n)) => (self, n),
+            (Some(n), _) => (other, n),
+            (None, None) => return Polynomial::default_k(self.k),
+        };
+        let bits = to_bits(n, self.k);
+        if bits == mask(self.k) {
+            return set;
+        }
+        if bits != 0 && bits & bits.wrapping_add(1) == 0 {
+            // Masking the low bits
+            let t = bits.count_ones() as u8;
+            return set.narrow(t).zero_extend(self.k);
+        }
+        set.bitwise_constant(n, mask(self.k), |x, y| x & y)
+           .unwrap_or_else(|| Polynomial::default_k(self.k))
+    }
+}
+
+impl BitOr for Polynomial {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "BitOr") {
+            return res;
+        }
+        if let Some(res) = self.pairwise(&other, |x, y| x | y) {
+            return res;
+        }
+        match (self.constant(), other.constant()) {
+            (_, Some(n)) => self.bitwise_constant(n, 0, |x, y| x | y),
+            (Some(n), _) => other.bitwise_constant(n, 0, |x, y| x | y),
+            (None, None) => None,
+        }.unwrap_or_else(|| Polynomial::default_k(self.k))
+    }
+}
+
+impl BitXor for Polynomial {
+    type Output = Self;
+
+    fn bitxor(self, other: Self) -> Self {
+        if let Some(res) = self.check(&other, "BitXor") {
+            return res;
+        }
+        if let Some(res) = self.pairwise(&other, |x, y| x ^ y) {
+            return res;
+        }
+        match (self.constant(), other.constant()) {
+            (_, Some(n)) => self.bitwise_constant(n, 0, |x, y| x ^ y),
+            (Some(n), _) => other.bitwise_constant(n, 0, |x, y| x ^ y),
+            (None, None) => None,
+        }.unwrap_or_else(|| Polynomial::default_k(self.k))
+    }
+}
+
+impl Not for Polynomial {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        if let Some(res) = self.check(&self, "Not") {
+            return res;
+        }
+        // !x == -x - 1
+        (-self) + Polynomial::from((self.k, -1))
+    }
+}
+
+impl Shr for Polynomial {
+    type Output = Self;
+
+    // All shift right in ESIl is logical shift
+    fn shr(self, other: Self) -> Self {
+        if self.is_empty() || other.is_empty() {
+            radeco_err!("Empty set cannot be used in arithmetical operation");
+            return Polynomial::default();
+        }
+        self.shift(&other, Polynomial::shr_constant)
+    }
+}
+
+impl Shl for Polynomial {
+    type Output = Self;
+
+    fn shl(self, other: Self) -> Self {
+        if self.is_empty() || other.is_empty() {
+            radeco_err!("Empty set cannot be used in arithmetical operation");
+            return Polynomial::default();
+        }
+        self.shift(&other, Polynomial::shl_constant)
+    }
+}
+
+// Implement trait AbstractSet for Polynomial
+impl AbstractSet for Polynomial {
+    fn null() -> Self {
+        Polynomial::null()
+    }
+
+    fn default_k(k: u8) -> Self {
+        Polynomial::default_k(k)
+    }
+
+    fn radix(&self) -> u8 {
+        self.k
+    }
+
+    fn meet(&self, other: &Self) -> Self {
+        if self.is_empty() || other.is_empty() {
+            return Polynomial::null();
+        }
+        if self.k != other.k {
+            radeco_err!("Meet two Polynomials with different radices");
+            return Polynomial::default();
+        }
+        if self.contains(other) {
+            return *other;
+        }
+        if other.contains(self) {
+            return *self;
+        }
+        let (small, big) = if self.capacity() <= other.capacity() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        match small.numbers(ENUMERATION_LIMIT) {
+            Some(numbers) => {
+                let numbers: Vec<inum> = numbers.into_iter().filter(|n| big.contains(n)).collect();
+                Polynomial::from_numbers(self.k, &numbers)
+            }
+            None => *small,
+        }
+    }
+
+    fn join(&self, other: &Self) -> Self {
+        if self.is_empty() || other.contains(self) {
+            return *other;
+        }
+        if other.is_empty() || self.contains(other) {
+            return *self;
+        }
+        if self.k != other.k {
+            radeco_err!("Join two Polynomials with different radices");
+            return Polynomial::default();
+        }
+        // Two progressions of the same step x are joined into one
+        let step = match (self.terms(), other.terms()) {
+            (&[(x, a)], &[]) => Some((x, a, 0)),
+            (&[], &[(x, a)]) => Some((x, 0, a)),
+            (&[(x, a)], &[(y, b)]) if x == y => Some((x, a, b)),
+            (&[], &[]) => Some((other.base.wrapping_sub(self.base) & mask(self.k), 0, 0)),
+            _ => None,
+        };
+        if let Some((x, a, b)) = step {
+            let d = from_bits(other.base.wrapping_sub(self.base) & mask(self.k), self.k) as i128;
+            let x_signed = from_bits(x, self.k) as i128;
+            if d % x_signed == 0 && -(b as i128) - 1 <= d / x_signed &&
+               d / x_signed <= a as i128 + 1 {
+                let d = d / x_signed;
+                let lb = cmp::min(0, d);
+                let ub = cmp::max(a as i128, d + b as i128);
+                let base = self.base.wrapping_add((lb as unum).wrapping_mul(x));
+                return Polynomial::make(self.k, base, vec![(x, (ub - lb) as unum)]);
+            }
+        }
+        // base1 + terms1 + terms2 + [1](base2 - base1)
+        let terms = self.terms().iter().chain(other.terms().iter()).cloned()
+                        .chain(Some((other.base.wrapping_sub(self.base), 1)))
+                        .collect();
+        Polynomial::make(self.k, self.base, terms)
+    }
+
+    // The join is widened to the numbers congruent to it modulo the biggest power of 2
+    // possible, keeping the bounds which did not move. Every widening which does not
+    // stabilize lowers the modulus or drops a bound.
+    fn widen(&self, other: &Self) -> Self {
+        let join = self.join(other);
+        if self.is_empty() || other.is_empty() || join == *self || join.k != self.k {
+            return join;
+        }
+        let lb = join.lower_bound().filter(|&lb| Some(lb) == self.lower_bound());
+        let ub = join.upper_bound().filter(|&ub| Some(ub) == self.upper_bound());
+        Polynomial::congruence(self.k, from_bits(join.base, self.k), join.modulus(), lb, ub)
+    }
+
+    fn remove_lower_bound(&self) -> Self {
+        match self.upper_bound() {
+            Some(ub) => Polynomial::congruence(self.k, ub, self.modulus(), None, Some(ub)),
+            None => *self,
+        }
+    }
+
+    fn set_lower_bound(&self, x: inum) -> Self {
+        if self.is_empty() || self.lower_bound().map_or(true, |lb| x <= lb) {
+            return *self;
+        }
+        if self.upper_bound().map_or(false, |ub| x > ub) {
+            radeco_warn!("Set a lower bound which is bigger than upper bound");
+            return Polynomial::null();
+        }
+        let max = ((1 as i128) << (self.k - 1)) - 1;
+        if let Some(res) = self.clamp(x as i128, max) {
+            return res;
+        }
+        match self.numbers(ENUMERATION_LIMIT) {
+            Some(numbers) => {
+                let numbers: Vec<inum> = numbers.into_iter().filter(|&n| n >= x).collect();
+                Polynomial::from_numbers(self.k, &numbers)
+            }
+            None => *self,
+        }
+    }
+
+    fn remove_upper_bound(&self) -> Self {
+        match self.lower_bound() {
+            Some(lb) => Polynomial::congruence(self.k, lb, self.modulus(), Some(lb), None),
+            None => *self,
+        }
+    }
+
+    fn set_upper_bound(&self, x: inum) -> Self {
+        if self.is_empty() || self.upper_bound().map_or(true, |ub| x >= ub) {
+            return *self;
+        }
+        if self.lower_bound().map_or(false, |lb| x < lb) {
+            radeco_warn!("Set a upper bound which is smaller than lower bound");
+            return Polynomial::null();
+        }
+        let min = -((1 as i128) << (self.k - 1));
+        if let Some(res) = self.clamp(min, x as i128) {
+            return res;
+        }
+        match self.numbers(ENUMERATION_LIMIT) {
+            Some(numbers) => {
+                let numbers: Vec<inum> = numbers.into_iter().filter(|&n| n <= x).collect();
+                Polynomial::from_numbers(self.k, &numbers)
+            }
+            None => *self,
+        }
+    }
+
+    fn lower_bound(&self) -> Option<inum> {
+        if self.is_empty() {
+            return None;
+        }
+        if let Some((lb, _)) = self.range(true) {
+            return Some(lb as inum);
+        }
+        match self.numbers(ENUMERATION_LIMIT) {
+            Some(numbers) => numbers.into_iter().min(),
+            None => Some(from_bits(1 << (self.k - 1), self.k)),
+        }
+    }
+
+    fn upper_bound(&self) -> Option<inum> {
+        if self.is_empty() {
+            return None;
+        }
+        if let Some((_, ub)) = self.range(true) {
+            return Some(ub as inum);
+        }
+        match self.numbers(ENUMERATION_LIMIT) {
+            Some(numbers) => numbers.into_iter().max(),
+            None => Some(from_bits(mask(self.k - 1), self.k)),
+        }
+    }
+
+    fn narrow(&self, k: u8) -> Self {
+        if self.is_empty() {
+            radeco_warn!("Empty Polynomial cannot be narrow");
+            *self
+        } else if k > self.k {
+            radeco_warn!("Polynomial cannot be narrowed to a bigger bits");
+            *self
+        } else {
+            self.map(k, |x| x)
+        }
+    }
+
+    fn sign_extend(&self, k: u8) -> Self {
+        let k = if k > _bits { _bits } else { k };
+        if self.is_empty() {
+            radeco_warn!("Empty Polynomial cannot be extended");
+            *self
+        } else if k < self.k {
+            radeco_warn!("Polynomial cannot be extended to a bigger bits");
+            *self
+        } else if self.range(true).is_some() {
+            let k0 = self.k;
+            self.map(k, |x| from_bits(x, k0) as unum)
+        } else if let Some(numbers) = self.numbers(ENUMERATION_LIMIT) {
+            Polynomial::from_numbers(k, &numbers)
+        } else {
+            Polynomial::new(k, from_bits(1 << (self.k - 1), self.k), &[(1, mask(self.k))])
+        }
+    }
+
+    fn zero_extend(&self, k: u8) -> Self {
+        let k = if k > _bits { _bits } else { k };
+        if self.is_empty() {
+            radeco_warn!("Empty Polynomial cannot be extended");
+            *self
+        } else if k < self.k {
+            radeco_warn!("Polynomial cannot be extended to a bigger bits");
+            *self
+        } else if self.range(false).is_some() {
+            let k0 = self.k;
+            let terms = self.terms().iter().map(|&(x, a)| (from_bits(x, k0) as unum, a)).collect();
+            Polynomial::make(k, self.base, terms)
+        } else if let Some(numbers) = self.numbers(ENUMERATION_LIMIT) {
+            let numbers: Vec<inum> = numbers.into_iter()
+                                            .map(|n| to_bits(n, self.k) as inum)
+                                            .collect();
+            Polynomial::from_numbers(k, &numbers)
+        } else {
+            Polynomial::new(k, 0, &[(1, mask(self.k))])
+        }
+    }
+
+    fn constant(&self) -> Option<inum> {
+        if !self.is_empty() && self.len == 0 {
+            Some(from_bits(self.base, self.k))
+        } else {
+            None
+        }
+    }
+
+    fn capacity(&self) -> inum {
+        if let Some(numbers) = self.numbers(ENUMERATION_LIMIT) {
+            return numbers.len() as inum;
+        }
+        // Some numbers may be counted more than once
+        let count = self.terms().iter().fold(1, |p: u128, &(_, a)| p.saturating_mul(a as u128 + 1));
+        let count = cmp::min(count, (1 as u128) << self.k);
+        if count > inum::max_value() as u128 { inum::max_value() } else { count as inum }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.k == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn polynomial_test_arith() {
+        let op1 = Polynomial::new(8, 1, &[(2, 2)]);
+        let op2 = Polynomial::new(8, 0x10, &[(0x10, 1)]);
+        assert_eq!(Some(vec![1, 3, 5]), op1.numbers(8));
+        assert_eq!(Polynomial::new(8, 0x11, &[(2, 2), (0x10, 1)]), op1 + op2);
+        assert_eq!(Polynomial::new(8, -0x1f, &[(2, 2), (0x10, 1)]), op1 - op2);
+        assert_eq!(Polynomial::new(8, -5, &[(2, 2)]), -op1);
+        assert_eq!(Polynomial::new(8, 3, &[(6, 2)]), op1 * Polynomial::from((8, 3)));
+        assert_eq!(Polynomial::new(8, 4, &[(8, 2)]), op1 << Polynomial::from((8, 2)));
+        assert_eq!(Polynomial::from_numbers(8, &[0, 1, 2]), op1 >> Polynomial::from((8, 1)));
+        assert_eq!(Polynomial::new(8, 0x11, &[(2, 2), (0x10, 1)]), op1 | op2);
+
+        // Overflow wraps around
+        let op1 = Polynomial::from((8, 0x7f));
+        assert_eq!(Polynomial::from((8, -0x80)), op1 + Polynomial::from((8, 1)));
+
+        // Alignment of a pointer keeps its stride
+        let op1 = Polynomial::new(64, 0x1003, &[(0x10, 0x100), (0x1000, 0x10)]);
+        assert_eq!(Polynomial::new(64, 0x1000, &[(0x10, 0x100), (0x1000, 0x10)]),
+                   op1 & Polynomial::from(-4));
+    }
+
+    #[test]
+    fn polynomial_test_setop() {
+        let op1 = Polynomial::from_numbers(8, &[-4, 0, 4, 8]);
+        assert_eq!(Polynomial::new(8, -4, &[(4, 3)]), op1);
+        assert_eq!(op1.capacity(), 4);
+        assert_eq!((op1.lower_bound(), op1.upper_bound()), (Some(-4), Some(8)));
+        assert_eq!(Polynomial::new(8, 4, &[(4, 1)]), op1.set_lower_bound(1));
+        assert_eq!(Polynomial::new(8, -4, &[(4, 1)]), op1.set_upper_bound(3));
+        assert!(op1.remove_lower_bound().contains(&-128));
+        assert!(!op1.remove_lower_bound().contains(&-127));
+        assert!(!op1.remove_lower_bound().contains(&12));
+
+        assert_eq!(Polynomial::from_numbers(4, &[-4, 0, 4, -8]), op1.narrow(4));
+        assert_eq!(Polynomial::new(16, -4, &[(4, 3)]), op1.sign_extend(16));
+        assert!(op1.zero_extend(16).contains(&vec![0xfc, 0, 4, 8]));
+        assert!(!op1.zero_extend(16).contains(&-4));
 
+        let op2 = Polynomial::from_numbers(8, &[2, 6]);
+        assert_eq!(Polynomial::new(8, -4, &[(4, 4), (6, 1)]), op1.join(&op2));
+        assert_eq!(Polynomial::new(8, -4, &[(4, 4)]), op1.join(&Polynomial::from((8, 12))));
+        assert_eq!(Polynomial::from((8, 4)), op1.meet(&Polynomial::new(8, 1, &[(3, 3)])));
 
-// TODO
+        // The stride of the numbers survives the widening
+        let widened = Polynomial::from((8, 0)).widen(&Polynomial::from_numbers(8, &[0, 4]));
+        assert!(widened.contains(&0x7c) && !widened.contains(&2) && !widened.contains(&-4));
+        assert_eq!(widened, widened.widen(&Polynomial::from_numbers(8, &[8, 12])));
+    }
+}
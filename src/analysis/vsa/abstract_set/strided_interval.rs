@@ -478,7 +478,8 @@ impl Container<StridedInterval> for StridedInterval {
             true
         } else if object.constant().is_some() {
             self.contains(&object.constant().unwrap_or(0))
-        } else if object.s % self.s != 0 {
+        } else if (self.s == 0) || (object.s % self.s != 0) {
+            // a constant cannot contain more than one number
             false
         } else {
             self.contains(&object.lb) && self.contains(&object.ub)
@@ -972,7 +973,8 @@ impl BitAnd for StridedInterval {
                         return StridedInterval::from((self.k, 0));
                     } else if (n == -1) {
                         return set_si.clone();
-                    } else {
+                    } else if set_si.lb >= 0 {
+                        // Remainder works as a mask for non-negative numbers only
                         return *set_si % StridedInterval::from((self.k, n + 1));
                     }
                 }
@@ -1179,7 +1181,9 @@ impl Shr for StridedInterval {
                 }
             }
             if other.ub >= self.k as inum {
-                res.unwrap().join(&StridedInterval::from((self.k, 0)))
+                // Shifting by k bits or more results in 0
+                let zero = StridedInterval::from((self.k, 0));
+                res.map_or(zero, |res| res.join(&zero))
             } else {
                 res.unwrap()
             }
@@ -1210,7 +1214,9 @@ impl Shl for StridedInterval {
                 }
             }
             if other.ub >= self.k as inum {
-                res.unwrap().join(&StridedInterval::from((self.k, 0)))
+                // Shifting by k bits or more results in 0
+                let zero = StridedInterval::from((self.k, 0));
+                res.map_or(zero, |res| res.join(&zero))
             } else {
                 res.unwrap()
             }
@@ -1220,6 +1226,18 @@ impl Shl for StridedInterval {
 
 // Implement trait AbstractSet for StridedInterval
 impl AbstractSet for StridedInterval {
+    fn null() -> Self {
+        StridedInterval::null()
+    }
+
+    fn default_k(k: u8) -> Self {
+        StridedInterval::default_k(k)
+    }
+
+    fn radix(&self) -> u8 {
+        self.k
+    }
+
     fn meet(&self, other: &Self) -> Self {
         if self.is_empty() || other.is_empty() {
             // meet an empty set --> empty set
@@ -1264,77 +1282,32 @@ impl AbstractSet for StridedInterval {
                     (other.lb, other.s, self.lb, self.s)
                 };
                 assert!(min_lb < max_lb && min_s > 0 && max_s > 0);
-                // For the smallest number n:
-                //      min_lb + x * min_s = max_lb + y * max_s = n
-                //      ( x >= 0, y >= 0)
-                //  =>  max_lb - min_lb = x * min_s - y * max_s
+                // The smallest number n in both sets goes like:
+                //      n = min_lb + x * min_s = max_lb + y * max_s (x >= 0, y >= 0)
                 //  let: s_ = gcd(min_s, max_s)
-                //  if (max_lb - min_lb) % s_ != 0 
+                //  if (max_lb - min_lb) % s_ != 0
                 //      then there is no number in the result set
-                //  let: lb_ = (max_lb - min_lb) / s_
-                //      --> lb_ != 0
-                //  let: min_s_ = min_s / s_
-                //  let: max_s_ = max_s / s_  
-                //      --> gcd(min_s_, max_s_) = 1
-                //  =>  lb_ = x * min_s_ - y * max_s_
-                //  =>  lb_ % max_s_ = (x * min_s_) % max_s_ 
-                //      because gcd(min_s_, max_s_) = 1, thus we have
-                //          (rev_min_s_ * min_s_) % max_s_ = 1
-                //  => x % max_s_ = ((lb_ % max_s_) * rev_min_s_) % max_s_
-                //  As we want to find the smallest number, which means
-                //      x should be as small as it can
-                //  let x_ = x % max_s_, then we solve the equation:
-                //      lb_ = x_ * min_s_ - y_ * max_s_
-                //  thus:
-                //      x = x_ + i * max_s_ 
-                //      y = y_ + i * min_s_
-                //  then we found the smallest positive i, which make x > 0 && y > 0
-                //  n = min_lb + x * min_s
-                
-                let s_ = gcd(min_s, max_s);
+                //  otherwise x is solved modulo max_s / s_ by the Chinese remainder
+                //  theorem, and n is the first number not smaller than max_lb.
+                // Numbers are held in i128 to avoid overflow.
+                let s_ = gcd(min_s, max_s) as i128;
+                let (min_lb, min_s, max_lb, max_s) =
+                    (min_lb as i128, min_s as i128, max_lb as i128, max_s as i128);
                 if (max_lb - min_lb) % s_ != 0 {
                     StridedInterval::null()
                 } else {
-                    // lb_ = x * min_s_ - y * max_s_
-                    let lb_ = (max_lb - min_lb) / s_;
-                    let max_s_ = max_s / s_;
-                    let min_s_ = min_s / s_;
-                    assert!(lb_ != 0);
-                    assert!(gcd(max_s_, min_s_) == 1);
-
-                    // x_ = x % max_s_ = ((lb_ % max_s_) * rev_min_s_) % max_s_
-                    let (a, b) = exgcd(min_s_, max_s_);
-                    // a * min_s_ + b * min_s_ = gcd(max_s_, min_s_) = 1
-                    assert_eq!(a * min_s_ + b * max_s_, gcd(min_s_, max_s_));
-                    let rev_min_s_ = a - periodNr(a, max_s_) * max_s_;
-                    assert_eq!((rev_min_s_ * min_s_) % max_s_, 1);
-                    let x_ = ((lb_ % max_s_) * rev_min_s_) % max_s_;
-                    assert!(x_ > 0);
-
-                    // let x_ = x % max_s_, then we solve the equation:
-                    //     lb_ = x_ * min_s_ - y_ * max_s_
-                    if (x_ * min_s_ - lb_) % max_s_ != 0 {
-                        // Cannot find the integer ans for the equation
-                        return StridedInterval::null();
-                    }
-                    let y_ = (x_ * min_s_ - lb_) / max_s_;
-                
-                    // thus:
-                    //     x = x_ + i * max_s_ 
-                    //     y = y_ + i * min_s_
-                    // then we found the smallest positive i, which make x > 0 && y > 0
-                    // n = min_lb + x * min_s
-                    let (x, y) = if y_ < 0 {
-                        let y = y_ - periodNr(y_, min_s_) * min_s_;
-                        let i = (y - y_) / min_s_;
-                        (x_ + i * max_s_, y)
+                    let lcm = min_s / s_ * max_s;
+                    let m = max_s / s_;
+                    // (min_s / s_) * x = (max_lb - min_lb) / s_ (mod m)
+                    let (rev, _) = exgcd(((min_s / s_) % m) as inum, m as inum);
+                    let x = (((max_lb - min_lb) / s_ % m) * (rev as i128 % m) % m + m) % m;
+                    let n0 = min_lb + x * min_s;
+                    let n = if n0 < max_lb {
+                        n0 + (max_lb - n0 + lcm - 1) / lcm * lcm
                     } else {
-                        (x_, y_)
+                        n0 - (n0 - max_lb) / lcm * lcm
                     };
-
-                    assert_eq!(min_lb + x * min_s, max_lb + y * max_s);
-                    assert!(x > 0 && y > 0);
-                    let n = min_lb + x * min_s;
+                    let n = n as inum;
                     if self.contains(&n) && other.contains(&n) {
                         StridedInterval::new(
                             self.k,
@@ -1362,8 +1335,13 @@ impl AbstractSet for StridedInterval {
             let mut s = gcd(self.s, other.s);
             // Trick to avoid overflow
             let (min_lb, max_lb) = (cmp::min(self.lb, other.lb), cmp::max(self.lb, other.lb));
-            // add 2 * s to make sure all arguments in gcd is positive
-            s = gcd(s, max_lb % s - min_lb % s + 2 * s);
+            s = if s == 0 {
+                // Two constants, the stride is their distance
+                max_lb.checked_sub(min_lb).unwrap_or(1)
+            } else {
+                // add 2 * s to make sure all arguments in gcd is positive
+                gcd(s, max_lb % s - min_lb % s + 2 * s)
+            };
             StridedInterval::new(
                 self.k,
                 s,
@@ -1382,23 +1360,15 @@ impl AbstractSet for StridedInterval {
             radeco_err!("Widen two strided intervals with different radices");
             StridedInterval::default()
         } else {
-            let s = gcd(self.s, other.s);
-            let lb = if self.lb <= other.lb {
-                self.lb
-            } else {
-                min_in_k_bits!(self.k)
-            };
-            let ub = if self.ub >= other.ub {
-                self.ub
-            } else {
-                max_in_k_bits!(self.k)
-            };
-            StridedInterval::new(
-                self.k,
-                s,
-                lb,
-                ub,
-            )
+            // Widen the bounds of the join, which keeps the stride of both
+            let mut si = self.join(other);
+            if self.lb > other.lb {
+                si = si.remove_lower_bound();
+            }
+            if self.ub < other.ub {
+                si = si.remove_upper_bound();
+            }
+            si
         }
     }
 
@@ -1407,13 +1377,15 @@ impl AbstractSet for StridedInterval {
             self.clone()
         } else {
             let min = min_in_k_bits!(self.k);
-            let ub_mod_s = self.ub % self.s;
-            let min_mod_s = min % self.s; // min_mod_s must be negative
-            let offset = (ub_mod_s - min_mod_s + self.s) % self.s;
+            // A constant has no stride, the numbers below it are all taken
+            let s = cmp::max(self.s, 1);
+            let ub_mod_s = self.ub % s;
+            let min_mod_s = min % s; // min_mod_s must be negative
+            let offset = (ub_mod_s - min_mod_s + s) % s;
             assert!(min_mod_s <= 0, "min_in_k_bits must be non-positive");
             StridedInterval::new(
                 self.k,
-                self.s,
+                s,
                 n_in_k_bits!(min + offset, self.k),
                 self.ub,
             )
@@ -1427,6 +1399,8 @@ impl AbstractSet for StridedInterval {
         } else if x > si.ub {
             radeco_warn!("Set a lower bound which is bigger than upper bound");
             StridedInterval::null()
+        } else if x <= si.lb {
+            si
         } else {
             let offset = self.ub - periodNr(self.ub, self.s) * self.s;
             let offset_ = x - periodNr(x, self.s) * self.s;
@@ -1467,6 +1441,22 @@ impl AbstractSet for StridedInterval {
         }
     }
 
+    fn lower_bound(&self) -> Option<inum> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.lb)
+        }
+    }
+
+    fn upper_bound(&self) -> Option<inum> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.ub)
+        }
+    }
+
     fn narrow(&self, k: u8) -> Self {
         if self.is_empty() {
             radeco_warn!("Empty StridedInterval cannot be narrow");
@@ -2014,15 +2004,17 @@ mod test {
         let op1 = StridedInterval::new(16, 30, 1, 901);
         let op2 = StridedInterval::new(16, 15, 6, 606);
         let op3 = StridedInterval::null();
-        assert_eq!(StridedInterval::new(16, 15, 1, 901), op1.widen(&op2));
-        assert_eq!(StridedInterval::new(16, 15, min_in_k_bits!(16), max_in_k_bits!(16)), op2.widen(&op1));
+        // The widening keeps the stride of the join of both
+        assert_eq!(StridedInterval::new(16, 5, 1, 901), op1.widen(&op2));
+        assert_eq!(StridedInterval::new(16, 5, min_in_k_bits!(16) + 4, max_in_k_bits!(16) - 1),
+                   op2.widen(&op1));
         assert_eq!(op1, op1.widen(&op3));
         assert_eq!(op1, op3.widen(&op1));
 
         let op1 = StridedInterval::new(16, 30, 1, 901);
         let op2 = StridedInterval::new(16, 15, -4, 696);
-        assert_eq!(StridedInterval::new(16, 15, min_in_k_bits!(16), 901), op1.widen(&op2));
-        assert_eq!(StridedInterval::new(16, 15, -4, max_in_k_bits!(16)), op2.widen(&op1));
+        assert_eq!(StridedInterval::new(16, 5, min_in_k_bits!(16) + 4, 901), op1.widen(&op2));
+        assert_eq!(StridedInterval::new(16, 5, -4, max_in_k_bits!(16) - 1), op2.widen(&op1));
 
         let op1 = StridedInterval::new(16, 30, 1, 901);
         let op2 = StridedInterval::new(16, 15, 6, 606);
@@ -2033,6 +2025,11 @@ mod test {
         assert_eq!(StridedInterval::new(16, 30, 1, 901), op1.join(&op2));
         assert_eq!(StridedInterval::new(16, 30, 1, 901), op2.join(&op1));
 
+        let op1 = StridedInterval::from((16, 4));
+        let op2 = StridedInterval::from((16, 12));
+        assert_eq!(StridedInterval::new(16, 8, 4, 12), op1.join(&op2));
+        assert_eq!(op1, op1.join(&op1));
+
         let op1 = StridedInterval::new(16, 30, 1, 901);
         let op2 = StridedInterval::null();
         assert_eq!(op2, op1.meet(&op2));
@@ -0,0 +1,720 @@
+// Copyright (c) 2018, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Module that implements intraprocedural value set analysis over SSAStorage.
+//!
+//! Every value of the SSA is given a value set, and every a-loc the function accesses
+//! the join of the value sets stored into it. The a-locs are the registers at the exit
+//! of the function and the memory at the singleton addresses of loads and stores: slots
+//! in the stack frame, globals and memory allocated on the heap, one region per
+//! allocating call site.
+//!
+//! The store of a-locs is flow-insensitive, an a-loc holds the join of every value the
+//! function may store to it. The locals of the stack frame are uninitialized on entry,
+//! but a local nothing is stored to may hold any value, as may any other memory. So
+//! does an a-loc a store may write part of, and any a-loc once something is stored
+//! through an address which may point anywhere. Calls may write to globals and to the
+//! regions whose addresses escape: are passed to a call, stored to memory or lost track
+//! of by the analysis. Phis at loop headers widen, and values evaluated too often are
+//! given up on.
+//!
+//! Offsets are strided intervals by default, any other `AbstractSet` may be analyzed
+//! with instead.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use frontend::radeco_containers::{RadecoFunction, RadecoModule};
+use middle::ir::MOpcode;
+use middle::regfile::RegisterId;
+use middle::ssa::cfg_traits::CFG;
+use middle::ssa::ssa_traits::*;
+use middle::ssa::ssastorage::SSAStorage;
+use middle::ssa::utils;
+use analysis::interproc::digstack;
+
+use super::abstract_set::abstract_set::{inum, AbstractSet, Container, _bits};
+use super::abstract_set::strided_interval::StridedInterval;
+use super::value_set::{ALoc, MemRegion, ValueSet};
+
+pub type SSAValue = <SSAStorage as SSA>::ValueRef;
+
+// Evaluations of a value after which it may hold any value.
+const MAX_EVALUATIONS: u32 = 64;
+
+/// Value sets of the values of a function and of the a-locs it accesses.
+#[derive(Clone, Debug, Default)]
+pub struct ValueSetAnalysis<A: AbstractSet = StridedInterval> {
+    values: HashMap<SSAValue, ValueSet<A>>,
+    alocs: HashMap<ALoc, ValueSet<A>>,
+}
+
+impl<A: AbstractSet> ValueSetAnalysis<A> {
+    /// Value set of `value`, if it holds a number or an address.
+    pub fn value_set(&self, value: SSAValue) -> Option<&ValueSet<A>> {
+        self.values.get(&value)
+    }
+
+    /// Value set of the a-loc `aloc`, if the function accesses it.
+    pub fn aloc(&self, aloc: &ALoc) -> Option<&ValueSet<A>> {
+        self.alocs.get(aloc)
+    }
+
+    pub fn alocs(&self) -> &HashMap<ALoc, ValueSet<A>> {
+        &self.alocs
+    }
+
+    pub fn value_sets(&self) -> &HashMap<SSAValue, ValueSet<A>> {
+        &self.values
+    }
+}
+
+/// Addresses of the imports of `rmod` which allocate memory on the heap.
+pub fn allocators(rmod: &RadecoModule) -> HashSet<u64> {
+    rmod.imports
+        .iter()
+        .filter(|&(_, import)| match import.name.rsplit('.').next() {
+            Some("malloc") | Some("calloc") | Some("realloc") => true,
+            _ => false,
+        })
+        .map(|(&addr, _)| addr)
+        .collect()
+}
+
+/// Analyzes the value sets of `rfn` and stores those of its values in it.
+pub fn run(rfn: &mut RadecoFunction, allocators: &HashSet<u64>) -> ValueSetAnalysis {
+    let vsa = analyze(rfn.ssa(), allocators);
+    radeco_trace!("vsa|{} a-locs in {}", vsa.alocs.len(), rfn.name);
+    rfn.set_value_sets(vsa.values.clone());
+    vsa
+}
+
+/// Analyzes the value sets of `ssa`, with offsets in the abstract sets `A`. Calls to
+/// the addresses in `allocators` return memory on the heap.
+pub fn analyze<A: AbstractSet>(ssa: &SSAStorage, allocators: &HashSet<u64>) -> ValueSetAnalysis<A> {
+    let mut analyzer = Analyzer::new(ssa, allocators);
+    analyzer.solve();
+    analyzer.finish()
+}
+
+struct Analyzer<'a, A: AbstractSet> {
+    ssa: &'a SSAStorage,
+    allocators: &'a HashSet<u64>,
+    sp: Option<RegisterId>,
+    sn: Option<RegisterId>,
+    bp: Option<RegisterId>,
+    values: HashMap<SSAValue, ValueSet<A>>,
+    // Address, width and value of every store, as last evaluated.
+    stores: HashMap<SSAValue, (ValueSet<A>, u16, ValueSet<A>)>,
+    // Regions whose addresses escape, which calls may write to.
+    escaped: HashSet<MemRegion>,
+    calls: bool,
+    // Offsets from the stack pointer on entry found by digstack.
+    stack: HashMap<SSAValue, inum>,
+    // Register of every value of the entry register state.
+    entry: HashMap<SSAValue, RegisterId>,
+    // Call and register of every value returned by a call.
+    returns: HashMap<SSAValue, (SSAValue, RegisterId)>,
+    // Phis which widen, those at loop headers.
+    widening: HashSet<SSAValue>,
+    loads: Vec<SSAValue>,
+    evaluations: HashMap<SSAValue, u32>,
+}
+
+impl<'a, A: AbstractSet> Analyzer<'a, A> {
+    fn new(ssa: &'a SSAStorage, allocators: &'a HashSet<u64>) -> Analyzer<'a, A> {
+        let mut analyzer = Analyzer {
+            ssa: ssa,
+            allocators: allocators,
+            sp: ssa.regfile.register_id_by_alias("SP"),
+            sn: ssa.regfile.register_id_by_alias("SN"),
+            bp: ssa.regfile.register_id_by_alias("BP"),
+            values: HashMap::new(),
+            stores: HashMap::new(),
+            escaped: HashSet::new(),
+            calls: false,
+            stack: HashMap::new(),
+            entry: HashMap::new(),
+            returns: HashMap::new(),
+            widening: HashSet::new(),
+            loads: Vec::new(),
+            evaluations: HashMap::new(),
+        };
+
+        let entry = match ssa.entry_node() {
+            Some(entry) => entry,
+            None => return analyzer,
+        };
+        if let Some(regstate) = ssa.registers_in(entry) {
+            for (reg, &(value, _)) in utils::register_state_info(regstate, ssa).iter() {
+                analyzer.entry.insert(value, reg);
+            }
+        }
+        if let Some(sp_name) = ssa.regfile.alias_info.get("SP").cloned() {
+            let bp_name = ssa.regfile.alias_info.get("BP").cloned().unwrap_or_default();
+            analyzer.stack = digstack::rounded_analysis(ssa, sp_name, bp_name);
+        }
+        for value in ssa.values() {
+            match ssa.opcode(value) {
+                Some(MOpcode::OpCall) => {
+                    analyzer.calls = true;
+                    for (reg, &(ret, _)) in utils::call_rets(value, ssa).iter() {
+                        analyzer.returns.insert(ret, (value, reg));
+                    }
+                }
+                Some(MOpcode::OpLoad(..)) => analyzer.loads.push(value),
+                _ => {}
+            }
+        }
+        for header in loop_headers(ssa, entry) {
+            analyzer.widening.extend(ssa.phis_in(header));
+        }
+        analyzer
+    }
+
+    fn solve(&mut self) {
+        let mut worklist: VecDeque<SSAValue> = self.ssa.values().into_iter().collect();
+        let mut queued: HashSet<SSAValue> = worklist.iter().cloned().collect();
+        while let Some(value) = worklist.pop_front() {
+            queued.remove(&value);
+            for next in self.evaluate(value) {
+                if queued.insert(next) {
+                    worklist.push_back(next);
+                }
+            }
+        }
+    }
+
+    // Evaluates `value`, returning the values to evaluate again.
+    fn evaluate(&mut self, value: SSAValue) -> Vec<SSAValue> {
+        match self.ssa.opcode(value) {
+            Some(MOpcode::OpStore(width, _)) => {
+                return if self.store(value, width) { self.loads.clone() } else { Vec::new() };
+            }
+            Some(MOpcode::OpCall) => {
+                let mut next: Vec<SSAValue> = self.returns
+                                                  .iter()
+                                                  .filter(|&(_, &(call, _))| call == value)
+                                                  .map(|(&ret, _)| ret)
+                                                  .collect();
+                if self.call(value) {
+                    next.extend(self.loads.iter().cloned());
+                }
+                return next;
+            }
+            _ => {}
+        }
+
+        let vs = match self.transfer(value) {
+            Some(vs) => vs,
+            None => return Vec::new(),
+        };
+        let old = self.value(value);
+        let evaluations = {
+            let count = self.evaluations.entry(value).or_insert(0);
+            *count += 1;
+            *count
+        };
+        let new = if evaluations > MAX_EVALUATIONS {
+            radeco_trace!("vsa|Giving up on {:?}", value);
+            ValueSet::Top
+        } else if self.widening.contains(&value) && !old.is_bottom() {
+            old.widen(&vs)
+        } else {
+            vs
+        };
+        if new == old {
+            return Vec::new();
+        }
+        let lost = new.is_top() && self.loses_addresses(value, &old);
+        self.values.insert(value, new);
+        let mut next = self.ssa.uses_of(value);
+        if lost {
+            next.extend(self.loads.iter().cloned());
+        }
+        next
+    }
+
+    // Marks the regions `value` or its operands may point into as escaped once it may
+    // hold any value, returning whether any was not before. Neither loads nor flags
+    // compute what they hold from the addresses they are given.
+    fn loses_addresses(&mut self, value: SSAValue, old: &ValueSet<A>) -> bool {
+        let mut escaped = self.escape(old);
+        match self.ssa.opcode(value) {
+            Some(MOpcode::OpLoad(..)) => return escaped,
+            _ if width(self.ssa, value) == Some(1) => return escaped,
+            _ => {}
+        }
+        for op in self.ssa.operands_of(value) {
+            let vs = self.value(op);
+            escaped |= self.escape(&vs);
+        }
+        escaped
+    }
+
+    // Marks the regions `vs` may point into as escaped, returning whether any was not
+    // before. Numbers are absolute addresses, which anything may use anyway.
+    fn escape(&mut self, vs: &ValueSet<A>) -> bool {
+        let mut escaped = false;
+        if let ValueSet::Regions(ref regions) = *vs {
+            for &region in regions.keys() {
+                if region != MemRegion::Global && self.escaped.insert(region) {
+                    radeco_trace!("vsa|{:?} escapes", region);
+                    escaped = true;
+                }
+            }
+        }
+        escaped
+    }
+
+    // Marks the regions the arguments of `call` point into as escaped, returning
+    // whether any was not before. The stack and frame pointers are assumed to be
+    // restored rather than written through.
+    fn call(&mut self, call: SSAValue) -> bool {
+        let args = utils::register_args(call, self.ssa);
+        let mut escaped = false;
+        for (reg, &arg) in args.iter() {
+            if Some(reg) != self.sp && Some(reg) != self.bp {
+                let vs = self.value(arg);
+                escaped |= self.escape(&vs);
+            }
+        }
+        escaped
+    }
+
+    // Value set of `value` given those of its operands, if it holds a number or an
+    // address.
+    fn transfer(&self, value: SSAValue) -> Option<ValueSet<A>> {
+        let ssa = self.ssa;
+        let k = match width(ssa, value) {
+            Some(k) if k <= _bits as u16 => k as u8,
+            Some(_) => return Some(ValueSet::Top),
+            None => return None,
+        };
+        if let Some(n) = ssa.constant(value) {
+            return Some(ValueSet::constant(k, n as inum));
+        }
+
+        let vs = if let Some(&reg) = self.entry.get(&value) {
+            if Some(reg) == self.sp {
+                ValueSet::new(MemRegion::Stack, A::from((k, 0)))
+            } else {
+                ValueSet::Top
+            }
+        } else if let Some(&(call, reg)) = self.returns.get(&value) {
+            self.call_return(call, reg, k)
+        } else if ssa.is_phi(value) {
+            ssa.operands_of(value)
+               .into_iter()
+               .fold(ValueSet::bottom(), |vs, op| vs.join(&self.value(op)))
+        } else {
+            self.expression(value, k)
+        };
+
+        // Addresses in the stack frame which were lost are recovered from digstack.
+        match self.stack.get(&value) {
+            Some(&offset) if vs.is_top() => {
+                Some(ValueSet::new(MemRegion::Stack, A::from((k, offset))))
+            }
+            _ => Some(vs),
+        }
+    }
+
+    fn call_return(&self, call: SSAValue, reg: RegisterId, k: u8) -> ValueSet<A> {
+        let info = match utils::call_info(call, self.ssa) {
+            Some(info) => info,
+            None => return ValueSet::Top,
+        };
+        // Calls are assumed to restore the stack pointer.
+        if Some(reg) == self.sp {
+            return info.register_args
+                       .get(reg)
+                       .map_or(ValueSet::Top, |&arg| self.value(arg));
+        }
+        let allocates = self.ssa
+                            .constant(info.target)
+                            .map_or(false, |target| self.allocators.contains(&target));
+        if allocates && Some(reg) == self.sn {
+            ValueSet::new(MemRegion::Heap(call), A::from((k, 0)))
+        } else {
+            ValueSet::Top
+        }
+    }
+
+    fn expression(&self, value: SSAValue, k: u8) -> ValueSet<A> {
+        let ssa = self.ssa;
+        let opcode = match ssa.opcode(value) {
+            Some(opcode) => opcode,
+            None => return ValueSet::Top,
+        };
+        let operands = ssa.operands_of(value);
+
+        if let MOpcode::OpLoad(width, _) = opcode {
+            return match operands.get(1) {
+                Some(&address) => self.load(&self.value(address), width),
+                None => ValueSet::Top,
+            };
+        }
+
+        let ops: Vec<ValueSet<A>> = operands.iter().map(|&op| self.value(op)).collect();
+        if ops.iter().any(|vs| vs.is_bottom()) {
+            return ValueSet::bottom();
+        }
+        // Comparisons and flags are not tracked.
+        if k == 1 {
+            return ValueSet::Top;
+        }
+        match (opcode, ops.len()) {
+            (MOpcode::OpAdd, 2) => ops[0].add(&ops[1]),
+            (MOpcode::OpSub, 2) => ops[0].sub(&ops[1]),
+            (MOpcode::OpMul, 2) => ops[0].numeric(&ops[1], k, |a, b| a * b),
+            (MOpcode::OpAnd, 2) => ops[0].numeric(&ops[1], k, |a, b| a & b),
+            (MOpcode::OpOr, 2) => ops[0].numeric(&ops[1], k, |a, b| a | b),
+            (MOpcode::OpXor, 2) => ops[0].numeric(&ops[1], k, |a, b| a ^ b),
+            (MOpcode::OpLsl, 2) if shift_in_range(&ops[1], k) => {
+                ops[0].numeric(&ops[1], k, |a, b| a << b)
+            }
+            (MOpcode::OpLsr, 2) if shift_in_range(&ops[1], k) => {
+                ops[0].numeric(&ops[1], k, |a, b| a >> b)
+            }
+            (MOpcode::OpNot, 1) => ops[0].numeric_unary(|a| !a),
+            (MOpcode::OpNarrow(_), 1) => {
+                ops[0].numeric_unary(|a| if a.radix() > k { a.narrow(k) } else { a })
+            }
+            (MOpcode::OpSignExt(_), 1) => {
+                ops[0].numeric_unary(|a| if a.radix() < k { a.sign_extend(k) } else { a })
+            }
+            (MOpcode::OpZeroExt(_), 1) => {
+                ops[0].numeric_unary(|a| if a.radix() < k { a.zero_extend(k) } else { a })
+            }
+            (_, _) => ValueSet::Top,
+        }
+    }
+
+    fn load(&self, address: &ValueSet<A>, width: u16) -> ValueSet<A> {
+        if address.is_bottom() {
+            return ValueSet::bottom();
+        }
+        match address.singleton() {
+            // Only the locals of the stack frame are known on entry.
+            Some((MemRegion::Stack, offset)) if offset < 0 => {
+                let vs = self.stored(MemRegion::Stack, offset, width);
+                if vs.is_bottom() { ValueSet::Top } else { vs }
+            }
+            _ => ValueSet::Top,
+        }
+    }
+
+    // Join of the values the function may store to the `width` bits at `offset` into
+    // `region`.
+    fn stored(&self, region: MemRegion, offset: inum, width: u16) -> ValueSet<A> {
+        if self.calls && (region == MemRegion::Global || self.escaped.contains(&region)) {
+            return ValueSet::Top;
+        }
+        let mut vs = ValueSet::bottom();
+        for &(ref address, w, ref value) in self.stores.values() {
+            let offsets = match *address {
+                ValueSet::Top => return ValueSet::Top,
+                ValueSet::Regions(ref regions) => {
+                    match regions.get(&region) {
+                        Some(offsets) => offsets,
+                        None => continue,
+                    }
+                }
+            };
+            // Stores to any offset which overlaps the a-loc.
+            let first = offset.saturating_sub(bytes(w)).saturating_add(1);
+            let end = offset.saturating_add(bytes(width));
+            for s in (first..end).filter(|s| offsets.contains(s)) {
+                if s != offset || w != width {
+                    return ValueSet::Top;
+                }
+                vs = vs.join(value);
+            }
+        }
+        vs
+    }
+
+    // Records the address and value of `store`, returning whether either changed.
+    fn store(&mut self, store: SSAValue, width: u16) -> bool {
+        let operands = self.ssa.operands_of(store);
+        let (address, vs) = match (operands.get(1), operands.get(2)) {
+            (Some(&address), Some(&value)) => (self.value(address), self.value(value)),
+            (_, _) => return false,
+        };
+        if address.is_bottom() || vs.is_bottom() {
+            return false;
+        }
+        // Anything may load an address stored to memory.
+        let escaped = self.escape(&vs);
+        let new = (address, width, vs);
+        if self.stores.get(&store) == Some(&new) {
+            return escaped;
+        }
+        self.stores.insert(store, new);
+        true
+    }
+
+    fn value(&self, value: SSAValue) -> ValueSet<A> {
+        self.values.get(&value).cloned().unwrap_or_default()
+    }
+
+    fn finish(self) -> ValueSetAnalysis<A> {
+        let mut alocs = HashMap::new();
+        for &(ref address, width, _) in self.stores.values() {
+            if let Some((region, offset)) = address.singleton() {
+                let aloc = ALoc::Memory {
+                    region: region,
+                    offset: offset,
+                    width: width,
+                };
+                alocs.insert(aloc, self.stored(region, offset, width));
+            }
+        }
+        let regstate = self.ssa.exit_node().and_then(|exit| self.ssa.registers_in(exit));
+        if let Some(regstate) = regstate {
+            for (reg, &(value, _)) in utils::register_state_info(regstate, self.ssa).iter() {
+                if let (Some(name), Some(vs)) = (self.ssa.regfile.get_name(reg),
+                                                 self.values.get(&value)) {
+                    alocs.insert(ALoc::Register(name.to_owned()), vs.clone());
+                }
+            }
+        }
+        ValueSetAnalysis {
+            values: self.values,
+            alocs: alocs,
+        }
+    }
+}
+
+fn width(ssa: &SSAStorage, value: SSAValue) -> Option<u16> {
+    let width = ssa.node_data(value).ok()?.vt.width().get_width()?;
+    if width == 0 { None } else { Some(width) }
+}
+
+// Number of bytes `width` bits take up.
+fn bytes(width: u16) -> inum {
+    (width as inum + 7) / 8
+}
+
+// Whether every amount in `amount` shifts a `k` bits wide number by less than its
+// width.
+fn shift_in_range<A: AbstractSet>(amount: &ValueSet<A>, k: u8) -> bool {
+    amount.global().map_or(false, |a| {
+        a.lower_bound().map_or(false, |lb| lb >= 0) &&
+        a.upper_bound().map_or(false, |ub| ub < k as inum)
+    })
+}
+
+// Blocks which are the target of a back edge, found by a depth-first search from
+// `entry`.
+fn loop_headers(ssa: &SSAStorage, entry: SSAValue) -> HashSet<SSAValue> {
+    let mut headers = HashSet::new();
+    let mut visited = HashSet::new();
+    let mut on_path = HashSet::new();
+    visited.insert(entry);
+    on_path.insert(entry);
+    let mut stack = vec![(entry, ssa.succs_of(entry).into_iter())];
+    while let Some(next) = stack.last_mut().map(|&mut (_, ref mut succs)| succs.next()) {
+        match next {
+            Some(succ) => {
+                if on_path.contains(&succ) {
+                    headers.insert(succ);
+                } else if visited.insert(succ) {
+                    on_path.insert(succ);
+                    stack.push((succ, ssa.succs_of(succ).into_iter()));
+                }
+            }
+            None => {
+                if let Some((block, _)) = stack.pop() {
+                    on_path.remove(&block);
+                }
+            }
+        }
+    }
+    headers
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use analysis::vsa::abstract_set::BDD::BDD;
+    use analysis::vsa::abstract_set::polynomial::Polynomial;
+    use middle::ir_reader::test_util::{at, parse};
+
+    // Stores 42 in a local, allocates 16 bytes and zeroes them in a loop.
+    const ALLOC: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rsp;
+        %2: $Unknown64 = $rdi;
+        %3: $Unknown0 = $mem;
+    bb_0x001000.0000(sz 0x8):
+        [@0x001000.0000] %4: $Unknown64 = %1 - #x10;
+        [@0x001000.0001] %5: $Unknown0 = Store64(%3, %4, #x2a);
+        [@0x001004.0000] (%6: $Unknown64 = $rax, %7: $Unknown64 = $rsp, %8: $Unknown0 = $mem) = CALL #x2000($rdi=#x10, $rsp=%4, $mem=%5);
+        [@0x001004.0001] %9: $Unknown64 = Load64(%8, %4);
+        JMP 0x001008.0000
+    bb_0x001008.0000(sz 0x10):
+        %10: $Unknown64 = Phi(#x0, %12);
+        %11: $Unknown0 = Phi(%8, %14);
+        [@0x001008.0000] %12: $Unknown64 = %10 + #x1;
+        [@0x00100C.0000] %13: $Unknown64 = %6 + %10;
+        [@0x00100C.0001] %14: $Unknown0 = Store64(%11, %13, %12);
+        [@0x001010.0000] %15: $Unknown1 = %12 < #x10;
+        JMP IF %15 0x001008.0000 ELSE 0x001018.0000
+    bb_0x001018.0000(sz 0x4):
+        JMP TO %2
+    exit-node:
+    final-register-state:
+        $rsp = %7;
+        $rax = %9;
+        $mem = %14;
+}
+";
+
+    fn check_analysis<A: AbstractSet>() {
+        let ssa = parse(ALLOC);
+        let allocators = vec![0x2000].into_iter().collect();
+        let vsa = analyze::<A>(&ssa, &allocators);
+
+        let local = at(&ssa, 0x1000, 0);
+        assert_eq!(vsa.value_set(local).and_then(|vs| vs.singleton()),
+                   Some((MemRegion::Stack, -16)));
+        let slot = ALoc::Memory {
+            region: MemRegion::Stack,
+            offset: -16,
+            width: 64,
+        };
+        assert_eq!(vsa.aloc(&slot).and_then(|vs| vs.singleton()),
+                   Some((MemRegion::Global, 42)));
+        let loaded = at(&ssa, 0x1004, 1);
+        assert_eq!(vsa.value_set(loaded).and_then(|vs| vs.singleton()),
+                   Some((MemRegion::Global, 42)));
+        assert_eq!(vsa.aloc(&ALoc::Register("rsp".to_owned())).and_then(|vs| vs.singleton()),
+                   Some((MemRegion::Stack, -16)));
+        assert_eq!(vsa.aloc(&ALoc::Register("rax".to_owned())).and_then(|vs| vs.singleton()),
+                   Some((MemRegion::Global, 42)));
+
+        // The induction variable is widened, and indexes the allocated memory.
+        let call = at(&ssa, 0x1004, 0);
+        let i = ssa.operands_of(at(&ssa, 0x1008, 0))[0];
+        let counter = vsa.value_set(i).and_then(|vs| vs.global()).unwrap();
+        assert!(counter.contains(&0) && counter.contains(&0x10) && counter.contains(&0x1000));
+        let element = vsa.value_set(at(&ssa, 0x100C, 0)).unwrap();
+        assert!(element.offsets(MemRegion::Heap(call)).map_or(false, |a| a.contains(&0)));
+        // Nothing was stored through the allocated memory at a known offset.
+        assert!(vsa.alocs().keys().all(|aloc| match *aloc {
+            ALoc::Memory { region, .. } => region == MemRegion::Stack,
+            ALoc::Register(_) => true,
+        }));
+    }
+
+    #[test]
+    fn analyze_test() {
+        check_analysis::<StridedInterval>();
+    }
+
+    #[test]
+    fn analyze_bdd_test() {
+        check_analysis::<BDD>();
+    }
+
+    #[test]
+    fn analyze_polynomial_test() {
+        check_analysis::<Polynomial>();
+    }
+
+    // Stores to overlapping locals of different widths, and loads from a local nothing
+    // is stored to.
+    const OVERLAP: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rsp;
+        %2: $Unknown64 = $rdi;
+        %3: $Unknown0 = $mem;
+    bb_0x001000.0000(sz 0x10):
+        [@0x001000.0000] %4: $Unknown64 = %1 - #x10;
+        [@0x001000.0001] %5: $Unknown0 = Store64(%3, %4, #x2a);
+        [@0x001000.0002] %6: $Unknown64 = %1 - #xc;
+        [@0x001000.0003] %7: $Unknown0 = Store32(%5, %6, #x1);
+        [@0x001004.0000] %8: $Unknown64 = %1 - #x20;
+        [@0x001004.0001] %9: $Unknown0 = Store64(%7, %8, #x7);
+        [@0x001008.0000] %10: $Unknown64 = Load64(%9, %4);
+        [@0x001008.0001] %11: $Unknown64 = Load64(%9, %8);
+        [@0x001008.0002] %12: $Unknown64 = %1 - #x30;
+        [@0x001008.0003] %13: $Unknown64 = Load64(%9, %12);
+        JMP TO %2
+    exit-node:
+    final-register-state:
+        $rax = %10;
+        $rbx = %11;
+        $rcx = %13;
+        $mem = %9;
+}
+";
+
+    fn is_top(vsa: &ValueSetAnalysis, value: SSAValue) -> bool {
+        vsa.value_set(value).map_or(false, |vs| vs.is_top())
+    }
+
+    fn local(offset: inum) -> ALoc {
+        ALoc::Memory {
+            region: MemRegion::Stack,
+            offset: offset,
+            width: 64,
+        }
+    }
+
+    #[test]
+    fn overlapping_stores_test() {
+        let ssa = parse(OVERLAP);
+        let vsa = analyze::<StridedInterval>(&ssa, &HashSet::new());
+        assert!(is_top(&vsa, at(&ssa, 0x1008, 0)));
+        assert!(vsa.aloc(&local(-16)).map_or(false, |vs| vs.is_top()));
+        assert_eq!(vsa.value_set(at(&ssa, 0x1008, 1)).and_then(|vs| vs.singleton()),
+                   Some((MemRegion::Global, 7)));
+        assert_eq!(vsa.aloc(&local(-32)).and_then(|vs| vs.singleton()),
+                   Some((MemRegion::Global, 7)));
+        assert!(is_top(&vsa, at(&ssa, 0x1008, 3)));
+
+        // A store through an argument may write to any local.
+        let ssa = parse(&OVERLAP.replace("Store64(%7, %8, #x7)", "Store64(%7, %2, #x7)"));
+        let vsa = analyze::<StridedInterval>(&ssa, &HashSet::new());
+        assert!(is_top(&vsa, at(&ssa, 0x1008, 1)));
+    }
+
+    #[test]
+    fn escaped_local_test() {
+        let il = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rsp;
+        %2: $Unknown64 = $rdi;
+        %3: $Unknown0 = $mem;
+    bb_0x001000.0000(sz 0x10):
+        [@0x001000.0000] %4: $Unknown64 = %1 - #x10;
+        [@0x001000.0001] %5: $Unknown0 = Store64(%3, %4, #x2a);
+        [@0x001004.0000] (%6: $Unknown0 = $mem) = CALL #x2000($rdi=ARG, $rsp=%1, $mem=%5);
+        [@0x001008.0000] %7: $Unknown64 = Load64(%6, %4);
+        JMP TO %2
+    exit-node:
+    final-register-state:
+        $rax = %7;
+        $mem = %6;
+}
+";
+        // Only a call given the address of the local may write to it.
+        let ssa = parse(&il.replace("ARG", "#x10"));
+        let vsa = analyze::<StridedInterval>(&ssa, &HashSet::new());
+        assert_eq!(vsa.value_set(at(&ssa, 0x1008, 0)).and_then(|vs| vs.singleton()),
+                   Some((MemRegion::Global, 42)));
+        let ssa = parse(&il.replace("ARG", "%4"));
+        let vsa = analyze::<StridedInterval>(&ssa, &HashSet::new());
+        assert!(is_top(&vsa, at(&ssa, 0x1008, 0)));
+        assert!(vsa.aloc(&local(-16)).map_or(false, |vs| vs.is_top()));
+    }
+}
@@ -0,0 +1,294 @@
+// Copyright (c) 2018, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Module that implements value sets and abstract locations (a-locs).
+//!
+//! A value set maps every memory region a value may point into to an abstract
+//! set of offsets in that region, strided intervals unless told otherwise.
+//! Numbers are offsets in the global region, i.e. absolute addresses.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use petgraph::graph::NodeIndex;
+
+use super::abstract_set::abstract_set::{inum, AbstractSet, _bits};
+use super::abstract_set::strided_interval::StridedInterval;
+
+/// Memory region a value points into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MemRegion {
+    /// Absolute addresses, and numbers which are no address at all.
+    Global,
+    /// Stack frame of the function, at offsets from the stack pointer on entry.
+    Stack,
+    /// Memory allocated by the call at this node.
+    Heap(NodeIndex),
+}
+
+/// Abstract location, a variable-like unit of storage.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ALoc {
+    /// A register, holding its value at the exit of the function.
+    Register(String),
+    /// `width` bits of memory at `offset` into `region`.
+    Memory {
+        region: MemRegion,
+        offset: inum,
+        width: u16,
+    },
+}
+
+/// Set of values, as offsets into memory regions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValueSet<A: AbstractSet = StridedInterval> {
+    /// Any value in any region.
+    Top,
+    /// Offsets into each region. A value set without regions holds no value (yet).
+    Regions(BTreeMap<MemRegion, A>),
+}
+
+impl<A: AbstractSet> Default for ValueSet<A> {
+    fn default() -> ValueSet<A> {
+        ValueSet::bottom()
+    }
+}
+
+impl<A: AbstractSet> ValueSet<A> {
+    /// Value set that holds no value.
+    pub fn bottom() -> ValueSet<A> {
+        ValueSet::Regions(BTreeMap::new())
+    }
+
+    /// Value set of the `offsets` into `region`.
+    pub fn new(region: MemRegion, offsets: A) -> ValueSet<A> {
+        if offsets.is_empty() {
+            return ValueSet::bottom();
+        }
+        let mut regions = BTreeMap::new();
+        regions.insert(region, offsets);
+        ValueSet::Regions(regions)
+    }
+
+    /// Value set of the `k` bits wide constant `n`.
+    pub fn constant(k: u8, n: inum) -> ValueSet<A> {
+        ValueSet::new(MemRegion::Global, A::from((k, n)))
+    }
+
+    pub fn is_top(&self) -> bool {
+        *self == ValueSet::Top
+    }
+
+    pub fn is_bottom(&self) -> bool {
+        match *self {
+            ValueSet::Regions(ref regions) => regions.is_empty(),
+            ValueSet::Top => false,
+        }
+    }
+
+    /// Offsets into `region`, if the values may point into it.
+    pub fn offsets(&self, region: MemRegion) -> Option<&A> {
+        match *self {
+            ValueSet::Regions(ref regions) => regions.get(&region),
+            ValueSet::Top => None,
+        }
+    }
+
+    /// The numbers of the set, if it holds nothing but numbers.
+    pub fn global(&self) -> Option<&A> {
+        match *self {
+            ValueSet::Regions(ref regions) if regions.len() == 1 => {
+                regions.get(&MemRegion::Global)
+            }
+            _ => None,
+        }
+    }
+
+    /// The only value of the set, as a region and an offset into it.
+    pub fn singleton(&self) -> Option<(MemRegion, inum)> {
+        match *self {
+            ValueSet::Regions(ref regions) if regions.len() == 1 => {
+                regions.iter().next().and_then(|(&r, a)| a.constant().map(|n| (r, n)))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn join(&self, other: &ValueSet<A>) -> ValueSet<A> {
+        self.combine(other, |a, b| a.join(b))
+    }
+
+    /// Widens `self` by `other`, widening the offsets into each region.
+    pub fn widen(&self, other: &ValueSet<A>) -> ValueSet<A> {
+        self.combine(other, |a, b| a.widen(b))
+    }
+
+    // Combines the offsets into the regions both sets point into with `f`.
+    fn combine<F>(&self, other: &ValueSet<A>, f: F) -> ValueSet<A>
+        where F: Fn(&A, &A) -> A
+    {
+        match (self, other) {
+            (&ValueSet::Regions(ref a), &ValueSet::Regions(ref b)) => {
+                let mut regions = a.clone();
+                for (&r, sb) in b {
+                    let offsets = match a.get(&r) {
+                        Some(sa) => f(sa, &resize(*sb, sa.radix())),
+                        None => *sb,
+                    };
+                    regions.insert(r, offsets);
+                }
+                ValueSet::Regions(regions)
+            }
+            (_, _) => ValueSet::Top,
+        }
+    }
+
+    /// Sum of the values, which points into a region if exactly one of the summands does.
+    pub fn add(&self, other: &ValueSet<A>) -> ValueSet<A> {
+        self.pairwise(other, |(ra, sa), (rb, sb)| {
+            match (ra, rb) {
+                (MemRegion::Global, r) => Some((r, resize(sa, sb.radix()) + sb)),
+                (r, MemRegion::Global) => Some((r, sa + resize(sb, sa.radix()))),
+                (_, _) => None,
+            }
+        })
+    }
+
+    /// Difference of the values. The difference of two offsets into the same region is a
+    /// number.
+    pub fn sub(&self, other: &ValueSet<A>) -> ValueSet<A> {
+        self.pairwise(other, |(ra, sa), (rb, sb)| {
+            match (ra, rb) {
+                (r, MemRegion::Global) => Some((r, sa - resize(sb, sa.radix()))),
+                (ra, rb) if ra == rb => Some((MemRegion::Global, sa - resize(sb, sa.radix()))),
+                (_, _) => None,
+            }
+        })
+    }
+
+    /// Applies `f` to the numbers of `self` and `other` resized to `k` bits. Any other
+    /// operation on addresses results in any value.
+    pub fn numeric<F>(&self, other: &ValueSet<A>, k: u8, f: F) -> ValueSet<A>
+        where F: Fn(A, A) -> A
+    {
+        if self.is_bottom() || other.is_bottom() {
+            return ValueSet::bottom();
+        }
+        match (self.global(), other.global()) {
+            (Some(&a), Some(&b)) => ValueSet::new(MemRegion::Global, f(resize(a, k), resize(b, k))),
+            (_, _) => ValueSet::Top,
+        }
+    }
+
+    /// Applies `f` to the numbers of `self`. Any other operation on addresses results in
+    /// any value.
+    pub fn numeric_unary<F>(&self, f: F) -> ValueSet<A>
+        where F: Fn(A) -> A
+    {
+        if self.is_bottom() {
+            return ValueSet::bottom();
+        }
+        match self.global() {
+            Some(&a) => ValueSet::new(MemRegion::Global, f(a)),
+            None => ValueSet::Top,
+        }
+    }
+
+    // Joins `f` of every pair of regions of `self` and `other`, giving up on any value if
+    // `f` does not know the region of a pair.
+    fn pairwise<F>(&self, other: &ValueSet<A>, f: F) -> ValueSet<A>
+        where F: Fn((MemRegion, A), (MemRegion, A)) -> Option<(MemRegion, A)>
+    {
+        let (a, b) = match (self, other) {
+            (&ValueSet::Regions(ref a), &ValueSet::Regions(ref b)) => (a, b),
+            (_, _) => return ValueSet::Top,
+        };
+        let mut result = ValueSet::bottom();
+        for (&ra, &sa) in a {
+            for (&rb, &sb) in b {
+                match f((ra, sa), (rb, sb)) {
+                    Some((r, offsets)) => result = result.join(&ValueSet::new(r, offsets)),
+                    None => return ValueSet::Top,
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Resizes the numbers `a` to `k` bits, sign extending or narrowing them.
+pub fn resize<A: AbstractSet>(a: A, k: u8) -> A {
+    let k = if k > _bits { _bits } else { k };
+    if a.is_empty() || a.radix() == k {
+        a
+    } else if a.radix() < k {
+        a.sign_extend(k)
+    } else {
+        a.narrow(k)
+    }
+}
+
+impl<A: AbstractSet + fmt::Display> fmt::Display for ValueSet<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValueSet::Top => write!(f, "T"),
+            ValueSet::Regions(ref regions) => {
+                write!(f, "{{")?;
+                for (i, (region, offsets)) in regions.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: {}", region, offsets)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn value_set_arith_test() {
+        let sp = ValueSet::constant(64, 0x100);
+        let local = ValueSet::new(MemRegion::Stack, StridedInterval::from((64, -16)));
+        let four = ValueSet::constant(64, 4);
+
+        assert_eq!(local.add(&four).singleton(), Some((MemRegion::Stack, -12)));
+        assert_eq!(four.add(&local).singleton(), Some((MemRegion::Stack, -12)));
+        assert_eq!(local.sub(&four).singleton(), Some((MemRegion::Stack, -20)));
+        assert_eq!(local.add(&four).sub(&local).singleton(), Some((MemRegion::Global, 4)));
+        // Addresses are not summed, nor subtracted from numbers.
+        assert!(local.add(&local).is_top());
+        assert!(sp.sub(&local).is_top());
+        assert!(local.numeric(&four, 64, |a, b| a * b).is_top());
+        assert_eq!(sp.numeric(&four, 64, |a, b| a * b).singleton(),
+                   Some((MemRegion::Global, 0x400)));
+        assert!(local.add(&ValueSet::bottom()).is_bottom());
+    }
+
+    #[test]
+    fn value_set_lattice_test() {
+        let zero = ValueSet::constant(64, 0);
+        let one = ValueSet::constant(64, 1);
+        let local = ValueSet::new(MemRegion::Stack, StridedInterval::from((64, -16)));
+
+        let both = zero.join(&one);
+        assert_eq!(both.global(), Some(&StridedInterval::new(64, 1, 0, 1)));
+        assert_eq!(both.join(&ValueSet::bottom()), both);
+        assert!(both.join(&ValueSet::Top).is_top());
+
+        let mixed = zero.join(&local);
+        assert_eq!(mixed.global(), None);
+        assert_eq!(mixed.offsets(MemRegion::Stack), local.offsets(MemRegion::Stack));
+
+        let widened = zero.widen(&both);
+        assert_eq!(widened.global(), Some(&StridedInterval::new(64, 1, 0, inum::max_value())));
+    }
+}
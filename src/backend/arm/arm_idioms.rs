@@ -0,0 +1,177 @@
+//! This module contains common ARM and AArch64 idioms as grep and replace patterns
+//! which maybe used for further reduction of the SSA form.
+//!
+//! ARM evaluates conditions from the N, Z, C and V flags set by `cmp`, so most of the
+//! patterns here fold a condition on these flags back into a comparison of the
+//! operands of the original subtraction, signed for GT, LE, GE and LT and unsigned for
+//! HI, LS, HS and LO.
+//!
+//! NOTE: This module is not stable.
+
+use analysis::matcher::gmatch;
+use middle::ssa::ssa_traits::{SSAMod, SSAWalk, SSA};
+
+#[allow(dead_code)]
+mod patterns {
+    pub const ZF: &'static str = "(OpNarrow1 (OpXor #x1, (OpAnd (OpSub %2, %3), #xffffffff)))";
+    pub const ZF_64: &'static str = "(OpNarrow1 (OpXor #x1, (OpAnd (OpSub %2, %3), #xffffffffffffffff)))";
+    pub const NF: &'static str = "(OpNarrow1 (OpLsr (OpSub %2, %3), (OpSub #x20, #x1)))";
+    pub const NF_64: &'static str = "(OpNarrow1 (OpLsr (OpSub %2, %3), (OpSub #x40, #x1)))";
+    pub const VF: &'static str = "(OpNarrow1 (OpEq (OpAnd (OpLsr (OpAnd (OpXor (OpNot %2), %3), (OpXor %1, %2)), #x1f), #x1), #x1))";
+    pub const VF_64: &'static str = "(OpNarrow1 (OpEq (OpAnd (OpLsr (OpAnd (OpXor (OpNot %2), %3), (OpXor %1, %2)), #x3f), #x1), #x1))";
+    // ARM sets the carry flag when a subtraction does *not* borrow.
+    pub const CF: &'static str = "(OpNot (OpNarrow1 (OpLt %2, %1)))";
+
+    lazy_static! {
+        // Conditions are ordered so that a pattern is always tried before the patterns
+        // for its subexpressions, e.g. `le` before `lt` and `eq`.
+        pub static ref COMPARE_PATTERNS: Vec<(String, String)> = {
+            let mut v = Vec::new();
+            for &(w, zf, nf, vf) in &[(32, ZF, NF, VF), (64, ZF_64, NF_64, VF_64)] {
+                let sgt = format!("(OpSGt{} %2, %3)", w);
+                let slt = format!("(OpSLt{} %2, %3)", w);
+                let negative = format!("(OpSLt{} (OpSub %2, %3), #x0)", w);
+                let overflow = format!("(OpXor {}, {})", slt, negative);
+                // GT - zf,!,nf,vf,^,!,&
+                v.push((format!("(OpAnd (OpNot (OpXor {}, {})), (OpNot {}))", vf, nf, zf),
+                        sgt.clone()));
+                // LE - zf,nf,vf,^,|
+                v.push((format!("(OpOr (OpXor {}, {}), {})", vf, nf, zf),
+                        format!("(OpNot {})", sgt)));
+                // HI - cf,zf,!,&
+                v.push((format!("(OpAnd {}, (OpNot {}))", CF, zf), "(OpGt %2, %3)".to_owned()));
+                // LS - cf,!,zf,|
+                v.push((format!("(OpOr (OpNot {}), {})", CF, zf),
+                        "(OpNot (OpGt %2, %3))".to_owned()));
+                // GE - nf,vf,^,!
+                v.push((format!("(OpNot (OpXor {}, {}))", vf, nf), format!("(OpNot {})", slt)));
+                // LT - nf,vf,^
+                v.push((format!("(OpXor {}, {})", vf, nf), slt));
+                // NE - zf,!
+                v.push((format!("(OpNot {})", zf), "(OpNot (OpEq %2, %3))".to_owned()));
+                // EQ - zf
+                v.push((zf.to_owned(), "(OpEq %2, %3)".to_owned()));
+                // PL - nf,!
+                v.push((format!("(OpNot {})", nf), format!("(OpNot {})", negative)));
+                // MI - nf
+                v.push((nf.to_owned(), negative));
+                // VC - vf,!
+                v.push((format!("(OpNot {})", vf), format!("(OpNot {})", overflow)));
+                // VS - vf
+                v.push((vf.to_owned(), overflow));
+            }
+            // LO - cf,!
+            v.push((format!("(OpNot {})", CF), "(OpLt %2, %1)".to_owned()));
+            // HS - cf
+            v.push((CF.to_owned(), "(OpNot (OpLt %2, %1))".to_owned()));
+            v
+        };
+    }
+}
+
+pub fn replace<I, S>(ssa: &mut S)
+where I: Iterator<Item=S::ValueRef>,
+      S: SSA + SSAMod + SSAWalk<I> {
+    for &(ref find, ref replace) in patterns::COMPARE_PATTERNS.iter() {
+        grep_and_replace!(ssa, find => replace)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use frontend::ssaconstructor::SSAConstruct;
+    use middle::dce;
+    use middle::ir::{Endian, MOpcode};
+    use middle::ir_reader::test_util::{compared, shape};
+    use middle::regfile::{Arch, SubRegisterFile};
+    use middle::ssa::ssastorage::SSAStorage;
+    use r2api::structs::{LOpInfo, LRegInfo};
+    use serde_json;
+    use std::fs;
+
+    // `cmp` as recorded in `sym.max` of arm_file and arm64_file, with the profile of each.
+    const CMPS: &'static [(&'static str, Arch, &'static str, u16)] = &[
+        ("test_files/arm_file/arm_file_register_profile.json",
+         Arch::Arm,
+         "r1,r0,==,$z,zf,=,$s,nf,=,$b32,!,cf,=,$o,vf,=",
+         32),
+        ("test_files/arm64_file/arm64_file_register_profile.json",
+         Arch::AArch64,
+         "x1,x0,==,$z,zf,=,$s,nf,=,$b64,!,cf,=,$o,vf,=",
+         64),
+    ];
+
+    // Lifts `cmp` followed by a branch on `cond`, replaces the idioms and renders the
+    // condition over the compared registers.
+    fn condition(profile: &str, arch: Arch, cmp: &str, cond: &str) -> String {
+        let s = fs::read_to_string(profile).unwrap();
+        let reg_info: LRegInfo = serde_json::from_str(&s).unwrap();
+        let regfile = SubRegisterFile::new_for(&reg_info, arch, Endian::Little);
+        let ops = format!("[{{\"offset\":4096,\"size\":4,\"opcode\":\"cmp\",\"esil\":\"{}\"}},\
+                           {{\"offset\":4100,\"size\":4,\"opcode\":\"b\",\
+                           \"esil\":\"{},?{{,0x100c,pc,=,}}\"}}]",
+                          cmp,
+                          cond);
+        let ops: Vec<LOpInfo> = serde_json::from_str(&ops).unwrap();
+        let mut ssa = SSAStorage::new();
+        SSAConstruct::new(&mut ssa, &regfile).run(&ops);
+        dce::collect(&mut ssa);
+        let operands = compared(&ssa, 0x1000);
+        replace(&mut ssa);
+        let ite = ssa.values()
+            .into_iter()
+            .find(|&v| ssa.opcode(v) == Some(MOpcode::OpITE))
+            .expect("No conditional found");
+        shape(&ssa, operands, ssa.operands_of(ite)[0])
+    }
+
+    fn check(cond: &str, expected: &Fn(u16) -> String) {
+        for &(profile, arch, cmp, w) in CMPS {
+            assert_eq!(condition(profile, arch, cmp, cond), expected(w), "{} at {} bits", cond, w);
+        }
+    }
+
+    #[test]
+    fn eq_ne() {
+        check("zf", &|_| "(OpEq a b)".to_owned());
+        check("zf,!", &|_| "(OpNot (OpEq a b))".to_owned());
+    }
+
+    #[test]
+    fn hs_lo() {
+        check("cf", &|_| "(OpNot (OpLt a b))".to_owned());
+        check("cf,!", &|_| "(OpLt a b)".to_owned());
+    }
+
+    #[test]
+    fn hi_ls() {
+        check("cf,zf,!,&", &|_| "(OpGt a b)".to_owned());
+        check("cf,!,zf,|", &|_| "(OpNot (OpGt a b))".to_owned());
+    }
+
+    #[test]
+    fn mi_pl() {
+        check("nf", &|w| format!("(OpSLt({}) (OpSub a b) #x0)", w));
+        check("nf,!", &|w| format!("(OpNot (OpSLt({}) (OpSub a b) #x0))", w));
+    }
+
+    #[test]
+    fn vs_vc() {
+        check("vf", &|w| format!("(OpXor (OpSLt({0}) a b) (OpSLt({0}) (OpSub a b) #x0))", w));
+        check("vf,!",
+              &|w| format!("(OpNot (OpXor (OpSLt({0}) a b) (OpSLt({0}) (OpSub a b) #x0)))", w));
+    }
+
+    #[test]
+    fn ge_lt() {
+        check("nf,vf,^,!", &|w| format!("(OpNot (OpSLt({}) a b))", w));
+        check("nf,vf,^", &|w| format!("(OpSLt({}) a b)", w));
+    }
+
+    #[test]
+    fn gt_le() {
+        check("zf,!,nf,vf,^,!,&", &|w| format!("(OpSGt({}) a b)", w));
+        check("zf,nf,vf,^,|", &|w| format!("(OpNot (OpSGt({}) a b))", w));
+    }
+}
@@ -49,12 +49,15 @@ pub enum BTy {
     Int,
     Float,
     Char,
+    Short,
     Double,
     Void,
     /// Pointer to type `BTy`.
     Ptr(Box<BTy>),
     /// Array of type `BTy` and length.
     Array(Box<BTy>, u64),
+    /// Record of the given name.
+    Struct(String),
 }
 
 impl fmt::Display for Ty {
@@ -81,10 +84,12 @@ impl fmt::Display for BTy {
             BTy::Int => "int".to_owned(),
             BTy::Float => "float".to_owned(),
             BTy::Char => "char".to_owned(),
+            BTy::Short => "short".to_owned(),
             BTy::Double => "double".to_owned(),
             BTy::Void => "void".to_owned(),
-            BTy::Ptr(ref bty) => format!("*{}", bty),
+            BTy::Ptr(ref bty) => format!("{}*", bty),
             BTy::Array(ref bty, count) => format!("{}[{}]", bty, count - 1),
+            BTy::Struct(ref name) => format!("struct {}", name),
         };
         write!(f, "{}", result)
     }
@@ -119,9 +124,14 @@ pub enum Expr {
     Add,
     //Add field `SignExt`, `ZeroExt`, `Narrow`
     Cast(usize),
+    /// Conversion to the unsigned integer type of the given width in bits.
+    UnsignedCast(usize),
+    /// Conversion to the signed integer type of the given width in bits.
+    SignedCast(usize),
     Sub,
     Mul,
-    DeRef,
+    // Dereference of an access of the given width in bits, 0 if unknown
+    DeRef(usize),
     Div,
     Mod,
     Shr,
@@ -136,6 +146,16 @@ pub enum Expr {
     Lt,
     LtEq,
     Eq,
+    /// Reverse the byte order of a value of the given width in bits.
+    ByteSwap(usize),
+    /// Number of bits set in a value.
+    PopCount,
+    /// Field of the given name of the record a pointer points to.
+    Member(String),
+    /// Element of an array at an index.
+    Index,
+    /// Conversion to the given pointer type.
+    PointerCast(Ty),
 }
 
 #[derive(Clone, Debug)]
@@ -186,6 +206,24 @@ fn format_with_indent(string: &str, depth: usize) -> String {
     iter::repeat(INDENT_UNIT).take(depth * INDENT_SHIFT as usize).collect::<String>() + string
 }
 
+// Reverses the bytes of `value` with shifts and masks, for the widths without a
+// `__builtin_bswap`.
+fn swap_bytes(value: &str, size: usize) -> String {
+    let n = size / 8;
+    let bytes = (0..n).map(|i| {
+        let byte = if i == 0 {
+            format!("({} & 0xff)", value)
+        } else {
+            format!("(({} >> {}) & 0xff)", value, 8 * i)
+        };
+        match 8 * (n - 1 - i) {
+            0 => byte,
+            shift => format!("({} << {})", byte, shift),
+        }
+    });
+    format!("({})", bytes.collect::<Vec<_>>().join(" | "))
+}
+
 //////////////////////////////////////////////////////////////////////////////
 //// Implementation to manipulate the C AST effectively.
 //////////////////////////////////////////////////////////////////////////////
@@ -471,13 +509,37 @@ impl CAST {
                     Expr::Cast(size) => format!("({} as {})",
                                          format_with_indent(&op_str[0], indent),
                                          &size.to_string()),
+                    Expr::UnsignedCast(size) => match size {
+                        8 | 16 | 32 | 64 => format!("{}(uint{}_t){}",
+                                                    format_with_indent("", indent),
+                                                    size,
+                                                    &op_str[0]),
+                        128 => format!("{}(unsigned __int128){}",
+                                       format_with_indent("", indent),
+                                       &op_str[0]),
+                        _ => format_with_indent(&op_str[0], indent),
+                    },
+                    Expr::SignedCast(size) => match size {
+                        8 | 16 | 32 | 64 => format!("{}(int{}_t){}",
+                                                    format_with_indent("", indent),
+                                                    size,
+                                                    &op_str[0]),
+                        128 => format!("{}(__int128){}", format_with_indent("", indent), &op_str[0]),
+                        _ => format_with_indent(&op_str[0], indent),
+                    },
                     Expr::Sub => format!("({} - {})",
                                          format_with_indent(&op_str[0], indent),
                                          &op_str[1]),
                     Expr::Mul => format!("({} * {})",
                                          format_with_indent(&op_str[0], indent),
                                          &op_str[1]),
-                    Expr::DeRef => format!("{}*({})", format_with_indent("", indent), &op_str[0]),
+                    Expr::DeRef(size) => match size {
+                        8 | 16 | 32 | 64 => format!("{}*(uint{}_t *)({})",
+                                                    format_with_indent("", indent),
+                                                    size,
+                                                    &op_str[0]),
+                        _ => format!("{}*({})", format_with_indent("", indent), &op_str[0]),
+                    },
                     Expr::Div => format!("({} / {})",
                                          format_with_indent(&op_str[0], indent),
                                          &op_str[1]),
@@ -516,6 +578,26 @@ impl CAST {
                     Expr::Eq => format!("({} == {})",
                                          format_with_indent(&op_str[0], indent),
                                          &op_str[1]),
+                    Expr::ByteSwap(size) => match size {
+                        16 | 32 | 64 => format!("{}__builtin_bswap{}({})",
+                                                format_with_indent("", indent),
+                                                size,
+                                                &op_str[0]),
+                        _ => format_with_indent(&swap_bytes(&op_str[0], size), indent),
+                    },
+                    Expr::PopCount => format!("{}__builtin_popcountll({})",
+                                              format_with_indent("", indent),
+                                              &op_str[0]),
+                    Expr::Member(ref field) => format!("{}->{}",
+                                                       format_with_indent(&op_str[0], indent),
+                                                       field),
+                    Expr::Index => format!("{}[{}]",
+                                           format_with_indent(&op_str[0], indent),
+                                           &op_str[1]),
+                    Expr::PointerCast(ref ty) => format!("{}(({}){})",
+                                                         format_with_indent("", indent),
+                                                         ty,
+                                                         &op_str[0]),
                 }
             }
             CASTNode::Var(ref ident) => {
@@ -655,4 +737,68 @@ mod test {
         let _ = c_ast.goto(lbl_str);
         println!("{}", c_ast.print());
     }
+
+    #[test]
+    fn c_ast_unsigned_cast_test() {
+        let mut c_ast = CAST::new("main");
+        let vars = c_ast.declare_vars(Ty::new(BTy::Int, true, 0), &["i".to_owned(), "j".to_owned()], false);
+        let cast = c_ast.expr(Expr::UnsignedCast(32), &[vars[1]], true);
+        let _ = c_ast.expr(Expr::Assign, &[vars[0], cast], false);
+        assert!(c_ast.print().contains("i = (uint32_t)j"));
+    }
+
+    #[test]
+    fn c_ast_signed_cast_test() {
+        let mut c_ast = CAST::new("main");
+        let vars = c_ast.declare_vars(Ty::new(BTy::Int, false, 0), &["i".to_owned(), "j".to_owned()], false);
+        let lhs = c_ast.expr(Expr::SignedCast(64), &[vars[0]], true);
+        let rhs = c_ast.expr(Expr::SignedCast(64), &[vars[1]], true);
+        let lt = c_ast.expr(Expr::Lt, &[lhs, rhs], true);
+        let _ = c_ast.expr(Expr::Assign, &[vars[0], lt], false);
+        assert!(c_ast.print().contains("i = ((int64_t)i < (int64_t)j)"));
+    }
+
+    #[test]
+    fn c_ast_wide_cast_test() {
+        let mut c_ast = CAST::new("main");
+        let vars = c_ast.declare_vars(Ty::new(BTy::Int, false, 0), &["i".to_owned(), "j".to_owned()], false);
+        let cast = c_ast.expr(Expr::UnsignedCast(128), &[vars[1]], true);
+        let _ = c_ast.expr(Expr::Assign, &[vars[0], cast], false);
+        let cast = c_ast.expr(Expr::SignedCast(128), &[vars[1]], true);
+        let _ = c_ast.expr(Expr::Assign, &[vars[0], cast], false);
+        let code = c_ast.print();
+        assert!(code.contains("i = (unsigned __int128)j"));
+        assert!(code.contains("i = (__int128)j"));
+    }
+
+    #[test]
+    fn c_ast_pointer_cast_test() {
+        let mut c_ast = CAST::new("main");
+        let vars = c_ast.declare_vars(Ty::new(BTy::Int, false, 1), &["p".to_owned(), "i".to_owned()], false);
+        let cast = c_ast.expr(Expr::PointerCast(Ty::new_ptr(BTy::Short, false, 0)), &[vars[0]], true);
+        let index = c_ast.expr(Expr::Index, &[cast, vars[1]], true);
+        let _ = c_ast.expr(Expr::Assign, &[vars[1], index], false);
+        assert!(c_ast.print().contains("i = ((unsigned short*)p)[i]"));
+    }
+
+    #[test]
+    fn c_ast_byte_swap_test() {
+        let mut c_ast = CAST::new("main");
+        let vars = c_ast.declare_vars(Ty::new(BTy::Int, false, 0), &["x".to_owned()], false);
+        let swap = c_ast.expr(Expr::ByteSwap(32), &[vars[0]], true);
+        let _ = c_ast.expr(Expr::Assign, &[vars[0], swap], false);
+        let swap = c_ast.expr(Expr::ByteSwap(24), &[vars[0]], true);
+        let _ = c_ast.expr(Expr::Assign, &[vars[0], swap], false);
+        let code = c_ast.print();
+        assert!(code.contains("x = __builtin_bswap32(x)"));
+        assert!(code.contains("x = (((x & 0xff) << 16) | (((x >> 8) & 0xff) << 8) | ((x >> 16) & 0xff))"));
+        assert!(!code.contains("__builtin_bswap24"));
+    }
+
+    #[test]
+    fn c_ty_display_test() {
+        assert_eq!(Ty::new(BTy::Short, true, 0).to_string(), "short");
+        assert_eq!(Ty::new(BTy::Short, false, 0).to_string(), "unsigned short");
+        assert_eq!(Ty::new_ptr(BTy::Short, false, 0).to_string(), "unsigned short*");
+    }
 }
@@ -181,8 +181,18 @@ impl SimpleCAST {
             .map(|e| e.source())
     }
 
-    pub fn deref(&mut self, operand: NodeIndex) -> NodeIndex {
-        let node = self.ast.add_node(SimpleCASTNode::Value(ValueNode::Expression(c_simple::Expr::DeRef)));
+    /// Declared type of the variable `node`, if it has one.
+    pub fn var_type(&self, node: NodeIndex) -> Option<&Ty> {
+        match self.ast.node_weight(node) {
+            Some(&SimpleCASTNode::Value(ValueNode::Variable(ref ty, _))) => ty.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Dereference `operand` as a pointer to a `width` bits wide value.
+    pub fn deref(&mut self, operand: NodeIndex, width: u16) -> NodeIndex {
+        let expr = c_simple::Expr::DeRef(width as usize);
+        let node = self.ast.add_node(SimpleCASTNode::Value(ValueNode::Expression(expr)));
         let _ = self.ast.add_edge(node, operand, SimpleCASTEdge::Value(ValueEdge::DeRef));
         // Operand edge is needed so that CAST can evaluate a derefed node from this.
         let _ = self.ast.add_edge(node, operand, SimpleCASTEdge::Value(ValueEdge::Operand(0)));
@@ -190,6 +200,29 @@ impl SimpleCAST {
         node
     }
 
+    /// Access the field `field` of the record `base` points to at the address
+    /// `address`. Like a dereference of `address`, it is found by `derefed_node`.
+    pub fn member(&mut self, address: NodeIndex, base: NodeIndex, field: &str) -> NodeIndex {
+        let expr = c_simple::Expr::Member(field.to_owned());
+        let node = self.ast.add_node(SimpleCASTNode::Value(ValueNode::Expression(expr)));
+        let _ = self.ast.add_edge(node, address, SimpleCASTEdge::Value(ValueEdge::DeRef));
+        let _ = self.ast.add_edge(node, base, SimpleCASTEdge::Value(ValueEdge::Operand(0)));
+        self.exprs.push((true, node));
+        node
+    }
+
+    /// Access the element `index` of the array `base` at the address `address`. Like
+    /// a dereference of `address`, it is found by `derefed_node`.
+    pub fn index(&mut self, address: NodeIndex, base: NodeIndex, index: NodeIndex) -> NodeIndex {
+        let expr = c_simple::Expr::Index;
+        let node = self.ast.add_node(SimpleCASTNode::Value(ValueNode::Expression(expr)));
+        let _ = self.ast.add_edge(node, address, SimpleCASTEdge::Value(ValueEdge::DeRef));
+        let _ = self.ast.add_edge(node, base, SimpleCASTEdge::Value(ValueEdge::Operand(0)));
+        let _ = self.ast.add_edge(node, index, SimpleCASTEdge::Value(ValueEdge::Operand(1)));
+        self.exprs.push((true, node));
+        node
+    }
+
     /// Add ActionNode of assignment
     pub fn assign(&mut self, dst: NodeIndex, src: NodeIndex, prev_action: NodeIndex) -> NodeIndex {
         let node = self.ast.add_node(SimpleCASTNode::Action(ActionNode::Assignment));
@@ -932,7 +965,7 @@ mod test {
     //     int i;
     //     unsigned long int u;
     //     double d;
-    //     *float f;
+    //     float* f;
     //     char c;
     //     void v;
     // }
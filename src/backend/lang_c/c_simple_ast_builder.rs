@@ -5,8 +5,9 @@
 //! SimpleCAST and we can obtain higher level representation than Radeco IR.
 
 use std::collections::{HashMap, HashSet};
+use analysis::tie::structs::RType;
 use frontend::radeco_containers::RadecoFunction;
-use middle::ir::{MOpcode, MAddress};
+use middle::ir::{Endian, MOpcode, MAddress};
 use middle::ssa::utils;
 use middle::ssa::ssastorage::{NodeData, SSAStorage};
 use middle::ssa::ssa_traits::{SSA, SSAExtra, SSAMod, SSAWalk, ValueInfo};
@@ -14,6 +15,7 @@ use middle::ssa::cfg_traits::CFG;
 use super::c_simple_ast::{ValueNode, SimpleCAST, SimpleCASTEdge, ValueEdge, ActionEdge, ActionNode};
 use super::c_simple;
 use super::c_simple::Ty;
+use super::expr_prop;
 use petgraph::visit::EdgeRef;
 use petgraph::graph::{Graph, NodeIndex, EdgeIndex, Edges, EdgeReference};
 use petgraph::{EdgeDirection, Direction, Directed};
@@ -24,9 +26,17 @@ fn is_debug() -> bool {
 
 /// This constructs SimpleCAST from an instance of RadecoFunction.
 pub fn recover_simple_ast(rfn: &RadecoFunction) -> SimpleCAST {
+    recover_simple_ast_with_limit(rfn, expr_prop::DEFAULT_MAX_SIZE)
+}
+
+/// Same as `recover_simple_ast`, with no expression having more than `max_expr_size`
+/// operations. Values which are not folded into their use by `expr_prop` are
+/// assigned to temporaries.
+pub fn recover_simple_ast_with_limit(rfn: &RadecoFunction, max_expr_size: usize) -> SimpleCAST {
     let mut builder = CASTBuilder::new(rfn);
+    let folded = expr_prop::propagate(rfn, max_expr_size);
     // Recover values
-    let data_graph = CASTDataMap::recover_data(rfn, &mut builder.ast);
+    let data_graph = CASTDataMap::recover_data(rfn, &folded, &mut builder.ast);
     builder.datamap = data_graph;
     builder.declare_vars();
     // Recover control flow graph
@@ -108,12 +118,16 @@ impl<'a> CASTBuilder<'a> {
                 }
                 ret
             }
-            MOpcode::OpStore => {
+            MOpcode::OpStore(width, endian) => {
                 let ops = self.ssa.operands_of(node);
                 let dst = self.datamap.var_map.get(&ops[1]).map(|&x| {
                     self.ast.derefed_node(x).unwrap_or(x)
                 });
-                let src = self.datamap.var_map.get(&ops[2]).cloned();
+                let src = if let Some(&s) = self.datamap.var_map.get(&ops[2]) {
+                    Some(self.datamap.byte_swap(s, endian, width, &mut self.ast))
+                } else {
+                    None
+                };
                 let ret = if let (Some(d), Some(s)) = (dst, src) {
                     self.assign(d, s)
                 } else {
@@ -132,7 +146,7 @@ impl<'a> CASTBuilder<'a> {
     fn is_recover_action(&self, node: NodeIndex) -> bool {
         let op = self.ssa.opcode(node).unwrap_or(MOpcode::OpInvalid);
         match op {
-            MOpcode::OpCall | MOpcode::OpStore => true,
+            MOpcode::OpCall | MOpcode::OpStore(..) => true,
             _ => false,
         }
     }
@@ -227,7 +241,10 @@ impl<'a> CASTBuilder<'a> {
     fn cfg_from_nodes(&mut self, block: NodeIndex) {
         let nodes = self.ssa.nodes_in(block);
         for node in nodes {
-            if self.is_recover_action(node) {
+            let temp = self.datamap.temps.get(&node).cloned();
+            if let Some((var, expr)) = temp {
+                self.assign(var, expr);
+            } else if self.is_recover_action(node) {
                 let n = self.recover_action(node);
                 self.action_map.insert(node, n);
             }
@@ -258,6 +275,8 @@ struct CASTDataMap<'a> {
     // a map from node of data_graph to one of SimpleCAST's register
     pub reg_map: HashMap<String, NodeIndex>,
     pub const_nodes: HashSet<NodeIndex>,
+    // a map from node of SSAStorage to its temporary and the expression assigned to it
+    pub temps: HashMap<NodeIndex, (NodeIndex, NodeIndex)>,
     seen: HashSet<NodeIndex>,
 }
 
@@ -269,12 +288,14 @@ impl<'a> CASTDataMap<'a> {
             var_map: HashMap::new(),
             reg_map: HashMap::new(),
             const_nodes: HashSet::new(),
+            temps: HashMap::new(),
             seen: HashSet::new(),
         }
     }
 
     // Returns data map from SSAStorage's NodeIndex to SimpleCAST's NodeIndex
-    fn recover_data(rfn: &'a RadecoFunction, ast: &mut SimpleCAST) -> Self {
+    fn recover_data(rfn: &'a RadecoFunction, folded: &HashSet<NodeIndex>,
+                    ast: &mut SimpleCAST) -> Self {
         let mut s = Self::new(rfn);
         s.prepare_consts(ast);
         s.prepare_regs(ast);
@@ -283,11 +304,46 @@ impl<'a> CASTDataMap<'a> {
                 s.handle_phi(node);
             } else if s.ssa.is_expr(node) {
                 s.update_values(node, ast);
+                if !folded.contains(&node) {
+                    s.make_temp(node, ast);
+                }
             }
         }
         s
     }
 
+    // Values which are not folded into their use are assigned to a temporary, which
+    // their users read instead.
+    fn make_temp(&mut self, node: NodeIndex, ast: &mut SimpleCAST) {
+        if self.temps.contains_key(&node) || self.rfn.local_at(node).is_some()
+            || expr_prop::users(self.ssa, node).is_empty() {
+            return;
+        }
+        let expr = match self.var_map.get(&node) {
+            Some(&expr) => expr,
+            None => return,
+        };
+        // Casts of constants and loads of variables are their operand already.
+        if self.ssa.operands_of(node).iter().any(|op| self.var_map.get(op) == Some(&expr)) {
+            return;
+        }
+        let ty = match self.rfn.record_of(node) {
+            Some(record) => {
+                Some(Ty::new_ptr(c_simple::BTy::Struct(record.to_owned()), true, 0))
+            }
+            None => {
+                self.rfn
+                    .array_element_width(node)
+                    .and_then(Self::element_type)
+                    .or_else(|| self.rfn.type_of(node).and_then(Self::type_from_rtype))
+            }
+        };
+        let var = ast.var(&format!("tmp_{}", node.index()), ty);
+        radeco_trace!("Assign {:?} to temporary {:?}", node, var);
+        self.temps.insert(node, (var, expr));
+        self.var_map.insert(node, var);
+    }
+
     fn handle_binop(&mut self, ret_node: NodeIndex, ops: Vec<NodeIndex>,
                     expr: c_simple::Expr, ast: &mut SimpleCAST) {
         assert!(ops.len() == 2);
@@ -299,6 +355,46 @@ impl<'a> CASTDataMap<'a> {
         self.var_map.insert(ret_node, expr_node);
     }
 
+    // Signed operations compare or divide the operands as signed integers of `width` bits,
+    // which C only does for operands of a signed type.
+    fn handle_signed_binop(&mut self, ret_node: NodeIndex, ops: Vec<NodeIndex>, width: u16,
+                           expr: c_simple::Expr, ast: &mut SimpleCAST) {
+        assert!(ops.len() == 2);
+        let ops_mapped = ops.iter()
+            .map(|op| {
+                let n = self.var_map.get(op).map(|n| *n).unwrap_or(ast.unknown);
+                ast.expr(&[n], c_simple::Expr::SignedCast(width as usize))
+            })
+            .collect::<Vec<_>>();
+        let expr_node = ast.expr(ops_mapped.as_slice(), expr.clone());
+        radeco_trace!("Add {:?} to {:?}, Operator: {:?}", ret_node, expr_node, expr);
+        self.var_map.insert(ret_node, expr_node);
+    }
+
+    // C has no operator for the high half of a product, so it is emitted as
+    // `(uint{w}_t)(((uint{2w}_t)a * (uint{2w}_t)b) >> w)`, or with the signed types.
+    fn handle_mul_high(&mut self, ret_node: NodeIndex, ops: Vec<NodeIndex>, width: u16,
+                       signed: bool, ast: &mut SimpleCAST) {
+        assert!(ops.len() == 2);
+        let cast = |w: u16| if signed {
+            c_simple::Expr::SignedCast(w as usize)
+        } else {
+            c_simple::Expr::UnsignedCast(w as usize)
+        };
+        let ops_mapped = ops.iter()
+            .map(|op| {
+                let n = self.var_map.get(op).map(|n| *n).unwrap_or(ast.unknown);
+                ast.expr(&[n], cast(2 * width))
+            })
+            .collect::<Vec<_>>();
+        let product = ast.expr(ops_mapped.as_slice(), c_simple::Expr::Mul);
+        let shift = ast.constant(&width.to_string(), None);
+        let high = ast.expr(&[product, shift], c_simple::Expr::Shr);
+        let expr_node = ast.expr(&[high], cast(width));
+        radeco_trace!("Add {:?} to {:?}, Operator: MulHi{}", ret_node, expr_node, width);
+        self.var_map.insert(ret_node, expr_node);
+    }
+
     fn handle_uniop(&mut self, ret_node: NodeIndex, op: NodeIndex,
                     expr: c_simple::Expr, ast: &mut SimpleCAST) {
         if let Some(&n) = self.var_map.get(&op) {
@@ -309,6 +405,33 @@ impl<'a> CASTDataMap<'a> {
         }
     }
 
+    // C has no rotate operator, so `x rol n` is emitted as
+    // `(x << (n & (w - 1))) | (x >> ((w - n) & (w - 1)))` with `x` converted to the
+    // unsigned type of the width `w` of the result. The masks keep both shift amounts
+    // below `w`, which C leaves undefined otherwise, also for `n == 0`.
+    fn handle_rotate(&mut self, ret_node: NodeIndex, ops: Vec<NodeIndex>, left: bool,
+                     ast: &mut SimpleCAST) {
+        assert!(ops.len() == 2);
+        let width = self.ssa.node_data(ret_node).ok()
+            .and_then(|nd| nd.vt.width().get_width())
+            .unwrap_or(64);
+        let value = self.var_map.get(&ops[0]).cloned().unwrap_or(ast.unknown);
+        let value = ast.expr(&[value], c_simple::Expr::UnsignedCast(width as usize));
+        let amount = self.var_map.get(&ops[1]).cloned().unwrap_or(ast.unknown);
+        let width_node = ast.constant(&width.to_string(), None);
+        let rest = ast.expr(&[width_node, amount], c_simple::Expr::Sub);
+        let mask = ast.constant(&(width - 1).to_string(), None);
+        let amount = ast.expr(&[amount, mask], c_simple::Expr::And);
+        let mask = ast.constant(&(width - 1).to_string(), None);
+        let rest = ast.expr(&[rest, mask], c_simple::Expr::And);
+        let (l, r) = if left { (amount, rest) } else { (rest, amount) };
+        let shl = ast.expr(&[value, l], c_simple::Expr::Shl);
+        let shr = ast.expr(&[value, r], c_simple::Expr::Shr);
+        let expr_node = ast.expr(&[shl, shr], c_simple::Expr::Or);
+        radeco_trace!("Add {:?} to {:?}, Operator: rotate", ret_node, expr_node);
+        self.var_map.insert(ret_node, expr_node);
+    }
+
     fn handle_cast(&mut self, ret_node: NodeIndex, op: NodeIndex,
                     expr: c_simple::Expr, ast: &mut SimpleCAST) {
         if self.const_nodes.contains(&op) {
@@ -319,10 +442,40 @@ impl<'a> CASTDataMap<'a> {
         }
     }
 
-    fn deref(&self, node: NodeIndex, ast: &mut SimpleCAST) -> NodeIndex {
+    fn deref(&self, node: NodeIndex, width: u16, ast: &mut SimpleCAST) -> NodeIndex {
         radeco_trace!("DeRef {:?}", node);
         let n = self.var_map.get(&node).cloned().unwrap_or(ast.unknown);
-        ast.deref(n)
+        // Fields of records are accessed through the pointer to the record.
+        if let Some(access) = self.rfn.field_access(node) {
+            let base = self.var_map.get(&access.base).cloned().unwrap_or(ast.unknown);
+            return ast.member(n, base, &access.field);
+        }
+        // Elements of arrays are indexed from the start of the array, which is converted to
+        // a pointer to the elements unless it is declared as one.
+        if let Some(access) = self.rfn.array_access(node) {
+            if let Some(ty) = Self::element_type(access.element_width) {
+                let base = self.var_map.get(&access.base).cloned().unwrap_or(ast.unknown);
+                let base = if ast.var_type(base) == Some(&ty) {
+                    base
+                } else {
+                    ast.expr(&[base], c_simple::Expr::PointerCast(ty))
+                };
+                let index = self.var_map.get(&access.index).cloned().unwrap_or(ast.unknown);
+                return ast.index(n, base, index);
+            }
+        }
+        ast.deref(n, width)
+    }
+
+    // Memory accesses in the byte order of the architecture are emitted as they are,
+    // while the ones in the other byte order read or write a swapped value.
+    fn byte_swap(&self, node: NodeIndex, endian: Endian, width: u16,
+                 ast: &mut SimpleCAST) -> NodeIndex {
+        if endian != self.ssa.regfile.endian && width > 8 {
+            ast.expr(&[node], c_simple::Expr::ByteSwap(width as usize))
+        } else {
+            node
+        }
     }
 
     fn handle_phi(&mut self, node: NodeIndex) {
@@ -344,6 +497,42 @@ impl<'a> CASTDataMap<'a> {
         }
     }
 
+    // Pointer to the elements of an array of `width` bits wide elements.
+    fn element_type(width: u16) -> Option<Ty> {
+        match width {
+            8 => Some(Ty::new_ptr(c_simple::BTy::Char, false, 0)),
+            16 => Some(Ty::new_ptr(c_simple::BTy::Short, false, 0)),
+            32 => Some(Ty::new_ptr(c_simple::BTy::Int, false, 0)),
+            64 => Some(Ty::new_ptr(c_simple::BTy::Int, false, 1)),
+            _ => None,
+        }
+    }
+
+    // C type of a value of the inferred type `ty`, `None` if there is no matching one.
+    fn type_from_rtype(ty: &RType) -> Option<Ty> {
+        Self::base_type_from_rtype(ty).map(|(base, signed, long)| Ty::new(base, signed, long))
+    }
+
+    fn base_type_from_rtype(ty: &RType) -> Option<(c_simple::BTy, bool, u8)> {
+        let base = match *ty {
+            RType::Int8 => (c_simple::BTy::Char, true, 0),
+            RType::UInt8 => (c_simple::BTy::Char, false, 0),
+            RType::Int16 => (c_simple::BTy::Short, true, 0),
+            RType::UInt16 => (c_simple::BTy::Short, false, 0),
+            RType::Int32 | RType::Bool => (c_simple::BTy::Int, true, 0),
+            RType::UInt32 => (c_simple::BTy::Int, false, 0),
+            RType::Int64 => (c_simple::BTy::Int, true, 1),
+            RType::UInt64 => (c_simple::BTy::Int, false, 1),
+            RType::Ptr(ref inner) => {
+                let (base, signed, long) = Self::base_type_from_rtype(inner)
+                    .unwrap_or((c_simple::BTy::Void, true, 0));
+                (c_simple::BTy::Ptr(Box::new(base)), signed, long)
+            }
+            _ => return None,
+        };
+        Some(base)
+    }
+
     fn update_values(&mut self, ret_node: NodeIndex, ast: &mut SimpleCAST) {
         assert!(self.ssa.is_expr(ret_node));
         radeco_trace!("CASTBuilder::update_values {:?}", ret_node);
@@ -362,22 +551,22 @@ impl<'a> CASTDataMap<'a> {
 
         radeco_trace!("CASTBuilder::update_values opcode: {:?}", self.ssa.opcode(ret_node));
         match self.ssa.opcode(ret_node).unwrap_or(MOpcode::OpInvalid) {
-            MOpcode::OpStore => {
+            MOpcode::OpStore(width, _) => {
                 assert!(ops.len() == 3);
                 // Variables do not need Deref
                 if self.rfn.local_at(ops[1]).is_none() {
-                    self.deref(ops[1], ast);
+                    self.deref(ops[1], width, ast);
                 }
             }
-            MOpcode::OpLoad => {
+            MOpcode::OpLoad(width, endian) => {
                 // Variables do not need Deref
-                if self.rfn.local_at(ops[1]).is_none() {
-                    let deref_node = self.deref(ops[1], ast);
-                    self.var_map.insert(ret_node, deref_node);
+                let ast_node = if self.rfn.local_at(ops[1]).is_none() {
+                    self.deref(ops[1], width, ast)
                 } else {
-                    let ast_node = *self.var_map.get(&ops[1]).expect("This can not be `None`");
-                    self.var_map.insert(ret_node, ast_node);
-                }
+                    *self.var_map.get(&ops[1]).expect("This can not be `None`")
+                };
+                let ast_node = self.byte_swap(ast_node, endian, width, ast);
+                self.var_map.insert(ret_node, ast_node);
             }
             MOpcode::OpAdd => self.handle_binop(ret_node, ops, c_simple::Expr::Add, ast),
             MOpcode::OpAnd => self.handle_binop(ret_node, ops, c_simple::Expr::And, ast),
@@ -391,13 +580,26 @@ impl<'a> CASTDataMap<'a> {
             MOpcode::OpLt => self.handle_binop(ret_node, ops, c_simple::Expr::Lt, ast),
             MOpcode::OpMod => self.handle_binop(ret_node, ops, c_simple::Expr::Mod, ast),
             MOpcode::OpMul => self.handle_binop(ret_node, ops, c_simple::Expr::Mul, ast),
+            MOpcode::OpMulHi(width) => self.handle_mul_high(ret_node, ops, width, false, ast),
+            MOpcode::OpSMulHi(width) => self.handle_mul_high(ret_node, ops, width, true, ast),
+            MOpcode::OpSGt(width) => self.handle_signed_binop(ret_node, ops, width,
+                                                                c_simple::Expr::Gt, ast),
+            MOpcode::OpSLt(width) => self.handle_signed_binop(ret_node, ops, width,
+                                                                c_simple::Expr::Lt, ast),
+            MOpcode::OpSDiv(width) => self.handle_signed_binop(ret_node, ops, width,
+                                                                 c_simple::Expr::Div, ast),
+            MOpcode::OpSMod(width) => self.handle_signed_binop(ret_node, ops, width,
+                                                                 c_simple::Expr::Mod, ast),
             // TODO Add `Narrow` info
             MOpcode::OpNarrow(size) => self.handle_cast(ret_node, ops[0],
                                                          c_simple::Expr::Cast(size as usize), ast),
             MOpcode::OpNot => self.handle_uniop(ret_node, ops[0], c_simple::Expr::Not, ast),
             MOpcode::OpOr => self.handle_binop(ret_node, ops, c_simple::Expr::Or, ast),
-            MOpcode::OpRol => unimplemented!(),
-            MOpcode::OpRor => unimplemented!(),
+            MOpcode::OpRol => self.handle_rotate(ret_node, ops, true, ast),
+            MOpcode::OpRor => self.handle_rotate(ret_node, ops, false, ast),
+            MOpcode::OpBSwap(width) => self.handle_uniop(ret_node, ops[0],
+                                                         c_simple::Expr::ByteSwap(width as usize), ast),
+            MOpcode::OpPopCount => self.handle_uniop(ret_node, ops[0], c_simple::Expr::PopCount, ast),
             // TODO Add `SignExt`
             MOpcode::OpSignExt(size) => self.handle_cast(ret_node, ops[0],
                                                           c_simple::Expr::Cast(size as usize), ast),
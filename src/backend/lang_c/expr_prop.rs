@@ -0,0 +1,242 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Expression propagation for the C backend.
+//!
+//! Each value computed by the function becomes a temporary in the C output unless
+//! it is folded into the expression using it. A value is folded if it has no side
+//! effects and a single use by another operation. Loads are only folded within
+//! their block, and only if neither a call nor a store which may alias them
+//! according to `MemorySSA` lies between the load and the statement it is moved
+//! into.
+//!
+//! No statement is given an expression of more than `max_size` operations, the
+//! largest folded operands are made temporaries instead until it fits.
+
+use std::collections::{HashMap, HashSet};
+
+use r2api::structs::LVarInfo;
+
+use frontend::radeco_containers::RadecoFunction;
+use middle::ir::MOpcode;
+use middle::ssa::memoryssa::MemorySSA;
+use middle::ssa::ssa_traits::*;
+use middle::ssa::ssastorage::SSAStorage;
+
+type SSAValue = <SSAStorage as SSA>::ValueRef;
+
+/// Default limit on the number of operations in a single expression.
+pub const DEFAULT_MAX_SIZE: usize = 12;
+
+/// Returns the values of `rfn` which are folded into their use.
+pub fn propagate(rfn: &RadecoFunction, max_size: usize) -> HashSet<SSAValue> {
+    fold(rfn.ssa(), rfn.datarefs(), max_size)
+}
+
+/// Uses of `value` which are emitted, i.e. all but the register states.
+pub fn users(ssa: &SSAStorage, value: SSAValue) -> Vec<SSAValue> {
+    ssa.uses_of(value)
+       .into_iter()
+       .filter(|&u| ssa.is_phi(u) || ssa.opcode(u).is_some())
+       .collect()
+}
+
+fn fold(ssa: &SSAStorage, datarefs: &Vec<u64>, max_size: usize) -> HashSet<SSAValue> {
+    let mut folded = ssa.inorder_walk()
+                        .filter(|&v| is_foldable(ssa, v))
+                        .collect::<HashSet<_>>();
+    let may_aliases = may_aliases(ssa, datarefs);
+    let loads = folded.iter()
+                      .cloned()
+                      .filter(|&v| match ssa.opcode(v) {
+                          Some(MOpcode::OpLoad(..)) => true,
+                          _ => false,
+                      })
+                      .collect::<Vec<_>>();
+    for load in loads {
+        if is_clobbered(ssa, &folded, &may_aliases, load) {
+            radeco_trace!("expr_prop|{:?} is clobbered before its use", load);
+            folded.remove(&load);
+        }
+    }
+    let mut sizes = HashMap::new();
+    for value in ssa.inorder_walk() {
+        if ssa.opcode(value).is_some() {
+            limit_size(ssa, &mut folded, &mut sizes, value, max_size);
+        }
+    }
+    folded
+}
+
+fn is_foldable(ssa: &SSAStorage, value: SSAValue) -> bool {
+    match ssa.opcode(value) {
+        Some(MOpcode::OpConst(_)) |
+        Some(MOpcode::OpCall) |
+        Some(MOpcode::OpCustom(_)) |
+        Some(MOpcode::OpInvalid) |
+        Some(MOpcode::OpNop) |
+        None => return false,
+        Some(ref op) if op.has_sideeffects() => return false,
+        _ => {}
+    }
+    match users(ssa, value).as_slice() {
+        &[user] => !ssa.is_phi(user),
+        _ => false,
+    }
+}
+
+/// The statement a folded `value` is evaluated in, i.e. the first of its transitive
+/// users which is not folded.
+fn root(ssa: &SSAStorage, folded: &HashSet<SSAValue>, value: SSAValue) -> SSAValue {
+    let mut root = value;
+    while folded.contains(&root) {
+        root = users(ssa, root)[0];
+    }
+    root
+}
+
+/// Whether memory read by `load` may be written between the load and the statement
+/// it would be folded into.
+fn is_clobbered(ssa: &SSAStorage,
+                folded: &HashSet<SSAValue>,
+                may_aliases: &HashMap<SSAValue, HashSet<usize>>,
+                load: SSAValue)
+                -> bool {
+    let root = root(ssa, folded, load);
+    let block = match (ssa.block_for(load), ssa.block_for(root)) {
+        (Some(b), Some(rb)) if b == rb => b,
+        _ => return true,
+    };
+    let exprs = ssa.exprs_in(block);
+    let start = match exprs.iter().position(|&n| n == load) {
+        Some(start) => start,
+        None => return true,
+    };
+    // Branch conditions are evaluated at the end of the block.
+    let end = if ssa.selector_in(block) == Some(root) {
+        exprs.len()
+    } else {
+        match exprs.iter().position(|&n| n == root) {
+            Some(end) if end > start => end,
+            _ => return true,
+        }
+    };
+    exprs[start + 1..end].iter().any(|&n| match ssa.opcode(n) {
+        Some(MOpcode::OpCall) => true,
+        Some(MOpcode::OpStore(..)) => may_alias(may_aliases, load, n),
+        _ => false,
+    })
+}
+
+/// An access without known variables may alias anything.
+fn may_alias(may_aliases: &HashMap<SSAValue, HashSet<usize>>,
+             a: SSAValue,
+             b: SSAValue)
+             -> bool {
+    match (may_aliases.get(&a), may_aliases.get(&b)) {
+        (Some(va), Some(vb)) if !va.is_empty() && !vb.is_empty() => !va.is_disjoint(vb),
+        _ => true,
+    }
+}
+
+fn may_aliases(ssa: &SSAStorage, datarefs: &Vec<u64>) -> HashMap<SSAValue, HashSet<usize>> {
+    let calls = ssa.inorder_walk()
+                   .filter(|&n| ssa.opcode(n) == Some(MOpcode::OpCall))
+                   .collect::<Vec<_>>();
+    let mut mssa = MemorySSA::new(ssa);
+    mssa.gather_variables(datarefs, &Vec::<LVarInfo>::new(), &calls);
+    mssa.run();
+    mssa.may_aliases
+}
+
+/// Number of operations in the expression computing `value`. While it is larger
+/// than `max_size`, its largest folded operand is made a temporary.
+fn limit_size(ssa: &SSAStorage,
+              folded: &mut HashSet<SSAValue>,
+              sizes: &mut HashMap<SSAValue, usize>,
+              value: SSAValue,
+              max_size: usize)
+              -> usize {
+    if let Some(&size) = sizes.get(&value) {
+        return size;
+    }
+    let mut operands = Vec::new();
+    for operand in ssa.operands_of(value) {
+        if folded.contains(&operand) {
+            operands.push((limit_size(ssa, folded, sizes, operand, max_size), operand));
+        }
+    }
+    operands.sort();
+    let mut size = 1 + operands.iter().map(|&(s, _)| s).sum::<usize>();
+    while size > max_size {
+        match operands.pop() {
+            Some((s, operand)) => {
+                radeco_trace!("expr_prop|{:?} is too large, not folding {:?}", value, operand);
+                folded.remove(&operand);
+                size -= s;
+            }
+            None => break,
+        }
+    }
+    sizes.insert(value, size);
+    size
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir_reader::test_util::{at, parse};
+
+    // `%6` may be overwritten by the store `%8` before its use in `%11`, while
+    // nothing is stored between `%9` and its use.
+    const STORES: &'static str = "\
+define-fun main(unknown) -> unknown {
+    entry-register-state:
+        %1: $Unknown64 = $rdi;
+        %2: $Unknown64 = $rsi;
+        %3: $Unknown0 = $mem;
+    bb_0x001000.0000(sz 0x14):
+        [@0x001000.0000] %4: $Unknown64 = %1 + #x8;
+        [@0x001000.0001] %5: $Unknown64 = %4 * %2;
+        [@0x001004.0000] %6: $Unknown64 = Load64(%3, %1);
+        [@0x001004.0001] %7: $Unknown64 = %6 ^ %2;
+        [@0x001008.0000] %8: $Unknown0 = Store64(%3, %2, %5);
+        [@0x00100C.0000] %9: $Unknown64 = Load64(%8, %2);
+        [@0x00100C.0001] %10: $Unknown64 = %9 & %7;
+        [@0x001010.0000] %11: $Unknown0 = Store64(%8, %1, %10);
+        JMP TO %1
+    exit-node:
+    final-register-state:
+        $mem = %11;
+}
+";
+
+    #[test]
+    fn fold_loads_test() {
+        let ssa = parse(STORES);
+        let folded = fold(&ssa, &Vec::new(), DEFAULT_MAX_SIZE);
+        assert!(folded.contains(&at(&ssa, 0x1000, 0)));
+        assert!(folded.contains(&at(&ssa, 0x1000, 1)));
+        assert!(!folded.contains(&at(&ssa, 0x1004, 0)));
+        assert!(folded.contains(&at(&ssa, 0x1004, 1)));
+        assert!(folded.contains(&at(&ssa, 0x100C, 0)));
+        assert!(folded.contains(&at(&ssa, 0x100C, 1)));
+        assert_eq!(folded.len(), 5);
+    }
+
+    #[test]
+    fn size_limit_test() {
+        let ssa = parse(STORES);
+        let folded = fold(&ssa, &Vec::new(), 2);
+        // Folding `%5` or `%10` would give the stores three operations.
+        assert!(folded.contains(&at(&ssa, 0x1000, 0)));
+        assert!(!folded.contains(&at(&ssa, 0x1000, 1)));
+        assert!(!folded.contains(&at(&ssa, 0x100C, 1)));
+        assert_eq!(folded.len(), 2);
+        assert!(fold(&ssa, &Vec::new(), 1).is_empty());
+    }
+}
@@ -9,3 +9,4 @@ pub mod c_simple;
 pub mod c_simple_ast;
 pub mod c_simple_ast_builder;
 pub mod c_ast_constructor;
+pub mod expr_prop;
@@ -1,6 +1,15 @@
 //! This module contains common x86_64 idioms as grep and replace patterns
 //! which maybe used for further reduction of the SSA form.
 //!
+//! The condition codes used by `Jcc`, `SETcc` and `CMOVcc` are lifted as boolean
+//! combinations of the flags written by the last `cmp`, `sub` or `test`. For every
+//! condition, and every operand size, these combinations are recognized and replaced
+//! by a single comparison of the original operands. Parity has no such equivalent and
+//! is left as is.
+//!
+//! `cmp` and `sub` compute the overflow flag while `test` clears it, so the signed
+//! conditions are recognised after any of them.
+//!
 //! NOTE: This module is not stable.
 
 use analysis::matcher::gmatch;
@@ -8,6 +17,8 @@ use middle::ssa::ssa_traits::{SSAMod, SSAWalk, SSA};
 
 #[allow(dead_code)]
 mod patterns {
+    use analysis::matcher::gmatch::Pattern;
+
     pub const OF: &'static str = "(OpNarrow1 (OpEq (OpAnd (OpLsr (OpAnd (OpXor (OpNot %2), %3), (OpXor %1, %2)), #x3f), #x1), #x1))";
     pub const PF: &'static str = "(OpNarrow1 (OpAnd (OpMod (OpAnd (OpMul (OpAnd %1, #xff), #x101010101010101), #x8040201008040201), #x1ff), #x1))";
     pub const SF: &'static str = "(OpNarrow1 (OpLsr (OpSub %2, %3), (OpSub #x40, #x1)))";
@@ -17,6 +28,9 @@ mod patterns {
     pub const ZF_32: &'static str = "(OpNarrow1 (OpXor #x1, (OpAnd (OpSub %2, %3), #xffffffff)))";
     pub const BF: &'static str = "(OpNarrow1 (OpLt %2, %1))";
 
+    /// Operand sizes of `cmp`, `sub` and `test`.
+    pub const WIDTHS: &'static [u16] = &[8, 16, 32, 64];
+
     pub const PATTERNS: &'static [(&'static str, &'static str)] = &[
         ("(OpXor %1, %1)", "#x0"),
         ("(OpXor %1, #x0)", "%1"),
@@ -27,14 +41,85 @@ mod patterns {
         ("(OpSub %1, %1)", "#x0"),
     ];
 
+    // In all of the flags below `(OpSub %2, %3)` is the difference computed by
+    // `cmp %2, %3`, so the replacements compare `%2` against `%3`.
+
+    pub fn zf(width: u16) -> String {
+        let mask = if width == 64 { !0 } else { (1u64 << width) - 1 };
+        format!("(OpNarrow1 (OpXor #x1, (OpAnd (OpSub %2, %3), #x{:x})))", mask)
+    }
+
+    pub fn sf(width: u16) -> String {
+        format!("(OpNarrow1 (OpLsr (OpSub %2, %3), (OpSub #x{:x}, #x1)))", width)
+    }
+
+    /// The overflow flag as computed by `cmp`/`sub`, followed by the constant it is
+    /// folded to after `test`, `and` and friends.
+    pub fn of(width: u16) -> Vec<String> {
+        vec![format!("(OpNarrow1 (OpEq (OpAnd (OpLsr (OpAnd (OpXor (OpNot %2), %3), (OpXor %1, %2)), #x{:x}), #x1), #x1))",
+                     width - 1),
+             "(OpNarrow1 #x0)".to_owned(),
+             "#x0".to_owned()]
+    }
+
+    // Conditions are ordered so that a pattern is always tried before the patterns
+    // for its subexpressions, e.g. `le` before `l` and `e`. The matcher takes care of
+    // the operand order of `OpAnd`, `OpOr` and `OpXor`.
+    fn compare_patterns() -> Vec<(String, String)> {
+        let mut v = Vec::new();
+        for &w in WIDTHS {
+            let (zf, sf) = (zf(w), sf(w));
+            let sgt = format!("(OpSGt{} %2, %3)", w);
+            let slt = format!("(OpSLt{} %2, %3)", w);
+            for of in of(w) {
+                let lt = format!("(OpXor {}, {})", sf, of);
+                // `cmovge` and `cmovg` test the flags for equality, `sf,of,==`.
+                let ge = vec![format!("(OpXor {}, (OpNot {}))", sf, of),
+                              format!("(OpNot {})", lt),
+                              format!("(OpEq {}, {})", sf, of)];
+
+                // G - sf,of,!,^,zf,!,&
+                for g in &ge {
+                    v.push((format!("(OpAnd (OpNot {}), {})", zf, g), sgt.clone()));
+                }
+                // LE - of,sf,^,zf,|
+                v.push((format!("(OpOr {}, {})", zf, lt), format!("(OpNot {})", sgt)));
+                // GE - of,!,sf,^
+                for g in ge {
+                    v.push((g, format!("(OpNot {})", slt)));
+                }
+                // L - of,sf,^
+                v.push((lt, slt.clone()));
+            }
+
+            // A - cf,zf,|,!
+            v.push((format!("(OpNot (OpOr {}, {}))", zf, BF), "(OpGt %2, %3)".to_owned()));
+            v.push((format!("(OpAnd (OpNot {}), (OpNot {}))", zf, BF), "(OpGt %2, %3)".to_owned()));
+            // BE - cf,zf,|
+            v.push((format!("(OpOr {}, {})", zf, BF), "(OpNot (OpGt %2, %3))".to_owned()));
+
+            // O and NO, only when the overflow was actually computed.
+            let overflow = format!("(OpXor {}, (OpSLt{} (OpSub %2, %3), #x0))", slt, w);
+            let of = of(w).swap_remove(0);
+            v.push((format!("(OpNot {})", of), format!("(OpNot {})", overflow)));
+            v.push((of, overflow));
+
+            // NE, NS, E and S.
+            v.push((format!("(OpNot {})", zf), "(OpNot (OpEq %2, %3))".to_owned()));
+            v.push((format!("(OpNot {})", sf), format!("(OpNot (OpSLt{} (OpSub %2, %3), #x0))", w)));
+            v.push((zf, "(OpEq %2, %3)".to_owned()));
+            v.push((sf, format!("(OpSLt{} (OpSub %2, %3), #x0)", w)));
+        }
+        // AE and B, the borrow already is an unsigned comparison.
+        v.push((format!("(OpNot {})", BF), "(OpNot (OpLt %2, %1))".to_owned()));
+        v.push((BF.to_owned(), "(OpLt %2, %1)".to_owned()));
+        v
+    }
+
     lazy_static! {
-        pub static ref COMPARE_PATTERNS: Vec<(String, &'static str)> = {
-            let mut v = Vec::new();
-            // LE -  of,sf,^,zf,|
-            v.push((format!("(OpOr {}, (OpXor {}, (OpNarrow1 #x0)))", ZF, SF), "(OpLt %2, %3)"));
-            v.push((format!("(OpOr {}, (OpXor {}, #x0))", ZF_32, SF_32), "(OpLt %2, %3)"));
-            v.push((format!("{}", ZF), "(OpEq %2, %3)"));
-            v
+        pub static ref COMPARE_PATTERNS: Vec<(Pattern, Pattern)> = {
+            let compile = |p: &str| Pattern::compile(p).expect("Invalid condition pattern");
+            compare_patterns().iter().map(|&(ref f, ref r)| (compile(f), compile(r))).collect()
         };
     }
 }
@@ -42,10 +127,258 @@ mod patterns {
 pub fn replace<I, S>(ssa: &mut S)
 where I: Iterator<Item=S::ValueRef>,
       S: SSA + SSAMod + SSAWalk<I> {
+    // Conditions go first, the simplifications below would break up the flags.
+    for &(ref find, ref replace) in patterns::COMPARE_PATTERNS.iter() {
+        let mut matcher = gmatch::GraphMatcher::new(ssa);
+        for m in matcher.grep_pattern(find) {
+            matcher.replace_pattern(m, replace);
+        }
+    }
     for pat in patterns::PATTERNS {
         grep_and_replace!(ssa, pat.0 => pat.1)
     }
-    for &(ref find, replace) in patterns::COMPARE_PATTERNS.iter() {
-        grep_and_replace!(ssa, find => replace)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use frontend::ssaconstructor::SSAConstruct;
+    use middle::dce;
+    use middle::ir::MOpcode;
+    use middle::ir_reader::test_util::{compared, register_profile, shape};
+    use middle::regfile::SubRegisterFile;
+    use middle::ssa::ssastorage::SSAStorage;
+    use r2api::structs::LOpInfo;
+    use serde_json;
+    use std::fs;
+
+    type SSAValue = <SSAStorage as SSA>::ValueRef;
+    type Insn = (&'static str, &'static str);
+
+    // Flag updates as radare2 lifts them, recorded in bin_file and ct1_sccp_ex. None of
+    // them has a 16-bit `cmp`, `CMP16` is written in the same form. `test` clears the
+    // overflow flag.
+    const CMP8: Insn = ("cmp byte [r12], 0x20",
+                        "32,r12,[1],==,$z,zf,=,$b8,cf,=,$p,pf,=,$s,sf,=,$o,of,=");
+    const CMP16: Insn = ("cmp word [rdi], 0x2a",
+                         "42,rdi,[2],==,$z,zf,=,$b16,cf,=,$p,pf,=,$s,sf,=,$o,of,=");
+    const CMP32: Insn = ("cmp dword [rbp - local_ch], 0x18",
+                         "24,0xc,rbp,-,[4],==,$z,zf,=,$b32,cf,=,$p,pf,=,$s,sf,=,$o,of,=");
+    const CMP64: Insn = ("cmp qword [rbp - local_8h], 0xa",
+                         "10,0x8,rbp,-,[8],==,$z,zf,=,$b64,cf,=,$p,pf,=,$s,sf,=,$o,of,=");
+    const TEST8: Insn =
+        ("test al, al", "0,al,al,&,==,$z,zf,=,$p,pf,=,$s,sf,=,$0,cf,=,$0,of,=");
+    const TEST16: Insn =
+        ("test dx, dx", "0,dx,dx,&,==,$z,zf,=,$p,pf,=,$s,sf,=,$0,cf,=,$0,of,=");
+    const TEST32: Insn =
+        ("test r10d, r10d", "0,r10d,r10d,&,==,$z,zf,=,$p,pf,=,$s,sf,=,$0,cf,=,$0,of,=");
+    const TEST64: Insn =
+        ("test rax, rax", "0,rax,rax,&,==,$z,zf,=,$p,pf,=,$s,sf,=,$0,cf,=,$0,of,=");
+
+    // Every flag update with its operand size, for the signed conditions.
+    const FLAGS: &'static [(Insn, u16)] = &[(CMP8, 8), (CMP16, 16), (CMP32, 32), (CMP64, 64),
+                                            (TEST8, 8), (TEST16, 16), (TEST32, 32), (TEST64, 64)];
+
+    fn lift(insts: &[(&str, &str)]) -> SSAStorage {
+        let regfile = SubRegisterFile::new(&register_profile());
+        let ops = insts.iter()
+            .enumerate()
+            .map(|(i, &(opcode, esil))| {
+                format!("{{\"offset\":{},\"size\":4,\"opcode\":\"{}\",\"esil\":\"{}\"}}",
+                        0x1000 + 4 * i, opcode, esil)
+            })
+            .collect::<Vec<_>>();
+        let ops: Vec<LOpInfo> = serde_json::from_str(&format!("[{}]", ops.join(","))).unwrap();
+        let mut ssa = SSAStorage::new();
+        SSAConstruct::new(&mut ssa, &regfile).run(&ops);
+        dce::collect(&mut ssa);
+        ssa
+    }
+
+    // The instruction at `offset` in the recorded function `insts`, as `(opcode, esil)`.
+    fn recorded(insts: &str, offset: u64) -> (String, String) {
+        let s = fs::read_to_string(insts).unwrap();
+        let ops: Vec<LOpInfo> = serde_json::from_str(&s).unwrap();
+        let op = ops.into_iter()
+            .find(|op| op.offset == Some(offset))
+            .expect("No instruction found");
+        (op.opcode.unwrap(), op.esil.unwrap())
+    }
+
+    // Lifts `flags` followed by the conditional `insn`, replaces the idioms and returns
+    // every expression rendered over the compared operands.
+    fn lift_and_replace(flags: (&str, &str), insn: (&str, &str)) -> (SSAStorage, (SSAValue, SSAValue)) {
+        let mut ssa = lift(&[flags, insn, ("mov ecx, 0", "0,rcx,="), ("mov ecx, 1", "1,rcx,=")]);
+        let operands = compared(&ssa, 0x1000);
+        replace(&mut ssa);
+        (ssa, operands)
+    }
+
+    // The branch condition of a `Jcc` or `CMOVcc`.
+    fn condition(ssa: &SSAStorage, operands: (SSAValue, SSAValue)) -> String {
+        let ite = ssa.values()
+            .into_iter()
+            .find(|&v| ssa.opcode(v) == Some(MOpcode::OpITE))
+            .expect("No conditional found");
+        shape(ssa, operands, ssa.operands_of(ite)[0])
+    }
+
+    fn check_jcc(flags: Insn, jcc: &str, expected: &str) {
+        let esil = format!("{},?{{,4108,rip,=,}}", jcc);
+        let (ssa, operands) = lift_and_replace(flags, ("jcc 0x100c", &esil));
+        assert_eq!(condition(&ssa, operands), expected, "{} after {}", jcc, flags.0);
+    }
+
+    fn check_setcc(flags: Insn, setcc: &str, expected: &str) {
+        let esil = format!("{},cl,=", setcc);
+        let (ssa, operands) = lift_and_replace(flags, ("setcc cl", &esil));
+        let shapes = ssa.values()
+            .into_iter()
+            .map(|v| shape(&ssa, operands, v))
+            .collect::<Vec<_>>();
+        assert!(shapes.iter().any(|s| s == expected),
+                "{} after {}: {} not in {:?}",
+                setcc,
+                flags.0,
+                expected,
+                shapes);
+    }
+
+    #[test]
+    fn je_64() {
+        check_jcc(CMP64, "zf", "(OpEq a b)");
+    }
+
+    #[test]
+    fn jne_32() {
+        check_jcc(CMP32, "zf,!", "(OpNot (OpEq a b))");
+    }
+
+    #[test]
+    fn jb_32() {
+        check_jcc(CMP32, "cf", "(OpLt a b)");
+    }
+
+    #[test]
+    fn jae_16() {
+        check_jcc(CMP16, "cf,!", "(OpNot (OpLt a b))");
+    }
+
+    #[test]
+    fn jbe_8() {
+        check_jcc(CMP8, "cf,zf,|", "(OpNot (OpGt a b))");
+    }
+
+    #[test]
+    fn ja_64() {
+        check_jcc(CMP64, "cf,zf,|,!", "(OpGt a b)");
+    }
+
+    #[test]
+    fn jl() {
+        for &(flags, w) in FLAGS {
+            check_jcc(flags, "of,sf,^", &format!("(OpSLt({}) a b)", w));
+        }
+    }
+
+    #[test]
+    fn jge() {
+        for &(flags, w) in FLAGS {
+            check_jcc(flags, "of,!,sf,^", &format!("(OpNot (OpSLt({}) a b))", w));
+        }
+    }
+
+    #[test]
+    fn jle() {
+        for &(flags, w) in FLAGS {
+            check_jcc(flags, "of,sf,^,zf,|", &format!("(OpNot (OpSGt({}) a b))", w));
+        }
+    }
+
+    #[test]
+    fn jg() {
+        for &(flags, w) in FLAGS {
+            check_jcc(flags, "sf,of,!,^,zf,!,&", &format!("(OpSGt({}) a b)", w));
+        }
+    }
+
+    #[test]
+    fn js() {
+        for &(flags, w) in FLAGS {
+            check_jcc(flags, "sf", &format!("(OpSLt({}) (OpSub a b) #x0)", w));
+        }
+    }
+
+    #[test]
+    fn jns() {
+        for &(flags, w) in FLAGS {
+            check_jcc(flags, "sf,!", &format!("(OpNot (OpSLt({}) (OpSub a b) #x0))", w));
+        }
+    }
+
+    #[test]
+    fn jp_is_kept() {
+        let (ssa, operands) = lift_and_replace(CMP32, ("jp 0x100c", "pf,?{,4108,rip,=,}"));
+        assert!(condition(&ssa, operands).starts_with("(OpNarrow(1) "));
+    }
+
+    #[test]
+    fn cmovge() {
+        let insts = "test_files/bin_file/bin_file_insts_0x2B80.json";
+        let (cmp, cmovge) = (recorded(insts, 0x2b80), recorded(insts, 0x2ba6));
+        let (ssa, operands) = lift_and_replace((&cmp.0, &cmp.1), (&cmovge.0, &cmovge.1));
+        assert_eq!(condition(&ssa, operands), "(OpNot (OpSLt(32) a b))");
+    }
+
+    #[test]
+    fn cmovg() {
+        let insts = "test_files/bin_file/bin_file_insts_0x2AB0.json";
+        let (test, cmovg) = (recorded(insts, 0x2b3f), recorded(insts, 0x2b41));
+        let (ssa, operands) = lift_and_replace((&test.0, &test.1), (&cmovg.0, &cmovg.1));
+        assert_eq!(condition(&ssa, operands), "(OpSGt(64) a b)");
+    }
+
+    #[test]
+    fn cmovae_64() {
+        let (ssa, operands) = lift_and_replace(CMP64, ("cmovae rax, rbx", "cf,!,?{,rbx,rax,=,}"));
+        assert_eq!(condition(&ssa, operands), "(OpNot (OpLt a b))");
+    }
+
+    #[test]
+    fn sete_16() {
+        check_setcc(CMP16, "zf", "(OpEq a b)");
+    }
+
+    #[test]
+    fn seta_32() {
+        check_setcc(CMP32, "cf,zf,|,!", "(OpGt a b)");
+    }
+
+    #[test]
+    fn setl() {
+        for &(flags, w) in FLAGS {
+            check_setcc(flags, "sf,of,^", &format!("(OpSLt({}) a b)", w));
+        }
+    }
+
+    #[test]
+    fn setge() {
+        for &(flags, w) in FLAGS {
+            check_setcc(flags, "sf,of,^,!", &format!("(OpNot (OpSLt({}) a b))", w));
+        }
+    }
+
+    #[test]
+    fn setle() {
+        for &(flags, w) in FLAGS {
+            check_setcc(flags, "zf,sf,of,^,|", &format!("(OpNot (OpSGt({}) a b))", w));
+        }
+    }
+
+    #[test]
+    fn setg() {
+        for &(flags, w) in FLAGS {
+            check_setcc(flags, "zf,!,sf,of,^,!,&", &format!("(OpSGt({}) a b)", w));
+        }
     }
 }
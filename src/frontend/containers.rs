@@ -276,12 +276,12 @@ fn analyze_memory(rfn: &mut DefaultFnTy) {
             while let Some(node) = wl.pop() {
                 let data = ssa.node_data(node).map(|x| x.nt);
                 match data {
-                    Ok(NodeType::Op(MOpcode::OpLoad)) |
-                    Ok(NodeType::Op(MOpcode::OpStore)) => {
+                    Ok(NodeType::Op(MOpcode::OpLoad(..))) |
+                    Ok(NodeType::Op(MOpcode::OpStore(..))) => {
                         let args = ssa.operands_of(node);
                         // If operation is a store it will produce a new memory instance. Hence,
                         // push all uses of new memory to the worklist.
-                        if let Ok(NodeType::Op(MOpcode::OpStore)) = data {
+                        if let Ok(NodeType::Op(MOpcode::OpStore(..))) = data {
                             wl.extend(&ssa.uses_of(node));
                         }
                         let mem_loc = args.get(1)
@@ -6,6 +6,8 @@ use std::borrow::Cow;
 
 use capstone_rust::capstone as cs;
 
+use middle::ir::InstructionSet;
+
 // TODO: Register must be replaced by Register information from arch-rs.
 // This will be a part of a bigger rewrite/refactor.
 /// Describes operands for an instruction
@@ -19,6 +21,8 @@ pub enum IOperand {
         index: Option<String>,
         scale: i32,
         disp: i64,
+        /// Width of the access in bits
+        size: u16,
     },
     Other,
     Invalid,
@@ -70,7 +74,7 @@ pub trait InstructionAnalyzer: Sized {
     /// Return if this instruction reads from memory
     fn has_memory_read(&self) -> bool {
         self.info().expect("Unable to get InstructionInfo").reads.iter().any(|x| match x { 
-            &IOperand::Memory { base: _, index: _, scale: _, disp: _ } => true,
+            &IOperand::Memory { base: _, index: _, scale: _, disp: _, size: _ } => true,
             _ => false,
         })
     }
@@ -78,7 +82,7 @@ pub trait InstructionAnalyzer: Sized {
     /// Return if this instruction writes to memory
     fn has_memory_written(&self) -> bool {
         self.info().expect("Unable to get InstructionInfo").writes.iter().any(|x| match x { 
-            &IOperand::Memory { base: _, index: _, scale: _, disp: _ } => true,
+            &IOperand::Memory { base: _, index: _, scale: _, disp: _, size: _ } => true,
             _ => false,
         })
     }
@@ -86,7 +90,7 @@ pub trait InstructionAnalyzer: Sized {
     /// Returns memory written to by the instruction, in standard sib form
     fn memory_written(&self) -> Option<&IOperand> {
         self.info().expect("Unable to get InstructionInfo").writes.iter().find(|&x| match x {
-            &IOperand::Memory { base: _, index: _, scale: _, disp: _ } => true,
+            &IOperand::Memory { base: _, index: _, scale: _, disp: _, size: _ } => true,
             _ => false,
         })
     }
@@ -94,7 +98,7 @@ pub trait InstructionAnalyzer: Sized {
     /// Returns memory read by the instruction, in standard sib form
     fn memory_read(&self) -> Option<&IOperand> {
         self.info().expect("Unable to get InstructionInfo").reads.iter().find(|&x| match x {
-            &IOperand::Memory { base: _, index: _, scale: _, disp: _ } => true,
+            &IOperand::Memory { base: _, index: _, scale: _, disp: _, size: _ } => true,
             _ => false,
         })
     }
@@ -156,6 +160,7 @@ impl X86_CS_IA {
                                 index: index,
                                 scale: scale,
                                 disp: disp,
+                                size: op.size as u16 * 8,
                             }
                         },
                         cs::x86_op_type::X86_OP_IMM => IOperand::Immediate(op.imm()),
@@ -192,3 +197,277 @@ impl InstructionAnalyzer for X86_CS_IA {
         Ok(&self.info)
     }
 }
+
+/// Condition codes which capstone appends to the mnemonic of a conditional ARM instruction.
+const ARM_CONDITIONS: [&'static str; 17] = ["eq", "ne", "cs", "hs", "cc", "lo", "mi", "pl", "vs",
+                                             "vc", "hi", "ls", "ge", "lt", "gt", "le", "al"];
+
+/// Width in bits of the memory access done by an ARM/AArch64 load or store, as encoded in
+/// its mnemonic. `transfer` is the register moved to or from memory.
+fn arm_access_width(mnemonic: &str, transfer: Option<&IOperand>, default: u16) -> u16 {
+    let suffix = if mnemonic.starts_with("ldr") || mnemonic.starts_with("str") {
+        &mnemonic[3..]
+    } else {
+        return default;
+    };
+    let suffix = suffix.trim_end_matches(".w").trim_end_matches(".n");
+    // The condition follows the size (`ldrhhs`), and no size ends in one, so a trailing
+    // condition code is never part of the size (`ldrhs` is a conditional word load).
+    let suffix = ARM_CONDITIONS.iter()
+                               .find(|c| suffix.ends_with(*c))
+                               .map_or(suffix, |c| &suffix[..suffix.len() - c.len()]);
+    if suffix.starts_with("sw") {
+        32
+    } else if suffix.starts_with("b") || suffix.starts_with("sb") {
+        8
+    } else if suffix.starts_with("h") || suffix.starts_with("sh") {
+        16
+    } else if suffix.starts_with("d") {
+        64
+    } else {
+        match transfer {
+            Some(&IOperand::Register(ref r)) if r.starts_with('x') => 64,
+            Some(&IOperand::Register(ref r)) if r.starts_with('w') => 32,
+            _ => default,
+        }
+    }
+}
+
+/// Sorts the operands of an ARM/AArch64 instruction into reads and writes. Unlike x86, the
+/// direction depends on the kind of instruction rather than on the operand position alone.
+fn arm_assign_operands(info: &mut InstructionInfo, operands: Vec<IOperand>, default_width: u16) {
+    let (stores, loads, reads_only) = {
+        let m: &str = &info.mnemonic;
+        let any = |p: &[&str]| p.iter().any(|x| m.starts_with(x));
+        (any(&["str", "stm", "stp", "push"]),
+         any(&["ldr", "ldm", "ldp", "pop"]),
+         any(&["cmp", "cmn", "tst", "teq", "cb", "tb", "ret"]) ||
+         (m.starts_with('b') && !any(&["bic", "bf"])))
+    };
+    let width = arm_access_width(&info.mnemonic, operands.first(), default_width);
+    for (i, op) in operands.into_iter().enumerate() {
+        let op = match op {
+            IOperand::Memory { base, index, scale, disp, .. } => {
+                let mem = IOperand::Memory {
+                    base: base,
+                    index: index,
+                    scale: scale,
+                    disp: disp,
+                    size: width,
+                };
+                if stores {
+                    info.writes.push(mem);
+                } else {
+                    info.reads.push(mem);
+                }
+                continue;
+            }
+            op => op,
+        };
+        if stores || reads_only {
+            info.reads.push(op);
+        } else if loads || i == 0 {
+            info.writes.push(op);
+        } else {
+            info.reads.push(op);
+        }
+    }
+}
+
+/// Capstone-based ARM (A32/T32) instruction analyzer
+#[allow(non_camel_case_types)]
+pub struct ARM_CS_IA {
+    bytes: Vec<u8>,
+    cs: cs::Capstone,
+    info: InstructionInfo,
+}
+
+impl ARM_CS_IA {
+    fn reg_name(&self, reg: u32) -> Option<String> {
+        self.cs.reg_name(reg).map(|x| x.to_owned())
+    }
+
+    fn analyze(&mut self) -> Result<(), IAError> {
+        let buf = match self.cs.disasm(self.bytes.as_slice(), 0x0, 1) {
+            Ok(d) => d,
+            Err(e) => return Err(Cow::from(e.to_string())),
+        };
+
+        for instr in buf.iter() {
+            self.info.mnemonic = Cow::from(instr.mnemonic);
+            let details = instr.detail.unwrap();
+            let mut operands = Vec::new();
+            if let cs::DetailsArch::ARM(arch) = details.arch {
+                for i in 0..arch.op_count {
+                    let op: cs::cs_arm_op = arch.operands[i as usize];
+                    let iop = match op.type_ {
+                        cs::arm_op_type::ARM_OP_REG => {
+                            let reg: cs::arm_reg = op.reg();
+                            self.reg_name(reg.as_int())
+                                .map_or(IOperand::Invalid, IOperand::Register)
+                        }
+                        cs::arm_op_type::ARM_OP_MEM => {
+                            let mem: &cs::arm_op_mem = op.mem();
+                            IOperand::Memory {
+                                base: self.reg_name(mem.base),
+                                index: self.reg_name(mem.index),
+                                scale: mem.scale,
+                                disp: mem.disp as i64,
+                                size: 0,
+                            }
+                        }
+                        cs::arm_op_type::ARM_OP_IMM => IOperand::Immediate(op.imm() as i64),
+                        _ => IOperand::Other,
+                    };
+                    operands.push(iop);
+                }
+            }
+            arm_assign_operands(&mut self.info, operands, 32);
+        }
+        Ok(())
+    }
+}
+
+impl ARM_CS_IA {
+    /// Construct the IA for an instruction in the given instruction set
+    pub fn new_in(bytes: Vec<u8>, mode: InstructionSet) -> Result<ARM_CS_IA, IAError> {
+        let dis = match mode {
+            InstructionSet::Arm => cs::Capstone::new(cs::cs_arch::CS_ARCH_ARM, cs::CS_MODE_ARM),
+            InstructionSet::Thumb => {
+                cs::Capstone::new(cs::cs_arch::CS_ARCH_ARM, cs::CS_MODE_THUMB)
+            }
+        }.unwrap();
+        dis.option(cs::cs_opt_type::CS_OPT_DETAIL, cs::cs_opt_value::CS_OPT_ON).unwrap();
+
+        let mut ia = ARM_CS_IA { bytes: bytes, cs: dis, info: InstructionInfo::default() };
+
+        ia.analyze()?;
+
+        Ok(ia)
+    }
+}
+
+impl InstructionAnalyzer for ARM_CS_IA {
+    fn new(bytes: Vec<u8>) -> Result<ARM_CS_IA, IAError> {
+        ARM_CS_IA::new_in(bytes, InstructionSet::Arm)
+    }
+
+    fn info(&self) -> Result<&InstructionInfo, IAError> {
+        Ok(&self.info)
+    }
+}
+
+/// Capstone-based AArch64 instruction analyzer
+#[allow(non_camel_case_types)]
+pub struct ARM64_CS_IA {
+    bytes: Vec<u8>,
+    cs: cs::Capstone,
+    info: InstructionInfo,
+}
+
+impl ARM64_CS_IA {
+    fn reg_name(&self, reg: u32) -> Option<String> {
+        self.cs.reg_name(reg).map(|x| x.to_owned())
+    }
+
+    fn analyze(&mut self) -> Result<(), IAError> {
+        let buf = match self.cs.disasm(self.bytes.as_slice(), 0x0, 1) {
+            Ok(d) => d,
+            Err(e) => return Err(Cow::from(e.to_string())),
+        };
+
+        for instr in buf.iter() {
+            self.info.mnemonic = Cow::from(instr.mnemonic);
+            let details = instr.detail.unwrap();
+            let mut operands = Vec::new();
+            if let cs::DetailsArch::ARM64(arch) = details.arch {
+                for i in 0..arch.op_count {
+                    let op: cs::cs_arm64_op = arch.operands[i as usize];
+                    let iop = match op.type_ {
+                        cs::arm64_op_type::ARM64_OP_REG => {
+                            let reg: cs::arm64_reg = op.reg();
+                            self.reg_name(reg.as_int())
+                                .map_or(IOperand::Invalid, IOperand::Register)
+                        }
+                        cs::arm64_op_type::ARM64_OP_MEM => {
+                            let mem: &cs::arm64_op_mem = op.mem();
+                            IOperand::Memory {
+                                base: self.reg_name(mem.base),
+                                index: self.reg_name(mem.index),
+                                scale: 1,
+                                disp: mem.disp as i64,
+                                size: 0,
+                            }
+                        }
+                        cs::arm64_op_type::ARM64_OP_IMM => IOperand::Immediate(op.imm()),
+                        _ => IOperand::Other,
+                    };
+                    operands.push(iop);
+                }
+            }
+            arm_assign_operands(&mut self.info, operands, 64);
+        }
+        Ok(())
+    }
+}
+
+impl InstructionAnalyzer for ARM64_CS_IA {
+    fn new(bytes: Vec<u8>) -> Result<ARM64_CS_IA, IAError> {
+        let dis = cs::Capstone::new(cs::cs_arch::CS_ARCH_ARM64, cs::CS_MODE_ARM).unwrap();
+        dis.option(cs::cs_opt_type::CS_OPT_DETAIL, cs::cs_opt_value::CS_OPT_ON).unwrap();
+
+        let mut ia = ARM64_CS_IA { bytes: bytes, cs: dis, info: InstructionInfo::default() };
+
+        ia.analyze()?;
+
+        Ok(ia)
+    }
+
+    fn info(&self) -> Result<&InstructionInfo, IAError> {
+        Ok(&self.info)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_size(bytes: &[u8]) -> u16 {
+        let ia = ARM_CS_IA::new(bytes.to_vec()).unwrap();
+        match ia.info().unwrap().reads.iter().find(|x| match **x {
+            IOperand::Memory { .. } => true,
+            _ => false,
+        }) {
+            Some(&IOperand::Memory { size, .. }) => size,
+            _ => panic!("{} does not read memory", ia.mnemonic()),
+        }
+    }
+
+    #[test]
+    fn conditional_word_load() {
+        // ldrhs r0, [r1]
+        assert_eq!(read_size(&[0x00, 0x00, 0x91, 0x25]), 32);
+    }
+
+    #[test]
+    fn conditional_halfword_load() {
+        // ldrhhs r0, [r1]
+        assert_eq!(read_size(&[0xb0, 0x00, 0xd1, 0x21]), 16);
+    }
+
+    #[test]
+    fn access_width_from_mnemonic() {
+        let r0 = IOperand::Register("r0".to_owned());
+        let width = |m| arm_access_width(m, Some(&r0), 32);
+        assert_eq!(width("ldrhs"), 32);
+        assert_eq!(width("strhs"), 32);
+        assert_eq!(width("ldrhhs"), 16);
+        assert_eq!(width("ldrsh"), 16);
+        assert_eq!(width("ldrshlt"), 16);
+        assert_eq!(width("ldrble"), 8);
+        assert_eq!(width("ldrsbeq"), 8);
+        assert_eq!(width("ldrdne"), 64);
+        assert_eq!(width("ldrh.w"), 16);
+        assert_eq!(width("ldr"), 32);
+    }
+}
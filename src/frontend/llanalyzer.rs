@@ -2,6 +2,9 @@
 
 use frontend::radeco_containers::{RadecoModule, CallGraph, CallContextInfo, RadecoFunction};
 use middle::ir::MOpcode;
+use middle::ssa::cfg_traits::CFG;
+use middle::ssa::ssastorage::SSAStorage;
+use middle::ssa::utils;
 use middle::ssa::ssa_traits::{SSAWalk, SSA, NodeType};
 use petgraph::Direction;
 use petgraph::graph::NodeIndex;
@@ -90,60 +93,60 @@ fn analyze_callsite_initial(rfn: &RadecoFunction) -> HashMap<u64, CallContextInf
     cctxs
 }
 
+/// Maps the values passed and returned at every callsite of `rmod` to the values of the callee
+/// they become. Run again after call sites are rewritten to keep the mapping up to date.
 pub fn init_call_ctx(rmod: &mut RadecoModule) {
-    for wrapper in rmod.functions.iter() {
-        let rfn = wrapper.1;
+    for rfn in rmod.functions.values() {
         let mut csites: HashMap<u64, CallContextInfo> = analyze_callsite_initial(rfn);
-        // Iterate through callsites
+        // Iterate through callsites. Every call is an edge of its own, so that calls to the
+        // same callee from different callsites keep their own mapping.
         let mut cgwalker =
             rmod.callgraph.neighbors_directed(rfn.cgid(), Direction::Outgoing).detach();
-        for (csi, callee) in cgwalker.next(&rmod.callgraph) {
+        while let Some((csi, callee)) = cgwalker.next(&rmod.callgraph) {
             let csite = rmod.callgraph[csi].csite;
-            // Get args of callee
             let callee_off = rmod.callgraph[callee];
+            let cctx = match csites.remove(&csite) {
+                Some(cctx) => cctx,
+                None => continue,
+            };
 
-            let callee_info = if let Some(calleefn) = rmod.functions.get(&callee_off) {
-                let mut args = calleefn.bindings()
-                    .into_iter()
-                    .filter(|x| x.btype.is_argument() || x.btype.is_return())
-                    .cloned()
-                    .collect::<Vec<_>>();
-                args.sort_by(|x, y| {
-                    match (x.ridx, y.ridx) {
-                        (Some(xidx), Some(ref yidx)) => xidx.cmp(yidx),
-                        (_, _) => unreachable!(),
-                    }
-                });
-                Some((calleefn.cgid(), args))
-            } else if let Some(calleefn) = rmod.imports.get(&callee_off).map(|ifn| ifn.rfn.borrow()) {
-                let mut args = calleefn.bindings()
-                    .into_iter()
-                    .filter(|x| x.btype.is_argument() || x.btype.is_return())
-                    .cloned()
-                    .collect::<Vec<_>>();
-                args.sort_by(|x, y| {
-                    match (x.ridx, y.ridx) {
-                        (Some(xidx), Some(ref yidx)) => xidx.cmp(yidx),
-                        (_, _) => unreachable!(),
-                    }
-                });
-                Some((calleefn.cgid(), args))
+            let map = if let Some(calleefn) = rmod.functions.get(&callee_off) {
+                callsite_map(rfn.ssa(), cctx.csite_node, calleefn.ssa())
+            } else if let Some(ifn) = rmod.imports.get(&callee_off) {
+                callsite_map(rfn.ssa(), cctx.csite_node, ifn.rfn.borrow().ssa())
             } else {
-                None
+                continue;
             };
 
-            if let Some((callee_cgid, args)) = callee_info {
-                // Access the actual callsite in rfn.
-                if let Some(mut cctx) = csites.remove(&csite) {
-                    cctx.map = cctx.map
-                        .iter()
-                        .map(|&(k, _)| k)
-                        .zip(args.into_iter().map(|v| v.idx))
-                        .collect();
-                    // Update callsite information in the callgraph.
-                    rmod.callgraph.update_edge(rfn.cgid(), callee_cgid, cctx);
-                }
+            // Update callsite information in the callgraph.
+            rmod.callgraph[csi] = CallContextInfo { map: map, ..cctx };
+        }
+    }
+}
+
+/// Maps every register passed as an argument at `call` to its value at the entry of `callee`,
+/// and every register returned to its value at the exit of `callee`.
+fn callsite_map(ssa: &SSAStorage,
+                call: NodeIndex,
+                callee: &SSAStorage)
+                -> Vec<(NodeIndex, NodeIndex)> {
+    let state_at = |node: Option<NodeIndex>| {
+        node.and_then(|n| callee.registers_in(n)).map(|rs| utils::register_state_info(rs, callee))
+    };
+    let mut map = Vec::new();
+    if let (Some(info), Some(entry)) = (utils::call_info(call, ssa), state_at(callee.entry_node())) {
+        for (reg, &arg) in info.register_args.iter() {
+            if let Some(&(param, _)) = entry.get(reg) {
+                map.push((arg, param));
+            }
+        }
+    }
+    if let Some(exit) = state_at(callee.exit_node()) {
+        for (reg, &(ret, _)) in utils::call_rets(call, ssa).iter() {
+            if let Some(&(value, _)) = exit.get(reg) {
+                map.push((ret, value));
             }
         }
     }
+    map
 }
@@ -18,3 +18,4 @@ pub mod bindings;
 pub mod instruction_analyzer;
 pub mod llanalyzer;
 pub mod imports;
+pub mod rodata;
@@ -31,12 +31,17 @@
 //! For more examples of loading, check the `examples/` directory of this project.
 
 
+use analysis::arrays::ArrayAccess;
+use analysis::records::FieldAccess;
+use analysis::tie::structs::RType;
+use analysis::vsa::value_set::ValueSet;
 use frontend::llanalyzer;
 use frontend::radeco_source::Source;
 use frontend::ssaconstructor::SSAConstruct;
 use frontend::imports::ImportInfo;
 
-use middle::regfile::{SubRegisterFile, RegisterUsage};
+use middle::ir::{Endian, InstructionSet};
+use middle::regfile::{Arch, SubRegisterFile, RegisterUsage};
 use middle::ssa::cfg_traits::CFG;
 use middle::ssa::ssa_traits::{SSA, NodeType};
 
@@ -52,10 +57,11 @@ use r2api::structs::{LVarInfo, LOpInfo, LSymbolInfo, LRelocInfo, LExportInfo,
 use r2pipe::r2::R2;
 use rayon::prelude::*;
 use std::fmt;
+use std::mem;
 use std::borrow::Cow;
 use std::cell::RefCell;
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::collections::btree_map;
 use std::rc::Rc;
 use std::slice;
@@ -160,6 +166,10 @@ pub struct RadecoModule {
     // Information from the loader
     symbols: Vec<LSymbolInfo>,
     sections: Arc<Vec<LSectionInfo>>,
+    // Byte order of the binary
+    endian: Endian,
+    // Instruction set in effect from each address on, for ARM/Thumb interworking
+    modes: BTreeMap<u64, InstructionSet>,
     // Map from PLT entry address to `ImportInfo` for an import
     pub imports: HashMap<u64, ImportInfo>,
     exports: Vec<LExportInfo>,
@@ -329,6 +339,14 @@ pub struct RadecoFunction {
     // XXX Should it be separated from RadecoFunction?
     // NodeIndex is SSAStorage's NodeIndex
     binding_map: HashMap<NodeIndex, VarBindings>,
+    /// Types inferred for the values of the SSA
+    types: HashMap<NodeIndex, RType>,
+    /// Record fields accessed, by the value of their address
+    field_accesses: HashMap<NodeIndex, FieldAccess>,
+    /// Array elements accessed, by the value of their address
+    array_accesses: HashMap<NodeIndex, ArrayAccess>,
+    /// Value sets of the values of the SSA
+    value_sets: HashMap<NodeIndex, ValueSet>,
     /// Calling convention of this function
     pub callconv: Option<LCCInfo>,
     /// Register usage of this function
@@ -336,6 +354,11 @@ pub struct RadecoFunction {
     /// Name of the calling convention of this function (e.g. amd64, ms, arm64, etc.)
     // see https://github.com/radare/radare2/tree/9e08da0fa6b6c36edf04db72d22e065ccc90d381/libr/anal/d
     pub callconv_name: String,
+    /// Instruction set the function is decoded in, on architectures that have several
+    pub mode: Option<InstructionSet>,
+    /// Instruction sets the callees of direct calls in this function are decoded in, by callee
+    /// address
+    pub callee_modes: HashMap<u64, InstructionSet>,
 }
 
 #[derive(Default)]
@@ -692,6 +715,66 @@ impl<'a> ModuleLoader<'a> {
         rfn.bindings = tbindings;
     }
 
+    // Instruction set selected by an ARM ELF mapping symbol (`$a`, `$t`, possibly followed by
+    // a suffix). `$d` marks data and selects nothing.
+    fn mapping_symbol_mode(name: &str) -> Option<InstructionSet> {
+        let name = name.trim_left_matches("sym.");
+        match name.split(|c| c == '.' || c == '_').next() {
+            Some("$a") => Some(InstructionSet::Arm),
+            Some("$t") => Some(InstructionSet::Thumb),
+            _ => None,
+        }
+    }
+
+    // Sets the instruction set of every function. Thumb functions may be reported with the
+    // low bit of their address set, which is stripped from the offset here.
+    fn load_modes(rmod: &mut RadecoModule) {
+        let functions = mem::replace(&mut rmod.functions, BTreeMap::new());
+        for (_, rfn) in functions.iter().filter(|&(&offset, _)| offset & 1 == 1) {
+            let (start, mode) = InstructionSet::from_target(rfn.offset);
+            rmod.set_mode_range(start, start + rfn.size, mode);
+        }
+        for (_, mut rfn) in functions {
+            rfn.offset = rfn.offset & !1;
+            rfn.mode = Some(rmod.mode_at(rfn.offset).unwrap_or(InstructionSet::Arm));
+            rmod.functions.entry(rfn.offset).or_insert(rfn);
+        }
+    }
+
+    // Decodes functions called in another instruction set than they were decoded in again,
+    // e.g. the callee of `blx` from ARM code, and constructs their SSA anew. A function is
+    // decoded again at most once, so that conflicting calls cannot make this loop forever.
+    fn load_callee_modes(rmod: &mut RadecoModule, source: &Source,
+                         regfile: &Arc<SubRegisterFile>, assume_cc: bool) {
+        let mut done = HashSet::new();
+        loop {
+            let switched = rmod.functions
+                .values()
+                .flat_map(|rfn| rfn.callee_modes.iter())
+                .filter(|&(addr, &mode)| {
+                    !done.contains(addr) &&
+                    rmod.functions.get(addr).map_or(false, |f| f.mode != Some(mode))
+                })
+                .map(|(&addr, &mode)| (addr, mode))
+                .collect::<BTreeMap<_, _>>();
+            if switched.is_empty() {
+                break;
+            }
+            for (addr, mode) in switched {
+                done.insert(addr);
+                let size = rmod.functions[&addr].size;
+                rmod.set_mode_range(addr, addr + size, mode);
+                let rfn = rmod.functions.get_mut(&addr).unwrap();
+                radeco_trace!("module_callee_mode|{:#x}|{}", addr, mode);
+                rfn.mode = Some(mode);
+                rfn.instructions = source.disassemble_n_bytes_in(size, addr, mode)
+                                         .unwrap_or(Vec::new());
+                *rfn.ssa_mut() = SSAStorage::new();
+                SSAConstruct::<SSAStorage>::construct(rfn, regfile, assume_cc, true);
+            }
+        }
+    }
+
     /// Kick everything off and load module information based on config and defaults
     pub fn load(&mut self, src: Rc<Source>) -> RadecoModule {
         let source = if self.source.is_some() {
@@ -720,6 +803,11 @@ impl<'a> ModuleLoader<'a> {
             Err(e) => radeco_warn!(e),
         }
 
+        match source.endianness() {
+            Ok(endian) => rmod.endian = endian,
+            Err(e) => radeco_warn!(e),
+        }
+
         match source.imports() {
             // TODO: Set the node in callgraph, either now or later.
             Ok(import_info) => {
@@ -758,6 +846,24 @@ impl<'a> ModuleLoader<'a> {
             Err(e) => radeco_warn!(e),
         }
 
+        let reg_p = source.register_profile().expect("Unable to load register profile");
+        let arch = source.arch().unwrap_or_else(|e| {
+            radeco_warn!(e);
+            Arch::default()
+        });
+        let sub_reg_f = Arc::new(SubRegisterFile::new_for(&reg_p, arch, rmod.endian));
+        let interworking = sub_reg_f.supports_interworking();
+
+        if interworking {
+            rmod.modes = rmod.symbols
+                .iter()
+                .filter_map(|s| {
+                    let mode = ModuleLoader::mapping_symbol_mode(s.name.as_ref()?)?;
+                    Some((s.vaddr?, mode))
+                })
+                .collect();
+        }
+
         let mut flresult = floader.load(&rmod);
         flresult.functions = if self.filter.is_some() {
             let filter_fn = self.filter.as_ref().unwrap();
@@ -768,10 +874,16 @@ impl<'a> ModuleLoader<'a> {
 
         rmod.functions = flresult.functions;
 
+        if interworking {
+            ModuleLoader::load_modes(&mut rmod);
+        }
+
         // Load instructions into functions
         for rfn in rmod.functions.values_mut() {
-            rfn.instructions = source.disassemble_n_bytes(rfn.size, rfn.offset)
-                .unwrap_or(Vec::new());
+            rfn.instructions = match rfn.mode {
+                Some(mode) => source.disassemble_n_bytes_in(rfn.size, rfn.offset, mode),
+                None => source.disassemble_n_bytes(rfn.size, rfn.offset),
+            }.unwrap_or(Vec::new());
         }
 
         // Load calling conventions for all functions and imports
@@ -785,30 +897,32 @@ impl<'a> ModuleLoader<'a> {
         }
 
         // Optionally construct the SSA.
-        let reg_p = source.register_profile().expect("Unable to load register profile");
-        let sub_reg_f = SubRegisterFile::new(&reg_p);
         if self.build_ssa {
             if self.parallel {
                 let ascc = self.assume_cc;
                 rmod.functions.par_iter_mut().for_each(|(_, rfn)| {
-                    SSAConstruct::<SSAStorage>::construct(rfn, &reg_p, ascc, true);
+                    SSAConstruct::<SSAStorage>::construct(rfn, &sub_reg_f, ascc, true);
                 });
             } else {
                 for rfn in rmod.functions.values_mut() {
-                    SSAConstruct::<SSAStorage>::construct(rfn, &reg_p, self.assume_cc, true);
+                    SSAConstruct::<SSAStorage>::construct(rfn, &sub_reg_f, self.assume_cc, true);
                 }
             }
+            if interworking {
+                ModuleLoader::load_callee_modes(&mut rmod, &**source, &sub_reg_f,
+                                                self.assume_cc);
+            }
         }
 
         if self.stub_imports {
             for ifn in rmod.imports.values_mut() {
-                SSAConstruct::<SSAStorage>::construct(&mut ifn.rfn.borrow_mut(), &reg_p, self.assume_cc, true);
+                SSAConstruct::<SSAStorage>::construct(&mut ifn.rfn.borrow_mut(), &sub_reg_f, self.assume_cc, true);
             }
         }
 
         // Load optional information. These need support from `Source` for analysis
         if self.build_callgraph || self.load_datarefs || self.load_locals {
-            let aux_info = match source.functions() {
+            let mut aux_info = match source.functions() {
                 Ok(info) => info,
                 Err(e) => {
                     radeco_warn!(e);
@@ -816,6 +930,16 @@ impl<'a> ModuleLoader<'a> {
                 }
             };
 
+            if interworking {
+                // Match the offsets that functions were loaded at.
+                for info in &mut aux_info {
+                    info.offset = info.offset.map(|o| o & !1);
+                    for cs in info.callrefs.iter_mut().flat_map(|c| c.iter_mut()) {
+                        cs.target = cs.target.map(|t| t & !1);
+                    }
+                }
+            }
+
             if self.build_callgraph {
                 rmod.callgraph = llanalyzer::load_call_graph(aux_info.as_slice(), &rmod);
                 // Iterate through nodes and associate nodes with the correct functions
@@ -1048,6 +1172,27 @@ impl RadecoModule {
         &self.sections
     }
 
+    pub fn relocs(&self) -> &[LRelocInfo] {
+        &self.relocs
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Instruction set of the code at `address`, if the module has ARM/Thumb interworking
+    /// information about it.
+    pub fn mode_at(&self, address: u64) -> Option<InstructionSet> {
+        self.modes.range(..address + 1).next_back().map(|(_, &mode)| mode)
+    }
+
+    // Marks `[start, end)` as decoded in `mode`, leaving the code after it as it was.
+    fn set_mode_range(&mut self, start: u64, end: u64, mode: InstructionSet) {
+        let after = self.mode_at(end).unwrap_or(InstructionSet::Arm);
+        self.modes.insert(start, mode);
+        self.modes.entry(end).or_insert(after);
+    }
+
     pub fn callees_of(&self, rfn: &RadecoFunction) -> Vec<(u64, NodeIndex)> {
         // TODO More efficient implementation
         let csite_nodes = rfn.call_sites(&self.callgraph)
@@ -1175,7 +1320,7 @@ impl RadecoFunction {
         let ssa = &self.ssa;
         for node in ssa.inorder_walk() {
             match ssa.opcode(node) {
-                Some(MOpcode::OpStore) | Some(MOpcode::OpLoad) => {
+                Some(MOpcode::OpStore(..)) | Some(MOpcode::OpLoad(..)) => {
                     let dst = ssa.operands_of(node)[1];
                     let bindings = self.retrieve_binding(dst);
                     if bindings.len() > 0 {
@@ -1190,6 +1335,58 @@ impl RadecoFunction {
     pub fn local_at(&self, node: NodeIndex) -> Option<VarBindings> {
         self.binding_map.get(&node).cloned()
     }
+
+    /// Type inferred for the value `node`, if types were inferred.
+    pub fn type_of(&self, node: NodeIndex) -> Option<&RType> {
+        self.types.get(&node)
+    }
+
+    pub fn set_types(&mut self, types: HashMap<NodeIndex, RType>) {
+        self.types = types;
+    }
+
+    /// Field of a record accessed at the address `node`, if records were recovered.
+    pub fn field_access(&self, node: NodeIndex) -> Option<&FieldAccess> {
+        self.field_accesses.get(&node)
+    }
+
+    /// Name of the record `node` points to, if any of its fields are accessed.
+    pub fn record_of(&self, node: NodeIndex) -> Option<&str> {
+        self.field_accesses
+            .values()
+            .find(|a| a.base == node)
+            .map(|a| a.record.as_str())
+    }
+
+    pub fn set_field_accesses(&mut self, field_accesses: HashMap<NodeIndex, FieldAccess>) {
+        self.field_accesses = field_accesses;
+    }
+
+    /// Element of an array accessed at the address `node`, if arrays were recovered.
+    pub fn array_access(&self, node: NodeIndex) -> Option<&ArrayAccess> {
+        self.array_accesses.get(&node)
+    }
+
+    /// Width of the elements of the array `node` points to, if it is indexed.
+    pub fn array_element_width(&self, node: NodeIndex) -> Option<u16> {
+        self.array_accesses
+            .values()
+            .find(|a| a.base == node)
+            .map(|a| a.element_width)
+    }
+
+    pub fn set_array_accesses(&mut self, array_accesses: HashMap<NodeIndex, ArrayAccess>) {
+        self.array_accesses = array_accesses;
+    }
+
+    /// Value set of the value `node`, if value sets were analyzed.
+    pub fn value_set(&self, node: NodeIndex) -> Option<&ValueSet> {
+        self.value_sets.get(&node)
+    }
+
+    pub fn set_value_sets(&mut self, value_sets: HashMap<NodeIndex, ValueSet>) {
+        self.value_sets = value_sets;
+    }
 }
 
 #[derive(Clone, Debug, Default)]
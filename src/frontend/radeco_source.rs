@@ -10,6 +10,8 @@ use std::cell::RefCell;
 use std::error::Error;
 use std::fmt;
 
+use middle::ir::{Endian, InstructionSet};
+use middle::regfile::Arch;
 use r2api::api_trait::R2Api;
 use r2api::structs::{FunctionInfo, LCCInfo, LFlagInfo, LOpInfo, LRegInfo, LSectionInfo, LStringInfo, LSymbolInfo,
 LImportInfo, LExportInfo, LRelocInfo, LEntryInfo, LVarInfo};
@@ -51,12 +53,27 @@ pub trait Source {
     fn entrypoint(&self) -> Result<Vec<LEntryInfo>, SourceErr> { unimplemented!() }
     fn disassemble_n_bytes(&self, _n: u64, _at: u64) -> Result<Vec<LOpInfo>, SourceErr> { unimplemented!() }
     fn disassemble_n_insts(&self, _n: u64, _at: u64) -> Result<Vec<LOpInfo>, SourceErr> { unimplemented!() }
+    /// Disassemble `n` bytes at `at` decoding them in the given instruction set. Sources that
+    /// cannot switch instruction sets disassemble as `disassemble_n_bytes` does.
+    fn disassemble_n_bytes_in(&self, n: u64, at: u64, _mode: InstructionSet) -> Result<Vec<LOpInfo>, SourceErr> {
+        self.disassemble_n_bytes(n, at)
+    }
     fn locals_of(&self, _start_addr: u64) -> Result<Vec<LVarInfo>, SourceErr> { unimplemented!() }
     fn cc_info_of(&self, _start_addr: u64) -> Result<LCCInfo, SourceErr> { unimplemented!() }
     fn raw(&self, _cmd: String) -> Result<String, SourceErr> { unimplemented!() }
+    /// Read `n` bytes of the loaded image at `at`.
+    fn read_bytes(&self, _n: u64, _at: u64) -> Result<Vec<u8>, SourceErr> {
+        Err(SourceErr::SrcErr("Source cannot read memory"))
+    }
 
     fn send(&self, _: String) -> Result<(), SourceErr> { Ok(()) }
 
+    /// Byte order of the loaded binary. Sources that cannot tell assume little-endian.
+    fn endianness(&self) -> Result<Endian, SourceErr> { Ok(Endian::Little) }
+
+    /// Architecture of the loaded binary. Sources that cannot tell assume x86.
+    fn arch(&self) -> Result<Arch, SourceErr> { Ok(Arch::X86) }
+
     // Non essential / functions with default implementation.
     fn function_at(&self, address: u64) -> Result<FunctionInfo, SourceErr> {
         for f in self.functions()? {
@@ -183,6 +200,15 @@ impl<R: R2Api> Source for WrappedR2Api<R> {
         Ok(self.try_borrow_mut()?.disassemble_n_insts(n, Some(at))?)
     }
 
+    fn disassemble_n_bytes_in(&self, n: u64, at: u64, mode: InstructionSet) -> Result<Vec<LOpInfo>, SourceErr> {
+        let mut r2 = self.try_borrow_mut()?;
+        let bits = r2.raw("e asm.bits".to_owned());
+        r2.raw(format!("e asm.bits={}", mode.bits()));
+        let ops = r2.disassemble_n_bytes(n, Some(at));
+        r2.raw(format!("e asm.bits={}", bits.trim()));
+        Ok(ops?)
+    }
+
     fn locals_of(&self, start_addr: u64) -> Result<Vec<LVarInfo>, SourceErr> {
         Ok(self.try_borrow_mut()?.locals_of(start_addr)?)
     }
@@ -195,10 +221,41 @@ impl<R: R2Api> Source for WrappedR2Api<R> {
         Ok(self.try_borrow_mut()?.raw(cmd))
     }
 
+    fn read_bytes(&self, n: u64, at: u64) -> Result<Vec<u8>, SourceErr> {
+        let hex = self.try_borrow_mut()?.raw(format!("p8 {} @ {}", n, at));
+        let hex = hex.trim();
+        if hex.len() as u64 != 2 * n {
+            return Err(SourceErr::SrcErr("Failed to read memory"));
+        }
+        (0..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16)
+                 .map_err(|_| SourceErr::SrcErr("Failed to read memory")))
+            .collect()
+    }
+
     fn send(&self, s: String) -> Result<(), SourceErr> {
         let _ = self.try_borrow_mut()?.raw(s);
         Ok(())
     }
+
+    fn endianness(&self) -> Result<Endian, SourceErr> {
+        let big = self.try_borrow_mut()?.raw("e cfg.bigendian".to_owned());
+        match big.trim() {
+            "true" => Ok(Endian::Big),
+            "false" => Ok(Endian::Little),
+            _ => Err(SourceErr::SrcErr("Unable to read cfg.bigendian")),
+        }
+    }
+
+    fn arch(&self) -> Result<Arch, SourceErr> {
+        let mut r2 = self.try_borrow_mut()?;
+        let arch = r2.raw("e asm.arch".to_owned());
+        let bits = r2.raw("e asm.bits".to_owned());
+        match bits.trim().parse() {
+            Ok(bits) => Ok(Arch::from_r2(arch.trim(), bits)),
+            Err(_) => Err(SourceErr::SrcErr("Unable to read asm.bits")),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -226,6 +283,17 @@ impl FileSource {
         json_str
     }
 
+    // Like `read_file`, but for information that a dump may not contain.
+    fn read_optional_file(&self, suffix: &str) -> Result<String, SourceErr> {
+        let mut path = PathBuf::from(&self.dir);
+        path.push(&format!("{}_{}.json", self.base_name, suffix));
+        if path.exists() {
+            Ok(self.read_file(suffix))
+        } else {
+            Err(SourceErr::SrcErr("Information not found in FileSource"))
+        }
+    }
+
     fn write_file(&mut self, suffix: &str, data: &str) {
         let mut path = PathBuf::from(&self.dir);
         path.push(&format!("{}_{}.json", self.base_name, suffix));
@@ -242,6 +310,13 @@ mod suffix {
     pub const FLAG: &'static str = "flags";
     pub const SECTION: &'static str = "sections";
     pub const STRING: &'static str = "strings";
+    pub const SYMBOL: &'static str = "symbols";
+    pub const IMPORT: &'static str = "imports";
+    pub const EXPORT: &'static str = "exports";
+    pub const RELOC: &'static str = "relocs";
+    pub const LIBRARY: &'static str = "libraries";
+    pub const ENTRYPOINT: &'static str = "entrypoint";
+    pub const ARCH: &'static str = "arch";
 }
 
 impl FileSource {
@@ -281,6 +356,58 @@ impl Source for FileSource {
     fn sections(&self) -> Result<Vec<LSectionInfo>, SourceErr> {
         Ok(serde_json::from_str(&self.read_file(suffix::SECTION))?)
     }
+
+    fn symbols(&self) -> Result<Vec<LSymbolInfo>, SourceErr> {
+        Ok(serde_json::from_str(&self.read_optional_file(suffix::SYMBOL)?)?)
+    }
+
+    fn imports(&self) -> Result<Vec<LImportInfo>, SourceErr> {
+        Ok(serde_json::from_str(&self.read_optional_file(suffix::IMPORT)?)?)
+    }
+
+    fn exports(&self) -> Result<Vec<LExportInfo>, SourceErr> {
+        Ok(serde_json::from_str(&self.read_optional_file(suffix::EXPORT)?)?)
+    }
+
+    fn relocs(&self) -> Result<Vec<LRelocInfo>, SourceErr> {
+        Ok(serde_json::from_str(&self.read_optional_file(suffix::RELOC)?)?)
+    }
+
+    fn libraries(&self) -> Result<Vec<String>, SourceErr> {
+        Ok(serde_json::from_str(&self.read_optional_file(suffix::LIBRARY)?)?)
+    }
+
+    fn entrypoint(&self) -> Result<Vec<LEntryInfo>, SourceErr> {
+        Ok(serde_json::from_str(&self.read_optional_file(suffix::ENTRYPOINT)?)?)
+    }
+
+    // Recorded as `{"arch": <asm.arch>, "bits": <asm.bits>}`.
+    fn arch(&self) -> Result<Arch, SourceErr> {
+        let info: serde_json::Value =
+            serde_json::from_str(&self.read_optional_file(suffix::ARCH)?)?;
+        match (info["arch"].as_str(), info["bits"].as_u64()) {
+            (Some(arch), Some(bits)) => Ok(Arch::from_r2(arch, bits)),
+            _ => Err(SourceErr::SrcErr("Invalid architecture in FileSource")),
+        }
+    }
+
+    // Instructions are recorded per function, so only ranges starting at a function can be
+    // served.
+    fn disassemble_n_bytes(&self, n: u64, at: u64) -> Result<Vec<LOpInfo>, SourceErr> {
+        let suffix = format!("{}_{:#X}", suffix::INSTRUCTIONS, at);
+        let insts: Vec<LOpInfo> = serde_json::from_str(&self.read_optional_file(&suffix)?)?;
+        Ok(insts.into_iter()
+               .filter(|op| op.offset.map_or(false, |o| o >= at && o < at + n))
+               .collect())
+    }
+
+    fn locals_of(&self, _start_addr: u64) -> Result<Vec<LVarInfo>, SourceErr> {
+        Err(SourceErr::SrcErr("Locals are not recorded in FileSource"))
+    }
+
+    fn cc_info_of(&self, _start_addr: u64) -> Result<LCCInfo, SourceErr> {
+        Err(SourceErr::SrcErr("Calling conventions are not recorded in FileSource"))
+    }
 }
 
 impl<R: R2Api> From<WrappedR2Api<R>> for FileSource {
@@ -316,6 +443,13 @@ impl<R: R2Api> From<WrappedR2Api<R>> for FileSource {
                 fsource.write_file(suffix::REGISTER, &json_str);
             }
             
+            {
+                let arch = r2.raw("e asm.arch".to_owned()).expect("Failed to load asm.arch");
+                let bits = r2.raw("e asm.bits".to_owned()).expect("Failed to load asm.bits");
+                let json_str = format!("{{\"arch\":{:?},\"bits\":{}}}", arch.trim(), bits.trim());
+                fsource.write_file(suffix::ARCH, &json_str);
+            }
+
             {
                 let flags = r2.flags().expect("Failed to load flag info");
                 let json_str = serde_json::to_string(&flags).expect("Failed to encode to json");
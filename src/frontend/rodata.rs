@@ -0,0 +1,126 @@
+//! Contents of the read-only data of a module, which loads from constant addresses can
+//! be folded into.
+
+use std::collections::BTreeSet;
+
+use r2api::structs::LSectionInfo;
+
+use frontend::radeco_containers::RadecoModule;
+use middle::ir::Endian;
+
+/// Number of bytes a relocation is assumed to patch from its address on.
+const RELOC_SIZE: u64 = 8;
+
+#[derive(Debug, Default)]
+pub struct ReadOnlyData {
+    // Address and bytes of each read-only section
+    sections: Vec<(u64, Vec<u8>)>,
+    // Addresses patched by relocations, which hold a different value once loaded
+    relocs: BTreeSet<u64>,
+}
+
+impl ReadOnlyData {
+    pub fn new() -> ReadOnlyData {
+        ReadOnlyData::default()
+    }
+
+    /// Reads the sections of `rmod` which are neither writable nor executable from its
+    /// source.
+    pub fn load(rmod: &RadecoModule) -> ReadOnlyData {
+        let mut rodata = ReadOnlyData::new();
+        let source = match rmod.source {
+            Some(ref source) => source,
+            None => return rodata,
+        };
+        for section in rmod.sections().iter().filter(|s| is_read_only(s)) {
+            let size = match (section.size, section.vsize) {
+                (Some(size), Some(vsize)) => size.min(vsize),
+                (size, _) => size.unwrap_or(0),
+            };
+            let address = section.vaddr.unwrap_or(0);
+            if address == 0 || size == 0 {
+                continue;
+            }
+            match source.read_bytes(size, address) {
+                Ok(bytes) => rodata.add_section(address, bytes),
+                Err(e) => radeco_warn!("Failed to read section {:?}: {}", section.name, e),
+            }
+        }
+        for reloc in rmod.relocs() {
+            if let Some(address) = reloc.vaddr {
+                rodata.add_reloc(address);
+            }
+        }
+        rodata
+    }
+
+    /// Adds read-only `bytes` at `address`.
+    pub fn add_section(&mut self, address: u64, bytes: Vec<u8>) {
+        self.sections.push((address, bytes));
+    }
+
+    /// Marks the bytes at `address` as patched by a relocation.
+    pub fn add_reloc(&mut self, address: u64) {
+        self.relocs.insert(address);
+    }
+
+    /// Value of `width` bits at `address` in the given byte order. `None` if any of it
+    /// is not read-only or is patched by a relocation.
+    pub fn read(&self, address: u64, width: u16, endian: Endian) -> Option<u64> {
+        if width == 0 || width % 8 != 0 || width > 64 {
+            return None;
+        }
+        let end = address.checked_add((width / 8) as u64)?;
+        let first_reloc = address.saturating_sub(RELOC_SIZE - 1);
+        if self.relocs.range(first_reloc..end).next().is_some() {
+            return None;
+        }
+        let &(start, ref bytes) = self.sections.iter().find(|&&(start, ref bytes)| {
+            start <= address && end <= start + bytes.len() as u64
+        })?;
+        endian.decode(&bytes[(address - start) as usize..], width)
+    }
+}
+
+fn is_read_only(section: &LSectionInfo) -> bool {
+    match section.flags {
+        Some(ref flags) => flags.contains('r') && !flags.contains('w') && !flags.contains('x'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rodata() -> ReadOnlyData {
+        let mut rodata = ReadOnlyData::new();
+        rodata.add_section(0x1000, vec![0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]);
+        rodata.add_section(0x2000, vec![0; 32]);
+        rodata.add_reloc(0x2010);
+        rodata
+    }
+
+    #[test]
+    fn read_test() {
+        let rodata = rodata();
+        assert_eq!(rodata.read(0x1000, 32, Endian::Little), Some(0x44332211));
+        assert_eq!(rodata.read(0x1000, 32, Endian::Big), Some(0x11223344));
+        assert_eq!(rodata.read(0x1006, 16, Endian::Little), Some(0x8877));
+        assert_eq!(rodata.read(0x1000, 64, Endian::Little), Some(0x8877665544332211));
+        assert_eq!(rodata.read(0x2000, 64, Endian::Little), Some(0));
+    }
+
+    #[test]
+    fn read_outside_test() {
+        let rodata = rodata();
+        // Past the end of the section.
+        assert_eq!(rodata.read(0x1006, 32, Endian::Little), None);
+        assert_eq!(rodata.read(0xfff, 8, Endian::Little), None);
+        // Overlapping a relocation.
+        assert_eq!(rodata.read(0x200c, 64, Endian::Little), None);
+        assert_eq!(rodata.read(0x2014, 32, Endian::Little), None);
+        assert_eq!(rodata.read(0x2018, 64, Endian::Little), Some(0));
+        assert_eq!(rodata.read(0x1000, 12, Endian::Little), None);
+    }
+}
@@ -15,19 +15,21 @@
 use esil::lexer::{Token, Tokenizer};
 
 use esil::parser::{Parse, Parser};
-use frontend::instruction_analyzer::{InstructionAnalyzer, X86_CS_IA, IOperand};
+use frontend::instruction_analyzer::{InstructionAnalyzer, ARM64_CS_IA, ARM_CS_IA, X86_CS_IA,
+                                     IOperand};
 use frontend::radeco_containers::RadecoFunction;
 
-use middle::ir::{self, MAddress, MOpcode};
+use middle::ir::{self, InstructionSet, MAddress, MOpcode};
 use middle::phiplacement::PhiPlacer;
-use middle::regfile::SubRegisterFile;
+use middle::regfile::{Arch, SubRegisterFile};
 use middle::ssa::graph_traits::Graph;
 use middle::ssa::ssa_traits::{SSAExtra, SSAMod, ValueInfo};
 
-use r2api::structs::{LOpInfo, LRegInfo};
+use r2api::structs::LOpInfo;
 
 use regex::Regex;
 use std::{fmt, cmp, u64};
+use std::collections::HashMap;
 use std::sync::Arc;
 
 pub type VarId = usize;
@@ -53,6 +55,14 @@ pub struct SSAConstruct<'a, T>
     mem_id: u64,
     assume_cc: bool,
     replace_pc: bool,
+    // Instruction set the function is decoded in, if the architecture has several.
+    mode: Option<InstructionSet>,
+    // Instruction sets selected by branches that switch them, by target address.
+    target_modes: HashMap<u64, InstructionSet>,
+    // Instruction sets the callees of direct calls are decoded in, by callee address.
+    callee_modes: HashMap<u64, InstructionSet>,
+    // Mnemonic of the instruction being lifted.
+    mnemonic: String,
 }
 
 impl<'a, T> SSAConstruct<'a, T>
@@ -71,6 +81,14 @@ impl<'a, T> SSAConstruct<'a, T>
             mem_id: 0,
             assume_cc: false,
             replace_pc: true,
+            mode: if regfile.supports_interworking() {
+                Some(InstructionSet::Arm)
+            } else {
+                None
+            },
+            target_modes: HashMap::new(),
+            callee_modes: HashMap::new(),
+            mnemonic: String::new(),
         };
 
         // Add all the registers to the variable list.
@@ -81,14 +99,21 @@ impl<'a, T> SSAConstruct<'a, T>
     }
 
     // Helper wrapper.
-    pub fn construct(rfn: &mut RadecoFunction, ri: &LRegInfo, assume_cc: bool, replace_pc: bool) {
+    pub fn construct(rfn: &mut RadecoFunction, regfile: &Arc<SubRegisterFile>,
+                     assume_cc: bool, replace_pc: bool) {
         let instructions = rfn.instructions().to_vec();
-        let regfile = Arc::new(SubRegisterFile::new(ri));
+        let mode = rfn.mode;
         rfn.ssa_mut().regfile = regfile.clone();
-        let mut constr = SSAConstruct::new(rfn.ssa_mut(), &regfile);
-        constr.assume_cc = assume_cc;
-        constr.replace_pc = replace_pc;
-        constr.run(instructions.as_slice());
+        rfn.callee_modes = {
+            let mut constr = SSAConstruct::new(rfn.ssa_mut(), &regfile);
+            constr.assume_cc = assume_cc;
+            constr.replace_pc = replace_pc;
+            if mode.is_some() {
+                constr.mode = mode;
+            }
+            constr.run(instructions.as_slice());
+            constr.callee_modes
+        };
     }
 
     fn set_mem_id(&mut self, id: u64) {
@@ -215,10 +240,16 @@ impl<'a, T> SSAConstruct<'a, T>
                         // determine are the ones where the rhs is a constant.
                         if let Some(Token::EConstant(target)) = operands[1] {
                             // Direct/known CF tranfer
+                            let target = self.branch_target(target);
                             let target_addr = MAddress::new(target, 0);
                             self.phiplacer
                                 .add_block(target_addr, Some(*address), Some(UNCOND_EDGE));
                             self.needs_new_block = true;
+                        } else if self.is_link_register(&operands[1]) {
+                            // Branching to the return address left by the caller, i.e.
+                            // `bx lr`/`ret` on ARM.
+                            self.phiplacer.add_return(address);
+                            self.needs_new_block = true;
                         } else {
                             // Indirect CF transfer
                             if let Some(ref jump_idx) = rhs {
@@ -241,7 +272,7 @@ impl<'a, T> SSAConstruct<'a, T>
                     // OpStore operation.
                     radeco_trace!("Memory Write");
                     let op_node = self.phiplacer
-                        .add_op(&MOpcode::OpStore,
+                        .add_op(&MOpcode::OpStore(rhs_size, self.regfile.endian),
                                 address,
                                 *MEM_VALUEINFO);
                     self.phiplacer.op_use(&op_node,
@@ -315,15 +346,19 @@ impl<'a, T> SSAConstruct<'a, T>
             Token::EMod => {
                 (MOpcode::OpMod, ValueInfo::new_scalar(ir::WidthSpec::from(result_size)))
             }
-            Token::EPoke(_) => {
-                // TODO: rhs has to be cast to size 'n' if it's size is not already n.
+            Token::EPoke(n) => {
+                let width = n as u16;
+                let value = self.phiplacer.fit_width(address,
+                                                     rhs.expect("rhs cannot be `None`"),
+                                                     width);
                 let mem_id = self.mem_id();
                 let mem = self.phiplacer.read_variable(address, mem_id);
-                let op_node = self.phiplacer.add_op(&MOpcode::OpStore, address, scalar!(0));
+                let op_node = self.phiplacer.add_op(&MOpcode::OpStore(width, self.regfile.endian),
+                                                    address, scalar!(0));
 
                 self.phiplacer.op_use(&op_node, 0, &mem);
                 self.phiplacer.op_use(&op_node, 1, lhs.as_ref().expect("lhs cannot be `None`"));
-                self.phiplacer.op_use(&op_node, 2, rhs.as_ref().expect("rhs cannot be `None`"));
+                self.phiplacer.op_use(&op_node, 2, &value);
 
                 self.phiplacer.write_variable(*address, self.mem_id, op_node);
                 return None;
@@ -331,7 +366,7 @@ impl<'a, T> SSAConstruct<'a, T>
             Token::EPeek(n) => {
                 let mem = self.phiplacer.read_variable(address, self.mem_id);
                 let op_node = self.phiplacer
-                    .add_op(&MOpcode::OpLoad,
+                    .add_op(&MOpcode::OpLoad(n as u16, self.regfile.endian),
                             address,
                             ValueInfo::new_unresolved(ir::WidthSpec::from(n as u16)));
 
@@ -479,6 +514,12 @@ impl<'a, T> SSAConstruct<'a, T>
 
             radeco_trace!("ssa_construct_esil|{}|{:?}", current_address, esil_str);
 
+            self.mnemonic = op.opcode
+                .as_ref()
+                .and_then(|o| o.split_whitespace().next())
+                .unwrap_or("")
+                .to_owned();
+
             // Handle call separately.
             // NOTE: This is a hack.
             if let Some(ref ty) = op.optype {
@@ -499,6 +540,18 @@ impl<'a, T> SSAConstruct<'a, T>
 
                     let op_call = self.phiplacer
                         .add_op(&MOpcode::OpCall, &mut current_address, value_type);
+                    self.call_target(esil_str);
+
+                    // Architectures with a link register pass the return address to the
+                    // callee in it rather than on the stack.
+                    if let Some(lr) = self.regfile.alias_info.get("LR") {
+                        let width = self.regfile.get_subregister(lr).map_or(64, |r| r.width);
+                        let ret_addr = offset + op.size.unwrap_or(0);
+                        let ret_node = self.phiplacer.add_const(&mut current_address,
+                                                                ret_addr,
+                                                                Some(scalar!(width as u16)));
+                        self.phiplacer.write_register(&mut current_address, lr, ret_node);
+                    }
 
 
                     // If `self.assume_cc` is set, then we assume that the callee strictly obeys the
@@ -566,6 +619,13 @@ impl<'a, T> SSAConstruct<'a, T>
                 }
             }
 
+            if let Some(ref opcode) = op.opcode {
+                if self.process_string_op(opcode, &mut current_address) ||
+                   self.process_mul_op(opcode, &mut current_address) {
+                    continue;
+                }
+            }
+
             // Some overrides as we do not support all esil and don't want to panic.
             let overrides = &["GOTO", "TRAP", "$", "TODO", "REPEAT"];
             if esil_str.split(",").any(|x| overrides.contains(&x)) {
@@ -580,10 +640,7 @@ impl<'a, T> SSAConstruct<'a, T>
                     .map(|cap| u8::from_str_radix(&cap[0], 16).expect("Cannot Fail"))
                     .collect::<Vec<u8>>();
 
-                // TODO/XXX:
-                // This needs to be selected based on the current arch
-                let ia = X86_CS_IA::new(bytes).expect("Unable to instantiate IA");
-                self.process_custom(&ia, &mut current_address);
+                self.analyze_custom(bytes, &mut current_address);
                 continue;
             }
 
@@ -601,6 +658,9 @@ impl<'a, T> SSAConstruct<'a, T>
         // BUG: The last block may not have the biggest address, which means current_address
         // may be not in the last basic block
         // self.phiplacer.add_edge(current_address, MAddress::new(u64::MAX, 0), UNCOND_EDGE);
+        if let Some(mode) = self.mode {
+            self.phiplacer.set_block_modes(mode, &self.target_modes);
+        }
         self.phiplacer.gather_exits();
         self.phiplacer.finish(op_info);
     }
@@ -663,6 +723,221 @@ impl<'a, T> SSAConstruct<'a, T>
     }
 
 
+    // Lifts a `rep` prefixed string instruction as a single `OpCustom` named after it,
+    // e.g. "rep movsb". Like a call, it takes every register it reads as operand
+    // `regid + 1` and memory as operand `mem_id + 1`, and every register it writes is a
+    // comment using it. This leaves their meaning to `analysis::idioms::memory`.
+    // `repe` repeats while the comparison is equal, which `rep` does not, so it is kept
+    // apart. Returns false for any other instruction.
+    fn process_string_op(&mut self, opcode: &str, addr: &mut MAddress) -> bool {
+        let mut words = opcode.split_whitespace();
+        let (prefix, instr) = match (words.next(), words.next()) {
+            (Some("rep"), Some(i)) => ("rep", i),
+            (Some("repe"), Some(i)) | (Some("repz"), Some(i)) => ("repe", i),
+            (Some("repne"), Some(i)) | (Some("repnz"), Some(i)) => ("repne", i),
+            _ => return false,
+        };
+        if instr.len() != 5 || !instr.ends_with(|c: char| "bwdq".contains(c)) {
+            return false;
+        }
+        let flags = ["cf", "pf", "af", "zf", "sf", "of"];
+        let (read, written, writes_mem): (&[&str], &[&str], bool) = match &instr[..4] {
+            "movs" => (&["di", "si", "cx"], &["di", "si", "cx"], true),
+            "stos" => (&["di", "cx", "ax"], &["di", "cx"], true),
+            "scas" => (&["di", "cx", "ax"], &["di", "cx"], false),
+            _ => return false,
+        };
+        let regfile = self.regfile;
+        let whole = |names: &[&str]| {
+            names.iter()
+                 .map(|n| regfile.register_id_by_name(n))
+                 .collect::<Option<Vec<_>>>()
+        };
+        let (mut read, mut written) = match (whole(read), whole(written)) {
+            (Some(r), Some(w)) => (r, w),
+            _ => return false,
+        };
+        // The direction flag decides whether the addresses count up or down. It is read
+        // like any other register, so that the instruction is only taken for a forward
+        // one where the flag is known to be clear.
+        read.extend(regfile.register_id_by_name("df")
+                           .filter(|&id| regfile.get_name(id) == Some("df")));
+        if instr.starts_with("scas") {
+            // Only flags which are registers of their own are written.
+            written.extend(flags.iter()
+                                .filter_map(|f| regfile.register_id_by_name(f))
+                                .filter(|&id| flags.contains(&regfile.get_name(id).unwrap_or(""))));
+        }
+
+        let vt = ValueInfo::new_unresolved(ir::WidthSpec::Unknown);
+        let opcode = MOpcode::OpCustom(format!("{} {}", prefix, instr));
+        let op_node = self.phiplacer.add_op(&opcode, addr, vt);
+        for id in read {
+            let name = self.regfile.get_name(id).expect("Invalid register").to_owned();
+            let rnode = self.phiplacer.read_register(addr, &name);
+            self.phiplacer.op_use(&op_node, id.to_u8() + 1, &rnode);
+        }
+        let mem_id = self.mem_id();
+        let mem_node = self.phiplacer.read_variable(addr, mem_id);
+        self.phiplacer.op_use(&op_node, (mem_id + 1) as u8, &mem_node);
+
+        for id in written {
+            let name = self.regfile.get_name(id).expect("Invalid register").to_owned();
+            let width = self.regfile.whole_registers[id.to_usize()];
+            let comment_node = self.phiplacer
+                .add_comment(*addr, width, format!("{}@{}", name, addr));
+            self.phiplacer.write_register(addr, &name, comment_node);
+            self.phiplacer.op_use(&comment_node, id.to_u8(), &op_node);
+        }
+        if writes_mem {
+            let comment_node = self.phiplacer
+                .add_comment(*addr, *MEM_VALUEINFO, format!("mem@{}", addr));
+            self.phiplacer.write_variable(*addr, mem_id, comment_node);
+            self.phiplacer.op_use(&comment_node, mem_id as u8, &op_node);
+        }
+
+        addr.offset += 1;
+        self.phiplacer.set_address(&op_node, *addr);
+        true
+    }
+
+    // Lifts `mul` and `imul` with a single register operand, which multiply `rax` (or
+    // `eax`) by it and leave the high half of the product in `rdx` (or `edx`). radare2
+    // only gives the low half, so the high half is built with `OpMulHi`/`OpSMulHi`.
+    // A 32-bit product is zero extended into the whole registers by `write_register`.
+    // Returns false for any other instruction, which falls back to ESIL.
+    fn process_mul_op(&mut self, opcode: &str, addr: &mut MAddress) -> bool {
+        if self.regfile.arch != Arch::X86 {
+            return false;
+        }
+        let mut words = opcode.split_whitespace();
+        let (signed, operand) = match (words.next(), words.next(), words.next()) {
+            (Some("mul"), Some(reg), None) => (false, reg),
+            (Some("imul"), Some(reg), None) => (true, reg),
+            _ => return false,
+        };
+        let width = match self.regfile.get_subregister(operand) {
+            Some(reg) => reg.width as u16,
+            None => return false,
+        };
+        let (lo, hi) = match width {
+            64 => ("rax", "rdx"),
+            32 => ("eax", "edx"),
+            _ => return false,
+        };
+        let regfile = self.regfile;
+        let whole = |name: &str| {
+            regfile.register_id_by_name(name)
+                   .and_then(|id| regfile.get_name(id))
+                   .map(|n| n.to_owned())
+        };
+        let (lo_whole, hi_whole) = match (whole(lo), whole(hi)) {
+            (Some(l), Some(h)) => (l, h),
+            _ => return false,
+        };
+
+        let lhs = self.phiplacer.read_register(addr, lo);
+        let rhs = self.phiplacer.read_register(addr, operand);
+        let high_op = if signed {
+            MOpcode::OpSMulHi(width)
+        } else {
+            MOpcode::OpMulHi(width)
+        };
+        let mut results = Vec::with_capacity(2);
+        for opcode in &[MOpcode::OpMul, high_op] {
+            let vt = ValueInfo::new_scalar(ir::WidthSpec::Known(width));
+            let node = self.phiplacer.add_op(opcode, addr, vt);
+            self.phiplacer.op_use(&node, 0, &lhs);
+            self.phiplacer.op_use(&node, 1, &rhs);
+            results.push(node);
+        }
+        self.phiplacer.write_register(addr, &lo_whole, results[0]);
+        self.phiplacer.write_register(addr, &hi_whole, results[1]);
+        true
+    }
+
+    // Chooses the instruction analyzer for the architecture of the binary and lifts the
+    // instruction with it.
+    fn analyze_custom(&mut self, bytes: Vec<u8>, addr: &mut MAddress) {
+        match self.regfile.arch {
+            Arch::X86 => {
+                let ia = X86_CS_IA::new(bytes).expect("Unable to instantiate IA");
+                self.process_custom(&ia, addr);
+            }
+            Arch::Arm => {
+                let mode = self.mode.unwrap_or(InstructionSet::Arm);
+                let ia = ARM_CS_IA::new_in(bytes, mode).expect("Unable to instantiate IA");
+                self.process_custom(&ia, addr);
+            }
+            Arch::AArch64 => {
+                let ia = ARM64_CS_IA::new(bytes).expect("Unable to instantiate IA");
+                self.process_custom(&ia, addr);
+            }
+            Arch::Unknown => {
+                radeco_warn!("No instruction analyzer for {} at {}", self.mnemonic, addr);
+            }
+        }
+    }
+
+    // Strips the instruction set selector from the target of a direct branch and records
+    // the instruction set the target is decoded in, if the branch switches to another one.
+    fn branch_target(&mut self, target: u64) -> u64 {
+        let mode = match self.mode {
+            Some(mode) => mode,
+            None => return target,
+        };
+        let (target, target_mode) = mode.branch(&self.mnemonic, target);
+        if target_mode != mode {
+            radeco_trace!("ssa_construct_mode_switch|{:#x}|{}", target, target_mode);
+            self.target_modes.insert(target, target_mode);
+        }
+        target
+    }
+
+    // Records the instruction set the callee of a direct call is decoded in. The target is
+    // the constant written to the program counter by `esil`. `blx` to an immediate calls
+    // into the other instruction set, which is the only way to tell for a callee that
+    // neither has a mapping symbol nor is reported with the low bit of its address set.
+    fn call_target(&mut self, esil: &str) {
+        let mode = match self.mode {
+            Some(mode) => mode,
+            None => return,
+        };
+        let pc = match self.regfile.alias_info.get("PC") {
+            Some(pc) => pc,
+            None => return,
+        };
+        let tokens = esil.split(',').collect::<Vec<_>>();
+        let target = tokens.windows(3)
+                           .filter(|w| w[1] == pc && w[2] == "=")
+                           .filter_map(|w| if w[0].starts_with("0x") {
+                               u64::from_str_radix(&w[0][2..], 16).ok()
+                           } else {
+                               w[0].parse::<u64>().ok()
+                           })
+                           .last();
+        if let Some(target) = target {
+            let (target, target_mode) = mode.branch(&self.mnemonic, target);
+            radeco_trace!("ssa_construct_callee_mode|{:#x}|{}", target, target_mode);
+            self.callee_modes.insert(target, target_mode);
+        }
+    }
+
+    // Returns true if `token` names the link register of the architecture, if it has one.
+    fn is_link_register(&self, token: &Option<Token>) -> bool {
+        let name = match *token {
+            Some(Token::ERegister(ref name)) | Some(Token::EIdentifier(ref name)) => name,
+            _ => return false,
+        };
+        match self.regfile.alias_info.get("LR") {
+            Some(lr) => {
+                let lr_id = self.regfile.register_id_by_name(lr);
+                lr_id.is_some() && self.regfile.register_id_by_name(name) == lr_id
+            }
+            None => false,
+        }
+    }
+
     fn process_custom<IA: InstructionAnalyzer>(&mut self, ia: &IA, addr: &mut MAddress) {
         let mnemonic = ia.mnemonic().clone();
         let opcode = MOpcode::OpCustom(mnemonic.into_owned());
@@ -712,32 +987,40 @@ impl<'a, T> SSAConstruct<'a, T>
             // base + index*scale +- disp
             let (opcode, maddr) = if let Some(mem_op) = ia.memory_written() {
                 // Insert a store
-                let st = MOpcode::OpStore;
-                let addr =
-                    if let &IOperand::Memory { ref base, ref index, ref scale, ref disp } =
+                let (addr, size) =
+                    if let &IOperand::Memory { ref base, ref index, ref scale, ref disp, size } =
                         mem_op {
-                        self.process_memory_op(base, index, *scale, *disp, addr)
+                        (self.process_memory_op(base, index, *scale, *disp, addr), size)
                     } else {
                         unreachable!()
                     };
-                (st, addr)
+                (MOpcode::OpStore(size, self.regfile.endian), addr)
             } else if let Some(mem_op) = ia.memory_read() {
                 // Insert load
-                let ld = MOpcode::OpLoad;
-                let addr =
-                    if let &IOperand::Memory { ref base, ref index, ref scale, ref disp } =
+                let (addr, size) =
+                    if let &IOperand::Memory { ref base, ref index, ref scale, ref disp, size } =
                         mem_op {
-                        self.process_memory_op(base, index, *scale, *disp, addr)
+                        (self.process_memory_op(base, index, *scale, *disp, addr), size)
                     } else {
                         unreachable!()
                     };
-                (ld, addr)
+                (MOpcode::OpLoad(size, self.regfile.endian), addr)
             } else {
                 // Memory has to be a load or a store operation, cannot be anything else
                 unreachable!()
             };
 
-            let mem_op = self.phiplacer.add_op(&opcode, addr, vt);
+            // Loads produce, and stores consume, a value of the access width.
+            let (mem_vt, value) = match opcode {
+                MOpcode::OpLoad(w, _) if w > 0 => {
+                    (ValueInfo::new_unresolved(ir::WidthSpec::from(w)), custom_opnode)
+                }
+                MOpcode::OpStore(w, _) if w > 0 => {
+                    (vt, self.phiplacer.fit_width(addr, custom_opnode, w))
+                }
+                _ => (vt, custom_opnode),
+            };
+            let mem_op = self.phiplacer.add_op(&opcode, addr, mem_vt);
             let mem = self.phiplacer.read_variable(addr, self.mem_id);
 
             // Op[Load/Store](mem, addr)
@@ -746,8 +1029,8 @@ impl<'a, T> SSAConstruct<'a, T>
 
             // Do some additional handling, such as associating the written value,
             // creating new instance of memory etc.
-            if opcode == MOpcode::OpStore {
-                self.phiplacer.op_use(&mem_op, 2, &custom_opnode);
+            if let MOpcode::OpStore(..) = opcode {
+                self.phiplacer.op_use(&mem_op, 2, &value);
                 // New instance of memory
                 self.phiplacer.write_variable(*addr, self.mem_id, mem_op);
             } else {
@@ -763,10 +1046,11 @@ mod test {
     use analysis::sccp;
     use middle::{dot, dce};
     use middle::ir_writer;
+    use middle::ssa::cfg_traits::CFG;
     use middle::ssa::ssastorage::SSAStorage;
     use r2api::structs::{LFunctionInfo, LRegInfo};
     use serde_json;
-    use std::fs::File;
+    use std::fs::{self, File};
     use std::io::prelude::*;
     use std::sync::Arc;
     use super::*;
@@ -871,6 +1155,43 @@ mod test {
         ir_writer::emit_il(&mut il, Some("main".to_owned()), &ssa).unwrap();
         println!("{}", il);
     }
+
+    // `bx r3`, with `r3` known to hold 0x3005, switches to Thumb code at 0x3004. The blocks
+    // reached from there, by falling through or by branching, are Thumb code as well.
+    #[test]
+    fn ssa_mode_fall_through() {
+        let s = fs::read_to_string("test_files/arm_file/arm_file_register_profile.json").unwrap();
+        let reg_profile: LRegInfo = serde_json::from_str(&s).unwrap();
+        let ops: Vec<LOpInfo> = serde_json::from_str(r#"[
+            {"esil":"0x3005,pc,=","offset":12288,"opcode":"bx r3","type":"ujmp","size":4},
+            {"esil":"1,r0,==,$z,zf,=","offset":12292,"opcode":"cmp r0, 1","type":"cmp","size":2},
+            {"esil":"zf,?{,0x300a,pc,=,}","offset":12294,"opcode":"beq 0x300a","type":"cjmp",
+             "size":2},
+            {"esil":"1,r0,+,r0,=","offset":12296,"opcode":"adds r0, 1","type":"add","size":2},
+            {"esil":"lr,pc,=","offset":12298,"opcode":"bx lr","type":"ret","size":2}
+        ]"#).unwrap();
+        let mut ssa = SSAStorage::new();
+        {
+            let regfile = SubRegisterFile::new_for(&reg_profile, Arch::Arm, ir::Endian::Little);
+            let regfile = Arc::new(regfile);
+            ssa.regfile = regfile.clone();
+            let mut constructor = SSAConstruct::new(&mut ssa, &*regfile);
+            constructor.run(ops.as_slice());
+        }
+        let mut starts = Vec::new();
+        for block in ssa.blocks() {
+            let start = match ssa.starting_address(block) {
+                Some(start) if start.address != u64::MAX => start.address,
+                _ => continue,
+            };
+            let mode = if start < 0x3004 { InstructionSet::Arm } else { InstructionSet::Thumb };
+            assert_eq!(ssa.block_mode(block), Some(mode), "Block at {:#x}", start);
+            starts.push(start);
+        }
+        for start in &[0x3004, 0x3008, 0x300a] {
+            assert!(starts.contains(start), "No block at {:#x}", start);
+        }
+    }
 }
 
 lazy_static! {
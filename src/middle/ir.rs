@@ -48,6 +48,124 @@ impl From<u16> for WidthSpec {
     }
 }
 
+/// Byte order used by a memory access.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Default for Endian {
+    fn default() -> Endian {
+        Endian::Little
+    }
+}
+
+impl Endian {
+    /// Interpret the first `width / 8` bytes of `bytes` as an unsigned integer
+    /// stored in this byte order. Returns `None` if there are not enough bytes.
+    pub fn decode(&self, bytes: &[u8], width: u16) -> Option<u64> {
+        let n = (width / 8) as usize;
+        if n == 0 || n > 8 || bytes.len() < n {
+            return None;
+        }
+        let bytes = &bytes[..n];
+        let fold = |acc: u64, b: &u8| (acc << 8) | (*b as u64);
+        Some(match *self {
+            Endian::Little => bytes.iter().rev().fold(0, fold),
+            Endian::Big => bytes.iter().fold(0, fold),
+        })
+    }
+
+    /// Inverse of `decode`.
+    pub fn encode(&self, val: u64, width: u16) -> Vec<u8> {
+        let n = (width / 8) as usize;
+        let mut bytes = (0..n).map(|i| (val >> (8 * i)) as u8).collect::<Vec<_>>();
+        if *self == Endian::Big {
+            bytes.reverse();
+        }
+        bytes
+    }
+}
+
+/// Reverse the byte order of the low `width` bits of `val`.
+pub fn swap_bytes(val: u64, width: u16) -> u64 {
+    match width {
+        16 => (val as u16).swap_bytes() as u64,
+        32 => (val as u32).swap_bytes() as u64,
+        64 => val.swap_bytes(),
+        _ => val,
+    }
+}
+
+impl fmt::Display for Endian {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Endian::Little => write!(f, "LE"),
+            Endian::Big => write!(f, "BE"),
+        }
+    }
+}
+
+/// Instruction set used to decode code at an address, for architectures that can switch
+/// between several of them at runtime (ARM/Thumb interworking).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InstructionSet {
+    Arm,
+    Thumb,
+}
+
+impl InstructionSet {
+    /// Splits the target of an interworking branch (`bx`, `blx`, `pop {pc}`, ...) into the
+    /// address actually branched to and the instruction set selected by its low bit.
+    pub fn from_target(target: u64) -> (u64, InstructionSet) {
+        if target & 1 == 1 {
+            (target & !1, InstructionSet::Thumb)
+        } else {
+            (target, InstructionSet::Arm)
+        }
+    }
+
+    /// Splits the target of a direct branch or call by `mnemonic`, decoded in this
+    /// instruction set, into the address branched to and the instruction set it is decoded
+    /// in. `blx` to an immediate always switches instruction sets, `bx` and other
+    /// interworking branches select one by the low bit of the target.
+    pub fn branch(&self, mnemonic: &str, target: u64) -> (u64, InstructionSet) {
+        if mnemonic.starts_with("blx") {
+            (target & !1, self.toggle())
+        } else if mnemonic.starts_with("bx") || target & 1 == 1 {
+            InstructionSet::from_target(target)
+        } else {
+            (target, *self)
+        }
+    }
+
+    /// The other instruction set, as selected by `blx` to an immediate.
+    pub fn toggle(&self) -> InstructionSet {
+        match *self {
+            InstructionSet::Arm => InstructionSet::Thumb,
+            InstructionSet::Thumb => InstructionSet::Arm,
+        }
+    }
+
+    /// Value of `asm.bits` selecting this instruction set in radare2.
+    pub fn bits(&self) -> u16 {
+        match *self {
+            InstructionSet::Arm => 32,
+            InstructionSet::Thumb => 16,
+        }
+    }
+}
+
+impl fmt::Display for InstructionSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InstructionSet::Arm => write!(f, "arm"),
+            InstructionSet::Thumb => write!(f, "thumb"),
+        }
+    }
+}
+
 #[derive(Clone, Default, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 // New address struct
 pub struct MAddress {
@@ -100,10 +218,12 @@ pub enum MArity {
     Zero,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum MOpcode {
     OpAdd,
     OpAnd,
+    // Byte Swap of a value of the given width
+    OpBSwap(u16),
     OpCJmp,
     OpCall,
     OpConst(u64),
@@ -116,23 +236,39 @@ pub enum MOpcode {
     OpIf,
     OpInvalid,
     OpJmp,
-    OpLoad,
+    // Load of the given width (in bits) from memory
+    OpLoad(u16, Endian),
     OpLsl,
     OpLsr,
     OpLt,
     OpMod,
     OpMul,
+    // High half of the product of unsigned operands of the given width
+    OpMulHi(u16),
     OpNarrow(u16),
     OpNop,
     OpNot,
     OpOr,
+    // Number of set bits
+    OpPopCount,
     // Rotate Shift Left
     OpRol,
     // Rotate Shift Right
     OpRor,
+    // Signed Division on operands of the given width
+    OpSDiv(u16),
+    // Signed Greater Than on operands of the given width
+    OpSGt(u16),
+    // Signed Less Than on operands of the given width
+    OpSLt(u16),
+    // Signed Remainder on operands of the given width
+    OpSMod(u16),
+    // High half of the product of signed operands of the given width
+    OpSMulHi(u16),
     // Sign Extend to width
     OpSignExt(u16),
-    OpStore,
+    // Store of the given width (in bits) to memory
+    OpStore(u16, Endian),
     OpSub,
     OpXor,
     // Zero Extend to width
@@ -160,6 +296,8 @@ impl MOpcode {
         match *self {
             MOpcode::OpAdd |
             MOpcode::OpMul |
+            MOpcode::OpMulHi(_) |
+            MOpcode::OpSMulHi(_) |
             MOpcode::OpAnd |
             MOpcode::OpOr |
             MOpcode::OpXor |
@@ -170,7 +308,7 @@ impl MOpcode {
 
     pub fn has_sideeffects(&self) -> bool {
         match *self {
-            MOpcode::OpStore |
+            MOpcode::OpStore(..) |
             MOpcode::OpJmp |
             MOpcode::OpCJmp |
             MOpcode::OpCall |
@@ -194,6 +332,7 @@ impl MOpcode {
         match *self {
             MOpcode::OpAdd => (Cow::from("OpAdd"), MArity::Binary),
             MOpcode::OpAnd => (Cow::from("OpAnd"), MArity::Binary),
+            MOpcode::OpBSwap(_) => (Cow::from("OpBSwap"), MArity::Unary),
             MOpcode::OpCJmp => (Cow::from("OpJmpIf"), MArity::Binary),
             MOpcode::OpCall => (Cow::from("OpCall"), MArity::Unary),
             MOpcode::OpConst(c) => (Cow::from(format!("OpCost({})", c)), MArity::Zero),
@@ -205,20 +344,27 @@ impl MOpcode {
             MOpcode::OpIf => (Cow::from("OpIf"), MArity::Unary),
             MOpcode::OpInvalid => (Cow::from("OpInvalid"), MArity::Zero),
             MOpcode::OpJmp => (Cow::from("OpJmp"), MArity::Unary),
-            MOpcode::OpLoad => (Cow::from("OpLoad"), MArity::Binary),
+            MOpcode::OpLoad(..) => (Cow::from("OpLoad"), MArity::Binary),
             MOpcode::OpLsl => (Cow::from("OpLsl"), MArity::Binary),
             MOpcode::OpLsr => (Cow::from("OpLsr"), MArity::Binary),
             MOpcode::OpLt => (Cow::from("OpLt"), MArity::Binary),
             MOpcode::OpMod => (Cow::from("OpMod"), MArity::Binary),
             MOpcode::OpMul => (Cow::from("OpMul"), MArity::Binary),
+            MOpcode::OpMulHi(_) => (Cow::from("OpMulHi"), MArity::Binary),
             MOpcode::OpNarrow(_) => (Cow::from("OpNarrow"), MArity::Unary),
             MOpcode::OpNop => (Cow::from("OpNop"), MArity::Zero),
             MOpcode::OpNot => (Cow::from("OpNot"), MArity::Unary),
             MOpcode::OpOr => (Cow::from("OpOr"), MArity::Binary),
+            MOpcode::OpPopCount => (Cow::from("OpPopCount"), MArity::Unary),
             MOpcode::OpRol => (Cow::from("OpRol"), MArity::Binary),
             MOpcode::OpRor => (Cow::from("OpRor"), MArity::Binary),
+            MOpcode::OpSDiv(_) => (Cow::from("OpSDiv"), MArity::Binary),
+            MOpcode::OpSGt(_) => (Cow::from("OpSGt"), MArity::Binary),
+            MOpcode::OpSLt(_) => (Cow::from("OpSLt"), MArity::Binary),
+            MOpcode::OpSMod(_) => (Cow::from("OpSMod"), MArity::Binary),
+            MOpcode::OpSMulHi(_) => (Cow::from("OpSMulHi"), MArity::Binary),
             MOpcode::OpSignExt(_) => (Cow::from("OpSignExt"), MArity::Unary),
-            MOpcode::OpStore => (Cow::from("OpStore"), MArity::Ternary),
+            MOpcode::OpStore(..) => (Cow::from("OpStore"), MArity::Ternary),
             MOpcode::OpSub => (Cow::from("OpSub"), MArity::Binary),
             MOpcode::OpXor => (Cow::from("OpXor"), MArity::Binary),
             MOpcode::OpZeroExt(_) => (Cow::from("OpZeroExt"), MArity::Unary),
@@ -240,7 +386,7 @@ impl MOpcode {
             MOpcode::OpIf => 10,
             MOpcode::OpInvalid => 11,
             MOpcode::OpJmp => 12,
-            MOpcode::OpLoad => 13,
+            MOpcode::OpLoad(..) => 13,
             MOpcode::OpLsl => 14,
             MOpcode::OpLsr => 15,
             MOpcode::OpLt => 16,
@@ -253,10 +399,44 @@ impl MOpcode {
             MOpcode::OpRol => 23,
             MOpcode::OpRor => 24,
             MOpcode::OpSignExt(_) => 25,
-            MOpcode::OpStore => 26,
+            MOpcode::OpStore(..) => 26,
             MOpcode::OpSub => 27,
             MOpcode::OpXor => 28,
             MOpcode::OpZeroExt(_) => 29,
+            MOpcode::OpSGt(_) => 30,
+            MOpcode::OpSLt(_) => 31,
+            MOpcode::OpSDiv(_) => 32,
+            MOpcode::OpSMod(_) => 33,
+            MOpcode::OpBSwap(_) => 34,
+            MOpcode::OpPopCount => 35,
+            MOpcode::OpMulHi(_) => 36,
+            MOpcode::OpSMulHi(_) => 37,
+        }
+    }
+
+    /// Byte order of a memory operation, `None` for every other opcode.
+    pub fn endian(&self) -> Option<Endian> {
+        match *self {
+            MOpcode::OpLoad(_, e) | MOpcode::OpStore(_, e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Number of bits read or written by a memory operation, `None` for every
+    /// other opcode.
+    pub fn access_width(&self) -> Option<u16> {
+        match *self {
+            MOpcode::OpLoad(w, _) | MOpcode::OpStore(w, _) => Some(w),
+            _ => None,
+        }
+    }
+
+    /// Returns true for opcodes producing a single bit from comparing their operands.
+    pub fn is_comparison(&self) -> bool {
+        match *self {
+            MOpcode::OpEq | MOpcode::OpGt | MOpcode::OpLt |
+            MOpcode::OpSGt(_) | MOpcode::OpSLt(_) => true,
+            _ => false,
         }
     }
 
@@ -264,6 +444,36 @@ impl MOpcode {
         use self::MOpcode::*;
         use std::num::Wrapping;
 
+        match *self {
+            OpSGt(w) => return Some((sign_extend(lhs, w) > sign_extend(rhs, w)) as u64),
+            OpSLt(w) => return Some((sign_extend(lhs, w) < sign_extend(rhs, w)) as u64),
+            OpSDiv(w) | OpSMod(w) => {
+                let (lhs, rhs) = (sign_extend(lhs, w), sign_extend(rhs, w));
+                if rhs == 0 {
+                    return None;
+                }
+                let res = if let OpSDiv(_) = *self {
+                    lhs.wrapping_div(rhs)
+                } else {
+                    lhs.wrapping_rem(rhs)
+                };
+                let mask = if w == 0 || w >= 64 { !0 } else { (1 << w) - 1 };
+                return Some(res as u64 & mask);
+            }
+            OpMulHi(w) | OpSMulHi(w) => {
+                if w == 0 || w > 64 {
+                    return None;
+                }
+                let product = if let OpMulHi(_) = *self {
+                    (lhs & width_mask(w)) as u128 * (rhs & width_mask(w)) as u128
+                } else {
+                    (sign_extend(lhs, w) as i128 * sign_extend(rhs, w) as i128) as u128
+                };
+                return Some((product >> w) as u64 & width_mask(w));
+            }
+            _ => (),
+        }
+
         let lhs = Wrapping(lhs);
         let rhs = Wrapping(rhs);
         Some(match self {
@@ -289,11 +499,45 @@ impl MOpcode {
     pub fn eval_unop(&self, val: u64) -> Option<u64> {
         use self::MOpcode::*;
 
-        Some(match self {
+        Some(match *self {
             OpNot => !val,
+            OpPopCount => val.count_ones() as u64,
+            OpBSwap(w) if w % 8 == 0 && w > 0 && w <= 64 => {
+                (val & width_mask(w)).swap_bytes() >> (64 - w)
+            }
             _ => return None,
         })
     }
+
+    /// Evaluates a rotate of the `width` bit `value`. Unlike `eval_binop` this does not
+    /// assume 64 bit operands.
+    pub fn eval_rotate(&self, value: u64, amount: u64, width: u16) -> Option<u64> {
+        if width == 0 || width > 64 {
+            return None;
+        }
+        let value = value & width_mask(width);
+        let amount = (amount % width as u64) as u32;
+        let (l, r) = match *self {
+            MOpcode::OpRol => (amount, (width as u32 - amount) % width as u32),
+            MOpcode::OpRor => ((width as u32 - amount) % width as u32, amount),
+            _ => return None,
+        };
+        Some(((value << l) | (value >> r)) & width_mask(width))
+    }
+}
+
+/// Mask of the low `width` bits.
+pub fn width_mask(width: u16) -> u64 {
+    if width >= 64 { !0 } else { (1 << width) - 1 }
+}
+
+/// Interprets the low `width` bits of `value` as a two's complement number.
+pub fn sign_extend(value: u64, width: u16) -> i64 {
+    if width == 0 || width >= 64 {
+        return value as i64;
+    }
+    let shift = 64 - width as u32;
+    ((value << shift) as i64) >> shift
 }
 
 impl fmt::Display for MOpcode {
@@ -301,3 +545,79 @@ impl fmt::Display for MOpcode {
         write!(f, "{}", self.to_string())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn endian_roundtrip() {
+        let bytes = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+        assert_eq!(Endian::Little.decode(&bytes, 32), Some(0x44332211));
+        assert_eq!(Endian::Big.decode(&bytes, 32), Some(0x11223344));
+        assert_eq!(Endian::Big.decode(&bytes, 64), Some(0x1122334455667788));
+        assert_eq!(Endian::Little.decode(&bytes[..1], 16), None);
+        for &e in &[Endian::Little, Endian::Big] {
+            for &w in &[8, 16, 32, 64] {
+                let v = e.decode(&bytes, w).unwrap();
+                assert_eq!(e.decode(&e.encode(v, w), w), Some(v));
+            }
+        }
+    }
+
+    #[test]
+    fn swap_bytes_width() {
+        assert_eq!(swap_bytes(0x1122, 16), 0x2211);
+        assert_eq!(swap_bytes(0x11223344, 32), 0x44332211);
+        assert_eq!(swap_bytes(0xab, 8), 0xab);
+        let v = 0x0102030405060708;
+        assert_eq!(swap_bytes(v, 64), Endian::Little.decode(&Endian::Big.encode(v, 64), 64).unwrap());
+    }
+
+    #[test]
+    fn interworking_target() {
+        assert_eq!(InstructionSet::from_target(0x10401), (0x10400, InstructionSet::Thumb));
+        assert_eq!(InstructionSet::from_target(0x10400), (0x10400, InstructionSet::Arm));
+        assert_eq!(InstructionSet::Thumb.toggle(), InstructionSet::Arm);
+    }
+
+    #[test]
+    fn signed_compare() {
+        assert_eq!(MOpcode::OpSLt(8).eval_binop(0xff, 0x1), Some(1));
+        assert_eq!(MOpcode::OpLt.eval_binop(0xff, 0x1), Some(0));
+        assert_eq!(MOpcode::OpSGt(32).eval_binop(0x7fffffff, 0x80000000), Some(1));
+        assert_eq!(MOpcode::OpSLt(64).eval_binop(!0, 0), Some(1));
+        assert_eq!(sign_extend(0x8000, 16), -0x8000);
+    }
+
+    #[test]
+    fn signed_divide() {
+        assert_eq!(MOpcode::OpSDiv(32).eval_binop(0xfffffff9, 2), Some(0xfffffffd));
+        assert_eq!(MOpcode::OpSMod(32).eval_binop(0xfffffff9, 2), Some(0xffffffff));
+        assert_eq!(MOpcode::OpSDiv(8).eval_binop(0x80, 0xff), Some(0x80));
+        assert_eq!(MOpcode::OpSMod(64).eval_binop(7, !0), Some(0));
+        assert_eq!(MOpcode::OpSDiv(64).eval_binop(7, 0), None);
+    }
+
+    #[test]
+    fn multiply_high() {
+        assert_eq!(MOpcode::OpMulHi(64).eval_binop(!0, !0), Some(!0 - 1));
+        assert_eq!(MOpcode::OpSMulHi(64).eval_binop(!0, !0), Some(0));
+        assert_eq!(MOpcode::OpMulHi(32).eval_binop(0xffffffff, 2), Some(1));
+        assert_eq!(MOpcode::OpSMulHi(32).eval_binop(0xffffffff, 2), Some(0xffffffff));
+        // The product with the magic number of 10, which is shifted right by 3 more.
+        assert_eq!(MOpcode::OpMulHi(64).eval_binop(0xcccccccccccccccd, 100), Some(80));
+    }
+
+    #[test]
+    fn bit_operations() {
+        assert_eq!(MOpcode::OpBSwap(32).eval_unop(0x11223344), Some(0x44332211));
+        assert_eq!(MOpcode::OpBSwap(16).eval_unop(0xff1122), Some(0x2211));
+        assert_eq!(MOpcode::OpBSwap(12).eval_unop(0x123), None);
+        assert_eq!(MOpcode::OpPopCount.eval_unop(0xf0f0), Some(8));
+        assert_eq!(MOpcode::OpRol.eval_rotate(0x81, 1, 8), Some(0x03));
+        assert_eq!(MOpcode::OpRor.eval_rotate(0x81, 1, 8), Some(0xc0));
+        assert_eq!(MOpcode::OpRol.eval_rotate(0x12345678, 32, 32), Some(0x12345678));
+        assert_eq!(MOpcode::OpRor.eval_rotate(1, 1, 64), Some(1 << 63));
+    }
+}
@@ -209,11 +209,20 @@ impl<'a> LowerSsa<'a> {
                         (lower_infix_op(sopcode), vec![sop0, sop1])
                     }
                     sast::Expr::Prefix(sopcode, sop0) => (lower_prefix_op(sopcode), vec![sop0]),
-                    sast::Expr::Load(sop0, sop1) => (IrOpcode::OpLoad, vec![sop0, sop1]),
-                    sast::Expr::Store(sop0, sop1, sop2) => {
-                        (IrOpcode::OpStore, vec![sop0, sop1, sop2])
+                    sast::Expr::Load(e, sws, sop0, sop1) => {
+                        (IrOpcode::OpLoad(sws.0, e), vec![sop0, sop1])
+                    }
+                    sast::Expr::Store(e, sws, sop0, sop1, sop2) => {
+                        (IrOpcode::OpStore(sws.0, e), vec![sop0, sop1, sop2])
                     }
                     sast::Expr::Resize(rst, ws, sop0) => (lower_resize_op(rst, ws), vec![sop0]),
+                    sast::Expr::Signed(sop, ws, sop0, sop1) => {
+                        (lower_signed_op(sop, ws), vec![sop0, sop1])
+                    }
+                    sast::Expr::MulHi(ws, sop0, sop1) => {
+                        (IrOpcode::OpMulHi(ws.0), vec![sop0, sop1])
+                    }
+                    sast::Expr::ByteSwap(ws, sop0) => (IrOpcode::OpBSwap(ws.0), vec![sop0]),
                 };
                 let res = self.ssa.insert_op(opcode, vi, None)?;
                 for (i, sop) in sops.into_iter().enumerate() {
@@ -330,12 +339,15 @@ fn lower_infix_op(siop: sast::InfixOp) -> IrOpcode {
         sast::InfixOp::Lt => IrOpcode::OpLt,
         sast::InfixOp::Lsl => IrOpcode::OpLsl,
         sast::InfixOp::Lsr => IrOpcode::OpLsr,
+        sast::InfixOp::Rol => IrOpcode::OpRol,
+        sast::InfixOp::Ror => IrOpcode::OpRor,
     }
 }
 
 fn lower_prefix_op(spop: sast::PrefixOp) -> IrOpcode {
     match spop {
         sast::PrefixOp::Not => IrOpcode::OpNot,
+        sast::PrefixOp::PopCount => IrOpcode::OpPopCount,
     }
 }
 
@@ -347,6 +359,16 @@ fn lower_resize_op(srst: sast::ResizeType, sws: sast::WidthSpec) -> IrOpcode {
     }
 }
 
+fn lower_signed_op(sop: sast::SignedOp, sws: sast::WidthSpec) -> IrOpcode {
+    match sop {
+        sast::SignedOp::Gt => IrOpcode::OpSGt(sws.0),
+        sast::SignedOp::Lt => IrOpcode::OpSLt(sws.0),
+        sast::SignedOp::Div => IrOpcode::OpSDiv(sws.0),
+        sast::SignedOp::Mod => IrOpcode::OpSMod(sws.0),
+        sast::SignedOp::MulHi => IrOpcode::OpSMulHi(sws.0),
+    }
+}
+
 fn lower_valueinfo(sty: sast::Type) -> ValueInfo {
     let ws = ir::WidthSpec::Known((sty.0).0);
     match sty.1 {
@@ -6,6 +6,8 @@ mod parser_util;
 mod simple_ast;
 #[cfg(test)]
 mod test;
+#[cfg(test)]
+pub mod test_util;
 
 use middle::regfile::SubRegisterFile;
 use middle::ssa::ssastorage::SSAStorage;
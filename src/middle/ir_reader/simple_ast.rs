@@ -60,9 +60,12 @@ pub struct CallArg {
 pub enum Expr {
     Infix(Operand, InfixOp, Operand),
     Prefix(PrefixOp, Operand),
-    Load(Operand, Operand),
-    Store(Operand, Operand, Operand),
+    Load(ir::Endian, WidthSpec, Operand, Operand),
+    Store(ir::Endian, WidthSpec, Operand, Operand, Operand),
     Resize(ResizeType, WidthSpec, Operand),
+    Signed(SignedOp, WidthSpec, Operand, Operand),
+    MulHi(WidthSpec, Operand, Operand),
+    ByteSwap(WidthSpec, Operand),
 }
 
 #[derive(Debug)]
@@ -72,6 +75,15 @@ pub enum ResizeType {
     ZeroExt,
 }
 
+#[derive(Debug)]
+pub enum SignedOp {
+    Gt,
+    Lt,
+    Div,
+    Mod,
+    MulHi,
+}
+
 #[derive(Debug)]
 pub enum Operand {
     ValueRef(ValueRef),
@@ -81,6 +93,7 @@ pub enum Operand {
 #[derive(Debug)]
 pub enum PrefixOp {
     Not,
+    PopCount,
 }
 
 #[derive(Debug)]
@@ -98,6 +111,8 @@ pub enum InfixOp {
     Lt,
     Lsl,
     Lsr,
+    Rol,
+    Ror,
 }
 
 #[derive(Debug)]
@@ -46,7 +46,7 @@ define-fun main(unknown) -> unknown {
         %34: $Unknown0 = $mem;
     bb_0x0005B5.0000(sz 0x0):
         [@0x0005B5.0001] %35: $Unknown64(*?) = #x1 + %14;
-        [@0x0005B9.0002] %36: $Unknown64(*?) = Load(%34, %26);
+        [@0x0005B9.0002] %36: $Unknown64(*?) = Load64(%34, %26);
         [@0x0005B9.0006] %37: $Unknown64(*?) = #x8 + %26;
         JMP TO %36
     exit-node:
@@ -153,6 +153,133 @@ fn roundtrip_basic_ssa() {
     roundtrip("main".to_owned(), SSA_TXT);
 }
 
+#[test]
+fn roundtrip_big_endian_ssa() {
+    use middle::ir::{Endian, MOpcode};
+    use middle::ssa::ssa_traits::SSA;
+    use middle::ssa::ssa_traits::SSAWalk;
+
+    let ssa_txt = SSA_TXT.replace("= Load64(", "= LoadBE64(");
+    let ssa = super::parse_il(&ssa_txt, REGISTER_FILE.clone());
+    let loads = ssa
+        .inorder_walk()
+        .filter_map(|n| ssa.opcode(n))
+        .filter(|op| op.endian().is_some())
+        .collect::<Vec<_>>();
+    assert_eq!(loads, vec![MOpcode::OpLoad(64, Endian::Big)]);
+    roundtrip("main".to_owned(), &ssa_txt);
+}
+
+#[test]
+fn access_width_matches_value() {
+    use middle::ssa::ssa_traits::SSA;
+    use middle::ssa::ssa_traits::SSAWalk;
+
+    let ssa_txt = SSA_TXT.replace("= Load64(", "= Load32(").replace(
+        "%36: $Unknown64(*?) = Load32(",
+        "%36: $Unknown32(*?) = Load32(",
+    );
+    let ssa = super::parse_il(&ssa_txt, REGISTER_FILE.clone());
+    let widths = ssa
+        .inorder_walk()
+        .filter_map(|n| {
+            let aw = ssa.opcode(n).and_then(|op| op.access_width())?;
+            let vw = ssa.node_data(n).ok()?.vt.width().get_width();
+            Some((aw, vw))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(widths, vec![(32, Some(32))]);
+    roundtrip("main".to_owned(), &ssa_txt);
+}
+
+#[test]
+fn roundtrip_signed_compare() {
+    use middle::ir::MOpcode;
+    use middle::ssa::ssa_traits::SSA;
+    use middle::ssa::ssa_traits::SSAWalk;
+
+    let ssa_txt = SSA_TXT.replace(
+        "%35: $Unknown64(*?) = #x1 + %14;",
+        "%35: $Unknown1 = SLt32(#x1, %14);",
+    );
+    let ssa = super::parse_il(&ssa_txt, REGISTER_FILE.clone());
+    assert!(ssa
+        .inorder_walk()
+        .any(|n| ssa.opcode(n) == Some(MOpcode::OpSLt(32))));
+    roundtrip("main".to_owned(), &ssa_txt);
+}
+
+#[test]
+fn roundtrip_signed_divide() {
+    use middle::ir::MOpcode;
+    use middle::ssa::ssa_traits::SSA;
+    use middle::ssa::ssa_traits::SSAWalk;
+
+    let ssa_txt = SSA_TXT.replace(
+        "%35: $Unknown64(*?) = #x1 + %14;",
+        "%35: $Unknown64 = SMod64(%14, #x7);",
+    );
+    let ssa = super::parse_il(&ssa_txt, REGISTER_FILE.clone());
+    assert!(ssa
+        .inorder_walk()
+        .any(|n| ssa.opcode(n) == Some(MOpcode::OpSMod(64))));
+    roundtrip("main".to_owned(), &ssa_txt);
+}
+
+#[test]
+fn roundtrip_multiply_high() {
+    use middle::ir::MOpcode;
+    use middle::ssa::ssa_traits::SSA;
+    use middle::ssa::ssa_traits::SSAWalk;
+
+    let ssa_txt = SSA_TXT
+        .replace(
+            "%35: $Unknown64(*?) = #x1 + %14;",
+            "%35: $Unknown64 = MulHi64(%14, #xcccccccccccccccd);",
+        )
+        .replace(
+            "%37: $Unknown64(*?) = #x8 + %26;",
+            "%37: $Unknown64 = SMulHi64(%26, #x5555555555555556);",
+        );
+    let ssa = super::parse_il(&ssa_txt, REGISTER_FILE.clone());
+    let ops = ssa.inorder_walk().filter_map(|n| ssa.opcode(n)).collect::<Vec<_>>();
+    assert!(ops.contains(&MOpcode::OpMulHi(64)));
+    assert!(ops.contains(&MOpcode::OpSMulHi(64)));
+    roundtrip("main".to_owned(), &ssa_txt);
+}
+
+#[test]
+fn roundtrip_bit_operations() {
+    use middle::ir::MOpcode;
+    use middle::ssa::ssa_traits::SSA;
+    use middle::ssa::ssa_traits::SSAWalk;
+
+    let ssa_txt = SSA_TXT
+        .replace(
+            "%35: $Unknown64(*?) = #x1 + %14;",
+            "%35: $Unknown64 = Rol(%14, #x1d);",
+        )
+        .replace(
+            "%37: $Unknown64(*?) = #x8 + %26;",
+            "%37: $Unknown64 = BSwap64(%26);",
+        );
+    let ssa = super::parse_il(&ssa_txt, REGISTER_FILE.clone());
+    let ops = ssa.inorder_walk().filter_map(|n| ssa.opcode(n)).collect::<Vec<_>>();
+    assert!(ops.contains(&MOpcode::OpRol));
+    assert!(ops.contains(&MOpcode::OpBSwap(64)));
+    roundtrip("main".to_owned(), &ssa_txt);
+
+    let ssa_txt = SSA_TXT.replace(
+        "%35: $Unknown64(*?) = #x1 + %14;",
+        "%35: $Unknown64 = PopCount(%14);",
+    );
+    let ssa = super::parse_il(&ssa_txt, REGISTER_FILE.clone());
+    assert!(ssa
+        .inorder_walk()
+        .any(|n| ssa.opcode(n) == Some(MOpcode::OpPopCount)));
+    roundtrip("main".to_owned(), &ssa_txt);
+}
+
 #[test]
 fn roundtrip_bin1_main() {
     roundtrip_file("main".to_owned(), "test_files/bin1_main_ssa");
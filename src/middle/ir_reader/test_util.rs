@@ -0,0 +1,89 @@
+//! Helpers for tests running passes on IL, parsed with the x86-64 register profile.
+
+use std::fs;
+use std::sync::Arc;
+
+use r2api::structs::LRegInfo;
+use serde_json;
+
+use middle::ir::{MAddress, MOpcode};
+use middle::regfile::SubRegisterFile;
+use middle::ssa::ssa_traits::*;
+use middle::ssa::ssastorage::SSAStorage;
+
+use super::parse_il;
+
+type SSAValue = <SSAStorage as SSA>::ValueRef;
+
+pub const REGISTER_PROFILE: &'static str = "test_files/x86_register_profile.json";
+
+/// The x86-64 register profile as reported by radare2.
+pub fn register_profile() -> LRegInfo {
+    let s = fs::read_to_string(REGISTER_PROFILE).unwrap();
+    serde_json::from_str(&s).unwrap()
+}
+
+pub fn regfile() -> Arc<SubRegisterFile> {
+    Arc::new(SubRegisterFile::new(&register_profile()))
+}
+
+pub fn parse(il: &str) -> SSAStorage {
+    parse_il(il, regfile())
+}
+
+/// The operation at `address`.`offset`.
+pub fn at(ssa: &SSAStorage, address: u64, offset: u64) -> SSAValue {
+    let addr = MAddress::new(address, offset);
+    ssa.inorder_walk()
+       .find(|&n| ssa.opcode(n).is_some() && ssa.address(n) == Some(addr))
+       .unwrap_or_else(|| panic!("No operation at {}", addr))
+}
+
+/// The last operation which is not a constant.
+pub fn last_op(ssa: &SSAStorage) -> SSAValue {
+    ssa.inorder_walk()
+       .filter(|&n| ssa.opcode(n).is_some() && !ssa.is_constant(n))
+       .last()
+       .unwrap()
+}
+
+/// The operands of the subtraction done at `address` whose flags a condition tests, skipping
+/// the address computation of a memory operand and the constant shift amounts of the sign
+/// flag.
+pub fn compared(ssa: &SSAStorage, address: u64) -> (SSAValue, SSAValue) {
+    let sub = ssa.values()
+        .into_iter()
+        .filter(|&v| ssa.opcode(v) == Some(MOpcode::OpSub))
+        .filter(|&v| !ssa.operands_of(v).iter().all(|&o| ssa.constant(o).is_some()))
+        .filter_map(|v| ssa.address(v).map(|addr| (addr, v)))
+        .filter(|&(addr, _)| addr.address == address)
+        .max_by_key(|&(addr, _)| addr.offset)
+        .expect("No comparison found")
+        .1;
+    let operands = ssa.operands_of(sub);
+    (operands[0], operands[1])
+}
+
+/// Renders `v` as an expression over the compared operands `a` and `b`, e.g.
+/// `(OpNot (OpSLt(32) a b))`.
+pub fn shape(ssa: &SSAStorage, (a, b): (SSAValue, SSAValue), v: SSAValue) -> String {
+    if v == a {
+        return "a".to_owned();
+    }
+    if v == b {
+        return "b".to_owned();
+    }
+    if let Some(c) = ssa.constant(v) {
+        return format!("#x{:x}", c);
+    }
+    match ssa.opcode(v) {
+        Some(op) => {
+            let operands = ssa.operands_of(v)
+                .into_iter()
+                .map(|o| shape(ssa, (a, b), o))
+                .collect::<Vec<_>>();
+            format!("({:?} {})", op, operands.join(" "))
+        }
+        None => "?".to_owned(),
+    }
+}
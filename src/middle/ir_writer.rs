@@ -7,7 +7,7 @@
 //! representation is inspired from (and probably similar) LLVM IR.
 
 use frontend::radeco_containers::RadecoFunction;
-use middle::ir::MOpcode;
+use middle::ir::{Endian, MOpcode};
 use middle::ssa::cfg_traits::CFG;
 use middle::ssa::ssa_traits::{SSAWalk, ValueInfo, SSA};
 use middle::ssa::ssastorage::{NodeData, SSAStorage};
@@ -66,6 +66,14 @@ pub fn pretty_print_function_proto(rfn: &RadecoFunction) -> String {
     format!("{} ({})", rfn.name, args)
 }
 
+// Little-endian accesses are the common case and are printed without a suffix.
+fn endian_suffix(e: Endian) -> &'static str {
+    match e {
+        Endian::Little => "",
+        Endian::Big => "BE",
+    }
+}
+
 #[derive(Clone, Debug)]
 struct IRWriter<'a, O: Write> {
     ssa: &'a SSAStorage,
@@ -220,16 +228,36 @@ impl<'a, O: Write> IRWriter<'a, O> {
                 self.emit_opt_operand(operands.get(0).cloned())?;
                 Ok(())
             }
-            OpLoad => {
-                write!(self.output, "Load(")?;
+            OpRol | OpRor => {
+                write!(self.output, "{}(", if let OpRol = *opcode { "Rol" } else { "Ror" })?;
                 self.emit_opt_operand(operands.get(0).cloned())?;
                 write!(self.output, ", ")?;
                 self.emit_opt_operand(operands.get(1).cloned())?;
                 write!(self.output, ")")?;
                 Ok(())
             }
-            OpStore => {
-                write!(self.output, "Store(")?;
+            OpBSwap(wd) => {
+                write!(self.output, "BSwap{}(", wd)?;
+                self.emit_opt_operand(operands.get(0).cloned())?;
+                write!(self.output, ")")?;
+                Ok(())
+            }
+            OpPopCount => {
+                write!(self.output, "PopCount(")?;
+                self.emit_opt_operand(operands.get(0).cloned())?;
+                write!(self.output, ")")?;
+                Ok(())
+            }
+            OpLoad(wd, e) => {
+                write!(self.output, "Load{}{}(", endian_suffix(e), wd)?;
+                self.emit_opt_operand(operands.get(0).cloned())?;
+                write!(self.output, ", ")?;
+                self.emit_opt_operand(operands.get(1).cloned())?;
+                write!(self.output, ")")?;
+                Ok(())
+            }
+            OpStore(wd, e) => {
+                write!(self.output, "Store{}{}(", endian_suffix(e), wd)?;
                 self.emit_opt_operand(operands.get(0).cloned())?;
                 write!(self.output, ", ")?;
                 self.emit_opt_operand(operands.get(1).cloned())?;
@@ -256,6 +284,22 @@ impl<'a, O: Write> IRWriter<'a, O> {
                 write!(self.output, ")")?;
                 Ok(())
             }
+            OpSGt(wd) | OpSLt(wd) | OpSDiv(wd) | OpSMod(wd) | OpMulHi(wd) | OpSMulHi(wd) => {
+                let name = match *opcode {
+                    OpSGt(_) => "SGt",
+                    OpSLt(_) => "SLt",
+                    OpSDiv(_) => "SDiv",
+                    OpSMod(_) => "SMod",
+                    OpMulHi(_) => "MulHi",
+                    _ => "SMulHi",
+                };
+                write!(self.output, "{}{}(", name, wd)?;
+                self.emit_opt_operand(operands.get(0).cloned())?;
+                write!(self.output, ", ")?;
+                self.emit_opt_operand(operands.get(1).cloned())?;
+                write!(self.output, ")")?;
+                Ok(())
+            }
             _ => {
                 radeco_warn!("unknown opcode: {:?}", opcode);
                 write!(self.output, "{}(", opcode)?;
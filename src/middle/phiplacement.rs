@@ -16,7 +16,7 @@ use std::u64;
 use r2api::structs::{LOpInfo};
 use middle::ssa::ssa_traits::{SSAMod, SSAExtra, ValueInfo};
 use middle::ssa::graph_traits::{Graph, EdgeInfo, ConditionInfo};
-use middle::ir::{self, MAddress, MOpcode};
+use middle::ir::{self, InstructionSet, MAddress, MOpcode};
 
 use middle::ssa::ssa_traits::{NodeType, NodeData};
 use middle::regfile::{RegisterId, SubRegisterFile};
@@ -383,6 +383,46 @@ impl<'a, T> PhiPlacer<'a, T>
         self.op_use(&op_node, 0, selector);
     }
 
+    // Function to add a return from the function, i.e. a transfer to the exit node
+    pub fn add_return(&mut self, current_addr: &MAddress) {
+        let source_block = self.block_of(*current_addr).unwrap_or_else(|| {
+            radeco_err!("Block not found @ {:?}", current_addr);
+            self.ssa.invalid_action().unwrap()
+        });
+        let exit_node = exit_node_err!(self.ssa);
+        radeco_trace!("phip_add_return|{} --{}--> exit", current_addr, UNCOND_EDGE);
+        self.ssa.insert_control_edge(source_block, exit_node, UNCOND_EDGE);
+    }
+
+    // Records the instruction set of every block. Blocks starting at the target of a branch
+    // that switches instruction sets are decoded in the one it selects, every other block in
+    // the one of the blocks it is reached from, falling through or branched to. Blocks that
+    // are not reachable from the entry are decoded in `default`.
+    pub fn set_block_modes(&mut self, default: InstructionSet,
+                           targets: &HashMap<u64, InstructionSet>) {
+        let mut modes = HashMap::new();
+        let mut worklist = self.ssa
+                               .entry_node()
+                               .into_iter()
+                               .map(|b| (b, default))
+                               .collect::<Vec<_>>();
+        while let Some((block, mode)) = worklist.pop() {
+            let mode = match self.ssa.starting_address(block) {
+                Some(addr) if addr.offset == 0 => {
+                    targets.get(&addr.address).cloned().unwrap_or(mode)
+                }
+                _ => mode,
+            };
+            if modes.insert(block, mode).is_some() {
+                continue;
+            }
+            worklist.extend(self.ssa.succs_of(block).into_iter().map(|b| (b, mode)));
+        }
+        for block in self.blocks.values() {
+            self.ssa.set_block_mode(*block, modes.get(block).cloned().unwrap_or(default));
+        }
+    }
+
     pub fn add_edge(&mut self, source: MAddress, target: MAddress, cftype: u8) {
         let source_block = self.block_of(source).unwrap_or_else(|| {
             radeco_err!("Block not found @ {:?}", source);
@@ -607,7 +647,7 @@ impl<'a, T> PhiPlacer<'a, T>
         let vt = vt_option.unwrap();
         let width = vt.width().get_width().unwrap_or(64);
         if width < 64 {
-            let val: u64 = value & (1 << (width) - 1);
+            let val: u64 = value & ((1 << width) - 1);
             let const_node = self.ssa.insert_const(val)
                                         .unwrap_or_else(|| {
                                             radeco_err!("Cannot insert new constants");
@@ -835,6 +875,23 @@ impl<'a, T> PhiPlacer<'a, T>
         }
     }
 
+    /// Narrow or zero extend `value` so that it is exactly `width` bits wide.
+    pub fn fit_width(&mut self,
+                     address: &mut MAddress,
+                     value: T::ValueRef,
+                     width: u16)
+                     -> T::ValueRef {
+        let opcode = match width.cmp(&self.operand_width(&value)) {
+            Ordering::Equal => return value,
+            Ordering::Less => MOpcode::OpNarrow(width),
+            Ordering::Greater => MOpcode::OpZeroExt(width),
+        };
+        let vt = ValueInfo::new_unresolved(ir::WidthSpec::from(width));
+        let node = self.add_op(&opcode, address, vt);
+        self.op_use(&node, 0, &value);
+        node
+    }
+
     // For constant, we should narrow it rather widening another OpCode
     pub fn narrow_const_operand(&mut self, 
                                 address: &mut MAddress, 
@@ -41,6 +41,37 @@ impl SubRegister {
     }
 }
 
+/// Architectures with an instruction analyzer, used for the instructions that ESIL does
+/// not describe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arch {
+    X86,
+    /// 32-bit ARM, which switches between the ARM and Thumb instruction sets.
+    Arm,
+    AArch64,
+    /// Any other architecture, whose instructions are only lifted from ESIL.
+    Unknown,
+}
+
+impl Arch {
+    /// The architecture radare2 names `arch` (as in `asm.arch` or `bin.arch`) for code of
+    /// `bits` bits.
+    pub fn from_r2(arch: &str, bits: u64) -> Arch {
+        match (arch, bits) {
+            ("x86", _) => Arch::X86,
+            ("arm", 64) => Arch::AArch64,
+            ("arm", _) => Arch::Arm,
+            _ => Arch::Unknown,
+        }
+    }
+}
+
+impl Default for Arch {
+    fn default() -> Arch {
+        Arch::X86
+    }
+}
+
 /// A structure containing information about whole and partial registers of a platform.
 /// Upon creation it builds a vector of `ValueType`s representing whole registers
 /// to be added to a `PhiPlacer`.
@@ -60,6 +91,10 @@ pub struct SubRegisterFile {
     pub alias_info: HashMap<String, String>,
     /// Contains the type information for every registers.
     pub type_info: HashMap<String, String>,
+    /// Byte order used for memory accesses on this architecture.
+    pub endian: ir::Endian,
+    /// Architecture of the loaded binary.
+    pub arch: Arch,
 }
 
 pub struct RegisterIter(Box<Iterator<Item = (usize, String)>>);
@@ -85,7 +120,7 @@ impl SubRegisterFile {
     pub fn new(reg_info: &LRegInfo) -> SubRegisterFile {
         let mut aliases: HashMap<String, String> = HashMap::new();
         for reg in &reg_info.alias_info {
-            aliases.insert(reg.role_str.clone(), reg.reg.clone());
+            aliases.insert(role_name(&reg.role_str).to_owned(), reg.reg.clone());
         }
 
         let mut slices = HashMap::new();
@@ -99,6 +134,9 @@ impl SubRegisterFile {
             if reg.name.ends_with("flags") {
                 continue;
             } // HARDCODED x86
+            if reg.type_str == "flg" && reg.size > 1 {
+                continue;
+            } // Status words (cpsr, pstate) overlap the individual flag bits.
             events.push(SubRegister::new(
                 i as u64,
                 reg.offset as u64,
@@ -147,9 +185,25 @@ impl SubRegisterFile {
             whole_names: names,
             alias_info: aliases,
             type_info: types,
+            endian: ir::Endian::Little,
+            arch: Arch::X86,
         }
     }
 
+    /// Same as `new`, but for the given architecture and byte order.
+    pub fn new_for(reg_info: &LRegInfo, arch: Arch, endian: ir::Endian) -> SubRegisterFile {
+        let mut regfile = SubRegisterFile::new(reg_info);
+        regfile.arch = arch;
+        regfile.endian = endian;
+        regfile
+    }
+
+    /// Returns true for 32-bit ARM, where the low bit of branch targets switches between
+    /// the ARM and Thumb instruction sets.
+    pub fn supports_interworking(&self) -> bool {
+        self.arch == Arch::Arm
+    }
+
     // API for Sub Reigster.
     pub fn get_subregister(&self, name: &str) -> Option<SubRegister> {
         self.named_registers.get(name).cloned()
@@ -285,6 +339,16 @@ impl RegisterId {
     }
 }
 
+/// Name of the register role `role` as printed by radare2. Older versions print the
+/// role of the first argument as `Ao` and have no name for the one of the return value.
+fn role_name(role: &str) -> &str {
+    match role {
+        "Ao" => "A0",
+        "(null)" => "SN",
+        _ => role,
+    }
+}
+
 // TODO: if r2 ever starts keeping track of preserved registers, use that instead of this
 /// For a given named calling convention, return the set of registers it
 /// preserves across calls (are callee-saved).
@@ -317,3 +381,47 @@ fn callconv_name_to_preserved_list(cc_name: &str) -> &'static [&'static str] {
         _ => &[],
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir_reader::test_util::register_profile;
+
+    #[test]
+    fn role_name_test() {
+        assert_eq!(role_name("Ao"), "A0");
+        assert_eq!(role_name("(null)"), "SN");
+        assert_eq!(role_name("A0"), "A0");
+        assert_eq!(role_name("SP"), "SP");
+    }
+
+    #[test]
+    fn arch_test() {
+        assert_eq!(Arch::from_r2("x86", 64), Arch::X86);
+        assert_eq!(Arch::from_r2("arm", 32), Arch::Arm);
+        assert_eq!(Arch::from_r2("arm", 16), Arch::Arm);
+        assert_eq!(Arch::from_r2("arm", 64), Arch::AArch64);
+        assert_eq!(Arch::from_r2("mips", 32), Arch::Unknown);
+        assert_eq!(Arch::from_r2("ppc", 64), Arch::Unknown);
+    }
+
+    #[test]
+    fn interworking_test() {
+        // MIPS and PowerPC name their program counter `pc` too.
+        let arm = SubRegisterFile::new_for(&register_profile(), Arch::Arm, ir::Endian::Little);
+        let mips = SubRegisterFile::new_for(&register_profile(), Arch::Unknown, ir::Endian::Big);
+        assert!(arm.supports_interworking());
+        assert!(!mips.supports_interworking());
+        assert!(!SubRegisterFile::new(&register_profile()).supports_interworking());
+    }
+
+    #[test]
+    fn old_role_names_test() {
+        // The x86-64 profile comes from a radare2 which prints the old names.
+        let regfile = SubRegisterFile::new(&register_profile());
+        assert_eq!(regfile.alias_info.get("A0").map(String::as_str), Some("rdi"));
+        assert_eq!(regfile.alias_info.get("SN").map(String::as_str), Some("rax"));
+        assert!(!regfile.alias_info.contains_key("Ao"));
+        assert!(!regfile.alias_info.contains_key("(null)"));
+    }
+}
@@ -40,7 +40,7 @@
 use std::fmt::Debug;
 use std::hash::Hash;
 
-use middle::ir::MAddress;
+use middle::ir::{InstructionSet, MAddress};
 use super::graph_traits::{Graph, ConditionInfo};
 
 /// Provides __accessors__ to the underlying storage
@@ -102,6 +102,11 @@ pub trait CFG: Graph {
 
     /// Reference that represents an Invalid control flow edge.
     fn invalid_edge(&self) -> Option<Self::CFEdgeRef>;
+
+    /// Instruction set the basic block was decoded in, for architectures that have several.
+    fn block_mode(&self, _block: Self::ActionRef) -> Option<InstructionSet> {
+        None
+    }
 }
 
 /// Provides __mutators__ to the underlying storage
@@ -130,4 +135,7 @@ pub trait CFGMod: CFG {
     fn remove_control_edge(&mut self, source: Self::CFEdgeRef);
 
     fn set_block_size(&mut self, bb: Self::ActionRef, last: u64);
+
+    /// Record the instruction set the basic block was decoded in
+    fn set_block_mode(&mut self, _bb: Self::ActionRef, _mode: InstructionSet) { }
 }
@@ -17,6 +17,7 @@
 //!     * http://www.airs.com/dnovillo/Papers/mem-ssa.pdf
 
 
+use std::{cmp, u64};
 use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
 use petgraph::EdgeDirection;
@@ -197,6 +198,21 @@ impl<'a, I, T> MemorySSA<'a, I, T>
         return false;
     } 
 
+    // Check whether an access of `width` bits at `addr` touches the global at
+    // `start`. A global is assumed to extend up to the next known global.
+    fn global_overlaps(&self, start: u64, addr: u64, width: u16) -> bool {
+        let bytes = cmp::max(1, (width as u64 + 7) / 8);
+        let end = self.variables
+                      .iter()
+                      .filter_map(|var| match *var {
+                          VariableType::Global(g) if g > start => Some(g),
+                          _ => None,
+                      })
+                      .min()
+                      .unwrap_or(u64::MAX);
+        start < addr.saturating_add(bytes) && addr < end
+    }
+
     // Check reg is a BP/SP register or not
     fn check_local(&self, mut comment: String) -> bool {
         radeco_trace!("MemorrySSA|New comment added into test: {:?}", &comment);
@@ -299,10 +315,14 @@ impl<'a, I, T> MemorySSA<'a, I, T>
     // global variables;
     //      Otherwise, it will include all the variables.
     //
+    // When mem is a constant global address, only the globals overlapping the
+    // `width` bits accessed there are included.
+    //
     // NOTE: This function is only used in raw MemorySSA, and may be replaces by VSA.
-    fn calculate_may_alias(&mut self, idx: &T::ValueRef, mem: &T::ValueRef) {
+    fn calculate_may_alias(&mut self, idx: &T::ValueRef, mem: &T::ValueRef, width: u16) {
         let involve_local = self.local_nodes.contains(mem);
         let involve_global = self.global_nodes.contains(mem);
+        let const_addr = self.ssa.constant(*mem);
         let mut may_alias = HashSet::new();
         
         let involve_all = !(involve_local ^ involve_global);
@@ -325,8 +345,12 @@ impl<'a, I, T> MemorySSA<'a, I, T>
                     }
                 }
 
-                VariableType::Global(_) => {
-                    if involve_global || involve_all {
+                VariableType::Global(start) => {
+                    let overlaps = match const_addr {
+                        Some(addr) if !involve_all => self.global_overlaps(start, addr, width),
+                        _ => true,
+                    };
+                    if (involve_global || involve_all) && overlaps {
                         may_alias.insert(i);
                     }
                 }
@@ -357,11 +381,12 @@ impl<'a, I, T> MemorySSA<'a, I, T>
                 match ndata.nt {
                     NodeType::Op(opc) => {
                         match opc {
-                            MOpcode::OpLoad | MOpcode::OpStore => {
+                            MOpcode::OpLoad(..) | MOpcode::OpStore(..) => {
                                 // TODO: Token::EEq will cause OpStore uses the first
                                 // argument as memory address, although it's impossible
                                 // to happen in normal situation.
-                                self.calculate_may_alias(&expr, &operands[1]);
+                                let width = opc.access_width().unwrap_or(64);
+                                self.calculate_may_alias(&expr, &operands[1], width);
                             }
 
                             _ => {
@@ -636,7 +661,7 @@ impl<'a, I, T> MemorySSA<'a, I, T>
                 radeco_trace!("MemorrySSA|Deal with node: {:?}", expr);
 
                 match ndata.nt {
-                    NodeType::Op(MOpcode::OpLoad) => {
+                    NodeType::Op(MOpcode::OpLoad(..)) => {
                         let set: Vec<VarId> = self.may_aliases.get(&expr)
                                                                 .unwrap_or_else(|| {
                                                                     radeco_err!("Cannot find may_alias set!");
@@ -656,7 +681,7 @@ impl<'a, I, T> MemorySSA<'a, I, T>
                         }
                     }
 
-                    NodeType::Op(MOpcode::OpStore) => {  
+                    NodeType::Op(MOpcode::OpStore(..)) => {  
                         let set: Vec<VarId> = self.may_aliases.get(&expr)
                                                                 .unwrap_or_else(|| {
                                                                     radeco_err!("Cannot find may_alias set!");
@@ -16,7 +16,7 @@ use petgraph::visit::{IntoEdgeReferences, EdgeRef};
 use petgraph::EdgeDirection;
 use petgraph::stable_graph::StableDiGraph;
 use petgraph::graph::{EdgeIndex,  NodeIndex};
-use middle::ir::{self, MAddress, MOpcode};
+use middle::ir::{self, InstructionSet, MAddress, MOpcode};
 use middle::regfile::SubRegisterFile;
 
 use super::ssa_traits::NodeData as TNodeData;
@@ -171,6 +171,8 @@ pub struct SSAStorage {
     pub assoc_data: AssociatedData,
     pub regfile: Arc<SubRegisterFile>,
     pub constants: HashMap<u64, NodeIndex>,
+    /// Instruction set of basic blocks, for architectures with ARM/Thumb interworking
+    pub block_modes: HashMap<NodeIndex, InstructionSet>,
 }
 
 impl default::Default for SSAStorage {
@@ -182,6 +184,7 @@ impl default::Default for SSAStorage {
             assoc_data: HashMap::new(),
             regfile: Arc::default(),
             constants: HashMap::new(),
+            block_modes: HashMap::new(),
         }
     }
 }
@@ -195,6 +198,7 @@ impl SSAStorage {
             assoc_data: HashMap::new(),
             regfile: Arc::default(),
             constants: HashMap::new(),
+            block_modes: HashMap::new(),
         }
     }
 
@@ -595,6 +599,10 @@ impl CFG for SSAStorage {
     fn invalid_edge(&self) -> Option<Self::CFEdgeRef> {
         Some(EdgeIndex::end())
     }
+
+    fn block_mode(&self, block: Self::ActionRef) -> Option<InstructionSet> {
+        self.block_modes.get(&block).cloned()
+    }
 }
 
 impl CFGMod for SSAStorage {
@@ -652,6 +660,7 @@ impl CFGMod for SSAStorage {
 
         let preds = self.preds_of(exi);
         self.remove_value(exi);
+        self.block_modes.remove(&exi);
 
         // block removal can make predecessors lose selectors
         for pred_e in preds {
@@ -682,6 +691,11 @@ impl CFGMod for SSAStorage {
         }
     }
 
+    fn set_block_mode(&mut self, bb: Self::ActionRef, mode: InstructionSet) {
+        if self.is_block(bb) {
+            self.block_modes.insert(bb, mode);
+        }
+    }
 }
 
 ///////////////////////////////////////////////////////////////////////////////
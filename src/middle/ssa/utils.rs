@@ -35,6 +35,20 @@ pub fn call_info(call_node: NodeIndex, ssa: &SSAStorage) -> Option<CallInfo> {
     })
 }
 
+/// Extracts the value of every register read by an operation which takes them like a
+/// call does, i.e. register `regid` as operand `regid + 1`.
+pub fn register_args(node: NodeIndex, ssa: &SSAStorage) -> RegisterMap<NodeIndex> {
+    let mut register_args = ssa.regfile.new_register_map();
+    for edge_ref in ssa.g.edges_directed(node, Outgoing) {
+        if let &EdgeData::Data(op_idx) = edge_ref.weight() {
+            if op_idx > 0 {
+                register_args.insert(RegisterId::from_u8(op_idx - 1), edge_ref.target());
+            }
+        }
+    }
+    register_args
+}
+
 /// Extracts the values of all registers modified by a call.
 pub fn call_rets(call_node: NodeIndex, ssa: &SSAStorage) -> RegisterMap<(NodeIndex, ValueInfo)> {
     let mut ret = ssa.regfile.new_register_map();
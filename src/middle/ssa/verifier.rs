@@ -170,11 +170,17 @@ impl Verify for SSAStorage {
             match (ndata.nt, ndata.vt) {
                 (TNodeType::Op(opcode), vi) => { 
                     let w = vi.width().get_width().unwrap_or(64);
+                    // Operands which are not values are reported rather than panicked on.
+                    let width_of = |n: NodeIndex| -> Result<u16, SSAErr<SSAStorage>> {
+                        self.node_data(n)
+                            .map(|vi| vi.vt.width().get_width().unwrap_or(64))
+                            .map_err(|_| SSAErr::InvalidExpr(n))
+                    };
 
                     let opfilter = |&x: &NodeIndex| -> bool {
                         if let Some(op) = self.opcode(x) {
                             match op {
-                                MOpcode::OpLoad | MOpcode::OpStore => false,
+                                MOpcode::OpLoad(..) | MOpcode::OpStore(..) => false,
                                 _ => true,
                             }
                         } else {
@@ -203,7 +209,19 @@ impl Verify for SSAStorage {
                         }
                     }
 
-                    // TODO: We do not consider OpStore and OpLoad's width now.
+                    // Memory accesses carry their own width, which is checked against
+                    // the accessed value below.
+                    match opcode {
+                        MOpcode::OpLoad(aw, _) => {
+                            check!(w == aw, SSAErr::IncompatibleWidth(*exi, aw, w));
+                        }
+                        MOpcode::OpStore(aw, _) if op_len == 3 => {
+                            let vw = width_of(operands[2])?;
+                            check!(vw == aw, SSAErr::IncompatibleWidth(*exi, aw, vw));
+                        }
+                        _ => {}
+                    }
+
                     operands.retain(&opfilter);
 
                     if n == 0 || operands.len() == 0 {
@@ -211,28 +229,25 @@ impl Verify for SSAStorage {
                     }
                     match opcode {
                         MOpcode::OpNarrow(w0) => {
-                            let opw = self.node_data(operands[0]).map(|vi| vi.vt.width().get_width().unwrap_or(64)).unwrap();
+                            let opw = width_of(operands[0])?;
                             check!(opw > w0, SSAErr::IncompatibleWidth(*exi, opw, w0));
                             check!(w == w0, SSAErr::IncompatibleWidth(*exi, w, w0));
                         }
                         MOpcode::OpZeroExt(w0) | MOpcode::OpSignExt(w0) => {
-                            let opw = self.node_data(operands[0]).map(|vi| vi.vt.width().get_width().unwrap_or(64)).unwrap();
+                            let opw = width_of(operands[0])?;
                             check!(opw < w0, SSAErr::IncompatibleWidth(*exi, opw, w0));
                             check!(w == w0, SSAErr::IncompatibleWidth(*exi, w, w0));
                         }
-                        MOpcode::OpEq |
-                        MOpcode::OpGt |
-                        MOpcode::OpLt => {
+                        ref op if op.is_comparison() => {
                             check!(w == 1, SSAErr::IncompatibleWidth(*exi, 1, w));
                         }
-                        // TODO: Width of OpStore and OpLoad now is not certain.
-                        MOpcode::OpCall | MOpcode::OpStore | MOpcode::OpLoad => {}
+                        MOpcode::OpCall | MOpcode::OpStore(..) | MOpcode::OpLoad(..) => {}
                         _ => {
                             // All operands to an expr must have the same width.
-                            let w0 = self.node_data(operands[0]).map(|vi| vi.vt.width().get_width().unwrap_or(64)).unwrap();
+                            let w0 = width_of(operands[0])?;
                             check!(w0 == w, SSAErr::IncompatibleWidth(*exi, w, w0));
                             for op in operands.iter() {
-                                let w1 = self.node_data(*op).map(|vi| vi.vt.width().get_width().unwrap_or(64)).unwrap();
+                                let w1 = width_of(*op)?;
                                 check!(w == w1, SSAErr::IncompatibleWidth(*exi, w, w1));
                             }
                         }
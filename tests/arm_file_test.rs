@@ -0,0 +1,178 @@
+//! Lifts recorded ARM and AArch64 dumps and checks the resulting SSA.
+
+extern crate radeco_lib;
+
+use std::rc::Rc;
+
+use radeco_lib::backend::arm::arm_idioms;
+use radeco_lib::frontend::radeco_containers::{ModuleLoader, ProjectLoader, RadecoFunction,
+                                              RadecoProject};
+use radeco_lib::frontend::radeco_source::FileSource;
+use radeco_lib::middle::ir::{InstructionSet, MOpcode};
+use radeco_lib::middle::ssa::cfg_traits::CFG;
+use radeco_lib::middle::ssa::ssa_traits::SSA;
+use radeco_lib::middle::ssa::ssastorage::SSAStorage;
+use radeco_lib::middle::ssa::verifier;
+
+fn load(path: &str) -> RadecoProject {
+    let fsource = Rc::new(FileSource::open(Some(path)));
+    ProjectLoader::new()
+        .source(fsource.clone())
+        .module_loader(ModuleLoader::default().source(fsource).build_ssa().build_callgraph())
+        .load()
+}
+
+fn function<'a>(rproj: &'a RadecoProject, name: &str) -> &'a RadecoFunction {
+    let rmod = rproj.nth_module(0).expect("No module loaded");
+    rmod.functions
+        .values()
+        .find(|rfn| rfn.name == name)
+        .expect("Function not loaded")
+}
+
+fn opcodes(ssa: &SSAStorage) -> Vec<MOpcode> {
+    ssa.values().into_iter().filter_map(|v| ssa.opcode(v)).collect()
+}
+
+// The operands of the subtraction done by `cmp` at `address`.
+fn compared(ssa: &SSAStorage, address: u64) -> Vec<<SSAStorage as SSA>::ValueRef> {
+    let cmp = ssa.values()
+        .into_iter()
+        .filter(|&v| ssa.opcode(v) == Some(MOpcode::OpSub))
+        .filter(|&v| ssa.address(v).map(|a| a.address) == Some(address))
+        .find(|&v| ssa.operands_of(v).iter().all(|&o| ssa.constant(o).is_none()))
+        .expect("No comparison found");
+    ssa.operands_of(cmp)
+}
+
+// The function returns through the link register, so the block holding `bx lr`/`ret` is
+// the only predecessor of the exit node.
+fn check_return(ssa: &SSAStorage, ret_addr: u64) {
+    let exit = ssa.exit_node().expect("No exit node");
+    let preds = ssa.preds_of(exit)
+        .into_iter()
+        .filter_map(|b| ssa.starting_address(b))
+        .map(|a| a.address)
+        .collect::<Vec<_>>();
+    assert_eq!(preds, vec![ret_addr]);
+}
+
+// The call writes the return address to the link register before the callee reads it.
+fn check_call(ssa: &SSAStorage, ret_addr: u64) {
+    let call = ssa.values()
+        .into_iter()
+        .find(|&v| ssa.opcode(v) == Some(MOpcode::OpCall))
+        .expect("No call found");
+    let passes_ret_addr = ssa.operands_of(call).into_iter().any(|op| {
+        let op = match ssa.opcode(op) {
+            Some(MOpcode::OpNarrow(_)) => ssa.operands_of(op)[0],
+            _ => op,
+        };
+        ssa.constant(op) == Some(ret_addr)
+    });
+    assert!(passes_ret_addr);
+}
+
+fn check_project(path: &str, max: u64, main_ret: u64, width: u16) {
+    let mut rproj = load(path);
+    for rfn in rproj.nth_module(0).unwrap().functions.values() {
+        verifier::verify(rfn.ssa()).expect("SSA verification failed");
+    }
+
+    check_return(function(&rproj, "sym.max").ssa(), max + 12);
+    check_call(function(&rproj, "sym.main").ssa(), main_ret);
+
+    let ops = opcodes(function(&rproj, "sym.copy").ssa());
+    assert!(ops.iter().any(|op| if let MOpcode::OpLoad(32, _) = *op { true } else { false }));
+    assert!(ops.iter().any(|op| if let MOpcode::OpStore(32, _) = *op { true } else { false }));
+    // Saving the link register on the stack spills a whole register.
+    assert!(opcodes(function(&rproj, "sym.main").ssa())
+        .iter()
+        .any(|op| if let MOpcode::OpStore(w, _) = *op { w == width } else { false }));
+
+    let operands = compared(function(&rproj, "sym.max").ssa(), max);
+    {
+        let rmod = rproj.nth_module_mut(0).unwrap();
+        for rfn in rmod.functions.values_mut() {
+            arm_idioms::replace(rfn.ssa_mut());
+            verifier::verify(rfn.ssa()).expect("SSA verification failed after idiom replacement");
+        }
+    }
+
+    // `bge` is taken unless the first operand of `cmp` is less than the second one, both
+    // read as signed integers.
+    let ssa = function(&rproj, "sym.max").ssa();
+    let ite = ssa.values()
+        .into_iter()
+        .find(|&v| ssa.opcode(v) == Some(MOpcode::OpITE))
+        .expect("No conditional branch found");
+    let cond = ssa.operands_of(ite)[0];
+    assert_eq!(ssa.opcode(cond), Some(MOpcode::OpNot));
+    let lt = ssa.operands_of(cond)[0];
+    assert_eq!(ssa.opcode(lt), Some(MOpcode::OpSLt(width)));
+    assert_eq!(ssa.operands_of(lt), operands);
+}
+
+#[test]
+fn arm_file_test() {
+    check_project("./test_files/arm_file/arm_file", 0x10400, 0x10420, 32);
+}
+
+#[test]
+fn arm64_file_test() {
+    check_project("./test_files/arm64_file/arm64_file", 0x400500, 0x400520, 64);
+}
+
+// Blocks of `rfn` that have an instruction set recorded are all decoded in `mode`.
+fn check_block_modes(rfn: &RadecoFunction, mode: InstructionSet) {
+    let ssa = rfn.ssa();
+    let modes = ssa.blocks().into_iter().filter_map(|b| ssa.block_mode(b)).collect::<Vec<_>>();
+    assert!(!modes.is_empty());
+    assert!(modes.iter().all(|&m| m == mode));
+}
+
+#[test]
+fn arm_thumb_interworking_test() {
+    let rproj = load("./test_files/arm_file/arm_file");
+    let rmod = rproj.nth_module(0).unwrap();
+    assert_eq!(rmod.mode_at(0x10400), Some(InstructionSet::Arm));
+    assert_eq!(rmod.mode_at(0x10438), Some(InstructionSet::Thumb));
+
+    // `sym.inc` is recorded with the low bit of its address set.
+    let inc = rmod.function(0x10438).expect("Thumb function not loaded at its real address");
+    assert_eq!(inc.mode, Some(InstructionSet::Thumb));
+    check_block_modes(inc, InstructionSet::Thumb);
+    check_return(inc.ssa(), 0x10438);
+
+    let max = function(&rproj, "sym.max");
+    assert_eq!(max.mode, Some(InstructionSet::Arm));
+    check_block_modes(max, InstructionSet::Arm);
+}
+
+#[test]
+fn arm64_has_no_modes() {
+    let rproj = load("./test_files/arm64_file/arm64_file");
+    let max = function(&rproj, "sym.max");
+    assert_eq!(max.mode, None);
+    assert!(max.ssa().blocks().into_iter().all(|b| max.ssa().block_mode(b).is_none()));
+}
+
+#[test]
+fn arm_blx_to_thumb_test() {
+    let rproj = load("./test_files/arm_file/arm_file");
+    let rmod = rproj.nth_module(0).unwrap();
+
+    // `sym.dec` is reported at its real address and lies in ARM code by the mapping
+    // symbols. Only the `blx` calling it from `sym.twice` tells that it is Thumb code.
+    let twice = function(&rproj, "sym.twice");
+    assert_eq!(twice.mode, Some(InstructionSet::Arm));
+    assert_eq!(twice.callee_modes.get(&0x1044c), Some(&InstructionSet::Thumb));
+    check_block_modes(twice, InstructionSet::Arm);
+
+    let dec = rmod.function(0x1044c).expect("Callee of blx not loaded");
+    assert_eq!(dec.mode, Some(InstructionSet::Thumb));
+    assert_eq!(rmod.mode_at(0x1044c), Some(InstructionSet::Thumb));
+    check_block_modes(dec, InstructionSet::Thumb);
+    check_return(dec.ssa(), 0x1044c);
+    verifier::verify(dec.ssa()).expect("SSA verification failed");
+}